@@ -12,19 +12,24 @@ fn main() {
         "pub static KEYWORDS: phf::Map<&'static str, TokenType> = {}",
         phf_codegen::Map::new()
             .entry("and", "TokenType::And")
+            .entry("catch", "TokenType::Catch")
             .entry("class", "TokenType::Class")
+            .entry("defer", "TokenType::Defer")
             .entry("else", "TokenType::Else")
             .entry("false", "TokenType::False")
             .entry("for", "TokenType::For")
             .entry("fun", "TokenType::Fun")
             .entry("if", "TokenType::If")
+            .entry("match", "TokenType::Match")
             .entry("nil", "TokenType::Nil")
             .entry("or", "TokenType::Or")
             .entry("print", "TokenType::Print")
             .entry("return", "TokenType::Return")
             .entry("super", "TokenType::Super")
             .entry("this", "TokenType::This")
+            .entry("throw", "TokenType::Throw")
             .entry("true", "TokenType::True")
+            .entry("try", "TokenType::Try")
             .entry("var", "TokenType::Var")
             .entry("while", "TokenType::While")
             .build()
@@ -0,0 +1,41 @@
+#!/usr/bin/env bash
+# Runs the benchmark programs from the book, checks what each prints against
+# the .out file next to it and reports how long it took. The last line a
+# benchmark prints is its running time in milliseconds, which is left out of
+# the comparison.
+#
+#   examples/benchmark/run.sh [interpreter] [results.csv]
+#
+# The interpreter defaults to the release build. With a results file, a line
+# "commit,benchmark,milliseconds" is appended for every benchmark so the
+# numbers can be tracked over time. Flags for the interpreter, like --vm, go
+# in LOX_FLAGS.
+set -euo pipefail
+
+dir=$(dirname "$0")
+lox=${1:-./target/release/lox-interpreter-rs}
+results=${2:-}
+flags=${LOX_FLAGS:-}
+commit=$(git rev-parse --short HEAD 2>/dev/null || echo unknown)
+failed=0
+
+for file in "$dir"/*.lox; do
+  name=$(basename "$file" .lox)
+  if ! output=$("$lox" $flags "$file"); then
+    echo "$name: failed to run"
+    failed=1
+    continue
+  fi
+  elapsed=$(tail -n 1 <<< "$output")
+  if ! diff -u "${file%.lox}.out" <(sed '$d' <<< "$output"); then
+    echo "$name: unexpected output"
+    failed=1
+    continue
+  fi
+  printf '%-16s %8s ms\n' "$name" "$elapsed"
+  if [[ -n "$results" ]]; then
+    echo "$commit,$name,$elapsed" >> "$results"
+  fi
+done
+
+exit $failed
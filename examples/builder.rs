@@ -0,0 +1,66 @@
+// Puts together a Lox for scripts that can't be trusted: no built-ins but
+// the one native the host gives them, no file writes, `print` captured in a
+// buffer that can't grow past ten lines and recursion cut off early.
+//
+//   cargo run --example builder
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use lox_interpreter_rs::error::Error;
+use lox_interpreter_rs::interpreter::Interpreter;
+use lox_interpreter_rs::object::Object;
+use lox_interpreter_rs::Lox;
+
+// Collects what the scripts print, shared with the host to read it after.
+#[derive(Clone, Default)]
+struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn shout(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    Ok(Object::String(Rc::from(args[0].to_string().to_uppercase())))
+}
+
+fn main() {
+    let output = Buffer::default();
+    let mut lox = Lox::builder()
+        .with_stdlib(false)
+        .with_io(false)
+        .with_output(output.clone())
+        .native("shout(text)", shout)
+        .with_globals([("user".to_string(), Object::String(Rc::from("Ada")))])
+        .max_depth(50)
+        .max_output_lines(10)
+        .build();
+
+    let source = "
+        print shout(\"hello, \" + user);
+        fun down(n) { return down(n + 1); }
+        down(0);
+    ";
+    let result = lox.run(source.to_string());
+    print!("Printed: {}", String::from_utf8_lossy(&output.0.borrow()));
+    if !matches!(result, Err(Error::ResourceLimit { .. })) {
+        std::process::exit(70);
+    }
+    // Without the standard library there is no clock().
+    if lox.run("clock();".to_string()).is_ok() {
+        std::process::exit(70);
+    }
+    // Printing without end stops at the tenth line.
+    let result = lox.run("while (true) print user;".to_string());
+    let lines = output.0.borrow().iter().filter(|&&b| b == b'\n').count();
+    if !matches!(result, Err(Error::ResourceLimit { .. })) || lines != 10 {
+        std::process::exit(70);
+    }
+}
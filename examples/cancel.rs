@@ -0,0 +1,42 @@
+// Stops a script that would run forever from another thread, the way the
+// stop button of an editor would, and runs another one after.
+//
+//   cargo run --example cancel
+
+use std::thread;
+use std::time::Duration;
+
+use lox_interpreter_rs::cancel::CancellationToken;
+use lox_interpreter_rs::error::Error;
+use lox_interpreter_rs::Lox;
+
+fn main() {
+    let mut lox = Lox::new();
+    let token = lox.cancellation_token();
+    let stopper = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        token.cancel();
+    });
+
+    // A catch doesn't stop the cancellation.
+    let source = "
+        var spins = 0;
+        try {
+          while (true) spins = spins + 1;
+        } catch (error) {
+          print \"caught \" + error.message;
+        }
+    ";
+    match lox.run(source.to_string()) {
+        Err(Error::Cancelled { token }) => println!("Stopped at line {}.", token.line),
+        _ => std::process::exit(70),
+    }
+    stopper.join().unwrap();
+
+    // The token stays tripped, a new one lets programs run again.
+    lox.set_cancellation_token(CancellationToken::new());
+    let source = "assert(spins > 0); print \"Running again.\";";
+    if lox.run(source.to_string()).is_err() {
+        std::process::exit(70);
+    }
+}
@@ -0,0 +1,46 @@
+// Lists the classes a script declares, with their methods and doc comments,
+// the way a host would generate bindings or documentation for them.
+//
+//   cargo run --example classes
+
+use lox_interpreter_rs::Lox;
+
+fn main() {
+    let mut lox = Lox::new();
+    let source = "
+        /// A point on the plane.
+        class Point {
+          init(x, y) {
+            this.x = x;
+            this.y = y;
+          }
+
+          /// The distance to the origin, squared.
+          norm() { return this.x * this.x + this.y * this.y; }
+        }
+
+        /// A point with a name.
+        class Place < Point {
+          label(name) { this.name = name; }
+        }
+    ";
+    if lox.run(source.to_string()).is_err() {
+        std::process::exit(65);
+    }
+
+    for class in lox.classes() {
+        match &class.superclass {
+            Some(superclass) => println!("class {}({}) < {}", class.name, class.arity, superclass),
+            None => println!("class {}({})", class.name, class.arity),
+        }
+        if let Some(doc) = &class.doc {
+            println!("  {}", doc);
+        }
+        for method in &class.methods {
+            println!("  {}({})", method.name, method.params.join(", "));
+            if let Some(doc) = &method.doc {
+                println!("    {}", doc);
+            }
+        }
+    }
+}
@@ -0,0 +1,286 @@
+#!/usr/bin/env bash
+# Checks the exit codes and messages of the command line, the contract
+# scripts and editors calling the interpreter rely on:
+#
+#   0 success, 64 usage error, 65 syntax or resolution error,
+#   70 runtime error, 74 file that can't be read or written
+#
+#   examples/cli/run.sh [interpreter]
+#
+# The interpreter defaults to the release build.
+set -uo pipefail
+
+lox=${1:-./target/release/lox-interpreter-rs}
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+failed=0
+
+printf 'print "ok";\n' > "$tmp/ok.lox"
+printf 'print 1 +;\n' > "$tmp/syntax.lox"
+printf 'print missing;\n' > "$tmp/runtime.lox"
+printf 'return 1;\n' > "$tmp/resolve.lox"
+
+# expect <description> <exit code> <text in stdout and stderr> <command...>
+# Standard input comes from the file $input, empty by default.
+input=/dev/null
+expect() {
+  local description=$1 code=$2 text=$3
+  shift 3
+  local output status
+  output=$("$@" < "$input" 2>&1)
+  status=$?
+  if [[ $status -ne $code ]]; then
+    echo "$description: exit code $status, expected $code"
+    echo "$output"
+    failed=1
+  elif ! grep -qF -- "$text" <<< "$output"; then
+    echo "$description: expected output containing '$text'"
+    echo "$output"
+    failed=1
+  else
+    echo "$description: ok"
+  fi
+}
+
+expect "script" 0 "ok" "$lox" "$tmp/ok.lox"
+expect "script with a syntax error" 65 "[E107]" "$lox" "$tmp/syntax.lox"
+expect "script with a resolution error" 65 "[E209]" "$lox" "$tmp/resolve.lox"
+expect "script with a runtime error" 70 "[R201]" "$lox" "$tmp/runtime.lox"
+expect "missing script" 74 "Could not read '$tmp/missing.lox'" "$lox" "$tmp/missing.lox"
+
+expect "-e" 0 "3" "$lox" -e 'print 1 + 2;'
+expect "-e with a syntax error" 65 "[E107]" "$lox" -e 'print 1 +;'
+expect "-e with a runtime error" 70 "[R201]" "$lox" -e 'print missing;'
+expect "-e without a program" 64 "-e expects a program." "$lox" -e
+expect "-e with a script" 64 "Usage:" "$lox" -e 'print 1;' "$tmp/ok.lox"
+
+# String literals are limited to 1MB.
+printf 'var s = "%s";\n' "$(head -c 1048576 /dev/zero | tr '\0' a)" > "$tmp/long_string.lox"
+printf 'var s = "%s";\n' "$(head -c 1048577 /dev/zero | tr '\0' a)" > "$tmp/too_long_string.lox"
+expect "string literal of 1MB" 0 "" "$lox" "$tmp/long_string.lox"
+expect "string literal over 1MB" 65 "String literal exceeds 1MB limit. [E003]" "$lox" "$tmp/too_long_string.lox"
+# Only the part of such a line around the caret is shown.
+expect "long source line cut" 65 'aaaaaaaaaaaaaaaa...' "$lox" "$tmp/too_long_string.lox"
+if (( $("$lox" "$tmp/too_long_string.lox" 2>&1 | wc -c) > 1000 )); then
+  echo "long source line cut: more than 1000 bytes of output"
+  failed=1
+fi
+
+expect "check" 0 "" "$lox" check "$tmp/ok.lox"
+expect "check with a syntax error" 65 "[E107]" "$lox" check "$tmp/syntax.lox"
+expect "check of a missing script" 74 "Could not read" "$lox" check "$tmp/missing.lox"
+printf 'var x = 1;\nfun show(x) {\n  print x;\n}\n' > "$tmp/shadow.lox"
+expect "explain bindings" 0 "fun show(x) {  // shadows outer 'x' from line 1" "$lox" --explain-bindings "$tmp/shadow.lox"
+expect "explain bindings with a syntax error" 65 "[E107]" "$lox" --explain-bindings "$tmp/syntax.lox"
+printf 'fun counter() {\n  var count = 0;\n  fun tick() { count = count + 1; }\n  return tick;\n}\nprint clock();\n' > "$tmp/captured.lox"
+expect "explain resolution" 0 "local 1 slot 0  var count at 2:7, captured" "$lox" --explain-resolution "$tmp/captured.lox"
+expect "explain resolution of a built-in" 0 "global          built-in" "$lox" --explain-resolution "$tmp/captured.lox"
+expect "explain resolution with a syntax error" 65 "[E107]" "$lox" --explain-resolution "$tmp/syntax.lox"
+# rename refuses a new name that would make a use refer to another declaration.
+printf 'var a = 1;\nfun f() {\n  var b = 2;\n  print a;\n}\n' > "$tmp/rename.lox"
+expect "rename" 0 "print c;" "$lox" rename "$tmp/rename.lox" 1:5 c
+expect "rename shadowed by a local" 65 "Can't rename 'a' to 'b': 'b' would be shadowed by var b at 3:7." "$lox" rename "$tmp/rename.lox" 4:9 b
+expect "rename shadowing a global" 65 "'a' would shadow var a at 1:5." "$lox" rename "$tmp/rename.lox" 3:7 a
+expect "rename to a built-in" 65 "'clock' would shadow the built-in 'clock'." "$lox" rename "$tmp/rename.lox" 1:5 clock
+expect "eval" 0 "ok" "$lox" eval "$tmp/ok.lox"
+expect "eval with a runtime error" 70 "[R201]" "$lox" eval "$tmp/runtime.lox"
+
+expect "bench" 0 "3 runs after 1 warmup runs" "$lox" bench "$tmp/ok.lox" 3 1
+expect "bench of a failing script" 70 "[R201]" "$lox" bench "$tmp/runtime.lox" 1 0
+expect "bench with an invalid count" 64 "bench expects a number of runs but got 'x'." "$lox" bench "$tmp/ok.lox" x
+
+printf 'fun square(n) { return n * n; }\nclass Box { get() { return square(2); } }\nprint Box().get();\n' > "$tmp/profile.lox"
+expect "profile" 0 "Box.get" "$lox" profile "$tmp/profile.lox"
+expect "profile of a failing script" 70 "[R201]" "$lox" profile "$tmp/runtime.lox"
+expect "profile with an invalid count" 64 "profile expects a number of functions but got 'x'." "$lox" profile "$tmp/ok.lox" x
+expect "profile to an unwritable file" 74 "Could not write" "$lox" profile "$tmp/ok.lox" 5 "$tmp/missing/stacks.folded"
+"$lox" profile "$tmp/profile.lox" 5 "$tmp/stacks.folded" > /dev/null 2>&1
+if ! grep -q "^script;Box.get;square [0-9]*$" "$tmp/stacks.folded"; then
+  echo "profile folded stacks: expected a line for script;Box.get;square"
+  cat "$tmp/stacks.folded"
+  failed=1
+else
+  echo "profile folded stacks: ok"
+fi
+
+# The preprocessor keeps the tokens of the files it reads in the cache
+# directory and only scans the ones that changed, --verbose logs how many it
+# found there.
+printf '#include "included.lox"\nprint greeting;\n' > "$tmp/including.lox"
+printf 'var greeting = "hello";\n' > "$tmp/included.lox"
+cached=(env XDG_CACHE_HOME="$tmp/cache" "$lox" --verbose --preprocess)
+expect "include cache, first run" 0 "include cache hits=0 misses=2" "${cached[@]}" "$tmp/including.lox"
+expect "include cache, second run" 0 "include cache hits=2 misses=0" "${cached[@]}" "$tmp/including.lox"
+printf 'var greeting = "changed";\n' > "$tmp/included.lox"
+expect "include cache, changed file" 0 "include cache hits=1 misses=1" "${cached[@]}" "$tmp/including.lox"
+expect "include cache, changed file output" 0 "changed" "${cached[@]}" "$tmp/including.lox"
+expect "--no-cache" 0 "include cache hits=0 misses=0" "${cached[@]}" --no-cache "$tmp/including.lox"
+
+expect "too many arguments" 64 "Usage:" "$lox" "$tmp/ok.lox" extra arguments
+expect "--help" 0 "--deny-file-writes" "$lox" --help
+expect "--version" 0 "lox-rs " "$lox" --version
+expect "option without its value" 64 "--seed expects a number." "$lox" --seed
+expect "invalid option value" 64 "--seed expects a number but got 'x'." "$lox" --seed x "$tmp/ok.lox"
+expect "unwritable recording" 74 "Could not write" "$lox" --record "$tmp/missing/events.json" "$tmp/ok.lox"
+
+# Errors in the REPL are reported but don't end the session.
+printf 'print 1 +;\nprint missing;\nprint "still here";\n' > "$tmp/session.txt"
+input="$tmp/session.txt"
+expect "REPL" 0 "still here" "$lox"
+
+# Definitions persist for the rest of the session. A line with a compile
+# error runs none of its statements, a line with a runtime error keeps what
+# ran before the error.
+cat > "$tmp/persist.txt" << 'EOF'
+var count = 1;
+fun bump() { count = count + 1; return count; }
+class Box { init(value) { this.value = value; } }
+print count +;
+var broken = ;
+var partial = "kept"; print missing;
+bump();
+print "box " + to_string(Box(bump()).value);
+print "count " + to_string(globals().count);
+print partial;
+print broken;
+EOF
+input="$tmp/persist.txt"
+expect "REPL definitions persist after a compile error" 0 "box 3" "$lox"
+expect "REPL globals()" 0 "count 3" "$lox"
+expect "REPL keeps what ran before a runtime error" 0 "kept" "$lox"
+expect "REPL doesn't run a line with a compile error" 0 "Undefined variable 'broken'." "$lox"
+
+# Input that isn't a complete statement yet continues on the next line.
+printf 'fun add(a, b)\n{\n  return a + b;\n}\nif (add(1, 2) == 3)\n  print "three";\nadd(1,\n  2) * 2\n' > "$tmp/lines.txt"
+input="$tmp/lines.txt"
+expect "REPL statement over several lines" 0 "..> three" "$lox"
+expect "REPL expression over several lines" 0 "..> 6" "$lox"
+
+# Every line is scanned from line 1. A function keeps finding its variables
+# where they are when a later line has a global at the same line and column.
+cat > "$tmp/columns.txt" << 'EOF'
+fun f() { var x = "local"; { return x; } }
+var x = "global";
+print                               x;
+print "f " + f();
+EOF
+input="$tmp/columns.txt"
+expect "REPL lines with tokens in the same place" 0 "f local" "$lox"
+
+# :help lists the REPL's commands, an unknown one points to it.
+printf ':help\n:nope\nprint version() == __version__;\n' > "$tmp/help.txt"
+input="$tmp/help.txt"
+expect "REPL help" 0 ":eval-at-frame <n> <expression>" "$lox"
+expect "REPL unknown command" 0 "Unknown command ':nope'. Type :help for the commands." "$lox"
+expect "REPL version" 0 "> true" "$lox"
+
+# The REPL echoes the value of an expression statement, except nil.
+printf '1 + 2\nclass Point { to_string() { return "a point"; } }\nPoint();\n' > "$tmp/echo.txt"
+input="$tmp/echo.txt"
+expect "REPL echo" 0 "> 3" "$lox"
+expect "REPL echo with to_string()" 0 "> a point" "$lox"
+
+# A statement spanning several lines is run once it is complete.
+printf 'fun add(a, b) {\n  return a +\n    b;\n}\nprint add(1, 2);\n' > "$tmp/lines.txt"
+input="$tmp/lines.txt"
+expect "REPL continuation prompt" 0 "..> ..> ..> > 3" "$lox"
+
+# The frames of a runtime error can be inspected after it, a caught error
+# leaves them alone.
+cat > "$tmp/frames.txt" << 'EOF'
+fun inner(n) { var half = n / 2; return half.missing; }
+fun outer() { var m = 8; return inner(m); }
+outer();
+try { inner(4); } catch (error) {}
+:frames
+:eval-at-frame 0 half + n
+:eval-at-frame 1 "m is " + to_string(m)
+:eval-at-frame 3 m
+EOF
+input="$tmp/frames.txt"
+expect "REPL frames" 0 "#1 outer [line 1]" "$lox"
+expect "REPL eval in a frame" 0 "> 12" "$lox"
+expect "REPL eval in a caller's frame" 0 "m is 8" "$lox"
+expect "REPL eval in a missing frame" 0 "No frame '3'" "$lox"
+
+# :doc shows a declaration with the /// comment before it, :type the type of
+# a value.
+cat > "$tmp/doc.txt" << 'EOF'
+/// Says hello.
+fun greet(name) { return "Hello, " + name; }
+class Animal {
+  /// What the animal says.
+  speak() { return "..."; }
+}
+class Dog < Animal {}
+:doc greet
+:doc Dog.speak
+:type Dog()
+EOF
+input="$tmp/doc.txt"
+expect "REPL doc" 0 "fun greet(name)" "$lox"
+expect "REPL doc comment" 0 "Says hello." "$lox"
+expect "REPL doc of an inherited method" 0 "What the animal says." "$lox"
+expect "REPL type" 0 "Dog instance" "$lox"
+
+# --why-kept lists what tree shaking removed.
+expect "tree shaking" 0 "removed Triangle" "$lox" --preprocess --why-kept examples/tree_shake/main.lox
+
+# graph works out calls without running the script.
+expect "call graph" 0 "Shape.describe -> Square.area" "$lox" graph --format=text examples/call_graph.lox
+expect "include graph" 0 "main.lox -> examples/tree_shake/geometry.lox" "$lox" graph --format=text --includes examples/tree_shake/main.lox
+
+# With --allow-reopen an instance created before a class is declared again
+# gets the new methods.
+printf 'class A { f() { return 1; } }\nvar a = A();\nclass A { f() { return 2; } }\nprint "f: " + to_string(a.f());\n' > "$tmp/reopen.txt"
+input="$tmp/reopen.txt"
+expect "REPL reopening a class" 0 "f: 2" "$lox" --allow-reopen
+expect "REPL replacing a class" 0 "f: 1" "$lox"
+
+# print_err writes to stderr, apart from what print writes. A writer's file
+# holds what was written once it is closed, or when the writer is gone.
+cat > "$tmp/streams.lox" << EOF
+print "data";
+print_err("warning");
+open_writer("$tmp/out.txt").write("a").write_line("b");
+var log = open_writer("$tmp/out.txt", true);
+log.write_line(42);
+log.close();
+log.write("too late");
+EOF
+expect "print_err to stderr" 70 "warning" "$lox" "$tmp/streams.lox"
+if "$lox" "$tmp/streams.lox" 2>/dev/null | grep -q warning; then
+  echo "print_err: wrote to stdout"
+  failed=1
+fi
+expect "write after close" 70 "is closed. [R250]" "$lox" "$tmp/streams.lox"
+input="$tmp/out.txt"
+expect "writer output" 0 "ab 42" tr '\n' ' '
+input=/dev/null
+expect "writes denied" 70 "writing files isn't allowed" "$lox" --deny-file-writes "$tmp/streams.lox"
+
+# --max-output stops a script printing without end, print_err counts too.
+cat > "$tmp/flood.lox" <<'EOF'
+var i = 0;
+while (true) {
+  print "line " + to_string(i);
+  print_err("noise");
+  i = i + 1;
+}
+EOF
+expect "output limit" 70 "Resource limit exceeded: more than 100 bytes of output." "$lox" --max-output 100 "$tmp/flood.lox"
+expect "output limit lines" 70 "line 7" "$lox" --max-output 100 "$tmp/flood.lox"
+# Neither try statements nor try expressions catch an exceeded limit.
+printf 'fun flood() { while (true) print "x"; }\nprint try flood() else "caught";\n' > "$tmp/try_flood.lox"
+expect "output limit in a try expression" 70 "Resource limit exceeded: more than 100 bytes of output." "$lox" --max-output 100 "$tmp/try_flood.lox"
+# The VM counts the output like the interpreter, and so a runtime error
+# stops it with 70 too.
+expect "output limit on the VM" 70 "Resource limit exceeded: more than 100 bytes of output." "$lox" --vm --max-output 100 "$tmp/flood.lox"
+expect "VM runtime error" 70 "Undefined variable 'missing'." "$lox" --vm "$tmp/runtime.lox"
+# --strict stops integers read in a radix from rounding.
+printf 'print to_number("20000000000001", 16);\n' > "$tmp/radix.lox"
+expect "radix without strict" 0 "9007199254740992" "$lox" "$tmp/radix.lox"
+expect "radix with strict" 70 "to_number(\"20000000000001\", 16) is past 2^53 - 1" "$lox" --strict "$tmp/radix.lox"
+expect "invalid output limit" 64 "--max-output expects a number of bytes but got 'lots'." "$lox" --max-output lots "$tmp/ok.lox"
+
+exit $failed
@@ -0,0 +1,29 @@
+// Gets every error of a program at once, the way an editor underlines them,
+// instead of having them printed.
+//
+//   cargo run --example diagnostics
+
+use lox_interpreter_rs::Lox;
+
+fn main() {
+    let mut lox = Lox::new();
+    let source = "
+        fun f() {
+          var a = 1;
+          var a = 2;
+          return this;
+        }
+        class A < A {}
+        return;
+    ";
+    for diagnostic in lox.diagnostics(source.to_string()) {
+        println!(
+            "{}:{}-{} {} {}",
+            diagnostic.line,
+            diagnostic.column,
+            diagnostic.column + diagnostic.length,
+            diagnostic.code,
+            diagnostic.message
+        );
+    }
+}
@@ -0,0 +1,54 @@
+// Hands a Rust value to a script as a foreign value. The script calls the
+// methods registered for its type and the host reads the value back after.
+//
+//   cargo run --example foreign
+
+use std::cell::RefCell;
+
+use lox_interpreter_rs::interpreter::Interpreter;
+use lox_interpreter_rs::object::Object;
+use lox_interpreter_rs::Lox;
+
+struct Counter {
+    count: RefCell<f64>,
+}
+
+fn counter(this: &Object) -> Result<&Counter, String> {
+    this.downcast_foreign::<Counter>()
+        .ok_or_else(|| format!("Expected a Counter but got {}.", this))
+}
+
+fn add(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let Object::Number(n) = args[1] else {
+        return Err(format!("Can only add numbers but got {}.", args[1]));
+    };
+    *counter(&args[0])?.count.borrow_mut() += n;
+    Ok(Object::Null)
+}
+
+fn count(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    Ok(Object::Number(*counter(&args[0])?.count.borrow()))
+}
+
+fn main() {
+    let mut lox = Lox::new();
+    lox.register_type::<Counter>("Counter", &[("add(n)", add), ("count()", count)]);
+    let counter = lox.foreign(Counter {
+        count: RefCell::new(0.0),
+    });
+    lox.define("counter", counter.clone());
+
+    let source = "
+        print counter;
+        for (var i = 1; i <= 4; i = i + 1) counter.add(i);
+        counter
+    ";
+    match lox.eval(source.to_string()) {
+        Ok(value) if value.equals(&counter) => {
+            let count = value.downcast_foreign::<Counter>().unwrap().count.borrow();
+            println!("The script counted to {}.", count);
+        }
+        Ok(value) => println!("The script returned {} instead of the counter.", value),
+        Err(_) => std::process::exit(70),
+    }
+}
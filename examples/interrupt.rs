@@ -0,0 +1,37 @@
+// Interrupts an endless script the way Ctrl-C does on the command line, by
+// setting the interrupt flag from another thread, and runs another one after.
+// Unlike cancelling, an interrupt is a runtime error and only stops the
+// program running when it comes.
+//
+//   cargo run --example interrupt
+
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use lox_interpreter_rs::error::{Code, Error};
+use lox_interpreter_rs::Lox;
+
+fn main() {
+    let mut lox = Lox::new();
+    let interrupted = lox.interrupt_flag();
+    let interrupter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        interrupted.store(true, Ordering::Relaxed);
+    });
+
+    match lox.run("while (true) {}".to_string()) {
+        Err(Error::Runtime {
+            code: Code::Interrupted,
+            message,
+            token,
+        }) if message == "Interrupted." => println!("Interrupted at line {}.", token.line),
+        _ => std::process::exit(70),
+    }
+    interrupter.join().unwrap();
+
+    // The flag was cleared when the interrupt was noticed.
+    if lox.run("print \"Running again.\";".to_string()).is_err() {
+        std::process::exit(70);
+    }
+}
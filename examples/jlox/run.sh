@@ -0,0 +1,67 @@
+#!/usr/bin/env bash
+# Runs the programs next to this script with --jlox and checks that they
+# print, fail and exit exactly like jlox would. The expectations are comments
+# in the format of the book's own test suite:
+#
+#   // expect: <line printed to stdout>
+#   // expect runtime error: <message>   the error of the line, exit code 70
+#   // Error at 'x': <message>           a compile error of the line, exit 65
+#   // [line 3] Error at 'x': <message>  one of another line
+#
+#   examples/jlox/run.sh [interpreter]
+#
+# Without an interpreter the release build is built if needed and run through
+# cargo.
+set -uo pipefail
+
+dir=$(dirname "$0")
+if [[ $# -gt 0 ]]; then
+  lox=("$1")
+  if [[ ! -x "$1" ]] && ! command -v "$1" > /dev/null; then
+    echo "No interpreter at '$1'. Build it with 'cargo build --release' or leave it out." >&2
+    exit 2
+  fi
+else
+  lox=(cargo run --release --quiet --manifest-path "$dir/../../Cargo.toml" --)
+  "${lox[@]}" --version > /dev/null || exit 2
+fi
+failed=0
+
+for file in "$dir"/*.lox; do
+  name=$(basename "$file" .lox)
+  expected_stdout=$(sed -n 's|.*// expect: ||p' "$file")
+  expected_stderr=$(awk '
+    match($0, /\/\/ expect runtime error: /) {
+      print substr($0, RSTART + RLENGTH); print "[line " NR "]"
+    }
+    match($0, /\/\/ \[line [0-9]+\] Error/) { print substr($0, RSTART + 3) }
+    match($0, /\/\/ Error/) { print "[line " NR "] " substr($0, RSTART + 3) }
+  ' "$file")
+  expected_code=0
+  if grep -q '// expect runtime error: ' "$file"; then
+    expected_code=70
+  elif grep -qE '// (\[line [0-9]+\] )?Error' "$file"; then
+    expected_code=65
+  fi
+
+  stderr=$(mktemp)
+  stdout=$("${lox[@]}" --jlox "$file" 2> "$stderr")
+  code=$?
+  if [[ "$stdout" != "$expected_stdout" ]]; then
+    echo "$name: unexpected output"
+    diff -u <(echo "$expected_stdout") <(echo "$stdout")
+    failed=1
+  fi
+  if [[ "$(cat "$stderr")" != "$expected_stderr" ]]; then
+    echo "$name: unexpected errors"
+    diff -u <(echo "$expected_stderr") "$stderr"
+    failed=1
+  fi
+  if [[ $code -ne $expected_code ]]; then
+    echo "$name: exited with $code instead of $expected_code"
+    failed=1
+  fi
+  rm -f "$stderr"
+done
+
+exit $failed
@@ -0,0 +1,79 @@
+// Runs scripts right at each of the resource limits and one step over them:
+// at a limit a script runs, over it the script stops with
+// Error::ResourceLimit. What a stopped script had allocated is given back, so
+// the scripts after it can use it again.
+//
+//   cargo run --example limits
+
+use std::process::exit;
+
+use lox_interpreter_rs::error::Error;
+use lox_interpreter_rs::limits::Limits;
+use lox_interpreter_rs::Lox;
+
+fn main() {
+    // The globals are one environment, every block being run and every call
+    // in progress another, and so is a call a closure was created in for as
+    // long as the closure is kept.
+    let mut lox = limited(Limits {
+        environments: Some(3),
+        ..Limits::default()
+    });
+    run(&mut lox, "{ { print \"two blocks deep\"; } }");
+    over(&mut lox, "{ { { } } }", "more than 3 live environments");
+    run(&mut lox, "{ { print \"two blocks deep again\"; } }");
+    run(
+        &mut lox,
+        "fun counter() { var n = 0; fun tick() { n = n + 1; return n; } return tick; }
+         var tick = counter();
+         print tick();",
+    );
+    over(&mut lox, "{ { } }", "more than 3 live environments");
+
+    let mut lox = limited(Limits {
+        instances: Some(2),
+        ..Limits::default()
+    });
+    run(&mut lox, "class Box {} var a = Box(); var b = Box();");
+    over(&mut lox, "var c = Box();", "more than 2 live instances");
+    run(
+        &mut lox,
+        "a = nil; var c = Box(); print \"replaced a box\";",
+    );
+
+    // Only strings the script builds are limited, not its literals.
+    let mut lox = limited(Limits {
+        string_bytes: Some(8),
+        ..Limits::default()
+    });
+    run(&mut lox, "var s = \"abcd\" + \"efgh\"; print s;");
+    over(&mut lox, "s = s + \"i\";", "more than 8 bytes in a string");
+    run(&mut lox, "print s + \"\";");
+}
+
+fn limited(limits: Limits) -> Lox {
+    let mut lox = Lox::new();
+    lox.set_limits(limits);
+    lox
+}
+
+fn run(lox: &mut Lox, source: &str) {
+    if lox.run(source.to_string()).is_err() {
+        exit(70);
+    }
+}
+
+// Runs a script that must go over a limit, with the message saying which.
+// It counts as a runtime error, which the command line exits with 70 for.
+fn over(lox: &mut Lox, source: &str, what: &str) {
+    let expected = format!("Resource limit exceeded: {}.", what);
+    match lox.run(source.to_string()) {
+        Err(Error::ResourceLimit { message }) if message == expected && lox.had_runtime_error() => {
+            println!("Stopped: {}", message)
+        }
+        _ => {
+            eprintln!("Expected the script to stop with '{}'.", expected);
+            exit(70)
+        }
+    }
+}
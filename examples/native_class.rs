@@ -0,0 +1,56 @@
+// Exposes a Rust type to scripts as a class with native methods. Scripts
+// create and use instances like those of any class, the host finds its Rust
+// state in them afterwards.
+//
+//   cargo run --example native_class
+
+use lox_interpreter_rs::interpreter::Interpreter;
+use lox_interpreter_rs::natives::{native_state, set_native_state};
+use lox_interpreter_rs::object::Object;
+use lox_interpreter_rs::Lox;
+
+struct Log {
+    prefix: String,
+    lines: Vec<String>,
+}
+
+fn init(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let log = Log {
+        prefix: args[1].to_string(),
+        lines: Vec::new(),
+    };
+    set_native_state(&args[0], log);
+    Ok(Object::Null)
+}
+
+// write(message) returns the number of lines written so far. Every line
+// notes the line of the script it was written from.
+fn write(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let line_number = interpreter.call_site().line;
+    native_state(&args[0], "Log", |log: &mut Log| {
+        let line = format!("{}: {} (line {})", log.prefix, args[1], line_number);
+        log.lines.push(line);
+        Ok(Object::Number(log.lines.len() as f64))
+    })
+}
+
+fn main() {
+    let mut lox = Lox::new();
+    lox.register_class("Log", &[("init(prefix)", init), ("write(message)", write)]);
+
+    let source = r#"
+        var log = Log("build");
+        log.write("compiling");
+        log.write("linking");
+        log
+    "#;
+    let log = match lox.eval(source.to_string()) {
+        Ok(log) => log,
+        Err(_) => std::process::exit(70),
+    };
+    let lines = native_state(&log, "Log", |log: &mut Log| Ok(log.lines.clone()));
+    match lines {
+        Ok(lines) => println!("{}", lines.join("\n")),
+        Err(message) => eprintln!("{}", message),
+    }
+}
@@ -0,0 +1,88 @@
+// Gives the operators a meaning for a host type, here lengths with units, so
+// scripts compute with them like with numbers.
+//
+//   cargo run --example operators
+
+use lox_interpreter_rs::interpreter::Interpreter;
+use lox_interpreter_rs::object::Object;
+use lox_interpreter_rs::operators::Operator;
+use lox_interpreter_rs::Lox;
+
+// In meters.
+struct Length(f64);
+
+fn length(value: &Object) -> Option<f64> {
+    value.downcast_foreign::<Length>().map(|length| length.0)
+}
+
+// length + length
+fn add(
+    interpreter: &mut Interpreter,
+    left: &Object,
+    right: &Object,
+) -> Result<Option<Object>, String> {
+    match (length(left), length(right)) {
+        (Some(left), Some(right)) => Ok(Some(interpreter.foreign(Length(left + right)))),
+        (Some(_), None) | (None, Some(_)) => Err("Can only add a length to a length.".to_string()),
+        (None, None) => Ok(None),
+    }
+}
+
+// number * length and length * number
+fn multiply(
+    interpreter: &mut Interpreter,
+    left: &Object,
+    right: &Object,
+) -> Result<Option<Object>, String> {
+    let product = match (left, right) {
+        (Object::Number(n), other) | (other, Object::Number(n)) => length(other).map(|l| n * l),
+        _ => None,
+    };
+    Ok(product.map(|product| interpreter.foreign(Length(product))))
+}
+
+// Lengths are equal when they are as long, whatever units they were made of.
+fn equal(
+    _interpreter: &mut Interpreter,
+    left: &Object,
+    right: &Object,
+) -> Result<Option<Object>, String> {
+    match (length(left), length(right)) {
+        (Some(left), Some(right)) => Ok(Some(Object::Boolean((left - right).abs() < 1e-9))),
+        _ => Ok(None),
+    }
+}
+
+fn meters(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    Ok(Object::Number(length(&args[0]).unwrap_or(f64::NAN)))
+}
+
+fn main() {
+    let mut lox = Lox::new();
+    lox.register_type::<Length>("Length", &[("meters()", meters)]);
+    lox.register_operator(Operator::Add, add);
+    lox.register_operator(Operator::Multiply, multiply);
+    lox.register_operator(Operator::Equal, equal);
+    let m = lox.foreign(Length(1.0));
+    let cm = lox.foreign(Length(0.01));
+    lox.define("m", m);
+    lox.define("cm", cm);
+
+    let source = "
+        var height = 1 * m + 85 * cm;
+        print \"The shelf is \" + to_string(height.meters()) + \" m high.\";
+        print height == 185 * cm;
+        print height != 2 * m;
+        // Equal lengths, but two different values.
+        print same(height, 185 * cm);
+        print 1 + 2;
+        try {
+          height + 1;
+        } catch (error) {
+          print error.message;
+        }
+    ";
+    if lox.run(source.to_string()).is_err() {
+        std::process::exit(70);
+    }
+}
@@ -0,0 +1,54 @@
+// Records a run and steps through its log backwards from the end, the way
+// `--replay` does with `b`, to find how a variable got its value.
+//
+//   cargo run --example replay
+
+use std::env;
+use std::fs;
+
+use lox_interpreter_rs::recorder::Replay;
+use lox_interpreter_rs::Lox;
+
+fn main() {
+    let path = env::temp_dir().join(format!("replay-{}.json", std::process::id()));
+    let path = path.to_str().expect("The temporary directory is UTF-8.");
+    let source = "
+        var total = 0;
+        for (var i = 1; i <= 100; i = i + 1) {
+          total = total + i;
+        }
+        total = total / 2;
+    ";
+
+    let mut lox = Lox::new();
+    if lox.start_recording(path).is_err() {
+        std::process::exit(74);
+    }
+    let ran = lox.run(source.to_string()).is_ok();
+    let recorded = lox.stop_recording().is_ok();
+    let log = fs::read_to_string(path);
+    let _ = fs::remove_file(path);
+    if !ran || !recorded {
+        std::process::exit(70);
+    }
+    let mut replay = Replay::load(&log.expect("The log was written.")).expect("The log is valid.");
+
+    // Run to the end, then step back past the last write of total.
+    while replay.next().is_some() {}
+    if let Some(last) = replay.value("total") {
+        println!("total is {} since event {}", last.value, last.number);
+    }
+    while replay.back().is_some() {
+        if replay
+            .value("total")
+            .is_some_and(|write| write.value != "2525")
+        {
+            break;
+        }
+    }
+    if let Some(before) = replay.value("total") {
+        println!("before that it was {}", before.value);
+    }
+    let history = replay.history("total");
+    println!("total was written {} times before", history.len());
+}
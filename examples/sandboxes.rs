@@ -0,0 +1,40 @@
+// Runs the scripts of two users in environments of their own on one
+// interpreter: both see the natives, neither sees the other's variables, and
+// their functions keep using their own environment when called later.
+//
+//   cargo run --example sandboxes
+
+use std::rc::Rc;
+
+use lox_interpreter_rs::object::Object;
+use lox_interpreter_rs::Lox;
+
+fn main() {
+    let mut lox = Lox::new();
+    let script = "
+        var visits = 0;
+        fun greet() {
+          visits = visits + 1;
+          return \"Hello, \" + user + \" (visit \" + to_string(visits) + \")\";
+        }
+    ";
+
+    let mut sandboxes = Vec::new();
+    for user in ["Ada", "Grace"] {
+        let sandbox = lox.new_environment();
+        sandbox
+            .borrow_mut()
+            .define("user".to_string(), Object::String(Rc::from(user)));
+        if lox.run_with_env(script.to_string(), &sandbox).is_err() {
+            std::process::exit(70);
+        }
+        sandboxes.push(sandbox);
+    }
+
+    for sandbox in [&sandboxes[0], &sandboxes[0], &sandboxes[1]] {
+        match lox.run_with_env("greet()".to_string(), sandbox) {
+            Ok(greeting) => println!("{}", greeting),
+            Err(_) => std::process::exit(70),
+        }
+    }
+}
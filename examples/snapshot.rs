@@ -0,0 +1,62 @@
+// Saves the globals of one interpreter and loads them into a fresh one, like
+// `:save` and `:load` in the REPL: a function stored under two names is still
+// one function, and Lists, Sets and Maps come back with what they held,
+// cycles included.
+//
+//   cargo run --example snapshot
+
+use std::process::exit;
+
+use lox_interpreter_rs::Lox;
+
+fn main() {
+    let mut lox = Lox::new();
+    run(
+        &mut lox,
+        "fun square(n) { return n * n; }
+         var alias = square;
+         var numbers = List();
+         numbers.push(1);
+         numbers.push(numbers);
+         var ages = Map();
+         ages.set(\"Ada\", 36);
+         ages.set(square, numbers);
+         var seen = Set();
+         seen.add(\"Ada\");
+         seen.add(nil);",
+    );
+    let snapshot = match lox.save_snapshot(None) {
+        Ok(snapshot) => snapshot,
+        Err(error) => {
+            eprintln!("{}", error);
+            exit(70)
+        }
+    };
+
+    let mut restored = Lox::new();
+    if let Err(error) = restored.load_snapshot(&snapshot) {
+        eprintln!("{}", error);
+        exit(65)
+    }
+    run(
+        &mut restored,
+        "assert(alias == square);
+         assert(numbers.get(1) == numbers);
+         assert(ages.get(\"Ada\") == 36);
+         assert(ages.get(square) == numbers);
+         assert(seen.contains(nil) and seen.length() == 2);
+         print \"Restored \" + to_string(alias(6)) + \".\";",
+    );
+
+    // The state of other native instances belongs to Rust.
+    run(&mut lox, "var builder = StringBuilder(\"text\");");
+    if let Err(error) = lox.save_snapshot(Some(&["builder".to_string()])) {
+        println!("{}", error);
+    }
+}
+
+fn run(lox: &mut Lox, source: &str) {
+    if lox.run(source.to_string()).is_err() {
+        exit(70);
+    }
+}
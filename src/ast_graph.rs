@@ -0,0 +1,434 @@
+use std::fmt::Write;
+
+use crate::error::Error;
+use crate::syntax::{expr, stmt, Expr, LiteralValue, MatchArm, Resolution, Stmt};
+use crate::token::Token;
+
+// Draws the syntax tree of a program, for seeing what the parser made of it.
+// Every node shows its kind and the token that matters most, like the operator
+// of a binary expression or the name of a variable, and its line when it has
+// one. Edges to children that play a particular role are labelled with it, the
+// condition and branches of an `if` for example.
+//
+// The tree is first built by a visitor and then written out, either as a
+// Graphviz graph or as an HTML page of nested, collapsible lists.
+pub struct AstGraph {
+    root: Node,
+}
+
+struct Node {
+    kind: &'static str,
+    detail: Option<String>,
+    line: Option<i32>,
+    // Each child with the role it plays, if any.
+    children: Vec<(Option<&'static str>, Node)>,
+}
+
+impl Node {
+    fn new(kind: &'static str, token: Option<&Token>) -> Self {
+        Node {
+            kind,
+            detail: token.map(|token| token.lexeme.clone()),
+            line: token.map(|token| token.line),
+            children: Vec::new(),
+        }
+    }
+
+    // Only the line of the token, for keywords that say nothing the kind
+    // doesn't.
+    fn at(mut self, token: &Token) -> Self {
+        self.line = Some(token.line);
+        self
+    }
+
+    fn detail(mut self, detail: String) -> Self {
+        self.detail = Some(detail);
+        self
+    }
+
+    fn child(mut self, role: Option<&'static str>, child: Node) -> Self {
+        self.children.push((role, child));
+        self
+    }
+
+    fn label(&self) -> String {
+        let mut label = self.kind.to_string();
+        if let Some(detail) = &self.detail {
+            label.push(' ');
+            label.push_str(detail);
+        }
+        if let Some(line) = self.line {
+            write!(label, " (line {})", line).unwrap();
+        }
+        label
+    }
+}
+
+impl AstGraph {
+    pub fn new(statements: &[Stmt]) -> Self {
+        let mut root = Node::new("Program", None);
+        for statement in statements {
+            root = root.child(None, TreeBuilder.statement(statement));
+        }
+        AstGraph { root }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        out.push_str("digraph ast {\n");
+        out.push_str("  node [shape=box, fontname=monospace];\n");
+        let mut next = 0;
+        write_dot(&mut out, &self.root, &mut next);
+        out.push_str("}\n");
+        out
+    }
+
+    // A standalone page, the nodes with children can be folded with a click.
+    pub fn to_html(&self) -> String {
+        let mut out = String::new();
+        out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        out.push_str("<title>Lox AST</title>\n<style>\n");
+        out.push_str("body { font-family: monospace; }\n");
+        out.push_str("ul { list-style: none; padding-left: 1.5em; }\n");
+        out.push_str(".role { color: gray; }\n");
+        out.push_str("</style>\n</head>\n<body>\n<ul>\n");
+        write_html(&mut out, None, &self.root, 1);
+        out.push_str("</ul>\n</body>\n</html>\n");
+        out
+    }
+}
+
+fn write_dot(out: &mut String, node: &Node, next: &mut usize) -> usize {
+    let id = *next;
+    *next += 1;
+    writeln!(out, "  n{} [label={:?}];", id, node.label()).unwrap();
+    for (role, child) in &node.children {
+        let child_id = write_dot(out, child, next);
+        match role {
+            Some(role) => writeln!(out, "  n{} -> n{} [label={:?}];", id, child_id, role),
+            None => writeln!(out, "  n{} -> n{};", id, child_id),
+        }
+        .unwrap();
+    }
+    id
+}
+
+fn write_html(out: &mut String, role: Option<&str>, node: &Node, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let role = match role {
+        Some(role) => format!("<span class=\"role\">{}:</span> ", role),
+        None => String::new(),
+    };
+    let label = escape(&node.label());
+    if node.children.is_empty() {
+        writeln!(out, "{}<li>{}{}</li>", indent, role, label).unwrap();
+        return;
+    }
+    writeln!(
+        out,
+        "{}<li><details open><summary>{}{}</summary><ul>",
+        indent, role, label
+    )
+    .unwrap();
+    for (child_role, child) in &node.children {
+        write_html(out, *child_role, child, depth + 1);
+    }
+    writeln!(out, "{}</ul></details></li>", indent).unwrap();
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+struct TreeBuilder;
+
+impl TreeBuilder {
+    fn statement(&mut self, statement: &Stmt) -> Node {
+        statement
+            .accept(self)
+            .expect("Building the AST graph never fails.")
+    }
+
+    fn expression(&mut self, expression: &Expr) -> Node {
+        expression
+            .accept(self)
+            .expect("Building the AST graph never fails.")
+    }
+
+    fn function(
+        &mut self,
+        kind: &'static str,
+        name: &Token,
+        params: &[Token],
+        body: &[Stmt],
+    ) -> Node {
+        let params: Vec<&str> = params.iter().map(|param| param.lexeme.as_str()).collect();
+        let mut node =
+            Node::new(kind, Some(name)).detail(format!("{}({})", name.lexeme, params.join(", ")));
+        for statement in body {
+            node = node.child(None, self.statement(statement));
+        }
+        node
+    }
+}
+
+impl expr::Visitor<Node> for TreeBuilder {
+    fn visit_binary_expr(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<Node, Error> {
+        Ok(Node::new("Binary", Some(operator))
+            .child(None, self.expression(left))
+            .child(None, self.expression(right)))
+    }
+
+    fn visit_call_expr(
+        &mut self,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Expr],
+        optional: bool,
+    ) -> Result<Node, Error> {
+        let mut node = Node::new(if optional { "Call?" } else { "Call" }, Some(paren))
+            .detail(format!("{} arguments", arguments.len()))
+            .child(Some("callee"), self.expression(callee));
+        for argument in arguments {
+            node = node.child(None, self.expression(argument));
+        }
+        Ok(node)
+    }
+
+    fn visit_get_expr(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        optional: bool,
+    ) -> Result<Node, Error> {
+        Ok(Node::new(if optional { "Get?" } else { "Get" }, Some(name))
+            .child(Some("object"), self.expression(object)))
+    }
+
+    fn visit_set_expr(&mut self, object: &Expr, name: &Token, value: &Expr) -> Result<Node, Error> {
+        Ok(Node::new("Set", Some(name))
+            .child(Some("object"), self.expression(object))
+            .child(Some("value"), self.expression(value)))
+    }
+
+    fn visit_super_expr(
+        &mut self,
+        _keyword: &Token,
+        method: &Token,
+        _resolution: &Resolution,
+    ) -> Result<Node, Error> {
+        Ok(Node::new("Super", Some(method)))
+    }
+
+    fn visit_this_expr(
+        &mut self,
+        keyword: &Token,
+        _resolution: &Resolution,
+    ) -> Result<Node, Error> {
+        Ok(Node::new("This", None).at(keyword))
+    }
+
+    fn visit_logical_expr(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<Node, Error> {
+        Ok(Node::new("Logical", Some(operator))
+            .child(None, self.expression(left))
+            .child(None, self.expression(right)))
+    }
+
+    fn visit_grouping_expr(&mut self, expression: &Expr) -> Result<Node, Error> {
+        Ok(Node::new("Grouping", None).child(None, self.expression(expression)))
+    }
+
+    fn visit_literal_expr(&self, value: &LiteralValue) -> Result<Node, Error> {
+        let value = match value {
+            LiteralValue::Null => "nil".to_string(),
+            LiteralValue::String(s) => format!("{:?}", s),
+            LiteralValue::Boolean(_) | LiteralValue::Number(_) => value.to_string(),
+        };
+        Ok(Node::new("Literal", None).detail(value))
+    }
+
+    fn visit_unary_expr(&mut self, operator: &Token, right: &Expr) -> Result<Node, Error> {
+        Ok(Node::new("Unary", Some(operator)).child(None, self.expression(right)))
+    }
+
+    fn visit_variable_expr(
+        &mut self,
+        name: &Token,
+        _resolution: &Resolution,
+    ) -> Result<Node, Error> {
+        Ok(Node::new("Variable", Some(name)))
+    }
+
+    fn visit_assign_expr(
+        &mut self,
+        name: &Token,
+        value: &Expr,
+        _resolution: &Resolution,
+    ) -> Result<Node, Error> {
+        Ok(Node::new("Assign", Some(name)).child(Some("value"), self.expression(value)))
+    }
+
+    // Patterns are shown the way they are written, as the detail of their arm.
+    fn visit_match_expr(
+        &mut self,
+        keyword: &Token,
+        subject: &Expr,
+        arms: &[MatchArm],
+    ) -> Result<Node, Error> {
+        let mut node = Node::new("Match", None)
+            .at(keyword)
+            .child(Some("subject"), self.expression(subject));
+        for arm in arms {
+            let arm = Node::new("Arm", None)
+                .detail(arm.pattern.to_string())
+                .child(None, self.expression(&arm.body));
+            node = node.child(None, arm);
+        }
+        Ok(node)
+    }
+
+    fn visit_try_expr(
+        &mut self,
+        keyword: &Token,
+        body: &Expr,
+        default: &Expr,
+    ) -> Result<Node, Error> {
+        Ok(Node::new("Try", None)
+            .at(keyword)
+            .child(Some("body"), self.expression(body))
+            .child(Some("default"), self.expression(default)))
+    }
+}
+
+impl stmt::Visitor<Node> for TreeBuilder {
+    fn visit_expression_stmt(&mut self, expression: &Expr) -> Result<Node, Error> {
+        Ok(Node::new("Expression", None).child(None, self.expression(expression)))
+    }
+
+    fn visit_print_stmt(&mut self, expression: &Expr) -> Result<Node, Error> {
+        Ok(Node::new("Print", None).child(None, self.expression(expression)))
+    }
+
+    fn visit_function_stmt(
+        &mut self,
+        name: &Token,
+        params: &[Token],
+        body: &[Stmt],
+    ) -> Result<Node, Error> {
+        Ok(self.function("Function", name, params, body))
+    }
+
+    fn visit_return_stmt(&mut self, keyword: &Token, value: &Option<Expr>) -> Result<Node, Error> {
+        let mut node = Node::new("Return", None).at(keyword);
+        if let Some(value) = value {
+            node = node.child(None, self.expression(value));
+        }
+        Ok(node)
+    }
+
+    fn visit_var_stmt(&mut self, name: &Token, initializer: &Option<Expr>) -> Result<Node, Error> {
+        let mut node = Node::new("Var", Some(name));
+        if let Some(initializer) = initializer {
+            node = node.child(Some("initializer"), self.expression(initializer));
+        }
+        Ok(node)
+    }
+
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> Result<Node, Error> {
+        let mut node = Node::new("Block", None);
+        for statement in statements {
+            node = node.child(None, self.statement(statement));
+        }
+        Ok(node)
+    }
+
+    fn visit_class_stmt(
+        &mut self,
+        name: &Token,
+        superclass: &Option<Expr>,
+        methods: &[Stmt],
+    ) -> Result<Node, Error> {
+        let mut node = Node::new("Class", Some(name));
+        if let Some(superclass) = superclass {
+            node = node.child(Some("superclass"), self.expression(superclass));
+        }
+        for method in methods {
+            if let Stmt::Function { name, params, body } = method {
+                node = node.child(None, self.function("Method", name, params, body));
+            }
+        }
+        Ok(node)
+    }
+
+    fn visit_if_stmt(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: &Option<Stmt>,
+    ) -> Result<Node, Error> {
+        let mut node = Node::new("If", None)
+            .child(Some("condition"), self.expression(condition))
+            .child(Some("then"), self.statement(then_branch));
+        if let Some(else_branch) = else_branch {
+            node = node.child(Some("else"), self.statement(else_branch));
+        }
+        Ok(node)
+    }
+
+    fn visit_while_stmt(
+        &mut self,
+        keyword: &Token,
+        condition: &Expr,
+        body: &Stmt,
+    ) -> Result<Node, Error> {
+        Ok(Node::new("While", None)
+            .at(keyword)
+            .child(Some("condition"), self.expression(condition))
+            .child(Some("body"), self.statement(body)))
+    }
+
+    fn visit_try_stmt(
+        &mut self,
+        keyword: &Token,
+        body: &[Stmt],
+        name: &Token,
+        handler: &[Stmt],
+    ) -> Result<Node, Error> {
+        let mut block = Node::new("Block", None);
+        for statement in body {
+            block = block.child(None, self.statement(statement));
+        }
+        let mut catch = Node::new("Catch", Some(name));
+        for statement in handler {
+            catch = catch.child(None, self.statement(statement));
+        }
+        Ok(Node::new("Try", None)
+            .at(keyword)
+            .child(Some("body"), block)
+            .child(Some("handler"), catch))
+    }
+
+    fn visit_throw_stmt(&mut self, keyword: &Token, value: &Expr) -> Result<Node, Error> {
+        Ok(Node::new("Throw", None)
+            .at(keyword)
+            .child(None, self.expression(value)))
+    }
+
+    fn visit_defer_stmt(&mut self, keyword: &Token, body: &Stmt) -> Result<Node, Error> {
+        Ok(Node::new("Defer", None)
+            .at(keyword)
+            .child(None, self.statement(body)))
+    }
+}
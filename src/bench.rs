@@ -0,0 +1,118 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+// Benchmarking a script: it is run a number of times and the wall time of each
+// run is summarized, so two builds of the interpreter can be compared without
+// external tools. Every run is a separate process, like in difftest, so no
+// state carries over and what the script prints doesn't mix with the report.
+// The first runs warm up the caches of the machine and are left out.
+
+#[derive(Debug)]
+pub struct Bench {
+    pub script: PathBuf,
+    pub warmup: usize,
+    // Milliseconds, one for every measured run.
+    pub times: Vec<f64>,
+}
+
+// Runs the script with the command `warmup + iterations` times. The command is
+// a program followed by its arguments, the script path is appended. A run that
+// fails stops the benchmark with an error holding what the script printed to
+// stderr.
+pub fn bench(
+    command: &[String],
+    script: &Path,
+    iterations: usize,
+    warmup: usize,
+) -> io::Result<Bench> {
+    let (program, arguments) = command
+        .split_first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+
+    let mut times = Vec::with_capacity(iterations);
+    for run in 0..warmup + iterations {
+        let start = Instant::now();
+        let output = Command::new(program)
+            .args(arguments)
+            .arg(script)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|error| {
+                io::Error::new(error.kind(), format!("cannot run '{}': {}", program, error))
+            })?;
+        let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "run {} failed:\n{}",
+                run + 1,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            )));
+        }
+        if run >= warmup {
+            times.push(elapsed);
+        }
+    }
+
+    Ok(Bench {
+        script: script.to_path_buf(),
+        warmup,
+        times,
+    })
+}
+
+impl Bench {
+    pub fn mean(&self) -> f64 {
+        self.times.iter().sum::<f64>() / self.times.len() as f64
+    }
+
+    pub fn median(&self) -> f64 {
+        let mut sorted = self.times.clone();
+        sorted.sort_by(f64::total_cmp);
+        let middle = sorted.len() / 2;
+        if sorted.len().is_multiple_of(2) {
+            (sorted[middle - 1] + sorted[middle]) / 2.0
+        } else {
+            sorted[middle]
+        }
+    }
+
+    // The sample standard deviation, 0 for a single run.
+    pub fn stddev(&self) -> f64 {
+        if self.times.len() < 2 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        let squares: f64 = self.times.iter().map(|time| (time - mean).powi(2)).sum();
+        (squares / (self.times.len() - 1) as f64).sqrt()
+    }
+
+    pub fn min(&self) -> f64 {
+        self.times.iter().copied().fold(f64::INFINITY, f64::min)
+    }
+
+    pub fn max(&self) -> f64 {
+        self.times.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+    }
+}
+
+impl fmt::Display for Bench {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {} runs after {} warmup runs",
+            self.script.display(),
+            self.times.len(),
+            self.warmup
+        )?;
+        writeln!(f, "  mean   {:>10.2} ms", self.mean())?;
+        writeln!(f, "  median {:>10.2} ms", self.median())?;
+        writeln!(f, "  stddev {:>10.2} ms", self.stddev())?;
+        writeln!(f, "  min    {:>10.2} ms", self.min())?;
+        writeln!(f, "  max    {:>10.2} ms", self.max())
+    }
+}
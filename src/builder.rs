@@ -0,0 +1,262 @@
+use std::io::Write;
+
+use crate::cancel::CancellationToken;
+use crate::features::Features;
+use crate::function::NativeFn;
+use crate::limits::Limits;
+use crate::lox::Lox;
+use crate::natives::native;
+use crate::object::Object;
+use crate::scanner::LiteralLimits;
+
+// Puts a Lox together from the options a host wants, instead of creating one
+// and calling its setters one by one:
+//
+//   let lox = Lox::builder()
+//       .with_stdlib(false)
+//       .with_io(false)
+//       .with_output(sink)
+//       .with_globals(globals)
+//       .max_depth(200)
+//       .build();
+//
+// Everything left out is what Lox::new gives: the built-in functions and
+// classes, file writes allowed, `print` to stdout, every extension and no
+// limits.
+pub struct LoxBuilder {
+    stdlib: bool,
+    io: bool,
+    output: Option<Box<dyn Write>>,
+    // Defined in order, after the built-ins are taken away.
+    globals: Vec<(String, Object)>,
+    natives: Vec<(&'static str, NativeFn)>,
+    classes: Vec<(String, Vec<(&'static str, NativeFn)>)>,
+    limits: Limits,
+    literal_limits: LiteralLimits,
+    features: Features,
+    strict: bool,
+    preprocess: bool,
+    include_cache: bool,
+    allow_reopen: bool,
+    hoist: bool,
+    tree_shake: bool,
+    why_kept: bool,
+    vm: bool,
+    jlox: bool,
+    random_seed: Option<u64>,
+    tab_width: Option<usize>,
+    cancellation_token: Option<CancellationToken>,
+}
+
+impl Default for LoxBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LoxBuilder {
+    pub fn new() -> Self {
+        LoxBuilder {
+            stdlib: true,
+            io: true,
+            output: None,
+            globals: Vec::new(),
+            natives: Vec::new(),
+            classes: Vec::new(),
+            limits: Limits::default(),
+            literal_limits: LiteralLimits::default(),
+            features: Features::default(),
+            strict: false,
+            preprocess: false,
+            include_cache: false,
+            allow_reopen: false,
+            hoist: false,
+            tree_shake: false,
+            why_kept: false,
+            vm: false,
+            jlox: false,
+            random_seed: None,
+            tab_width: None,
+            cancellation_token: None,
+        }
+    }
+
+    // Whether scripts get the built-in functions and classes, see
+    // Interpreter::remove_natives.
+    pub fn with_stdlib(mut self, stdlib: bool) -> Self {
+        self.stdlib = stdlib;
+        self
+    }
+
+    // Whether scripts may write files, see Lox::set_allow_file_writes.
+    pub fn with_io(mut self, io: bool) -> Self {
+        self.io = io;
+        self
+    }
+
+    // Where `print` writes instead of stdout.
+    pub fn with_output(mut self, output: impl Write + 'static) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    pub fn with_globals(mut self, globals: impl IntoIterator<Item = (String, Object)>) -> Self {
+        self.globals.extend(globals);
+        self
+    }
+
+    pub fn define(mut self, name: &str, value: Object) -> Self {
+        self.globals.push((name.to_string(), value));
+        self
+    }
+
+    // A global native function, named by its signature like the built-ins:
+    // `native("greet(name)", greet)`.
+    pub fn native(mut self, signature: &'static str, body: NativeFn) -> Self {
+        self.natives.push((signature, body));
+        self
+    }
+
+    // See Interpreter::register_class.
+    pub fn class(mut self, name: &str, methods: &[(&'static str, NativeFn)]) -> Self {
+        self.classes.push((name.to_string(), methods.to_vec()));
+        self
+    }
+
+    // How deep calls can nest, see Limits::calls.
+    pub fn max_depth(mut self, calls: usize) -> Self {
+        self.limits.calls = Some(calls);
+        self
+    }
+
+    // How much print and print_err may write, see Limits::output_bytes.
+    pub fn max_output_bytes(mut self, bytes: usize) -> Self {
+        self.limits.output_bytes = Some(bytes);
+        self
+    }
+
+    pub fn max_output_lines(mut self, lines: usize) -> Self {
+        self.limits.output_lines = Some(lines);
+        self
+    }
+
+    // Replaces every limit, max_depth and the output limits included.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn literal_limits(mut self, literal_limits: LiteralLimits) -> Self {
+        self.literal_limits = literal_limits;
+        self
+    }
+
+    pub fn features(mut self, features: Features) -> Self {
+        self.features = features;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn preprocess(mut self, preprocess: bool) -> Self {
+        self.preprocess = preprocess;
+        self
+    }
+
+    pub fn include_cache(mut self, include_cache: bool) -> Self {
+        self.include_cache = include_cache;
+        self
+    }
+
+    pub fn allow_reopen(mut self, allow_reopen: bool) -> Self {
+        self.allow_reopen = allow_reopen;
+        self
+    }
+
+    pub fn hoist(mut self, hoist: bool) -> Self {
+        self.hoist = hoist;
+        self
+    }
+
+    pub fn tree_shake(mut self, tree_shake: bool, why_kept: bool) -> Self {
+        self.tree_shake = tree_shake;
+        self.why_kept = why_kept;
+        self
+    }
+
+    // Runs programs on the bytecode VM, see Lox::set_vm.
+    pub fn vm(mut self, vm: bool) -> Self {
+        self.vm = vm;
+        self
+    }
+
+    // Behaves like jlox, see Lox::set_jlox. Of the built-ins only clock() is
+    // kept, and the features are the book's whatever was chosen.
+    pub fn jlox(mut self, jlox: bool) -> Self {
+        self.jlox = jlox;
+        self
+    }
+
+    pub fn random_seed(mut self, seed: u64) -> Self {
+        self.random_seed = Some(seed);
+        self
+    }
+
+    pub fn tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = Some(tab_width);
+        self
+    }
+
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancellation_token = Some(token);
+        self
+    }
+
+    pub fn build(self) -> Lox {
+        let mut lox = Lox::new();
+        if !self.stdlib {
+            lox.remove_natives();
+        }
+        lox.set_jlox(self.jlox);
+        lox.set_allow_file_writes(self.io);
+        if let Some(output) = self.output {
+            lox.set_output(output);
+        }
+        for (signature, body) in self.natives {
+            let function = native(signature, body);
+            let name = function.name().to_string();
+            lox.define(&name, Object::Callable(function));
+        }
+        for (name, methods) in &self.classes {
+            lox.register_class(name, methods);
+        }
+        for (name, value) in self.globals {
+            lox.define(&name, value);
+        }
+        lox.set_limits(self.limits);
+        lox.set_literal_limits(self.literal_limits);
+        if !self.jlox {
+            lox.set_features(self.features);
+        }
+        lox.set_strict(self.strict);
+        lox.set_preprocess(self.preprocess);
+        lox.set_include_cache(self.include_cache);
+        lox.set_allow_reopen(self.allow_reopen);
+        lox.set_hoist(self.hoist);
+        lox.set_tree_shake(self.tree_shake, self.why_kept);
+        lox.set_vm(self.vm);
+        if let Some(seed) = self.random_seed {
+            lox.set_random_seed(seed);
+        }
+        if let Some(tab_width) = self.tab_width {
+            lox.set_tab_width(tab_width);
+        }
+        if let Some(token) = self.cancellation_token {
+            lox.set_cancellation_token(token);
+        }
+        lox
+    }
+}
@@ -0,0 +1,467 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+
+use crate::syntax::{Expr, MatchArm, Pattern, Stmt};
+use crate::token::Token;
+
+// Which functions and methods of a program call which, worked out from the
+// source without running it. Functions nested in others are named after them,
+// `outer.inner`, methods after their class, `Point.init`, and the top level is
+// `script`.
+//
+// It is an approximation, Lox being dynamic:
+//
+// - `name(...)` calls the function or class the name refers to where it is
+//   called, unless that is a variable or parameter, whose value isn't known.
+//   Calling a class calls its `init`.
+// - `this.name(...)` calls the method of the class around it, or the one of
+//   a subclass overriding it, and `super.name(...)` that of the superclass.
+// - `value.name(...)` could call the method of that name of any class, there
+//   is an edge to each of them.
+//
+// Natives aren't drawn, and neither are calls made through variables, fields
+// or values returned by other calls.
+#[derive(Debug, Default)]
+pub struct CallGraph {
+    // Every function and method, `script` first, in the order they appear.
+    pub functions: Vec<String>,
+    // Callers and callees in the order the calls appear, each pair once.
+    pub calls: Vec<(String, String)>,
+}
+
+impl CallGraph {
+    pub fn new(statements: &[Stmt]) -> Self {
+        let mut builder = Builder::default();
+        builder.collect_classes(statements);
+        builder.graph.functions.push("script".to_string());
+        builder.functions.push("script".to_string());
+        builder.scopes.push(declarations(statements, None));
+        builder.stmts(statements);
+        builder.graph
+    }
+
+    pub fn to_dot(&self) -> String {
+        to_dot("calls", &self.functions, &self.calls)
+    }
+}
+
+// One edge a line, `caller -> callee`.
+impl fmt::Display for CallGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (caller, callee) in &self.calls {
+            writeln!(f, "{} -> {}", caller, callee)?;
+        }
+        Ok(())
+    }
+}
+
+// A Graphviz digraph of the nodes, drawn even without edges, and the edges.
+pub fn to_dot(name: &str, nodes: &[String], edges: &[(String, String)]) -> String {
+    let mut dot = format!("digraph {} {{\n  node [shape=box];\n", name);
+    for node in nodes {
+        let _ = writeln!(dot, "  {};", quote(node));
+    }
+    for (from, to) in edges {
+        let _ = writeln!(dot, "  {} -> {};", quote(from), quote(to));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// What a name refers to where it is used.
+#[derive(Clone)]
+enum Target {
+    Function(String),
+    Class(String),
+    // A variable or parameter.
+    Value,
+}
+
+struct Class {
+    superclass: Option<String>,
+    methods: HashSet<String>,
+}
+
+#[derive(Default)]
+struct Builder {
+    graph: CallGraph,
+    edges: HashSet<(String, String)>,
+    // Every class of the program by name, and the names in order.
+    classes: HashMap<String, Class>,
+    class_names: Vec<String>,
+    scopes: Vec<HashMap<String, Target>>,
+    // The function being walked, with the ones it is nested in.
+    functions: Vec<String>,
+    // The class whose methods are being walked.
+    class: Option<String>,
+}
+
+// The functions and classes declared directly in the statements, so they can
+// be called before their declaration, from a function declared earlier.
+fn declarations(statements: &[Stmt], enclosing: Option<&str>) -> HashMap<String, Target> {
+    statements
+        .iter()
+        .filter_map(|statement| match statement {
+            Stmt::Function { name, .. } => Some((
+                name.lexeme.clone(),
+                Target::Function(qualify(enclosing, &name.lexeme)),
+            )),
+            Stmt::Class { name, .. } => {
+                Some((name.lexeme.clone(), Target::Class(name.lexeme.clone())))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn qualify(enclosing: Option<&str>, name: &str) -> String {
+    match enclosing {
+        Some(enclosing) => format!("{}.{}", enclosing, name),
+        None => name.to_string(),
+    }
+}
+
+impl Builder {
+    fn collect_classes(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            match statement {
+                Stmt::Class {
+                    name,
+                    superclass,
+                    methods,
+                } => {
+                    let superclass = match superclass {
+                        Some(Expr::Variable { name, .. }) => Some(name.lexeme.clone()),
+                        _ => None,
+                    };
+                    let names = methods
+                        .iter()
+                        .filter_map(|method| match method {
+                            Stmt::Function { name, .. } => Some(name.lexeme.clone()),
+                            _ => None,
+                        })
+                        .collect();
+                    if !self.classes.contains_key(&name.lexeme) {
+                        self.class_names.push(name.lexeme.clone());
+                    }
+                    self.classes.insert(
+                        name.lexeme.clone(),
+                        Class {
+                            superclass,
+                            methods: names,
+                        },
+                    );
+                    self.collect_classes(methods);
+                }
+                Stmt::Function { body, .. } => self.collect_classes(body),
+                Stmt::Block { statements } => self.collect_classes(statements),
+                Stmt::If {
+                    then_branch,
+                    else_branch,
+                    ..
+                } => {
+                    self.collect_classes(std::slice::from_ref(then_branch));
+                    if let Some(else_branch) = else_branch.as_ref() {
+                        self.collect_classes(std::slice::from_ref(else_branch));
+                    }
+                }
+                Stmt::While { body, .. } | Stmt::Defer { body, .. } => {
+                    self.collect_classes(std::slice::from_ref(body))
+                }
+                Stmt::Try { body, handler, .. } => {
+                    self.collect_classes(body);
+                    self.collect_classes(handler);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // `Owner.method` for the method the class has, declared by it or by one
+    // of its superclasses.
+    fn find_method(&self, class: &str, method: &str) -> Option<String> {
+        let mut current = Some(class.to_string());
+        // A superclass chain longer than the number of classes has a cycle.
+        for _ in 0..=self.classes.len() {
+            let name = current?;
+            let class = self.classes.get(&name)?;
+            if class.methods.contains(method) {
+                return Some(format!("{}.{}", name, method));
+            }
+            current = class.superclass.clone();
+        }
+        None
+    }
+
+    // Whether the class inherits from the other one, directly or not.
+    fn is_subclass(&self, class: &str, superclass: &str) -> bool {
+        let mut current = self.classes.get(class).and_then(|c| c.superclass.clone());
+        for _ in 0..self.classes.len() {
+            let Some(name) = current else {
+                return false;
+            };
+            if name == superclass {
+                return true;
+            }
+            current = self.classes.get(&name).and_then(|c| c.superclass.clone());
+        }
+        false
+    }
+
+    fn call(&mut self, callee: String) {
+        let caller = self
+            .functions
+            .last()
+            .expect("The script is always being walked.")
+            .clone();
+        if self.edges.insert((caller.clone(), callee.clone())) {
+            self.graph.calls.push((caller, callee));
+        }
+    }
+
+    fn look_up(&self, name: &str) -> Option<Target> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).cloned())
+    }
+
+    fn declare_value(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), Target::Value);
+        }
+    }
+
+    // The body of a function or method, in a scope of its own.
+    fn function(&mut self, name: String, params: &[Token], body: &[Stmt]) {
+        self.graph.functions.push(name.clone());
+        let mut scope = declarations(body, Some(&name));
+        for param in params {
+            scope.insert(param.lexeme.clone(), Target::Value);
+        }
+        self.scopes.push(scope);
+        self.functions.push(name);
+        self.stmts(body);
+        self.functions.pop();
+        self.scopes.pop();
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        let enclosing = self.enclosing_function();
+        self.scopes
+            .push(declarations(statements, enclosing.as_deref()));
+        self.stmts(statements);
+        self.scopes.pop();
+    }
+
+    // The function nested declarations are named after, None at the top
+    // level.
+    fn enclosing_function(&self) -> Option<String> {
+        match self.functions.last() {
+            Some(function) if self.functions.len() > 1 => Some(function.clone()),
+            _ => None,
+        }
+    }
+
+    fn stmts(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.stmt(statement);
+        }
+    }
+
+    fn stmt(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Block { statements } => self.block(statements),
+            Stmt::Class { name, methods, .. } => {
+                let enclosing = self.class.replace(name.lexeme.clone());
+                for method in methods {
+                    if let Stmt::Function {
+                        name: method,
+                        params,
+                        body,
+                    } = method
+                    {
+                        let qualified = format!("{}.{}", name.lexeme, method.lexeme);
+                        self.function(qualified, params, body);
+                    }
+                }
+                self.class = enclosing;
+            }
+            Stmt::Function { name, params, body } => {
+                let enclosing = self.enclosing_function();
+                let qualified = qualify(enclosing.as_deref(), &name.lexeme);
+                let class = self.class.take();
+                self.function(qualified, params, body);
+                self.class = class;
+            }
+            Stmt::Expression { expression } | Stmt::Print { expression } => self.expr(expression),
+            Stmt::Return { value, .. } => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            Stmt::Var { name, initializer } => {
+                if let Some(initializer) = initializer {
+                    self.expr(initializer);
+                }
+                // A global variable is looked up by name anywhere, it may
+                // hold anything.
+                self.declare_value(&name.lexeme);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(condition);
+                self.stmt(then_branch);
+                if let Some(else_branch) = else_branch.as_ref() {
+                    self.stmt(else_branch);
+                }
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.expr(condition);
+                self.stmt(body);
+            }
+            Stmt::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => {
+                self.block(body);
+                self.scopes
+                    .push(HashMap::from([(name.lexeme.clone(), Target::Value)]));
+                self.stmts(handler);
+                self.scopes.pop();
+            }
+            Stmt::Throw { value, .. } => self.expr(value),
+            Stmt::Defer { body, .. } => self.stmt(body),
+        }
+    }
+
+    fn expr(&mut self, expression: &Expr) {
+        match expression {
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.callee(callee);
+                self.expr(callee);
+                for argument in arguments {
+                    self.expr(argument);
+                }
+            }
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::Get { object, .. } => self.expr(object),
+            Expr::Set { object, value, .. } => {
+                self.expr(object);
+                self.expr(value);
+            }
+            Expr::Unary { right, .. } => self.expr(right),
+            Expr::Grouping { expression } => self.expr(expression),
+            Expr::Assign { value, .. } => self.expr(value),
+            Expr::Match { subject, arms, .. } => {
+                self.expr(subject);
+                for MatchArm { pattern, body } in arms {
+                    let mut scope = HashMap::new();
+                    bindings(pattern, &mut scope);
+                    self.scopes.push(scope);
+                    self.expr(body);
+                    self.scopes.pop();
+                }
+            }
+            Expr::Try { body, default, .. } => {
+                self.expr(body);
+                self.expr(default);
+            }
+            Expr::Variable { .. }
+            | Expr::Super { .. }
+            | Expr::This { .. }
+            | Expr::Literal { .. } => {}
+        }
+    }
+
+    // Adds the edges of a call of the callee.
+    fn callee(&mut self, callee: &Expr) {
+        match callee {
+            Expr::Variable { name, .. } => match self.look_up(&name.lexeme) {
+                Some(Target::Function(function)) => self.call(function),
+                Some(Target::Class(class)) => {
+                    if let Some(init) = self.find_method(&class, "init") {
+                        self.call(init);
+                    }
+                }
+                Some(Target::Value) | None => (),
+            },
+            Expr::Get { object, name, .. } => {
+                let method = &name.lexeme;
+                let own = match (object.as_ref(), &self.class) {
+                    (Expr::This { .. }, Some(class)) => self
+                        .find_method(class, method)
+                        .map(|own| (class.clone(), own)),
+                    _ => None,
+                };
+                // The method of the class of `this`, or of a subclass
+                // overriding it.
+                let candidates: Vec<String> = self
+                    .class_names
+                    .iter()
+                    .filter(|class| self.classes[*class].methods.contains(method))
+                    .filter(|class| match &own {
+                        Some((this, _)) => self.is_subclass(class, this),
+                        None => true,
+                    })
+                    .map(|class| format!("{}.{}", class, method))
+                    .collect();
+                if let Some((_, own)) = own {
+                    self.call(own);
+                }
+                for candidate in candidates {
+                    self.call(candidate);
+                }
+            }
+            Expr::Super { method, .. } => {
+                let superclass = self
+                    .class
+                    .as_ref()
+                    .and_then(|class| self.classes.get(class))
+                    .and_then(|class| class.superclass.clone());
+                if let Some(method) =
+                    superclass.and_then(|superclass| self.find_method(&superclass, &method.lexeme))
+                {
+                    self.call(method);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+// The variables a match pattern binds.
+fn bindings(pattern: &Pattern, scope: &mut HashMap<String, Target>) {
+    match pattern {
+        Pattern::Binding(name) => {
+            scope.insert(name.lexeme.clone(), Target::Value);
+        }
+        Pattern::Instance { fields, .. } => {
+            for (_, pattern) in fields {
+                bindings(pattern, scope);
+            }
+        }
+        Pattern::List { elements, .. } => {
+            for element in elements {
+                bindings(element, scope);
+            }
+        }
+        Pattern::Wildcard | Pattern::Literal(_) => (),
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// A way for a host to stop a script from another thread, like the stop button
+// of an editor running it. Tripping the token makes the running program fail
+// with Error::Cancelled the next time it goes round a loop or calls a
+// function, which are the only places it can spend an unbounded amount of
+// time. Unlike an interrupt the error is no runtime error a script could
+// catch, and the token stays tripped: every program run after it fails too,
+// until the host gives the interpreter a new token.
+//
+//   let token = lox.cancellation_token();
+//   thread::spawn(move || {
+//       thread::sleep(Duration::from_secs(5));
+//       token.cancel();
+//   });
+//   lox.run(script);
+//
+// Checking it is a relaxed atomic load, which costs about as much as reading
+// any other field of the interpreter.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
@@ -1,9 +1,11 @@
+use std::any::Any;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
-use crate::error::Error;
+use crate::error::{Code, Error};
 use crate::function::Function;
+use crate::limits::Allocation;
 use crate::object::Object;
 use crate::token::Token;
 
@@ -12,13 +14,70 @@ use crate::token::Token;
 pub struct LoxClass {
     pub name: String,
     pub superclass: Option<Rc<RefCell<LoxClass>>>,
+    // Changed with add_methods, which keeps `init` in step.
     pub methods: HashMap<String, Function>,
+    // The class's own `init`, also in `methods`, kept apart because every
+    // call of the class needs it.
+    pub(crate) init: Option<Function>,
+    // Instances of a sealed class, or of its subclasses, only get new fields
+    // while their initializer runs. Set with the seal() native.
+    pub sealed: bool,
 }
 
 impl LoxClass {
+    pub fn new(
+        name: String,
+        superclass: Option<Rc<RefCell<LoxClass>>>,
+        methods: HashMap<String, Function>,
+    ) -> Self {
+        LoxClass {
+            name,
+            superclass,
+            init: methods.get("init").cloned(),
+            methods,
+            sealed: false,
+        }
+    }
+
+    // Adds methods to the class, replacing those with the same name.
+    pub fn add_methods(&mut self, methods: HashMap<String, Function>) {
+        if let Some(init) = methods.get("init") {
+            self.init = Some(init.clone());
+        }
+        self.methods.extend(methods);
+    }
+
+    // What calling the class runs, its own `init` or an inherited one. The
+    // same as find_method("init") without looking up the name in the method
+    // table of every class along the way. The superclasses are still asked
+    // on every call, a class reopened with a new `init` changes the
+    // initializer of its subclasses too.
+    pub fn initializer(&self) -> Option<Function> {
+        match (&self.init, &self.superclass) {
+            (Some(init), _) => Some(init.clone()),
+            (None, Some(superclass)) => superclass.borrow().initializer(),
+            (None, None) => None,
+        }
+    }
+
+    // A built-in class like StringBuilder, with methods written in Rust.
+    pub fn is_native(&self) -> bool {
+        self.methods
+            .values()
+            .any(|method| matches!(method, Function::Native { .. }))
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+            || self
+                .superclass
+                .as_ref()
+                .is_some_and(|superclass| superclass.borrow().is_sealed())
+    }
+
     pub fn find_method(&self, name: &str) -> Option<Function> {
         if self.methods.contains_key(name) {
-            self.methods.get(name).map(|f| f.clone())
+            self.methods.get(name).cloned()
         } else {
             if let Some(ref superclass) = self.superclass {
                 superclass.borrow().find_method(name)
@@ -29,41 +88,196 @@ impl LoxClass {
     }
 }
 
+// What a host sees of a class declared by a script, from
+// Interpreter::classes, to generate bindings, forms or documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassInfo {
+    pub name: String,
+    pub superclass: Option<String>,
+    // The arguments calling the class takes, those of `init` which may be
+    // inherited.
+    pub arity: usize,
+    // The methods the class declares itself, sorted by name. Inherited ones
+    // are on the superclass.
+    pub methods: Vec<MethodInfo>,
+    // The `///` comment in front of the declaration.
+    pub doc: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodInfo {
+    pub name: String,
+    pub params: Vec<String>,
+    pub doc: Option<String>,
+}
+
+impl ClassInfo {
+    // `docs` are the doc comments of the globals and of the methods, as
+    // `Class.method`.
+    pub(crate) fn new(class: &LoxClass, docs: &HashMap<String, String>) -> Self {
+        let mut methods: Vec<MethodInfo> = class
+            .methods
+            .iter()
+            .map(|(name, method)| MethodInfo {
+                name: name.clone(),
+                params: params(method),
+                doc: docs.get(&format!("{}.{}", class.name, name)).cloned(),
+            })
+            .collect();
+        methods.sort_by(|a, b| a.name.cmp(&b.name));
+        ClassInfo {
+            name: class.name.clone(),
+            superclass: class
+                .superclass
+                .as_ref()
+                .map(|superclass| superclass.borrow().name.clone()),
+            arity: class.initializer().map_or(0, |init| params(&init).len()),
+            methods,
+            doc: docs.get(&class.name).cloned(),
+        }
+    }
+}
+
+fn params(method: &Function) -> Vec<String> {
+    match method {
+        Function::User { params, .. } => params.iter().map(|param| param.lexeme.clone()).collect(),
+        Function::Native { signature, .. } => signature
+            .trim_end_matches(')')
+            .split_once('(')
+            .map_or("", |(_, params)| params)
+            .split(',')
+            .map(str::trim)
+            .filter(|param| !param.is_empty())
+            .map(str::to_string)
+            .collect(),
+    }
+}
+
 #[derive(Debug)]
 pub struct LoxInstance {
     pub class: Rc<RefCell<LoxClass>>,
     fields: HashMap<String, Object>,
+    // Counts the instance as alive for the interpreter's resource limits.
+    pub(crate) allocation: Option<Allocation>,
+    // No field of a frozen instance can be set. Set with the freeze() native.
+    pub frozen: bool,
+    // The initializer is running for this instance, it can add fields even
+    // when the class is sealed.
+    pub(crate) initializing: bool,
+    // Rust state of an instance of a native class, like the buffer of a
+    // StringBuilder. Set by the class' native initializer.
+    pub(crate) native: Option<Box<dyn Any>>,
 }
 
 impl LoxInstance {
-    // Returns a new `LoxInstance` wrapped in an `Object::Instance`
-    pub fn new(class: &Rc<RefCell<LoxClass>>) -> Object {
-        let instance = LoxInstance {
+    pub fn new(class: &Rc<RefCell<LoxClass>>) -> Self {
+        LoxInstance {
             class: Rc::clone(class),
             fields: HashMap::new(),
-        };
+            allocation: None,
+            frozen: false,
+            initializing: false,
+            native: None,
+        }
+    }
+
+    // All fields, including private ones. Used for debugging output.
+    pub fn fields(&self) -> &HashMap<String, Object> {
+        &self.fields
+    }
 
-        Object::Instance(Rc::new(RefCell::new(instance)))
+    // Sets a field without the private access check, for restoring saved
+    // instances and for natives building them.
+    pub(crate) fn set_field(&mut self, name: String, value: Object) {
+        self.fields.insert(name, value);
     }
 
     // Returns a member field of this instance.
     // instance - A reference to this instance as an object.
-    pub fn get(&self, name: &Token, instance: &Object) -> Result<Object, Error> {
+    // context - The class whose method is performing the access, if any.
+    pub fn get(
+        &self,
+        name: &Token,
+        instance: &Object,
+        context: Option<&Rc<RefCell<LoxClass>>>,
+    ) -> Result<Object, Error> {
+        self.check_access(name, context)?;
         if let Some(field) = self.fields.get(&name.lexeme) {
             Ok(field.clone())
         } else if let Some(method) = self.class.borrow().find_method(&name.lexeme) {
             Ok(Object::Callable(method.bind(instance.clone())))
         } else {
-            Err(Error::Runtime {
-                token: name.clone(),
-                message: format!("Undefined property '{}'.", name.lexeme),
-            })
+            Err(Error::runtime(
+                name,
+                Code::UndefinedProperty,
+                &[&name.lexeme],
+            ))
         }
     }
 
     // Since Lox allows freely creating new fields on instances, there’s no need
-    // to see if the key is already present.
-    pub fn set(&mut self, name: &Token, value: Object) {
+    // to see if the key is already present. Unless the instance was frozen or
+    // its class sealed, which catches misspelled field names.
+    pub fn set(
+        &mut self,
+        name: &Token,
+        value: Object,
+        context: Option<&Rc<RefCell<LoxClass>>>,
+    ) -> Result<(), Error> {
+        self.check_access(name, context)?;
+        if self.frozen {
+            return Err(Error::runtime(name, Code::FrozenInstance, &[&name.lexeme]));
+        }
+        if !self.initializing
+            && !self.fields.contains_key(&name.lexeme)
+            && self.class.borrow().is_sealed()
+        {
+            return Err(Error::runtime(
+                name,
+                Code::SealedClass,
+                &[&name.lexeme, &self.class.borrow().name],
+            ));
+        }
         self.fields.insert(name.lexeme.clone(), value);
+        Ok(())
     }
+
+    // Fields and methods starting with an underscore are private: they can only
+    // be used from the methods of the instance's class or one of its
+    // superclasses (and functions nested inside those methods).
+    fn check_access(
+        &self,
+        name: &Token,
+        context: Option<&Rc<RefCell<LoxClass>>>,
+    ) -> Result<(), Error> {
+        if !is_private(&name.lexeme) {
+            return Ok(());
+        }
+
+        let mut class = Some(Rc::clone(&self.class));
+        while let Some(current) = class {
+            if context.is_some_and(|context| Rc::ptr_eq(&current, context)) {
+                return Ok(());
+            }
+            class = current.borrow().superclass.clone();
+        }
+
+        Err(Error::runtime(name, Code::PrivateAccess, &[&name.lexeme]))
+    }
+}
+
+// Whether the class is the ancestor or inherits from it.
+pub fn inherits(class: &Rc<RefCell<LoxClass>>, ancestor: &Rc<RefCell<LoxClass>>) -> bool {
+    let mut class = Some(Rc::clone(class));
+    while let Some(current) = class {
+        if Rc::ptr_eq(&current, ancestor) {
+            return true;
+        }
+        class = current.borrow().superclass.clone();
+    }
+    false
+}
+
+pub fn is_private(name: &str) -> bool {
+    name.starts_with('_')
 }
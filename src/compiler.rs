@@ -0,0 +1,657 @@
+use std::rc::Rc;
+
+use crate::error::{Code, Diagnostic};
+use crate::syntax::{Expr, LiteralValue, Stmt};
+use crate::token::{Token, TokenType};
+
+// Compiles a program to bytecode for the VM of vm.rs, the second half of the
+// book done on top of the first: the statements come from the same scanner,
+// parser and resolver as for the tree-walk interpreter, so every static error
+// has already been reported and only the code has to be generated.
+//
+// Like clox, every function becomes a prototype with a chunk of instructions
+// for a stack machine. Local variables live in slots of the stack, found at
+// compile time, and variables of enclosing functions are captured as
+// upvalues. Unlike clox the instructions are an enum rather than bytes, so
+// there is no limit on the number of constants, locals or the length of a
+// jump.
+//
+// The VM runs the Lox of the book. The extensions with syntax of their own,
+// `**`, `?.`, `match`, `try`, `throw` and `defer`, are reported as compile
+// errors instead of being run differently from the interpreter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    // Pushes the constant at the index.
+    Constant(usize),
+    Nil,
+    True,
+    False,
+    Pop,
+    // The operand is the slot of the local in the current call's window of
+    // the stack.
+    GetLocal(usize),
+    SetLocal(usize),
+    // The operand is the constant holding the name.
+    GetGlobal(usize),
+    DefineGlobal(usize),
+    SetGlobal(usize),
+    GetUpvalue(usize),
+    SetUpvalue(usize),
+    GetProperty(usize),
+    SetProperty(usize),
+    GetSuper(usize),
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    // Jumps forward to the index of an instruction.
+    Jump(usize),
+    // Leave the condition on the stack, like in clox.
+    JumpIfFalse(usize),
+    JumpIfTrue(usize),
+    // Jumps back to the index of an instruction.
+    Loop(usize),
+    // The operand is the number of arguments above the callee.
+    Call(usize),
+    // Makes a closure of the function prototype at the constant index.
+    Closure(usize),
+    CloseUpvalue,
+    Return,
+    Class(usize),
+    Inherit,
+    Method(usize),
+}
+
+#[derive(Debug, Clone)]
+pub enum Constant {
+    Number(f64),
+    String(Rc<str>),
+    Function(Rc<Prototype>),
+}
+
+// The instructions of a function, with the line each came from for runtime
+// errors, and the constants they refer to.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub lines: Vec<i32>,
+    pub constants: Vec<Constant>,
+}
+
+// Where a closure finds a variable of an enclosing function when it is
+// created: a local of the function creating it or one of that function's own
+// upvalues.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Upvalue {
+    pub is_local: bool,
+    pub index: usize,
+}
+
+// A compiled function, what clox calls ObjFunction. The top level of a program
+// is one too, named "script".
+#[derive(Debug)]
+pub struct Prototype {
+    pub name: Rc<str>,
+    pub arity: usize,
+    pub upvalues: Vec<Upvalue>,
+    pub chunk: Chunk,
+}
+
+// Compiles the statements into the prototype of the top level of the program,
+// or returns the constructs the VM doesn't support.
+pub fn compile(statements: &[Stmt]) -> Result<Rc<Prototype>, Vec<Diagnostic>> {
+    let mut compiler = Compiler {
+        functions: vec![FunctionState::new("script", Kind::Script)],
+        classes: Vec::new(),
+        line: 1,
+        diagnostics: Vec::new(),
+    };
+    for statement in statements {
+        compiler.statement(statement);
+    }
+    compiler.emit_return();
+    let script = compiler.functions.pop().expect("Compiler has no function.");
+    if !compiler.diagnostics.is_empty() {
+        return Err(compiler.diagnostics);
+    }
+    Ok(Rc::new(script.prototype))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    // Whether a closure captured it, so leaving its scope has to move it off
+    // the stack.
+    captured: bool,
+}
+
+// What is being compiled of a function, clox's Compiler struct.
+struct FunctionState {
+    prototype: Prototype,
+    kind: Kind,
+    locals: Vec<Local>,
+    scope_depth: usize,
+}
+
+impl FunctionState {
+    fn new(name: &str, kind: Kind) -> Self {
+        // Slot 0 holds the function being called, or the instance for
+        // methods, where `this` finds it.
+        let slot_zero = match kind {
+            Kind::Method | Kind::Initializer => "this",
+            Kind::Script | Kind::Function => "",
+        };
+        FunctionState {
+            prototype: Prototype {
+                name: Rc::from(name),
+                arity: 0,
+                upvalues: Vec::new(),
+                chunk: Chunk::default(),
+            },
+            kind,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: 0,
+                captured: false,
+            }],
+            scope_depth: 0,
+        }
+    }
+}
+
+struct Compiler {
+    // The function being compiled last, the ones it is nested in before it.
+    functions: Vec<FunctionState>,
+    // Whether each class being compiled has a superclass.
+    classes: Vec<bool>,
+    // The line of the last token seen, given to the instructions emitted.
+    line: i32,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Compiler {
+    fn statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Expression { expression } => {
+                self.expression(expression);
+                self.emit(Op::Pop);
+            }
+            Stmt::Print { expression } => {
+                self.expression(expression);
+                self.emit(Op::Print);
+            }
+            Stmt::Var { name, initializer } => {
+                self.line = name.line;
+                match initializer {
+                    Some(initializer) => self.expression(initializer),
+                    None => self.emit(Op::Nil),
+                }
+                self.define_variable(name);
+            }
+            Stmt::Block { statements } => {
+                self.begin_scope();
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.end_scope();
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let then_jump = self.emit_jump(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.statement(then_branch);
+                let else_jump = self.emit_jump(Op::Jump(0));
+                self.patch_jump(then_jump);
+                self.emit(Op::Pop);
+                if let Some(else_branch) = else_branch.as_ref() {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(else_jump);
+            }
+            Stmt::While {
+                keyword,
+                condition,
+                body,
+            } => {
+                self.line = keyword.line;
+                let loop_start = self.code().len();
+                self.expression(condition);
+                let exit_jump = self.emit_jump(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.statement(body);
+                self.line = keyword.line;
+                self.emit(Op::Loop(loop_start));
+                self.patch_jump(exit_jump);
+                self.emit(Op::Pop);
+            }
+            Stmt::Function { name, params, body } => {
+                self.line = name.line;
+                // A local function is defined before its body is compiled, so
+                // the body can call it recursively.
+                if self.state().scope_depth > 0 {
+                    self.add_local(name);
+                }
+                self.function(name, params, body, Kind::Function);
+                if self.state().scope_depth == 0 {
+                    let name = self.identifier_constant(name);
+                    self.emit(Op::DefineGlobal(name));
+                }
+            }
+            Stmt::Return { keyword, value } => {
+                self.line = keyword.line;
+                match value {
+                    Some(value) => self.expression(value),
+                    None => self.emit_return_value(),
+                }
+                self.emit(Op::Return);
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => self.class(name, superclass.as_ref(), methods),
+            Stmt::Try { keyword, .. } => self.unsupported(keyword, "try statements"),
+            Stmt::Throw { keyword, .. } => self.unsupported(keyword, "throw"),
+            Stmt::Defer { keyword, .. } => self.unsupported(keyword, "defer"),
+        }
+    }
+
+    fn class(&mut self, name: &Token, superclass: Option<&Expr>, methods: &[Stmt]) {
+        self.line = name.line;
+        let constant = self.identifier_constant(name);
+        self.emit(Op::Class(constant));
+        self.define_variable(name);
+
+        let has_superclass = superclass.is_some();
+        if let Some(superclass) = superclass {
+            self.expression(superclass);
+            // Methods find the superclass in a scope of their own, bound to
+            // `super` like the interpreter's environment for it.
+            self.begin_scope();
+            self.add_local(&Token::new(TokenType::Super, "super", name.line, 0));
+            self.named_variable(name);
+            self.emit(Op::Inherit);
+        }
+        self.classes.push(has_superclass);
+
+        self.named_variable(name);
+        for method in methods {
+            if let Stmt::Function {
+                name: method,
+                params,
+                body,
+            } = method
+            {
+                let kind = if method.lexeme == "init" {
+                    Kind::Initializer
+                } else {
+                    Kind::Method
+                };
+                self.function(method, params, body, kind);
+                let constant = self.identifier_constant(method);
+                self.emit(Op::Method(constant));
+            }
+        }
+        self.emit(Op::Pop);
+
+        self.classes.pop();
+        if has_superclass {
+            self.end_scope();
+        }
+    }
+
+    // Compiles the function and emits the instruction making a closure of it.
+    fn function(&mut self, name: &Token, params: &[Token], body: &[Stmt], kind: Kind) {
+        let mut state = FunctionState::new(&name.lexeme, kind);
+        state.prototype.arity = params.len();
+        // The parameters and the body are in one scope, like in the resolver.
+        state.scope_depth = 1;
+        self.functions.push(state);
+        for param in params {
+            self.add_local(param);
+        }
+        for statement in body {
+            self.statement(statement);
+        }
+        self.emit_return();
+        let state = self.functions.pop().expect("Compiler has no function.");
+
+        let constant = self.make_constant(Constant::Function(Rc::new(state.prototype)));
+        self.line = name.line;
+        self.emit(Op::Closure(constant));
+    }
+
+    fn expression(&mut self, expression: &Expr) {
+        match expression {
+            Expr::Literal { value } => match value {
+                LiteralValue::Null => self.emit(Op::Nil),
+                LiteralValue::Boolean(true) => self.emit(Op::True),
+                LiteralValue::Boolean(false) => self.emit(Op::False),
+                LiteralValue::Number(n) => self.emit_constant(Constant::Number(*n)),
+                LiteralValue::String(s) => self.emit_constant(Constant::String(Rc::clone(s))),
+            },
+            Expr::Grouping { expression } => self.expression(expression),
+            Expr::Unary { operator, right } => {
+                self.expression(right);
+                self.line = operator.line;
+                match operator.token_type {
+                    TokenType::Minus => self.emit(Op::Negate),
+                    _ => self.emit(Op::Not),
+                }
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+                self.expression(right);
+                self.line = operator.line;
+                let op = match operator.token_type {
+                    TokenType::Plus => Op::Add,
+                    TokenType::Minus => Op::Subtract,
+                    TokenType::Star => Op::Multiply,
+                    TokenType::Slash => Op::Divide,
+                    TokenType::EqualEqual => Op::Equal,
+                    TokenType::BangEqual => Op::NotEqual,
+                    TokenType::Greater => Op::Greater,
+                    TokenType::GreaterEqual => Op::GreaterEqual,
+                    TokenType::Less => Op::Less,
+                    TokenType::LessEqual => Op::LessEqual,
+                    _ => return self.unsupported(operator, "the '**' operator"),
+                };
+                self.emit(op);
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+                self.line = operator.line;
+                let jump = match operator.token_type {
+                    TokenType::Or => self.emit_jump(Op::JumpIfTrue(0)),
+                    _ => self.emit_jump(Op::JumpIfFalse(0)),
+                };
+                self.emit(Op::Pop);
+                self.expression(right);
+                self.patch_jump(jump);
+            }
+            Expr::Variable { name, .. } => {
+                self.line = name.line;
+                self.named_variable(name);
+            }
+            Expr::Assign { name, value, .. } => {
+                self.expression(value);
+                self.line = name.line;
+                let op = match self.resolve(name) {
+                    Variable::Local(slot) => Op::SetLocal(slot),
+                    Variable::Upvalue(index) => Op::SetUpvalue(index),
+                    Variable::Global => Op::SetGlobal(self.identifier_constant(name)),
+                };
+                self.emit(op);
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                optional,
+            } => {
+                if *optional {
+                    return self.unsupported(paren, "'?.'");
+                }
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                self.line = paren.line;
+                self.emit(Op::Call(arguments.len()));
+            }
+            Expr::Get {
+                object,
+                name,
+                optional,
+            } => {
+                if *optional {
+                    return self.unsupported(name, "'?.'");
+                }
+                self.expression(object);
+                self.line = name.line;
+                let constant = self.identifier_constant(name);
+                self.emit(Op::GetProperty(constant));
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(value);
+                self.line = name.line;
+                let constant = self.identifier_constant(name);
+                self.emit(Op::SetProperty(constant));
+            }
+            Expr::This { keyword, .. } => {
+                self.line = keyword.line;
+                self.named_variable(keyword);
+            }
+            Expr::Super {
+                keyword, method, ..
+            } => {
+                self.line = keyword.line;
+                self.named_variable(&Token::new(TokenType::This, "this", keyword.line, 0));
+                self.named_variable(keyword);
+                let constant = self.identifier_constant(method);
+                self.emit(Op::GetSuper(constant));
+            }
+            Expr::Match { keyword, .. } => self.unsupported(keyword, "match"),
+            Expr::Try { keyword, .. } => self.unsupported(keyword, "try expressions"),
+        }
+    }
+
+    fn named_variable(&mut self, name: &Token) {
+        let op = match self.resolve(name) {
+            Variable::Local(slot) => Op::GetLocal(slot),
+            Variable::Upvalue(index) => Op::GetUpvalue(index),
+            Variable::Global => Op::GetGlobal(self.identifier_constant(name)),
+        };
+        self.emit(op);
+    }
+
+    // A local of the function being compiled, then a variable of one of the
+    // functions it is nested in, else a global.
+    fn resolve(&mut self, name: &Token) -> Variable {
+        let level = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(level, &name.lexeme) {
+            return Variable::Local(slot);
+        }
+        match self.resolve_upvalue(level, &name.lexeme) {
+            Some(index) => Variable::Upvalue(index),
+            None => Variable::Global,
+        }
+    }
+
+    fn resolve_local(&self, level: usize, name: &str) -> Option<usize> {
+        self.functions[level]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+    }
+
+    // Captures the variable from the enclosing function, which captures it
+    // from its own enclosing function in turn if it isn't one of its locals.
+    fn resolve_upvalue(&mut self, level: usize, name: &str) -> Option<usize> {
+        if level == 0 {
+            return None;
+        }
+        if let Some(slot) = self.resolve_local(level - 1, name) {
+            self.functions[level - 1].locals[slot].captured = true;
+            return Some(self.add_upvalue(level, slot, true));
+        }
+        let index = self.resolve_upvalue(level - 1, name)?;
+        Some(self.add_upvalue(level, index, false))
+    }
+
+    fn add_upvalue(&mut self, level: usize, index: usize, is_local: bool) -> usize {
+        let upvalue = Upvalue { is_local, index };
+        let upvalues = &mut self.functions[level].prototype.upvalues;
+        if let Some(existing) = upvalues.iter().position(|other| *other == upvalue) {
+            return existing;
+        }
+        upvalues.push(upvalue);
+        upvalues.len() - 1
+    }
+
+    // The value on top of the stack becomes the variable: a global, or the
+    // local living in that slot of the stack.
+    fn define_variable(&mut self, name: &Token) {
+        if self.state().scope_depth > 0 {
+            self.add_local(name);
+        } else {
+            let constant = self.identifier_constant(name);
+            self.emit(Op::DefineGlobal(constant));
+        }
+    }
+
+    fn add_local(&mut self, name: &Token) {
+        let state = self.state();
+        let depth = state.scope_depth;
+        state.locals.push(Local {
+            name: name.lexeme.clone(),
+            depth,
+            captured: false,
+        });
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    // Takes the locals of the scope off the stack. The ones closures captured
+    // move into their upvalues.
+    fn end_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        let mut ops = Vec::new();
+        while let Some(local) = state.locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            ops.push(if local.captured {
+                Op::CloseUpvalue
+            } else {
+                Op::Pop
+            });
+            state.locals.pop();
+        }
+        for op in ops {
+            self.emit(op);
+        }
+    }
+
+    fn emit_return(&mut self) {
+        self.emit_return_value();
+        self.emit(Op::Return);
+    }
+
+    // What a function returns without a value: nil, or the instance for an
+    // initializer.
+    fn emit_return_value(&mut self) {
+        if self.state().kind == Kind::Initializer {
+            self.emit(Op::GetLocal(0));
+        } else {
+            self.emit(Op::Nil);
+        }
+    }
+
+    fn emit(&mut self, op: Op) {
+        let line = self.line;
+        let chunk = &mut self.state().prototype.chunk;
+        chunk.code.push(op);
+        chunk.lines.push(line);
+    }
+
+    fn emit_constant(&mut self, constant: Constant) {
+        let index = self.make_constant(constant);
+        self.emit(Op::Constant(index));
+    }
+
+    // Emits a jump to be patched once its target is known.
+    fn emit_jump(&mut self, op: Op) -> usize {
+        self.emit(op);
+        self.code().len() - 1
+    }
+
+    fn patch_jump(&mut self, jump: usize) {
+        let target = self.code().len();
+        let code = &mut self.state().prototype.chunk.code;
+        code[jump] = match code[jump] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::JumpIfTrue(_) => Op::JumpIfTrue(target),
+            op => unreachable!("Patching {:?}, which isn't a jump.", op),
+        };
+    }
+
+    fn make_constant(&mut self, constant: Constant) -> usize {
+        let constants = &mut self.state().prototype.chunk.constants;
+        constants.push(constant);
+        constants.len() - 1
+    }
+
+    // Names are constants too, used by the instructions for globals and
+    // properties. The same name is stored once per function.
+    fn identifier_constant(&mut self, name: &Token) -> usize {
+        let constants = &mut self.state().prototype.chunk.constants;
+        let existing = constants
+            .iter()
+            .position(|constant| matches!(constant, Constant::String(s) if **s == *name.lexeme));
+        match existing {
+            Some(index) => index,
+            None => self.make_constant(Constant::String(Rc::from(name.lexeme.as_str()))),
+        }
+    }
+
+    fn unsupported(&mut self, token: &Token, what: &str) {
+        self.diagnostics
+            .push(Diagnostic::at(token, Code::VmUnsupported, &[what]));
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.functions
+            .last_mut()
+            .expect("Compiler has no function.")
+    }
+
+    fn code(&mut self) -> &[Op] {
+        &self.state().prototype.chunk.code
+    }
+}
+
+enum Variable {
+    Local(usize),
+    Upvalue(usize),
+    Global,
+}
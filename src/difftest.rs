@@ -0,0 +1,163 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+// Differential testing: every script is run by this interpreter and by a
+// reference implementation such as jlox or clox, and any difference in what
+// they print or how they exit is reported. Both are run as separate processes
+// so a crash or an endless loop in one script doesn't take the others down.
+
+// How a script behaved when run by one implementation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub stdout: String,
+    pub stderr: String,
+    // None when the process was killed by a signal.
+    pub code: Option<i32>,
+}
+
+#[derive(Debug)]
+pub struct Divergence {
+    pub script: PathBuf,
+    // One line per difference: which stream or the exit code, and how.
+    pub differences: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub scripts: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+// Runs every `.lox` file under `dir`, including subdirectories, with both
+// commands. Each command is a program followed by its arguments; the script
+// path is appended.
+pub fn difftest(dir: &Path, ours: &[String], reference: &[String]) -> io::Result<Report> {
+    let mut scripts = Vec::new();
+    collect_scripts(dir, &mut scripts)?;
+    scripts.sort();
+
+    let mut report = Report::default();
+    for script in scripts {
+        let expected = run(reference, &script)?;
+        let actual = run(ours, &script)?;
+        report.scripts += 1;
+
+        let differences = compare(&expected, &actual);
+        if !differences.is_empty() {
+            report.divergences.push(Divergence {
+                script,
+                differences,
+            });
+        }
+    }
+    Ok(report)
+}
+
+fn collect_scripts(dir: &Path, scripts: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_scripts(&path, scripts)?;
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            scripts.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn run(command: &[String], script: &Path) -> io::Result<Run> {
+    let (program, arguments) = command
+        .split_first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+    let Output {
+        stdout,
+        stderr,
+        status,
+    } = Command::new(program)
+        .args(arguments)
+        .arg(script)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|error| {
+            io::Error::new(error.kind(), format!("cannot run '{}': {}", program, error))
+        })?;
+    Ok(Run {
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        code: status.code(),
+    })
+}
+
+pub fn compare(expected: &Run, actual: &Run) -> Vec<String> {
+    let mut differences = Vec::new();
+    if let Some(difference) = compare_lines("stdout", &expected.stdout, &actual.stdout) {
+        differences.push(difference);
+    }
+    if let Some(difference) = compare_lines("stderr", &expected.stderr, &actual.stderr) {
+        differences.push(difference);
+    }
+    if expected.code != actual.code {
+        differences.push(format!(
+            "exit code: expected {}, got {}",
+            describe_code(expected.code),
+            describe_code(actual.code)
+        ));
+    }
+    differences
+}
+
+// Points at the first line that differs. Line endings are ignored, Windows
+// builds of the reference print "\r\n".
+fn compare_lines(stream: &str, expected: &str, actual: &str) -> Option<String> {
+    let expected: Vec<&str> = expected
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .collect();
+    let actual: Vec<&str> = actual
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .collect();
+    let line = (0..expected.len().max(actual.len()))
+        .find(|&index| expected.get(index) != actual.get(index))?;
+    Some(format!(
+        "{} line {}: expected {}, got {}",
+        stream,
+        line + 1,
+        describe_line(expected.get(line)),
+        describe_line(actual.get(line))
+    ))
+}
+
+fn describe_line(line: Option<&&str>) -> String {
+    match line {
+        Some(line) => format!("{:?}", line),
+        None => "nothing".to_string(),
+    }
+}
+
+fn describe_code(code: Option<i32>) -> String {
+    match code {
+        Some(code) => code.to_string(),
+        None => "a signal".to_string(),
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for divergence in &self.divergences {
+            writeln!(f, "{}", divergence.script.display())?;
+            for difference in &divergence.differences {
+                writeln!(f, "  {}", difference)?;
+            }
+        }
+        writeln!(
+            f,
+            "{} scripts, {} diverged",
+            self.scripts,
+            self.divergences.len()
+        )
+    }
+}
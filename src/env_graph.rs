@@ -0,0 +1,165 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::class::LoxInstance;
+use crate::environment::Environment;
+use crate::function::Function;
+use crate::inspect::inspect;
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+
+// Draws the environments of the running program as a Graphviz graph, for
+// seeing how closures capture their scopes. Every environment is a table of its
+// bindings with a solid arrow to its enclosing environment. Functions, and the
+// methods of classes and instances, get a dashed arrow from their binding to
+// the environment they closed over, which is often one the program already
+// left. The graph starts at the current scope and follows both kinds of arrows,
+// so it shows every environment reachable from there. Natives are left out,
+// they would only crowd the globals.
+//
+//   dot -Tsvg environments.dot -o environments.svg
+pub fn env_graph(interpreter: &Interpreter) -> String {
+    let mut graph = Graph::default();
+    let current = graph.id(interpreter.environment());
+
+    let mut out = String::new();
+    out.push_str("digraph environments {\n");
+    out.push_str("  node [shape=plaintext];\n");
+    let mut edges = String::new();
+    let mut next = 0;
+    while let Some(environment) = graph.queue.get(next).cloned() {
+        let id = next;
+        next += 1;
+        let title = if Rc::ptr_eq(&environment, &interpreter.globals) {
+            "globals".to_string()
+        } else if id == current {
+            "current scope".to_string()
+        } else {
+            format!("scope {}", id)
+        };
+
+        let environment = environment.borrow();
+        let mut bindings: Vec<_> = environment
+            .values()
+            .filter(|(_, value)| match value {
+                Object::Callable(Function::Native { .. }) => false,
+                Object::Class(class) => !class.borrow().is_native(),
+                _ => true,
+            })
+            .collect();
+        bindings.sort_by_key(|(name, _)| *name);
+
+        write!(
+            out,
+            "  env{} [label=<<table border=\"0\" cellborder=\"1\" cellspacing=\"0\">\
+             <tr><td colspan=\"2\"><b>{}</b></td></tr>",
+            id, title
+        )
+        .unwrap();
+        for (name, value) in bindings {
+            write!(
+                out,
+                "<tr><td>{}</td><td port=\"{}\">{}</td></tr>",
+                escape(name),
+                escape(name),
+                escape(&describe(value))
+            )
+            .unwrap();
+
+            let mut closures = Vec::new();
+            graph.closures(value, &mut closures);
+            let mut targets = HashSet::new();
+            for closure in closures {
+                let target = graph.id(&closure);
+                if targets.insert(target) {
+                    writeln!(
+                        edges,
+                        "  env{}:\"{}\" -> env{} [style=dashed];",
+                        id, name, target
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        out.push_str("</table>>];\n");
+
+        if let Some(enclosing) = &environment.enclosing {
+            let target = graph.id(enclosing);
+            writeln!(edges, "  env{} -> env{} [label=\"enclosing\"];", id, target).unwrap();
+        }
+    }
+    out.push_str(&edges);
+    out.push_str("}\n");
+    out
+}
+
+#[derive(Default)]
+struct Graph {
+    ids: HashMap<*const RefCell<Environment>, usize>,
+    // Environments in the order they were found, the index is their id.
+    queue: Vec<Rc<RefCell<Environment>>>,
+    // Instances already searched for closures, they can refer to each other.
+    instances: HashSet<*const RefCell<LoxInstance>>,
+}
+
+impl Graph {
+    // Numbers the environment the first time it is seen and queues it up for
+    // drawing.
+    fn id(&mut self, environment: &Rc<RefCell<Environment>>) -> usize {
+        let pointer = Rc::as_ptr(environment);
+        if let Some(&id) = self.ids.get(&pointer) {
+            return id;
+        }
+        let id = self.queue.len();
+        self.ids.insert(pointer, id);
+        self.queue.push(Rc::clone(environment));
+        id
+    }
+
+    // The environments a value keeps alive through the functions in it.
+    fn closures(&mut self, value: &Object, closures: &mut Vec<Rc<RefCell<Environment>>>) {
+        match value {
+            Object::Callable(Function::User { closure, .. }) => closures.push(Rc::clone(closure)),
+            Object::Class(class) => {
+                let class = class.borrow();
+                let mut methods: Vec<_> = class.methods.iter().collect();
+                methods.sort_by_key(|(name, _)| *name);
+                for (_, method) in methods {
+                    if let Function::User { closure, .. } = method {
+                        closures.push(Rc::clone(closure));
+                    }
+                }
+            }
+            Object::Instance(instance) => {
+                if !self.instances.insert(Rc::as_ptr(instance)) {
+                    return;
+                }
+                let instance = instance.borrow();
+                self.closures(&Object::Class(Rc::clone(&instance.class)), closures);
+                let mut fields: Vec<_> = instance.fields().iter().collect();
+                fields.sort_by_key(|(name, _)| *name);
+                for (_, field) in fields {
+                    self.closures(field, closures);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn describe(value: &Object) -> String {
+    match value {
+        Object::Instance(_) | Object::String(_) => inspect(value),
+        other => other.to_string(),
+    }
+}
+
+// Bindings are drawn in HTML-like labels.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
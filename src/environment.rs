@@ -2,117 +2,206 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
-use crate::{error::Error, object::Object, token::Token};
+use crate::error::{Code, Error};
+use crate::limits::Allocation;
+use crate::{object::Object, token::Token};
 
 pub struct Environment {
-    values: HashMap<String, Object>,
+    // The variables in the order they were defined. The resolver gives every
+    // local the slot it will have here, so reading one is indexing, not
+    // looking its name up.
+    values: Vec<Object>,
+    names: Vec<String>,
+    // Where each name is in `values`, for the globals and the environments of
+    // sandboxes, which are looked up by name. The names of a block or a call
+    // are searched in order when a debugger or a graph asks for them.
+    index: Option<HashMap<String, usize>>,
     pub enclosing: Option<Rc<RefCell<Environment>>>, // Parent-pointer
+    // Counts this environment as alive for the interpreter's resource limits.
+    // Inherited from the enclosing environment, so everything nested in the
+    // globals is counted.
+    pub(crate) allocation: Option<Allocation>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Environment {
+    // The globals, looked up by name.
     pub fn new() -> Self {
         Self {
-            values: HashMap::new(),
+            values: Vec::new(),
+            names: Vec::new(),
+            index: Some(HashMap::new()),
             enclosing: None,
+            allocation: None,
         }
     }
 
+    // The environment of a block or a call, whose variables are read by slot.
     pub fn from(enclosing: &Rc<RefCell<Environment>>) -> Self {
         Self {
-            values: HashMap::new(),
+            values: Vec::new(),
+            names: Vec::new(),
+            index: None,
             enclosing: Some(Rc::clone(enclosing)),
+            allocation: enclosing
+                .borrow()
+                .allocation
+                .as_ref()
+                .map(Allocation::another),
         }
     }
 
+    // An environment taking the place of the globals, looked up by name like
+    // them, see Interpreter::new_environment.
+    pub fn named(enclosing: &Rc<RefCell<Environment>>) -> Self {
+        Self {
+            index: Some(HashMap::new()),
+            ..Self::from(enclosing)
+        }
+    }
+
+    // A local goes in the next slot. Defining a global again replaces its
+    // value in place.
     pub fn define(&mut self, name: String, value: Object) {
-        self.values.insert(name, value);
+        if let Some(index) = self.index.as_mut() {
+            if let Some(&slot) = index.get(&name) {
+                self.values[slot] = value;
+                return;
+            }
+            index.insert(name.clone(), self.values.len());
+        }
+        self.values.push(value);
+        self.names.push(name);
+    }
+
+    // The slot of the name in this environment itself.
+    fn slot(&self, name: &str) -> Option<usize> {
+        match &self.index {
+            Some(index) => index.get(name).copied(),
+            None => self.names.iter().rposition(|defined| defined == name),
+        }
+    }
+
+    // Forgets every variable of this environment itself.
+    pub(crate) fn clear(&mut self) {
+        self.values.clear();
+        self.names.clear();
+        if let Some(index) = self.index.as_mut() {
+            index.clear();
+        }
+    }
+
+    // Whether the name is defined in this environment itself, ignoring the
+    // enclosing ones.
+    pub fn contains(&self, name: &str) -> bool {
+        self.slot(name).is_some()
+    }
+
+    // The value of the name in this environment itself, ignoring the enclosing
+    // ones.
+    pub fn get_own(&self, name: &str) -> Option<Object> {
+        self.slot(name).map(|slot| self.values[slot].clone())
+    }
+
+    // The value of the name in the environment or the closest enclosing one
+    // defining it, without a token to report an error at.
+    pub fn lookup(environment: &Rc<RefCell<Environment>>, name: &str) -> Option<Object> {
+        let environment = environment.borrow();
+        match environment.get_own(name) {
+            Some(value) => Some(value),
+            None => environment
+                .enclosing
+                .as_ref()
+                .and_then(|enclosing| Environment::lookup(enclosing, name)),
+        }
+    }
+
+    // The variables defined in this environment itself, in the order of their
+    // slots.
+    pub fn values(&self) -> impl Iterator<Item = (&str, &Object)> {
+        self.names.iter().map(String::as_str).zip(&self.values)
     }
 
     pub fn get(&self, name: &Token) -> Result<Object, Error> {
         let key = &*name.lexeme;
-        if let Some(value) = self.values.get(key) {
-            Ok((*value).clone())
+        if let Some(slot) = self.slot(key) {
+            Ok(self.values[slot].clone())
         } else {
             if let Some(ref enclosing) = self.enclosing {
                 // it is probably faster to iteratively walk the chain but recursion here is prettier
                 enclosing.borrow().get(name)
             } else {
-                Err(Error::Runtime {
-                    token: name.clone(),
-                    message: format!("Undefined variable '{}'.", key),
-                })
+                Err(Error::runtime(name, Code::UndefinedVariable, &[key]))
             }
         }
     }
 
-    fn ancestor(&self, distance: usize) -> Rc<RefCell<Environment>> {
-        // Get the first ancestor
-        let parent = self
-            .enclosing
-            .clone()
-            .expect(&format!("No enclosing environment at {}", 1));
-        let mut environment = Rc::clone(&parent);
-
-        // Get next ancestor
-        for i in 1..distance {
-            let parent = environment
-                .borrow()
-                .enclosing
-                .clone()
-                .expect(&format!("No enclosing environment at {}", i));
-            environment = Rc::clone(&parent);
-        }
-        environment
-    }
-
     // The older get() method dynamically walks the chain of enclosing
     // envrionments, scouring each one to see if the variable might be hiding in
     // there somewhere. But now we know exactly which environment in the chain
-    // will have the variable.
-    pub fn get_at(&self, distance: usize, name: &str) -> Result<Object, Error> {
+    // will have the variable, and which slot of it.
+    //
+    // The chain is walked by borrowing each enclosing environment in turn
+    // instead of cloning the Rc of every ancestor, reads are the hottest path
+    // of the interpreter.
+    pub fn get_at(&self, distance: usize, slot: usize) -> Object {
         if distance > 0 {
-            Ok(self
-                .ancestor(distance)
+            self.enclosing
+                .as_ref()
+                .unwrap_or_else(|| panic!("No enclosing environment at {}", distance))
                 .borrow()
-                .values
-                .get(name)
-                .expect(&format!("Undefined variable '{}'", name))
-                .clone())
+                .get_at(distance - 1, slot)
         } else {
-            Ok(self
-                .values
-                .get(name)
-                .expect(&format!("Undefined variable '{}'", name))
-                .clone())
+            self.values
+                .get(slot)
+                .unwrap_or_else(|| panic!("No variable in slot {}", slot))
+                .clone()
+        }
+    }
+
+    // Whether the environment `distance` hops up the chain has the name in the
+    // slot, for checking a resolution before get_at relies on it.
+    pub fn defines_at(&self, distance: usize, slot: usize, name: &str) -> bool {
+        if distance == 0 {
+            return self.names.get(slot).is_some_and(|defined| defined == name);
+        }
+        match self.enclosing {
+            Some(ref enclosing) => enclosing.borrow().defines_at(distance - 1, slot, name),
+            None => false,
         }
     }
 
     pub fn assign(&mut self, name: &Token, value: Object) -> Result<(), Error> {
         let key = &*name.lexeme;
-        if self.values.contains_key(key) {
-            self.values.insert(name.lexeme.clone(), value);
+        if let Some(slot) = self.slot(key) {
+            self.values[slot] = value;
             Ok(())
         } else {
             if let Some(ref enclosing) = self.enclosing {
                 enclosing.borrow_mut().assign(name, value)
             } else {
-                Err(Error::Runtime {
-                    token: name.clone(),
-                    message: format!("Undefined variable '{}'.", key),
-                })
+                Err(Error::runtime(name, Code::UndefinedVariable, &[key]))
             }
         }
     }
 
-    pub fn assign_at(&mut self, distance: usize, name: &Token, value: Object) -> Result<(), Error> {
+    pub fn assign_at(&mut self, distance: usize, slot: usize, value: Object) {
         if distance > 0 {
-            self.ancestor(distance)
+            self.enclosing
+                .as_ref()
+                .unwrap_or_else(|| panic!("No enclosing environment at {}", distance))
                 .borrow_mut()
-                .values
-                .insert(name.lexeme.clone(), value);
+                .assign_at(distance - 1, slot, value)
         } else {
-            self.values.insert(name.lexeme.clone(), value);
+            *self
+                .values
+                .get_mut(slot)
+                .unwrap_or_else(|| panic!("No variable in slot {}", slot)) = value;
         }
-        Ok(())
     }
 }
@@ -2,32 +2,460 @@ use std::convert;
 use std::fmt;
 use std::io;
 
+use unicode_width::UnicodeWidthChar;
+
+use crate::inspect::inspect;
 use crate::object::Object;
-use crate::token::{Token, TokenType};
+use crate::token::{Span, Token, TokenType};
+
+// Declares the Code enum from a table of the codes, their ids and the
+// templates of their messages.
+macro_rules! codes {
+    ( $( $code:ident $id:literal $template:literal, )* ) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Code {
+            $( $code, )*
+        }
+
+        impl Code {
+            pub fn id(self) -> &'static str {
+                match self {
+                    $( Code::$code => $id, )*
+                }
+            }
+
+            pub fn template(self) -> &'static str {
+                match self {
+                    $( Code::$code => $template, )*
+                }
+            }
+        }
+    };
+}
+
+// Every error the interpreter reports has a stable code, so tests and tools
+// can tell which error it was without depending on its wording, which may be
+// improved or translated. The letter and first digit say where it comes from:
+// E0 scanning and preprocessing, E1 parsing, E2 resolving, E3 compiling for
+// the VM and R2 running.
+//
+// A code's message is made by filling the `{}` of its template with
+// arguments, in order. `{{` and `}}` stand for braces. Codes are never
+// reused, new ones get the next free number of their group.
+codes! {
+    UnexpectedCharacter  "E001" "Unexpected character.",
+    UnterminatedString   "E002" "Unterminated string.",
+    StringLiteralTooLong "E003" "String literal exceeds {} limit.",
+    NumberLiteralTooLong "E004" "Number literal exceeds {} digits.",
+    NumberLiteralTooLarge "E005" "Number literal is too large.",
+    UnknownDirective     "E010" "Unknown directive '#{}'.",
+    ExpectIncludePath    "E011" "Expected a quoted file name after '#include'.",
+    CannotInclude        "E012" "Cannot include '{}': {}.",
+    ExpectDefineName     "E013" "Expected a name after '#define'.",
+    ExpectDefineValue    "E014" "Expected a value for '{}'.",
+    AlreadyDefined       "E015" "'{}' is already defined.",
+
+    ExpectSemicolon      "E101" "Expect ';' after {}.",
+    ExpectLeftParen      "E102" "Expect '(' after {}.",
+    ExpectRightParen     "E103" "Expect ')' after {}.",
+    // The argument says where, like "before class body".
+    ExpectLeftBrace      "E104" "Expect '{{' {}.",
+    ExpectRightBrace     "E105" "Expect '}}' after {}.",
+    // The argument says what, like "class name".
+    ExpectName           "E106" "Expect {}.",
+    ExpectExpression     "E107" "Expect expression.",
+    InvalidAssignment    "E108" "Invalid assignment target.",
+    TooManyParameters    "E109" "Can't have more than 255 parameters.",
+    TooManyArguments     "E110" "Can't have more than 255 arguments.",
+    CommaOperator        "E111" "Unexpected ',' after expression. Lox has no comma operator.",
+    ExpectCatch          "E112" "Expect 'catch' after try block.",
+    ExpectDot            "E113" "Expect '.' after 'super'.",
+    ExpectArrow          "E114" "Expect '->' after pattern.",
+    ExpectPattern        "E115" "Expect pattern.",
+    ExpectRightBracket   "E116" "Expect ']' after {}.",
+    ExpectPatternNumber  "E117" "Expect number after '-' in pattern.",
+    FeatureDisabled      "E118" "Feature '{}' is disabled.",
+    ExpectElse           "E119" "Expect 'else' after try expression.",
+
+    AlreadyDeclared      "E201" "Variable with this name already declared in this scope.",
+    GlobalRedeclared     "E202" "Global '{}' is already declared.",
+    OwnInitializer       "E203" "Cannot read local variable in its own initializer.",
+    PrivateOutsideClass  "E204" "Cannot access private property outside of a class.",
+    SuperOutsideClass    "E205" "Cannot use 'super' outside of a class.",
+    SuperWithoutSuperclass "E206" "Cannot use 'super' in a class with no superclass.",
+    ThisOutsideClass     "E207" "Cannot use 'this' outside of a class.",
+    InheritsItself       "E208" "A class cannot inherit from itself.",
+    TopLevelReturn       "E209" "Cannot return from top-level code.",
+    InitializerReturn    "E210" "Can't return a value from an initializer.",
+    // The arguments are the expected count, the function and the count given.
+    CallArity            "E211" "Expected {} to '{}' but got {}.",
+    TopLevelDefer        "E212" "Cannot defer outside of a function.",
+    DeferReturn          "E213" "Cannot return from a deferred statement.",
+
+    VmUnsupported        "E301" "The VM doesn't support {} yet.",
+
+    UndefinedVariable    "R201" "Undefined variable '{}'.",
+    UndefinedProperty    "R202" "Undefined property '{}'.",
+    NumberOperand        "R203" "Operand must be a number.",
+    AddOperands          "R204" "Operands must be two numbers or two strings.",
+    NotCallable          "R205" "Can only call functions and classes.",
+    // The arguments are the expected count, the callee, the count given and
+    // anything to add, like the usage of a native.
+    Arity                "R206" "Expected {} to '{}' but got {}.{}",
+    NotInstanceGet       "R207" "Only instances have properties.",
+    NotInstanceSet       "R208" "Only instances have fields.",
+    SuperclassNotClass   "R209" "Superclass must be a class.",
+    PrivateAccess        "R210" "Cannot access private property '{}' from outside its class.",
+    FrozenInstance       "R211" "Cannot set property '{}' of a frozen instance.",
+    SealedClass          "R212" "Cannot add field '{}' to an instance of sealed class '{}'.",
+    UndefinedMethod      "R213" "Undefined method '{}' for {}.",
+    PatternNotClass      "R214" "'{}' in a pattern must be a class.",
+    NoMatch              "R215" "No pattern matches {}.",
+    Interrupted          "R216" "Interrupted.",
+    AssertionFailed      "R217" "{}",
+    ReopenSuperclass     "R218" "Cannot change the superclass of reopened class '{}'.",
+    // The argument is the operation, like "9007199254740992 + 1".
+    PrecisionLoss        "R219" "{} is past 2^53 - 1, where integers lose precision.",
+    StackOverflow        "R220" "Stack overflow.",
+    // A native's own message.
+    Native               "R250" "{}",
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id())
+    }
+}
+
+// What kind of mistake a runtime error is, so a catch or an embedder can handle
+// every error of a kind without listing their codes. Printed with the message,
+// like `TypeError: Operand must be a number. [R203]`, and given to a catch as
+// the `category` field of the Error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Category {
+    // A variable, property or method that doesn't exist.
+    Name,
+    // A value of the wrong type for the operation.
+    Type,
+    // A call with the wrong number of arguments.
+    Arity,
+    // Private, frozen, sealed or reopened things changed or read from where
+    // they can't be.
+    Access,
+    Match,
+    // A number that can't be used exactly where it is.
+    Range,
+    Assertion,
+    Interrupt,
+    // A native failed with a message of its own.
+    Native,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Category::Name => "NameError",
+            Category::Type => "TypeError",
+            Category::Arity => "ArityError",
+            Category::Access => "AccessError",
+            Category::Match => "MatchError",
+            Category::Range => "RangeError",
+            Category::Assertion => "AssertionError",
+            Category::Interrupt => "Interrupt",
+            Category::Native => "NativeError",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Code {
+    // The category of a runtime error's code. The errors found before the
+    // program runs have none.
+    pub fn category(self) -> Option<Category> {
+        Some(match self {
+            Code::UndefinedVariable | Code::UndefinedProperty | Code::UndefinedMethod => {
+                Category::Name
+            }
+            Code::NumberOperand
+            | Code::AddOperands
+            | Code::NotCallable
+            | Code::NotInstanceGet
+            | Code::NotInstanceSet
+            | Code::SuperclassNotClass
+            | Code::PatternNotClass => Category::Type,
+            Code::Arity => Category::Arity,
+            Code::PrivateAccess
+            | Code::FrozenInstance
+            | Code::SealedClass
+            | Code::ReopenSuperclass => Category::Access,
+            Code::NoMatch => Category::Match,
+            Code::PrecisionLoss => Category::Range,
+            Code::AssertionFailed => Category::Assertion,
+            Code::Interrupted => Category::Interrupt,
+            Code::Native => Category::Native,
+            _ => return None,
+        })
+    }
+
+    // How jlox words the errors found before the program runs that this
+    // interpreter words differently, for --jlox. None for the others.
+    pub fn jlox_message(self) -> Option<&'static str> {
+        Some(match self {
+            Code::AlreadyDeclared => "Already a variable with this name in this scope.",
+            Code::OwnInitializer => "Can't read local variable in its own initializer.",
+            Code::SuperOutsideClass => "Can't use 'super' outside of a class.",
+            Code::SuperWithoutSuperclass => "Can't use 'super' in a class with no superclass.",
+            Code::ThisOutsideClass => "Can't use 'this' outside of a class.",
+            Code::InheritsItself => "A class can't inherit from itself.",
+            Code::TopLevelReturn => "Can't return from top-level code.",
+            _ => return None,
+        })
+    }
+}
+
+// The message of the code with the arguments filled in.
+pub fn message(code: Code, args: &[&str]) -> String {
+    let mut message = String::new();
+    let mut args = args.iter();
+    let mut chars = code.template().chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('}')) => {
+                chars.next();
+                message.push_str(
+                    args.next()
+                        .expect("Missing argument for a message template."),
+                );
+            }
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                message.push(c);
+            }
+            _ => message.push(c),
+        }
+    }
+    debug_assert!(args.next().is_none(), "Too many arguments for {}.", code);
+    message
+}
+
+// A compile error found while scanning, parsing or resolving. The passes
+// collect them instead of printing right away so the driver, which has the
+// source at hand, can show the offending line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub line: i32,
+    pub column: usize,
+    // Number of chars to underline, at least one.
+    pub length: usize,
+    // Either empty, " at end" or " at '<lexeme>'" like in jlox.
+    pub location: String,
+    pub code: Code,
+    pub message: String,
+    // The file the line is in, when the program was put together from several
+    // files by the preprocessor.
+    pub file: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(span: Span, code: Code, args: &[&str]) -> Self {
+        Diagnostic {
+            line: span.line,
+            column: span.column,
+            length: 1,
+            location: String::new(),
+            code,
+            message: message(code, args),
+            file: None,
+        }
+    }
 
-pub fn error(line: i32, message: &str) {
-    report(line, "", message);
+    pub fn at(token: &Token, code: Code, args: &[&str]) -> Self {
+        let location = if token.token_type == TokenType::Eof {
+            " at end".to_string()
+        } else {
+            format!(" at '{}'", token.lexeme)
+        };
+        Diagnostic {
+            line: token.line,
+            column: token.column,
+            length: token.lexeme.chars().count().max(1),
+            location,
+            code,
+            message: message(code, args),
+            file: None,
+        }
+    }
+
+    // The diagnostic followed by the line it points at and a caret under the
+    // offending lexeme. Tabs are expanded to `tab_width` columns and wide
+    // characters take two, so the caret lines up in a terminal. A line too
+    // long for that is cut down to the part around the caret.
+    pub fn render(&self, source: &str, tab_width: usize) -> String {
+        let mut rendered = self.to_string();
+        if let Some(line) = source_line(source, self.line).filter(|l| !l.trim().is_empty()) {
+            let gutter = self.line.to_string();
+            let (text, offset, width) = expand_line(line, self.column, self.length, tab_width);
+            let (text, offset, width) = clip_line(&text, offset, width);
+            rendered.push_str(&format!("\n {} | {}", gutter, text));
+            rendered.push_str(&format!(
+                "\n {} | {}{}",
+                " ".repeat(gutter.len()),
+                " ".repeat(offset),
+                "^".repeat(width.max(1))
+            ));
+        }
+        rendered
+    }
+
+    // The diagnostic the way jlox prints it, without the code, the file or
+    // the source line.
+    pub fn render_jlox(&self) -> String {
+        let message = self.code.jlox_message().unwrap_or(&self.message);
+        format!("[line {}] Error{}: {}", self.line, self.location, message)
+    }
 }
 
-pub fn report(line: i32, where_: &str, message: &str) {
-    eprintln!("[line {}] Error{}: {}", line, where_, message);
-    // had_error = true; TODO: Use custom Error type
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(
+                f,
+                "[line {} in {}] Error{}: {} [{}]",
+                self.line, file, self.location, self.message, self.code
+            ),
+            None => write!(
+                f,
+                "[line {}] Error{}: {} [{}]",
+                self.line, self.location, self.message, self.code
+            ),
+        }
+    }
 }
 
-pub fn parser_error(token: &Token, message: &str) {
-    if token.token_type == TokenType::Eof {
-        report(token.line, " at end", message);
-    } else {
-        report(token.line, &format!(" at '{}'", token.lexeme), message);
+// Lines end with "\n", and "\r\n" counts as a single terminator. A byte order
+// mark is skipped like the scanner skips it, columns start after it.
+fn source_line(source: &str, line: i32) -> Option<&str> {
+    let index = usize::try_from(line).ok()?.checked_sub(1)?;
+    source
+        .strip_prefix('\u{feff}')
+        .unwrap_or(source)
+        .split('\n')
+        .nth(index)
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+}
+
+// The most columns of a source line a diagnostic shows. A minified script or
+// an oversized string literal can put megabytes on one line.
+const MAX_LINE_WIDTH: usize = 120;
+
+// The part of an expanded line at most MAX_LINE_WIDTH columns wide that starts
+// a little before the caret, with "..." where it was cut, and the caret's
+// offset and width in it. The caret stops at the end of the part.
+fn clip_line(text: &str, offset: usize, width: usize) -> (String, usize, usize) {
+    let total: usize = text.chars().map(|c| c.width().unwrap_or(0)).sum();
+    if total <= MAX_LINE_WIDTH {
+        return (text.to_string(), offset, width);
     }
+    let start = offset
+        .saturating_sub(MAX_LINE_WIDTH / 4)
+        .min(total - MAX_LINE_WIDTH);
+    let end = start + MAX_LINE_WIDTH;
+    let mut clipped = String::new();
+    if start > 0 {
+        clipped.push_str("...");
+    }
+    let mut display = 0;
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+        if display >= start && display + char_width <= end {
+            clipped.push(c);
+        }
+        display += char_width;
+        if display >= end {
+            break;
+        }
+    }
+    if end < total {
+        clipped.push_str("...");
+    }
+    let prefix = if start > 0 { 3 } else { 0 };
+    let width = width.min(end.saturating_sub(offset)).max(1);
+    (clipped, offset - start + prefix, width)
+}
+
+// Returns the line with tabs expanded, and the display offset and width of the
+// `length` chars starting at the 1-based `column`.
+fn expand_line(
+    line: &str,
+    column: usize,
+    length: usize,
+    tab_width: usize,
+) -> (String, usize, usize) {
+    let mut text = String::new();
+    let (mut display, mut offset, mut width) = (0, 0, 0);
+    for (index, c) in line.chars().enumerate() {
+        let char_width = if c == '\t' {
+            let stop = tab_width.max(1);
+            let spaces = stop - display % stop;
+            text.push_str(&" ".repeat(spaces));
+            spaces
+        } else {
+            text.push(c);
+            c.width().unwrap_or(0)
+        };
+
+        if index + 1 < column {
+            offset += char_width;
+        } else if index + 1 < column + length {
+            width += char_width;
+        }
+        display += char_width;
+    }
+    (text, offset, width)
+}
+
+// Runtime errors are reported after the fact, with the line of the token
+// that was being evaluated when things went wrong.
+pub fn runtime_error(token: &Token, message: &str) {
+    eprintln!("{}\n[line {}]", message, token.line);
+}
+
+// What is reported for a thrown value nothing caught: the message of an
+// Error, or the value itself.
+pub fn uncaught(value: &Object) -> String {
+    if let Object::Instance(instance) = value {
+        if let Some(message) = instance.borrow().fields().get("message") {
+            return message.to_string();
+        }
+    }
+    format!("Uncaught {}.", inspect(value))
 }
 
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
     Parse,
-    Return { value: Object },
-    Runtime { token: Token, message: String },
+    Return {
+        value: Object,
+    },
+    Runtime {
+        token: Token,
+        code: Code,
+        message: String,
+    },
+    // A value thrown by `throw` on its way to a `catch`. Unwinds like Return.
+    Throw {
+        token: Token,
+        value: Object,
+    },
+    // A script went over one of the interpreter's Limits.
+    ResourceLimit {
+        message: String,
+    },
+    // The host tripped the CancellationToken, noticed at the token.
+    Cancelled {
+        token: Token,
+    },
 }
 
 impl fmt::Display for Error {
@@ -36,7 +464,32 @@ impl fmt::Display for Error {
             Error::Io(underlying) => write!(f, "IoError {}", underlying),
             Error::Parse => write!(f, "ParseError"),
             Error::Return { value } => write!(f, "Return {:?}", value),
-            Error::Runtime { message, .. } => write!(f, "RuntimeError {}", message),
+            Error::Runtime { message, code, .. } => match code.category() {
+                Some(category) => write!(f, "{}: {} [{}]", category, message, code),
+                None => write!(f, "RuntimeError: {} [{}]", message, code),
+            },
+            Error::Throw { value, .. } => write!(f, "Throw {}", uncaught(value)),
+            Error::ResourceLimit { message } => write!(f, "ResourceLimit {}", message),
+            Error::Cancelled { .. } => write!(f, "Cancelled."),
+        }
+    }
+}
+
+impl Error {
+    // A runtime error at the token, with the message of the code.
+    pub fn runtime(token: &Token, code: Code, args: &[&str]) -> Self {
+        Error::Runtime {
+            token: token.clone(),
+            code,
+            message: message(code, args),
+        }
+    }
+
+    // The category of a runtime error, None for any other error.
+    pub fn category(&self) -> Option<Category> {
+        match self {
+            Error::Runtime { code, .. } => code.category(),
+            _ => None,
         }
     }
 }
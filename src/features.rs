@@ -0,0 +1,60 @@
+// The extensions of the language beyond the Lox of the book. All of them are
+// on by default. Turning them off, all at once with Features::BOOK, gives the
+// book's Lox for teaching it and for comparing with jlox in difftest. The
+// parser reports a disabled extension where it is used, the keywords of the
+// extensions stay reserved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+    // `a ** b`
+    pub power: bool,
+    // `object?.field` and `object?.method()`
+    pub safe_navigation: bool,
+    // `try`/`catch` and `throw`
+    pub exceptions: bool,
+    // `match` expressions
+    pub match_expressions: bool,
+    // `defer` statements
+    pub defer: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl Features {
+    pub const ALL: Features = Features {
+        power: true,
+        safe_navigation: true,
+        exceptions: true,
+        match_expressions: true,
+        defer: true,
+    };
+
+    pub const BOOK: Features = Features {
+        power: false,
+        safe_navigation: false,
+        exceptions: false,
+        match_expressions: false,
+        defer: false,
+    };
+
+    // The names used on the command line and in errors.
+    pub const NAMES: &'static [&'static str] =
+        &["power", "safe-navigation", "exceptions", "match", "defer"];
+
+    // Turns off the extension with the name, false if there is none.
+    pub fn disable(&mut self, name: &str) -> bool {
+        let enabled = match name {
+            "power" => &mut self.power,
+            "safe-navigation" => &mut self.safe_navigation,
+            "exceptions" => &mut self.exceptions,
+            "match" => &mut self.match_expressions,
+            "defer" => &mut self.defer,
+            _ => return false,
+        };
+        *enabled = false;
+        true
+    }
+}
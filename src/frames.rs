@@ -0,0 +1,104 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::environment::Environment;
+
+// The frames a runtime error unwound through, so the REPL or a debugger can
+// look around where the program failed with Interpreter::eval_in_env. A frame
+// is the innermost environment of a function call, or of the script, when the
+// error left it. The environments are kept alive by the frames until the next
+// error replaces them.
+#[derive(Clone)]
+pub struct Frame {
+    // The name of the function, "script" for the top level.
+    pub function: String,
+    // Where the error happened in the innermost frame, where the call to the
+    // next inner frame is in the others.
+    pub line: i32,
+    pub environment: Rc<RefCell<Environment>>,
+}
+
+#[derive(Default)]
+pub(crate) struct Frames {
+    // Innermost first.
+    frames: Vec<Frame>,
+    state: State,
+}
+
+#[derive(Default)]
+enum State {
+    // No error is unwinding, the frames are those of the last one.
+    #[default]
+    Done,
+    // The error is leaving the blocks of the last frame.
+    InFrame,
+    // The error left a function called on the line, the next block it leaves
+    // belongs to the caller.
+    Returned(i32),
+}
+
+impl Frames {
+    // An error left a block whose environment is the one given. Only the
+    // innermost block of every frame is kept, the others are its ancestors.
+    pub(crate) fn leave_block(&mut self, environment: &Rc<RefCell<Environment>>, line: i32) {
+        let line = match self.state {
+            State::Done => {
+                self.frames.clear();
+                line
+            }
+            State::InFrame => return,
+            State::Returned(line) => line,
+        };
+        self.frames.push(Frame {
+            function: "script".to_string(),
+            line,
+            environment: Rc::clone(environment),
+        });
+        self.state = State::InFrame;
+    }
+
+    // An error left a call of the function, on the line of the call.
+    pub(crate) fn leave_function(&mut self, function: &str, line: i32) {
+        if let State::InFrame = self.state {
+            if let Some(frame) = self.frames.last_mut() {
+                frame.function = function.to_string();
+            }
+            self.state = State::Returned(line);
+        }
+    }
+
+    // The error reached the top level, where it happened on the line.
+    pub(crate) fn finish(&mut self, globals: &Rc<RefCell<Environment>>, line: i32) {
+        let line = match self.state {
+            // It happened outside of any block and function.
+            State::Done => {
+                self.frames.clear();
+                line
+            }
+            // The last frame is a block of the top level.
+            State::InFrame => {
+                self.state = State::Done;
+                return;
+            }
+            // It left a function called outside of any block.
+            State::Returned(line) => line,
+        };
+        self.frames.push(Frame {
+            function: "script".to_string(),
+            line,
+            environment: Rc::clone(globals),
+        });
+        self.state = State::Done;
+    }
+
+    pub(crate) fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    // Set aside around code whose errors may be caught or don't belong to
+    // the program, like a try block or an expression evaluated in a frame, so
+    // the frames of the last error survive them.
+    pub(crate) fn take(&mut self) -> Frames {
+        std::mem::take(self)
+    }
+}
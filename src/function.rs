@@ -1,13 +1,25 @@
+use crate::class::LoxClass;
 use crate::environment::Environment;
-use crate::error::Error;
+use crate::error::{Code, Error};
+use crate::inspect::inspect;
 use crate::interpreter::Interpreter;
 use crate::object::Object;
+use crate::recorder::Event;
 use crate::syntax::Stmt;
 use crate::token::Token;
 
 use std::cell::RefCell;
 use std::fmt;
-use std::rc::Rc;
+use std::mem;
+use std::ops::RangeInclusive;
+use std::rc::{Rc, Weak};
+
+use tracing::trace_span;
+
+// A native gets the interpreter, for the few that inspect the running program,
+// and the evaluated arguments. It returns the result, or a message describing
+// why it failed.
+pub type NativeFn = fn(&mut Interpreter, &[Object]) -> Result<Object, String>;
 
 #[derive(Clone)]
 pub enum Function {
@@ -21,17 +33,34 @@ pub enum Function {
     // add a native function, the book uses anonymous class instances that
     // implement the LoxCallable interface.
     Native {
-        arity: usize,
-        body: Box<fn(&Vec<Object>) -> Object>,
+        name: &'static str,
+        // How to call it, like `to_number(string, radix?)`. Shown when the
+        // native is called with the wrong number of arguments.
+        signature: &'static str,
+        // Natives can take optional arguments, so they accept a range of
+        // argument counts.
+        arity: RangeInclusive<usize>,
+        body: NativeFn,
+        // The instance a method of a native class is bound to.
+        this: Option<Rc<Object>>,
     },
 
-    // LoxFunction in the book
+    // LoxFunction in the book. Functions are copied whenever a variable
+    // holding one is read, so everything but the flags is shared.
     User {
-        name: Token,
-        params: Vec<Token>,
-        body: Vec<Stmt>,
+        name: Rc<Token>,
+        // Shared by every copy of the function, bound methods included.
+        params: Rc<[Token]>,
+        body: Rc<[Stmt]>,
         closure: Rc<RefCell<Environment>>,
+        // Where the global variables it uses are, the interpreter's globals
+        // or the environment given to run_with_env when it was declared.
+        globals: Rc<RefCell<Environment>>,
         is_initializer: bool,
+        // The class whose body lexically contains this function, if any. It
+        // decides whether private members can be accessed while it runs. Weak
+        // because the class itself owns its methods.
+        class: Option<Weak<RefCell<LoxClass>>>,
     },
 }
 
@@ -39,21 +68,67 @@ impl Function {
     // We pass in the interpreter in case the class implementing
     // call() needs it. We also give it the list of evaluated
     // argument values. The implementer’s job is then to return the
-    // value that the call expression produces.
+    // value that the call expression produces. `paren` is the closing
+    // parenthesis of the call, where errors raised by natives are reported.
     pub fn call(
         &self,
         interpreter: &mut Interpreter,
-        arguments: &Vec<Object>,
+        arguments: &[Object],
+        paren: &Token,
+    ) -> Result<Object, Error> {
+        interpreter.record(|| Event::call(paren.line, self.name(), arguments));
+        interpreter.enter_profile(|| self.qualified_name());
+        let result = self.invoke(interpreter, arguments, paren);
+        interpreter.exit_profile();
+        let value = result?;
+        interpreter.record(|| Event::Return {
+            line: paren.line,
+            function: self.name().to_string(),
+            value: inspect(&value),
+        });
+        Ok(value)
+    }
+
+    fn invoke(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[Object],
+        paren: &Token,
     ) -> Result<Object, Error> {
         match self {
-            Function::Native { body, .. } => Ok(body(arguments)),
+            Function::Native { body, this, .. } => {
+                let _span = trace_span!("call", function = "<native>").entered();
+                let call_site = interpreter.call_site.replace(paren.clone());
+                let result = match this {
+                    Some(this) => {
+                        let mut with_this = vec![(**this).clone()];
+                        with_this.extend_from_slice(arguments);
+                        body(interpreter, &with_this)
+                    }
+                    None => body(interpreter, arguments),
+                };
+                interpreter.call_site = call_site;
+                // A callback that failed fails the native with the same
+                // error, an interrupt or a runtime error in the callback.
+                let callback_error = interpreter.native_error.take();
+                result.map_err(|message| {
+                    callback_error
+                        .unwrap_or_else(|| Error::runtime(paren, Code::Native, &[&message]))
+                })
+            }
             Function::User {
+                name,
                 params,
                 body,
                 closure,
+                globals,
                 is_initializer,
-                ..
+                class,
             } => {
+                let _span =
+                    trace_span!("call", function = %name.lexeme, line = name.line).entered();
+                interpreter.check_interrupt(paren)?;
+                let _call = interpreter.enter_call(paren)?;
                 // This means each function gets its own environment where it stores those variables.
 
                 // Further, this environment must be created dynamically. Each
@@ -67,29 +142,35 @@ impl Function {
                         .borrow_mut()
                         .define(param.lexeme.clone(), argument.clone());
                 }
-                match interpreter.execute_block(body, environment) {
-                    Err(Error::Return { value }) => {
-                        if *is_initializer {
-                            Ok(closure
-                                .borrow()
-                                .get_at(0, "this")
-                                .expect("Initializer should return 'this'."))
-                        } else {
-                            Ok(value)
-                        }
+                let enclosing_class = mem::replace(
+                    &mut interpreter.class_context,
+                    class.as_ref().and_then(Weak::upgrade),
+                );
+                let globals = mem::replace(&mut interpreter.globals, Rc::clone(globals));
+                interpreter.begin_deferred();
+                let result = interpreter.execute_block(body, environment);
+                let result = interpreter.run_deferred(result);
+                interpreter.globals = globals;
+                interpreter.class_context = enclosing_class;
+
+                let value = match result {
+                    Err(Error::Return { value }) => value,
+                    Err(other) => {
+                        interpreter.frames.leave_function(&name.lexeme, paren.line);
+                        return Err(other);
                     }
-                    Err(other) => Err(other),
                     // We don't have a return statement
-                    Ok(..) => {
-                        if *is_initializer {
-                            Ok(closure
-                                .borrow()
-                                .get_at(0, "this")
-                                .expect("Initializer should return 'this'."))
-                        } else {
-                            Ok(Object::Null)
-                        }
-                    }
+                    Ok(..) => Object::Null,
+                };
+
+                // An initializer always hands back the instance it was bound
+                // to: when it runs to the end, when it exits early with a bare
+                // `return;` and when it is invoked directly as `instance.init()`.
+                // The resolver already rejects returning any other value.
+                if *is_initializer {
+                    Ok(closure.borrow().get_at(0, 0))
+                } else {
+                    Ok(value)
                 }
             }
         }
@@ -99,45 +180,160 @@ impl Function {
     // Sort of a closure-within-a-closure. When the method is called, that will
     // become the parent of the method body’s environment. We declare “this” as
     // a variable in that environment and bind it to the given instance, the
-    // instance that the method is being accessed from.ß
+    // instance that the method is being accessed from.
+    //
+    // The bound function is a copy of this one with only the closure swapped,
+    // so flags like is_initializer carry over. Natives have no closure, the
+    // methods of native classes get the instance as their first argument
+    // instead.
     pub fn bind(&self, instance: Object) -> Self {
-        match self {
-            Function::Native { .. } => unreachable!(),
+        let mut bound = self.clone();
+        match bound {
+            Function::Native { ref mut this, .. } => *this = Some(Rc::new(instance)),
             Function::User {
-                name,
-                params,
-                body,
-                closure,
-                is_initializer,
+                ref mut closure, ..
             } => {
                 let environment = Rc::new(RefCell::new(Environment::from(closure)));
                 environment
                     .borrow_mut()
                     .define("this".to_string(), instance);
+                *closure = environment;
+            }
+        }
+        bound
+    }
+
+    // Whether two values are the same function, for `==`. A function is equal
+    // to its copies, but every bind makes a new function, so like in jlox
+    // `obj.method == obj.method` is false.
+    pub fn is_same(&self, other: &Function) -> bool {
+        match (self, other) {
+            (
+                Function::Native {
+                    name: left,
+                    this: left_this,
+                    ..
+                },
+                Function::Native {
+                    name: right,
+                    this: right_this,
+                    ..
+                },
+            ) => {
+                left == right
+                    && match (left_this, right_this) {
+                        (Some(left), Some(right)) => Rc::ptr_eq(left, right),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            }
+            (
                 Function::User {
-                    name: name.clone(),
-                    params: params.clone(),
-                    body: body.clone(),
-                    closure: environment,
-                    is_initializer: *is_initializer,
-                }
+                    body: left,
+                    closure: left_closure,
+                    ..
+                },
+                Function::User {
+                    body: right,
+                    closure: right_closure,
+                    ..
+                },
+            ) => Rc::ptr_eq(left, right) && Rc::ptr_eq(left_closure, right_closure),
+            _ => false,
+        }
+    }
+
+    // Whether this is a method bound to an instance, `point.area` rather than
+    // the function declared in the class.
+    pub fn is_bound(&self) -> bool {
+        match self {
+            Function::Native { this, .. } => this.is_some(),
+            Function::User { closure, class, .. } => {
+                class.is_some() && closure.borrow().contains("this")
             }
         }
     }
 
-    pub fn arity(&self) -> usize {
+    pub fn arity(&self) -> RangeInclusive<usize> {
+        match self {
+            Function::Native { arity, .. } => arity.clone(),
+            Function::User { params, .. } => params.len()..=params.len(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
         match self {
-            Function::Native { arity, .. } => *arity,
-            Function::User { params, .. } => params.len(),
+            Function::Native { name, .. } => name,
+            Function::User { name, .. } => &name.lexeme,
         }
     }
+
+    // The name of a method qualified with its class, `Point.init`, the plain
+    // name otherwise. A bound method's closure defines `this`, a native
+    // method holds its instance.
+    pub fn qualified_name(&self) -> String {
+        let class = match self {
+            Function::Native {
+                this: Some(this), ..
+            } => match &**this {
+                Object::Instance(instance) => Some(Rc::clone(&instance.borrow().class)),
+                _ => None,
+            },
+            Function::User {
+                class: Some(class), ..
+            } if self.is_bound() => class.upgrade(),
+            _ => None,
+        };
+        match class {
+            Some(class) => format!("{}.{}", class.borrow().name, self.name()),
+            None => self.name().to_string(),
+        }
+    }
+
+    pub fn check_arity(&self, count: usize, paren: &Token) -> Result<(), Error> {
+        self.check_arity_as(self.name(), count, paren)
+    }
+
+    // Like check_arity but names the callee differently, for an initializer
+    // that runs when its class is called.
+    pub fn check_arity_as(&self, callee: &str, count: usize, paren: &Token) -> Result<(), Error> {
+        let arity = self.arity();
+        if arity.contains(&count) {
+            return Ok(());
+        }
+
+        let usage = match self {
+            Function::Native { signature, .. } => format!(" Usage: {}", signature),
+            _ => String::new(),
+        };
+        Err(Error::runtime(
+            paren,
+            Code::Arity,
+            &[&describe_arity(&arity), callee, &count.to_string(), &usage],
+        ))
+    }
+}
+
+// "1 argument", "2 arguments", "1 to 2 arguments"
+pub(crate) fn describe_arity(arity: &RangeInclusive<usize>) -> String {
+    let count = if arity.start() == arity.end() {
+        arity.start().to_string()
+    } else {
+        format!("{} to {}", arity.start(), arity.end())
+    };
+    let noun = if *arity.end() == 1 {
+        "argument"
+    } else {
+        "arguments"
+    };
+    format!("{} {}", count, noun)
 }
 
 // Implements to_string which corresponds to toString from the book
 impl fmt::Display for Function {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Function::Native { .. } => write!(f, "<native func>"),
+            Function::Native { name, .. } => write!(f, "<native fn {}>", name),
             Function::User { name, .. } => write!(f, "<fn {}>", name.lexeme),
         }
     }
@@ -146,7 +342,7 @@ impl fmt::Display for Function {
 impl fmt::Debug for Function {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Function::Native { .. } => write!(f, "<native func>"),
+            Function::Native { name, .. } => write!(f, "<native fn {}>", name),
             Function::User { name, .. } => write!(f, "<fn {}>", name.lexeme),
         }
     }
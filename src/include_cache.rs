@@ -0,0 +1,199 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::rename::is_identifier;
+use crate::scanner::LiteralLimits;
+use crate::snapshot::{quote, words};
+use crate::token::{Token, TokenType, KEYWORDS};
+
+const HEADER: &str = "lox tokens 1";
+
+// The tokens of the files the preprocessor read before, keyed by a hash of
+// their text, so a program put together from many files only scans the ones
+// that changed since the last run. Every entry is a file of its own in the
+// user's cache directory, `$XDG_CACHE_HOME/lox` or else `~/.cache/lox`, with a
+// token a line after the header:
+//
+//   lox tokens 1 <bytes of text>
+//   <line> <column> "<lexeme>"                   identifiers, keywords and punctuation
+//   <line> <column> "<lexeme>" string "<text>"   a string literal
+//   <line> <column> "<lexeme>" number <n>        a number literal
+//
+// The hash is FNV-1a over the text and the literal limits it is scanned with,
+// which unlike the hasher of the standard library stays the same from one
+// build to the next. An entry that can't be read, or was made for text of
+// another length, is a miss and written again. Text with scan errors isn't
+// cached, so its errors are reported every time.
+pub struct IncludeCache {
+    // None when caching is off or there is no cache directory.
+    dir: Option<PathBuf>,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl IncludeCache {
+    // A cache in the user's cache directory.
+    pub fn new() -> Self {
+        let dir = env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .map(|dir| dir.join("lox"));
+        IncludeCache {
+            dir,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    // A cache that never has anything, for --no-cache.
+    pub fn disabled() -> Self {
+        IncludeCache {
+            dir: None,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    // The tokens of the text, scanned from line 1, if they are in the cache.
+    pub fn tokens(&mut self, text: &str, limits: LiteralLimits) -> Option<Vec<Token>> {
+        let path = self.path(text, limits)?;
+        let tokens = fs::read_to_string(path)
+            .ok()
+            .and_then(|entry| read_entry(&entry, text.len()));
+        match tokens {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+        tokens
+    }
+
+    // Remembers the tokens of the text. A cache that can't be written to is
+    // only slower, so errors are ignored.
+    pub fn store(&self, text: &str, limits: LiteralLimits, tokens: &[Token]) {
+        let Some(path) = self.path(text, limits) else {
+            return;
+        };
+        let mut entry = format!("{} {}\n", HEADER, text.len());
+        for token in tokens {
+            entry.push_str(&format!(
+                "{} {} {}",
+                token.line,
+                token.column,
+                quote(&token.lexeme)
+            ));
+            match &token.token_type {
+                TokenType::String { literal } => {
+                    entry.push_str(&format!(" string {}", quote(literal)))
+                }
+                // Debug formatting keeps every digit.
+                TokenType::Number { literal } => entry.push_str(&format!(" number {:?}", literal)),
+                _ => (),
+            }
+            entry.push('\n');
+        }
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = fs::write(path, entry);
+    }
+
+    fn path(&self, text: &str, limits: LiteralLimits) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        let mut hash = Fnv::new();
+        hash.write(&limits.string_bytes.to_le_bytes());
+        hash.write(&limits.number_digits.to_le_bytes());
+        hash.write(text.as_bytes());
+        Some(dir.join(format!("{:016x}.tokens", hash.0)))
+    }
+}
+
+impl Default for IncludeCache {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+fn read_entry(entry: &str, length: usize) -> Option<Vec<Token>> {
+    let mut lines = entry.lines();
+    let header = lines.next()?;
+    if header.strip_prefix(HEADER)?.trim().parse::<usize>().ok()? != length {
+        return None;
+    }
+    lines.map(|line| read_token(&words(line)?)).collect()
+}
+
+fn read_token(words: &[String]) -> Option<Token> {
+    let [line, column, lexeme, literal @ ..] = words else {
+        return None;
+    };
+    let token_type = match literal {
+        [kind, literal] if kind == "string" => TokenType::String {
+            literal: literal.clone(),
+        },
+        [kind, literal] if kind == "number" => TokenType::Number {
+            literal: literal.parse().ok()?,
+        },
+        [] if lexeme.is_empty() => TokenType::Eof,
+        [] => match KEYWORDS.get(lexeme.as_str()) {
+            Some(keyword) => keyword.clone(),
+            None if is_identifier(lexeme) => TokenType::Identifier,
+            None => punctuation(lexeme)?,
+        },
+        _ => return None,
+    };
+    Some(Token::new(
+        token_type,
+        lexeme,
+        line.parse().ok()?,
+        column.parse().ok()?,
+    ))
+}
+
+fn punctuation(lexeme: &str) -> Option<TokenType> {
+    Some(match lexeme {
+        "(" => TokenType::LeftParen,
+        ")" => TokenType::RightParen,
+        "{" => TokenType::LeftBrace,
+        "}" => TokenType::RightBrace,
+        "[" => TokenType::LeftBracket,
+        "]" => TokenType::RightBracket,
+        ":" => TokenType::Colon,
+        "," => TokenType::Comma,
+        "." => TokenType::Dot,
+        "-" => TokenType::Minus,
+        "+" => TokenType::Plus,
+        ";" => TokenType::Semicolon,
+        "/" => TokenType::Slash,
+        "*" => TokenType::Star,
+        "!" => TokenType::Bang,
+        "!=" => TokenType::BangEqual,
+        "=" => TokenType::Equal,
+        "==" => TokenType::EqualEqual,
+        ">" => TokenType::Greater,
+        ">=" => TokenType::GreaterEqual,
+        "<" => TokenType::Less,
+        "<=" => TokenType::LessEqual,
+        "**" => TokenType::StarStar,
+        "?." => TokenType::QuestionDot,
+        "->" => TokenType::Arrow,
+        _ => return None,
+    })
+}
+
+// 64-bit FNV-1a.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::class::LoxInstance;
+use crate::key::Key;
+use crate::list;
+use crate::map;
+use crate::natives::native_state;
+use crate::object::Object;
+use crate::set;
+
+// Formats a value for debugging output. Unlike printing, strings are quoted and
+// instances show their fields, `Point { x: 1, y: 2 }`. The native collections
+// show what they hold before any fields: `List [1, 2]`, `Set [1, 2]`,
+// `Map ["a": 1]` and `StringBuilder "text"`. Instances can refer to
+// themselves, so the instances on the current path are remembered and a cycle
+// is printed as `<cycle Point>` instead of recursing forever.
+pub fn inspect(value: &Object) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value, &mut Vec::new());
+    out
+}
+
+fn write_value(out: &mut String, value: &Object, path: &mut Vec<*const RefCell<LoxInstance>>) {
+    match value {
+        Object::String(s) => write!(out, "{:?}", s).unwrap(),
+        Object::Instance(instance) => {
+            let pointer = Rc::as_ptr(instance);
+            let name = instance.borrow().class.borrow().name.clone();
+            if path.contains(&pointer) {
+                write!(out, "<cycle {}>", name).unwrap();
+                return;
+            }
+
+            path.push(pointer);
+            let contents = contents(value);
+            let instance = instance.borrow();
+            let mut fields: Vec<_> = instance.fields().iter().collect();
+            fields.sort_by_key(|(name, _)| *name);
+            out.push_str(&name);
+            if let Some(contents) = &contents {
+                out.push(' ');
+                write_contents(out, contents, path);
+            }
+            if fields.is_empty() {
+                if contents.is_none() {
+                    out.push_str(" {}");
+                }
+                path.pop();
+                return;
+            }
+
+            out.push_str(" { ");
+            for (i, (field, value)) in fields.into_iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write!(out, "{}: ", field).unwrap();
+                write_value(out, value, path);
+            }
+            out.push_str(" }");
+            path.pop();
+        }
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+// What an instance of a native collection holds in Rust rather than in
+// fields. An instance one of whose methods is running has none, see
+// native_state.
+enum Contents {
+    List(Vec<Object>),
+    Set(Vec<Object>),
+    Map(Vec<(Object, Object)>),
+    Text(String),
+}
+
+// Must not be called while the instance is borrowed.
+fn contents(value: &Object) -> Option<Contents> {
+    list::items(value)
+        .map(Contents::List)
+        .or_else(|_| set::elements(value).map(Contents::Set))
+        .or_else(|_| map::entries(value).map(Contents::Map))
+        .or_else(|_| {
+            native_state(value, "StringBuilder", |text: &mut String| {
+                Ok(Contents::Text(text.clone()))
+            })
+        })
+        .ok()
+}
+
+fn write_contents(
+    out: &mut String,
+    contents: &Contents,
+    path: &mut Vec<*const RefCell<LoxInstance>>,
+) {
+    match contents {
+        Contents::List(elements) | Contents::Set(elements) => {
+            out.push('[');
+            for (i, element) in elements.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_value(out, element, path);
+            }
+            out.push(']');
+        }
+        Contents::Map(entries) => {
+            out.push('[');
+            for (i, (key, value)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_value(out, key, path);
+                out.push_str(": ");
+                write_value(out, value, path);
+            }
+            out.push(']');
+        }
+        Contents::Text(text) => write!(out, "{:?}", text).unwrap(),
+    }
+}
+
+// Compares two values structurally and describes every difference on its own
+// line, prefixed with the path of fields leading to it (`.origin.x: ...`).
+// Instances of the same class are equal when all their fields are, and for
+// the native collections when what they hold is too: Lists element by element
+// and Maps entry by entry, where only the first differing index or key is
+// reported (`.items[2]: ...`, `["b"]: ...`), Sets by their elements in any
+// order and StringBuilders by their text. Everything else is compared like
+// `==` does. No differences means the values are equal.
+pub fn diff(expected: &Object, actual: &Object) -> Vec<String> {
+    let mut differences = Vec::new();
+    diff_values("", expected, actual, &mut Vec::new(), &mut differences);
+    differences
+}
+
+type InstancePair = (*const RefCell<LoxInstance>, *const RefCell<LoxInstance>);
+
+fn diff_values(
+    path: &str,
+    expected: &Object,
+    actual: &Object,
+    comparing: &mut Vec<InstancePair>,
+    differences: &mut Vec<String>,
+) {
+    let prefix = if path.is_empty() {
+        String::new()
+    } else {
+        format!("{}: ", path)
+    };
+
+    match (expected, actual) {
+        (Object::Instance(left), Object::Instance(right)) => {
+            // A pair that is already being compared further up is part of a
+            // cycle; its differences get reported there.
+            let pair = (Rc::as_ptr(left), Rc::as_ptr(right));
+            if Rc::ptr_eq(left, right) || comparing.contains(&pair) {
+                return;
+            }
+
+            let (left_class, right_class) =
+                (left.borrow().class.clone(), right.borrow().class.clone());
+            if !Rc::ptr_eq(&left_class, &right_class) {
+                differences.push(format!(
+                    "{}expected {} instance, got {} instance",
+                    prefix,
+                    left_class.borrow().name,
+                    right_class.borrow().name
+                ));
+                return;
+            }
+
+            // Neither instance stays borrowed while their values are compared,
+            // one of them can come up again paired with another instance.
+            comparing.push(pair);
+            let left_fields = left.borrow().fields().clone();
+            let right_fields = right.borrow().fields().clone();
+            let names: BTreeSet<&String> = left_fields.keys().chain(right_fields.keys()).collect();
+            for name in names {
+                let field_path = format!("{}.{}", path, name);
+                match (left_fields.get(name), right_fields.get(name)) {
+                    (Some(expected), Some(actual)) => {
+                        diff_values(&field_path, expected, actual, comparing, differences)
+                    }
+                    (Some(expected), None) => differences.push(format!(
+                        "{}: expected {}, but the field is missing",
+                        field_path,
+                        inspect(expected)
+                    )),
+                    (None, Some(actual)) => differences.push(format!(
+                        "{}: unexpected field with value {}",
+                        field_path,
+                        inspect(actual)
+                    )),
+                    (None, None) => unreachable!(),
+                }
+            }
+            if let (Some(left), Some(right)) = (contents(expected), contents(actual)) {
+                diff_contents(path, left, right, comparing, differences);
+            }
+            comparing.pop();
+        }
+        (Object::Class(left), Object::Class(right)) if Rc::ptr_eq(left, right) => (),
+        _ if expected.equals(actual) => (),
+        _ => differences.push(format!(
+            "{}expected {}, got {}",
+            prefix,
+            inspect(expected),
+            inspect(actual)
+        )),
+    }
+}
+
+// A Key hashes the identity of anything on the heap, never what is inside, so
+// the interior mutability clippy sees in one is harmless.
+#[allow(clippy::mutable_key_type)]
+fn diff_contents(
+    path: &str,
+    expected: Contents,
+    actual: Contents,
+    comparing: &mut Vec<InstancePair>,
+    differences: &mut Vec<String>,
+) {
+    let prefix = if path.is_empty() {
+        String::new()
+    } else {
+        format!("{}: ", path)
+    };
+
+    match (expected, actual) {
+        (Contents::List(left), Contents::List(right)) => {
+            for index in 0..left.len().max(right.len()) {
+                let element_path = format!("{}[{}]", path, index);
+                match (left.get(index), right.get(index)) {
+                    (Some(expected), Some(actual)) => {
+                        let before = differences.len();
+                        diff_values(&element_path, expected, actual, comparing, differences);
+                        if differences.len() > before {
+                            return;
+                        }
+                    }
+                    (Some(expected), None) => {
+                        return differences.push(format!(
+                            "{}: expected {}, but the element is missing",
+                            element_path,
+                            inspect(expected)
+                        ))
+                    }
+                    (None, Some(actual)) => {
+                        return differences.push(format!(
+                            "{}: unexpected element {}",
+                            element_path,
+                            inspect(actual)
+                        ))
+                    }
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        (Contents::Set(left), Contents::Set(right)) => {
+            let left_keys: HashSet<Key> = left.iter().filter_map(Key::new).collect();
+            let right_keys: HashSet<Key> = right.iter().filter_map(Key::new).collect();
+            if let Some(missing) = left
+                .iter()
+                .find(|element| Key::new(element).is_some_and(|key| !right_keys.contains(&key)))
+            {
+                differences.push(format!(
+                    "{}expected element {}, but it is missing",
+                    prefix,
+                    inspect(missing)
+                ));
+            } else if let Some(unexpected) = right
+                .iter()
+                .find(|element| Key::new(element).is_some_and(|key| !left_keys.contains(&key)))
+            {
+                differences.push(format!(
+                    "{}unexpected element {}",
+                    prefix,
+                    inspect(unexpected)
+                ));
+            }
+        }
+        (Contents::Map(left), Contents::Map(right)) => {
+            let right_values: HashMap<Key, &Object> = right
+                .iter()
+                .filter_map(|(key, value)| Some((Key::new(key)?, value)))
+                .collect();
+            for (key, expected) in &left {
+                let entry_path = format!("{}[{}]", path, inspect(key));
+                match Key::new(key).and_then(|key| right_values.get(&key).copied()) {
+                    Some(actual) => {
+                        let before = differences.len();
+                        diff_values(&entry_path, expected, actual, comparing, differences);
+                        if differences.len() > before {
+                            return;
+                        }
+                    }
+                    None => {
+                        return differences.push(format!(
+                            "{}: expected {}, but the key is missing",
+                            entry_path,
+                            inspect(expected)
+                        ))
+                    }
+                }
+            }
+            let left_keys: HashSet<Key> =
+                left.iter().filter_map(|(key, _)| Key::new(key)).collect();
+            if let Some((key, actual)) = right
+                .iter()
+                .find(|(key, _)| Key::new(key).is_some_and(|key| !left_keys.contains(&key)))
+            {
+                differences.push(format!(
+                    "{}[{}]: unexpected key with value {}",
+                    path,
+                    inspect(key),
+                    inspect(actual)
+                ));
+            }
+        }
+        (Contents::Text(left), Contents::Text(right)) if left != right => {
+            differences.push(format!("{}expected {:?}, got {:?}", prefix, left, right))
+        }
+        _ => (),
+    }
+}
@@ -1,77 +1,818 @@
+use std::any::{type_name, Any, TypeId};
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-use crate::class::{LoxClass, LoxInstance};
+use crate::cancel::CancellationToken;
+use crate::class::{inherits, ClassInfo, LoxClass, LoxInstance};
 use crate::environment::Environment;
-use crate::error::Error;
-use crate::function::Function;
-use crate::object::Object;
+use crate::error::{Code, Error};
+use crate::frames::{Frame, Frames};
+use crate::function::{Function, NativeFn};
+use crate::inspect::inspect;
+use crate::limits::{Allocation, Limits, Usage};
+use crate::list;
+use crate::natives::{define_class, define_natives, native};
+use crate::object::{format_number, is_integer, jlox_string, Foreign, Object, MAX_EXACT_INTEGER};
+use crate::operators::{Operator, OperatorFn, OperatorTable};
+use crate::profiler::Profiler;
+use crate::random::Random;
+use crate::recorder::{Event, Recorder};
+use crate::streams::{self, Writer};
 use crate::syntax::{expr, stmt, Stmt};
-use crate::syntax::{Expr, LiteralValue};
+use crate::syntax::{Depth, Expr, LiteralValue, MatchArm, Pattern, Resolution};
 use crate::token::{Token, TokenType};
+
+use tracing::error;
+
+// The most calls of Lox functions in progress, the script itself counted like
+// the VM's FRAMES_MAX does. Every call recurses on the host's stack here, so a
+// runaway recursion would otherwise overflow it and abort the process instead
+// of failing with a runtime error.
+pub const CALLS_MAX: usize = 1024;
+
+// The stack a thread running scripts needs to get CALLS_MAX calls deep, with
+// room for deeply nested expressions in each. A debug build takes tens of
+// kilobytes for the simplest call, more than the 8 MiB main thread has for
+// CALLS_MAX of them.
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
 pub struct Interpreter {
     // Fix reference to the outermost global env
     pub globals: Rc<RefCell<Environment>>,
     environment: Rc<RefCell<Environment>>,
-    // side table: tabular data structure that stores data separately from the
-    // objects it relates to Interactive tools like IDEs often incrementally
-    // reparse and re-resolve parts of the user’s program. It may be hard to
-    // find all of the bits of state that need recalculating when they’re hiding
-    // in the foliage of the syntax tree. A benefit of storing this data outside
-    // of the nodes is that it makes it easy to discard it—simply clear the map.
-    locals: HashMap<Token, usize>,
+    // The class whose method is currently executing. Private members are
+    // only accessible while this is set to the instance's class or one of
+    // its ancestors.
+    pub(crate) class_context: Option<Rc<RefCell<LoxClass>>>,
+    // The statements deferred by every call in progress, the innermost call
+    // last, with the environments they were deferred in.
+    deferred: Vec<Vec<(Stmt, Rc<RefCell<Environment>>)>>,
+    // Set from outside, usually a Ctrl-C handler, to stop the running program.
+    // Checked on every loop iteration and function call, which is where a
+    // program can spend an unbounded amount of time.
+    interrupted: Arc<AtomicBool>,
+    // Tripped by the host to stop the program for good, see cancel.rs.
+    cancellation: CancellationToken,
+    limits: Limits,
+    usage: Usage,
+    // Writes every statement, call and write to an event log when set.
+    recorder: Option<Recorder>,
+    // Times every call when set.
+    profiler: Option<Profiler>,
+    // The closing parenthesis of the native call in progress and the error
+    // it failed with, from a callback or fail().
+    pub(crate) call_site: Option<Token>,
+    pub(crate) native_error: Option<Error>,
+    native_classes: HashMap<String, Rc<RefCell<LoxClass>>>,
+    // The names and methods of the host types registered with register_type.
+    foreign_types: HashMap<TypeId, ForeignType>,
+    // The hosts' rules for binary operators, see operators.rs.
+    operators: OperatorTable,
+    // Where random() and shuffle() get their numbers, see random.rs.
+    random: Random,
+    // Declaring a class again adds its methods to the existing class instead
+    // of binding the name to a new one, see visit_class_stmt.
+    pub(crate) allow_reopen: bool,
+    // Whether natives like open_writer may create and write files.
+    allow_file_writes: bool,
+    // Where `print` writes, stdout unless the host gives another sink.
+    output: Box<dyn Write>,
+    // Top-level declarations run before the other statements, see hoist.
+    hoist: bool,
+    // Integer-like operations going past the integers a float holds exactly
+    // are errors, see check_exact.
+    strict: bool,
+    // Prints, compares and words runtime errors like jlox, see Lox::set_jlox.
+    jlox: bool,
+    // Where the last runtime error happened, see frames.rs.
+    pub(crate) frames: Frames,
+    // The doc comments of the top-level declarations, methods as
+    // `Class.method`. Lox keeps them up to date as programs are compiled.
+    pub(crate) docs: HashMap<String, String>,
+}
+
+struct ForeignType {
+    name: Rc<str>,
+    methods: HashMap<String, Function>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        let globals = Rc::new(RefCell::new(Environment::new()));
-        let clock: Object = Object::Callable(Function::Native {
-            arity: 0,
-            body: Box::new(|_args: &Vec<Object>| {
-                Object::Number(
-                    SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .expect("Could not retrieve time.")
-                        .as_millis() as f64,
-                )
-            }),
-        });
-        // In Lox functions and variables occupy the same namespace.
-        globals.borrow_mut().define("clock".to_string(), clock);
-        Self {
+        let usage = Usage::default();
+        let mut globals = Environment::new();
+        globals.allocation = Some(usage.environments.allocate());
+        define_natives(&mut globals);
+        let mut native_classes: HashMap<_, _> = globals
+            .values()
+            .filter_map(|(name, value)| match value {
+                Object::Class(class) => Some((name.to_string(), Rc::clone(class))),
+                _ => None,
+            })
+            .collect();
+        // The classes of what time_parts and globals return, which only have
+        // fields. They aren't globals, scripts don't make these themselves.
+        for name in ["TimeParts", "Globals"] {
+            let class = LoxClass::new(name.to_string(), None, HashMap::new());
+            native_classes.insert(name.to_string(), Rc::new(RefCell::new(class)));
+        }
+        let globals = Rc::new(RefCell::new(globals));
+        let mut interpreter = Self {
             globals: Rc::clone(&globals),
             environment: Rc::clone(&globals),
-            locals: HashMap::new(),
+            class_context: None,
+            deferred: Vec::new(),
+            interrupted: Arc::new(AtomicBool::new(false)),
+            cancellation: CancellationToken::new(),
+            limits: Limits::default(),
+            usage,
+            recorder: None,
+            profiler: None,
+            call_site: None,
+            native_error: None,
+            native_classes,
+            foreign_types: HashMap::new(),
+            operators: OperatorTable::default(),
+            random: Random::from_clock(),
+            allow_reopen: false,
+            allow_file_writes: true,
+            output: Box::new(io::stdout()),
+            hoist: false,
+            strict: false,
+            jlox: false,
+            frames: Frames::default(),
+            docs: HashMap::new(),
+        };
+        interpreter.register_type::<Writer>("Writer", streams::WRITER_METHODS);
+        interpreter
+    }
+
+    // Defines a global class whose methods are natives, for hosts exposing an
+    // API to scripts. The methods get the instance as their first argument,
+    // `init` usually gives it its Rust state with set_native_state and the
+    // other methods use it with native_state:
+    //
+    //   interpreter.register_class("File", &[("init(path)", open), ("read()", read)]);
+    //
+    // Instances of the class can't be saved in snapshots, like the ones of the
+    // built-in classes other than List, Set and Map.
+    pub fn register_class(
+        &mut self,
+        name: &str,
+        methods: &[(&'static str, NativeFn)],
+    ) -> Rc<RefCell<LoxClass>> {
+        let class = define_class(&mut self.globals.borrow_mut(), name, methods);
+        self.native_classes
+            .insert(name.to_string(), Rc::clone(&class));
+        class
+    }
+
+    // Lets scripts call methods on foreign values holding a T. The methods are
+    // natives like the ones of native classes, they get the foreign value as
+    // their first argument and can use Object::downcast_foreign to get the T
+    // back. Registering the type again replaces its name and methods.
+    pub fn register_type<T: Any>(&mut self, name: &str, methods: &[(&'static str, NativeFn)]) {
+        let methods = methods
+            .iter()
+            .map(|&(signature, body)| {
+                let method = native(signature, body);
+                (method.name().to_string(), method)
+            })
+            .collect();
+        let name = Rc::from(name);
+        self.foreign_types
+            .insert(TypeId::of::<T>(), ForeignType { name, methods });
+    }
+
+    // Adds a rule for the operator, tried before the built-in ones:
+    //
+    //   interpreter.register_operator(Operator::Add, add_vectors);
+    pub fn register_operator(&mut self, operator: Operator, rule: OperatorFn) {
+        self.operators.register(operator, rule);
+    }
+
+    // Wraps a host value for passing it to scripts. It is shown with the name
+    // it was registered under, or the Rust type name when T wasn't registered.
+    pub fn foreign<T: Any>(&self, value: T) -> Object {
+        let name = match self.foreign_types.get(&TypeId::of::<T>()) {
+            Some(foreign_type) => Rc::clone(&foreign_type.name),
+            None => Rc::from(type_name::<T>()),
+        };
+        Object::Foreign(Foreign::new(name, value))
+    }
+
+    // A built-in class like List, even when a script reused its name.
+    // The classes the scripts run so far declared at the top level, sorted by
+    // name. A class is listed once under its own name, whatever global it is
+    // in, and the built-in and registered classes are left out:
+    //
+    //   for class in lox.classes() {
+    //       println!("{}({})", class.name, class.arity);
+    //   }
+    pub fn classes(&self) -> Vec<ClassInfo> {
+        let mut seen = HashSet::new();
+        let mut classes: Vec<ClassInfo> = self
+            .globals
+            .borrow()
+            .values()
+            .filter_map(|(_, value)| match value {
+                Object::Class(class) if seen.insert(Rc::as_ptr(class)) => {
+                    let class = class.borrow();
+                    (!class.is_native()).then(|| ClassInfo::new(&class, &self.docs))
+                }
+                _ => None,
+            })
+            .collect();
+        classes.sort_by(|a, b| a.name.cmp(&b.name));
+        classes
+    }
+
+    pub(crate) fn native_class(&self, name: &str) -> Rc<RefCell<LoxClass>> {
+        Rc::clone(
+            self.native_classes
+                .get(name)
+                .unwrap_or_else(|| panic!("No native class '{}'.", name)),
+        )
+    }
+
+    // Counts a call of a Lox function until the allocation is dropped, failing
+    // when there are more calls in progress than the limits allow or than
+    // CALLS_MAX.
+    pub(crate) fn enter_call(&self, paren: &Token) -> Result<Allocation, Error> {
+        let call = self.usage.calls.allocate();
+        self.limits.check_calls(&self.usage)?;
+        if self.usage.calls.live() >= CALLS_MAX {
+            return Err(Error::runtime(paren, Code::StackOverflow, &[]));
         }
+        Ok(call)
+    }
+
+    // Counts an instance of the VM until the allocation is dropped, failing
+    // when there are more alive than the limits allow.
+    pub(crate) fn count_instance(&self) -> Result<Allocation, Error> {
+        let instance = self.usage.instances.allocate();
+        self.limits.check(&self.usage)?;
+        Ok(instance)
+    }
+
+    // Counts a line about to be printed against the output limits.
+    pub(crate) fn count_output(&self, line: &str) -> Result<(), Error> {
+        self.limits.count_output(&self.usage, line)
+    }
+
+    // Writes a line of what `print` shows, if the output limits allow it.
+    pub(crate) fn print(&mut self, text: &str) -> Result<(), Error> {
+        self.count_output(text)?;
+        writeln!(self.output, "{}", text)?;
+        Ok(())
     }
 
-    pub fn interpret(&mut self, statements: &Vec<Stmt>) -> Result<(), Error> {
-        for statement in statements {
-            self.execute(statement)?;
+    pub(crate) fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    // Makes the random numbers of the programs run from now on the same on
+    // every run with the seed.
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.random = Random::new(seed);
+    }
+
+    pub fn set_allow_reopen(&mut self, allow_reopen: bool) {
+        self.allow_reopen = allow_reopen;
+    }
+
+    pub fn set_hoist(&mut self, hoist: bool) {
+        self.hoist = hoist;
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub(crate) fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn set_jlox(&mut self, jlox: bool) {
+        self.jlox = jlox;
+    }
+
+    // In strict mode an operation on integers whose result is an integer past
+    // MAX_EXACT_INTEGER is an error, the result may already have been rounded
+    // to a neighbour. Elsewhere it is a float like any other. `operation`
+    // describes it for the message, like "9007199254740992 + 1".
+    pub(crate) fn check_exact(
+        &self,
+        result: f64,
+        token: &Token,
+        operation: impl FnOnce() -> String,
+    ) -> Result<(), Error> {
+        if self.strict && is_integer(result) && result.abs() > MAX_EXACT_INTEGER {
+            return Err(Error::runtime(token, Code::PrecisionLoss, &[&operation()]));
         }
         Ok(())
     }
 
-    fn execute(&mut self, stmt: &Stmt) -> Result<(), Error> {
-        stmt.accept(self)
+    // The result of arithmetic on two numbers, checked with check_exact when
+    // both are integers.
+    pub(crate) fn arithmetic(
+        &self,
+        operator: &Token,
+        left: f64,
+        right: f64,
+        result: f64,
+    ) -> Result<Object, Error> {
+        if is_integer(left) && is_integer(right) {
+            self.check_exact(result, operator, || {
+                format!(
+                    "{} {} {}",
+                    format_number(left),
+                    operator.lexeme,
+                    format_number(right)
+                )
+            })?;
+        }
+        Ok(Object::Number(result))
+    }
+
+    pub fn set_allow_file_writes(&mut self, allow_file_writes: bool) {
+        self.allow_file_writes = allow_file_writes;
+    }
+
+    // Removes every global, the built-in functions and classes included, so
+    // scripts only see what the host defines after. The interpreter still
+    // uses the built-in classes, a caught runtime error is an Error instance.
+    pub fn remove_natives(&mut self) {
+        self.globals.borrow_mut().clear();
+    }
+
+    // Sends what `print` writes to the sink from now on, to show it in a GUI
+    // or check it in tests.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    pub(crate) fn allow_file_writes(&self) -> bool {
+        self.allow_file_writes
+    }
+
+    pub(crate) fn random(&mut self) -> &mut Random {
+        &mut self.random
+    }
+
+    pub fn set_recorder(&mut self, recorder: Option<Recorder>) {
+        self.recorder = recorder;
+    }
+
+    pub fn take_recorder(&mut self) -> Option<Recorder> {
+        self.recorder.take()
+    }
+
+    // The event is only built when recording.
+    pub(crate) fn record(&mut self, event: impl FnOnce() -> Event) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(&event());
+        }
+    }
+
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    pub(crate) fn enter_profile(&mut self, name: impl FnOnce() -> String) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter(name());
+        }
+    }
+
+    pub(crate) fn exit_profile(&mut self) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.exit();
+        }
+    }
+
+    // Calls a function or a class, `paren` is where errors are reported.
+    pub(crate) fn call_value(
+        &mut self,
+        callee: Object,
+        args: &[Object],
+        paren: &Token,
+    ) -> Result<Object, Error> {
+        match callee {
+            Object::Callable(function) => {
+                if self.jlox {
+                    self.check_jlox_arity(function.arity(), args.len(), paren)?;
+                }
+                function.check_arity(args.len(), paren)?;
+                function.call(self, args, paren)
+            }
+            Object::Class(ref class) => {
+                if self.jlox {
+                    let initializer = class.borrow().initializer();
+                    let arity = initializer.map_or(0..=0, |initializer| initializer.arity());
+                    self.check_jlox_arity(arity, args.len(), paren)?;
+                }
+                // This is the call method of a class.
+                let instance = self.new_instance(class);
+                self.limits.check(&self.usage)?;
+                let initializer = class.borrow().initializer();
+                let name = class.borrow().name.clone();
+                if let Some(initializer) = initializer {
+                    initializer.check_arity_as(&name, args.len(), paren)?;
+                    let initializer = initializer.bind(instance.clone());
+                    set_initializing(&instance, true);
+                    let result = initializer.call(self, args, paren);
+                    set_initializing(&instance, false);
+                    result?;
+                } else if !args.is_empty() {
+                    // Without an initializer a class takes no arguments.
+                    return Err(Error::runtime(
+                        paren,
+                        Code::Arity,
+                        &["0 arguments", &name, &args.len().to_string(), ""],
+                    ));
+                }
+
+                Ok(instance)
+            }
+            _ => Err(Error::runtime(paren, Code::NotCallable, &[])),
+        }
+    }
+
+    // An instance of the built-in Error class, what a catch gets for a runtime
+    // error.
+    pub(crate) fn error_instance(&self, message: &str, line: i32, code: Code) -> Object {
+        let error = self.new_instance(&self.native_class("Error"));
+        if let Object::Instance(instance) = &error {
+            let mut instance = instance.borrow_mut();
+            instance.set_field("message".to_string(), Object::String(Rc::from(message)));
+            instance.set_field("line".to_string(), Object::Number(line.into()));
+            instance.set_field("code".to_string(), Object::String(Rc::from(code.id())));
+            let category = match code.category() {
+                Some(category) => Object::String(Rc::from(category.to_string())),
+                None => Object::Null,
+            };
+            instance.set_field("category".to_string(), category);
+        }
+        error
+    }
+
+    // Whether the class is Error or inherits from it.
+    fn is_error(&self, class: &Rc<RefCell<LoxClass>>) -> bool {
+        inherits(class, &self.native_class("Error"))
+    }
+
+    // Lets a native call back into Lox, a List's map() calling its function
+    // for example. Errors are reported at the call of the native, and the
+    // error itself is kept so the native can pass it on as its own failure.
+    pub(crate) fn call_back(&mut self, callee: &Object, args: &[Object]) -> Result<Object, String> {
+        let paren = self.call_site().clone();
+        self.call_value(callee.clone(), args, &paren)
+            .map_err(|error| self.fail(error))
     }
 
-    // Each time it visits a variable, it tells the interpreter how many scopes
-    // there are between the current scope and the scope where the variable is
-    // defined. At runtime, this corresponds exactly to the number of
-    // environments between the current one and the enclosing one where the
-    // interpreter can find the variable’s value.
-    pub fn resolve(&mut self, name: &Token, depth: usize) {
-        // We want to store the resolution information somewhere so we can use
-        // it when the variable or assignment expression is later executed, but
-        // where? One obvious place is right in the syntax tree node itself.
-        // That’s a fine approach, and that’s where many compilers store the
-        // results of analyses like this. But instead, we’ll take another common
-        // approach and store it off to the side in a map that associates each
-        // syntax tree node with its resolved data.
-        self.locals.insert(name.clone(), depth);
+    // The closing parenthesis of the call of the running native, where its
+    // errors are reported. Natives use it for the line of the call.
+    pub fn call_site(&self) -> &Token {
+        self.call_site
+            .as_ref()
+            .expect("Only natives have a call site.")
+    }
+
+    // Makes the running native fail with the error instead of the one its
+    // message would become, for a native whose error deserves a code of its
+    // own. Returns the message for the native to return:
+    //
+    //   let error = Error::runtime(interpreter.call_site(), Code::AssertionFailed, &[message]);
+    //   return Err(interpreter.fail(error));
+    pub fn fail(&mut self, error: Error) -> String {
+        let message = error.to_string();
+        self.native_error = Some(error);
+        message
+    }
+
+    // How the REPL shows a value. An instance whose class has a to_string()
+    // method taking no arguments is shown as what that returns, everything
+    // else like `print` would show it. `paren` is where errors are reported.
+    pub fn stringify(&mut self, value: &Object, paren: &Token) -> Result<String, Error> {
+        let Object::Instance(instance) = value else {
+            return Ok(value.to_string());
+        };
+        let method = instance.borrow().class.borrow().find_method("to_string");
+        match method {
+            Some(method) if method.arity().contains(&0) => {
+                let text = method.bind(value.clone()).call(self, &[], paren)?;
+                Ok(text.to_string())
+            }
+            _ => Ok(value.to_string()),
+        }
+    }
+
+    // The innermost scope of the code running right now.
+    pub(crate) fn environment(&self) -> &Rc<RefCell<Environment>> {
+        &self.environment
+    }
+
+    // Setting the limits starts counting the output again.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.usage.output_bytes.set(0);
+        self.usage.output_lines.set(0);
+    }
+
+    // An instance without any fields, counted for the resource limits.
+    pub(crate) fn new_instance(&self, class: &Rc<RefCell<LoxClass>>) -> Object {
+        let mut instance = LoxInstance::new(class);
+        instance.allocation = Some(self.usage.instances.allocate());
+        Object::Instance(Rc::new(RefCell::new(instance)))
+    }
+
+    // When we interpret a class declaration statement, we turn the syntactic
+    // representation of the class—its AST node—into its runtime
+    // representation. Now, we need to do that for the methods contained in the
+    // class as well. Each method declaration blossoms into a LoxFunction
+    // object. The methods keep a weak reference back to the class so we know
+    // which class' code is running when they are called.
+    //
+    // With a superclass, the methods close over an environment inside
+    // `environment` where "super" is bound to it.
+    pub(crate) fn new_class(
+        &self,
+        class_name: &Token,
+        superclass: Option<Rc<RefCell<LoxClass>>>,
+        methods: &[Stmt],
+        environment: &Rc<RefCell<Environment>>,
+    ) -> Rc<RefCell<LoxClass>> {
+        Rc::new_cyclic(|class| {
+            let methods = self.class_methods(methods, &superclass, environment, class);
+            RefCell::new(LoxClass::new(
+                class_name.lexeme.clone(),
+                superclass,
+                methods,
+            ))
+        })
+    }
+
+    // The methods of a class declaration as functions closing over the
+    // environment, with `super` bound when there is a superclass.
+    fn class_methods(
+        &self,
+        methods: &[Stmt],
+        superclass: &Option<Rc<RefCell<LoxClass>>>,
+        environment: &Rc<RefCell<Environment>>,
+        class: &Weak<RefCell<LoxClass>>,
+    ) -> HashMap<String, Function> {
+        let closure = match superclass {
+            Some(class) => {
+                let mut environment = Environment::from(environment);
+                environment.define("super".to_string(), Object::Class(Rc::clone(class)));
+                Rc::new(RefCell::new(environment))
+            }
+            None => Rc::clone(environment),
+        };
+
+        let mut class_methods: HashMap<String, Function> = HashMap::new();
+        for method in methods {
+            if let Stmt::Function { name, params, body } = method {
+                let function = Function::User {
+                    name: Rc::new(name.clone()),
+                    params: Rc::from(params.as_slice()),
+                    body: Rc::from(body.as_slice()),
+                    closure: Rc::clone(&closure),
+                    globals: Rc::clone(&self.globals),
+                    is_initializer: name.lexeme == "init",
+                    class: Some(Weak::clone(class)),
+                };
+                class_methods.insert(name.lexeme.clone(), function);
+            } else {
+                unreachable!()
+            }
+        }
+        class_methods
+    }
+
+    // A class declared again in the same scope with reopening allowed. The new
+    // methods go into the method table of the class the name already holds,
+    // replacing those with the same name, so instances created before see
+    // them too. The superclass stays, a declaration naming another one fails.
+    fn reopen_class(
+        &self,
+        class: &Rc<RefCell<LoxClass>>,
+        potential_superclass: &Option<Expr>,
+        superclass: Option<Rc<RefCell<LoxClass>>>,
+        methods: &[Stmt],
+    ) -> Result<(), Error> {
+        let current = class.borrow().superclass.clone();
+        if let (Some(Expr::Variable { name, .. }), Some(superclass)) =
+            (potential_superclass, &superclass)
+        {
+            let same = current
+                .as_ref()
+                .is_some_and(|current| Rc::ptr_eq(current, superclass));
+            if !same {
+                let class_name = class.borrow().name.clone();
+                return Err(Error::runtime(name, Code::ReopenSuperclass, &[&class_name]));
+            }
+        }
+
+        let environment = Rc::clone(&self.environment);
+        let methods = self.class_methods(methods, &current, &environment, &Rc::downgrade(class));
+        class.borrow_mut().add_methods(methods);
+        Ok(())
+    }
+
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.interrupted)
+    }
+
+    // The token the host trips to stop the programs this interpreter runs.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    // Replaces a tripped token so programs can run again.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation = token;
+    }
+
+    // Fails with a runtime error at `token` if the program was interrupted,
+    // or with Error::Cancelled if the host cancelled it. The interrupt flag
+    // is cleared so the next program can run. Called on every loop iteration
+    // and call, so the flags are only loaded until one is set.
+    pub(crate) fn check_interrupt(&self, token: &Token) -> Result<(), Error> {
+        if self.cancellation.is_cancelled() {
+            return Err(Error::Cancelled {
+                token: token.clone(),
+            });
+        }
+        if self.interrupted.load(Ordering::Relaxed)
+            && self.interrupted.swap(false, Ordering::Relaxed)
+        {
+            return Err(Error::runtime(token, Code::Interrupted, &[]));
+        }
+        Ok(())
+    }
+
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), Error> {
+        self.run_program(statements, false).map(|_| ())
+    }
+
+    // Like interpret but returns the value of the last statement when it is an
+    // expression statement, and nil otherwise.
+    pub fn interpret_value(&mut self, statements: &[Stmt]) -> Result<Object, Error> {
+        self.run_program(statements, true)
+    }
+
+    // An empty environment on top of the globals, for run_with_env. The host
+    // fills it with the variables of a sandbox using Environment::define.
+    pub fn new_environment(&self) -> Rc<RefCell<Environment>> {
+        Rc::new(RefCell::new(Environment::named(&self.globals)))
+    }
+
+    // Like interpret_value with the environment taking the place of the
+    // globals. The statements see its variables, and those of the
+    // environments it is enclosed in, and declare theirs in it. The
+    // functions and classes they declare keep using it when they are called
+    // later. This way scripts sharing one interpreter and its natives can each
+    // have variables of their own, if their environments enclose the globals
+    // and not each other.
+    pub fn run_with_env(
+        &mut self,
+        statements: &[Stmt],
+        environment: &Rc<RefCell<Environment>>,
+    ) -> Result<Object, Error> {
+        let globals = std::mem::replace(&mut self.globals, Rc::clone(environment));
+        let enclosing = std::mem::replace(&mut self.environment, Rc::clone(environment));
+        let result = self.run_program(statements, true);
+        self.environment = enclosing;
+        self.globals = globals;
+        result
+    }
+
+    fn run_program(&mut self, statements: &[Stmt], with_value: bool) -> Result<Object, Error> {
+        let result = self.hoist(statements).and_then(|hoisted| {
+            let mut value = Object::Null;
+            for (index, statement) in statements.iter().enumerate() {
+                value = match statement {
+                    _ if hoisted[index] => Object::Null,
+                    Stmt::Expression { expression } if with_value => {
+                        self.record(|| Event::statement(statement));
+                        self.evaluate(expression)?
+                    }
+                    _ => {
+                        self.execute(statement)?;
+                        Object::Null
+                    }
+                };
+            }
+            Ok(value)
+        });
+        self.finish_frames(&result);
+        result
+    }
+
+    // With hoisting on, the function and class declarations of the top level
+    // run before the other statements, in the order they are written, so a
+    // program can call a function or create an instance of a class declared
+    // further down. A class is only hoisted when its superclass is there by
+    // then: a class hoisted before it or a global class the program doesn't
+    // declare again. Returns which statements ran.
+    fn hoist(&mut self, statements: &[Stmt]) -> Result<Vec<bool>, Error> {
+        let mut hoisted = vec![false; statements.len()];
+        if !self.hoist {
+            return Ok(hoisted);
+        }
+
+        let declared: HashSet<&str> = statements
+            .iter()
+            .filter_map(|statement| match statement {
+                Stmt::Var { name, .. } | Stmt::Function { name, .. } => Some(&*name.lexeme),
+                _ => None,
+            })
+            .collect();
+        let mut classes: HashSet<&str> = HashSet::new();
+        for (index, statement) in statements.iter().enumerate() {
+            let hoist = match statement {
+                Stmt::Function { .. }
+                | Stmt::Class {
+                    superclass: None, ..
+                } => true,
+                Stmt::Class {
+                    superclass: Some(Expr::Variable { name, .. }),
+                    ..
+                } => {
+                    let global = self.globals.borrow().get_own(&name.lexeme);
+                    classes.contains(&*name.lexeme)
+                        || (!declared.contains(&*name.lexeme)
+                            && matches!(global, Some(Object::Class(_))))
+                }
+                _ => false,
+            };
+            if hoist {
+                self.execute(statement)?;
+                hoisted[index] = true;
+                if let Stmt::Class { name, .. } = statement {
+                    classes.insert(&name.lexeme);
+                }
+            }
+        }
+        Ok(hoisted)
+    }
+
+    // The frames of the last runtime error that reached the top level,
+    // innermost first. Empty if there was none.
+    pub fn error_frames(&self) -> &[Frame] {
+        self.frames.frames()
+    }
+
+    // Evaluates the expression as if it were written where the environment
+    // is, to inspect a frame of a failed or paused program. The expression
+    // must have been resolved against the environment with
+    // Resolver::resolve_in. Private members are accessible when the frame is
+    // a method. An error here doesn't replace the frames of the last one.
+    pub fn eval_in_env(
+        &mut self,
+        expr: &Expr,
+        environment: &Rc<RefCell<Environment>>,
+    ) -> Result<Object, Error> {
+        let frames = self.frames.take();
+        let class = match Environment::lookup(environment, "this") {
+            Some(Object::Instance(instance)) => Some(Rc::clone(&instance.borrow().class)),
+            _ => None,
+        };
+        let enclosing_class = std::mem::replace(&mut self.class_context, class);
+        let result = self.evaluate_in(expr, Rc::clone(environment));
+        self.class_context = enclosing_class;
+        self.frames = frames;
+        result
+    }
+
+    fn finish_frames<T>(&mut self, result: &Result<T, Error>) {
+        if let Err(
+            Error::Runtime { token, .. } | Error::Throw { token, .. } | Error::Cancelled { token },
+        ) = result
+        {
+            self.frames.finish(&self.globals, token.line);
+        }
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), Error> {
+        self.record(|| Event::statement(stmt));
+        stmt.accept(self)
     }
 
     /*
@@ -81,9 +822,10 @@ impl Interpreter {
      */
     pub fn execute_block(
         &mut self,
-        statements: &Vec<Stmt>,
+        statements: &[Stmt],
         environment: Rc<RefCell<Environment>>,
     ) -> Result<(), Error> {
+        self.limits.check(&self.usage)?;
         let previous = self.environment.clone();
 
         self.environment = environment;
@@ -92,65 +834,310 @@ impl Interpreter {
             .iter()
             .try_for_each(|statement| self.execute(statement));
 
+        if let Err(
+            Error::Runtime { token, .. } | Error::Throw { token, .. } | Error::Cancelled { token },
+        ) = &result
+        {
+            self.frames.leave_block(&self.environment, token.line);
+        }
         self.environment = previous;
 
         result
     }
 
+    // A call is starting, the statements it defers are kept apart from the
+    // ones of its caller.
+    pub(crate) fn begin_deferred(&mut self) {
+        self.deferred.push(Vec::new());
+    }
+
+    // Runs the statements the call deferred, the last one first, now that its
+    // body is done with the result. They run whether the body returned or
+    // failed with an error `catch` could see. An error in one of them replaces
+    // the result, like an error in the `finally` block of other languages,
+    // and the others still run. An interrupted or cancelled call, or one over
+    // a resource limit, runs none: the program is being stopped.
+    //
+    // The call's list stays in place until they are done, so a statement one
+    // of them defers is the call's too and runs right after it.
+    pub(crate) fn run_deferred(&mut self, mut result: Result<(), Error>) -> Result<(), Error> {
+        while let Some((statement, environment)) = self.deferred.last_mut().and_then(Vec::pop) {
+            if matches!(
+                result,
+                Err(Error::Cancelled { .. }
+                    | Error::ResourceLimit { .. }
+                    | Error::Runtime {
+                        code: Code::Interrupted,
+                        ..
+                    })
+            ) {
+                break;
+            }
+            if let Err(error) = self.execute_block(std::slice::from_ref(&statement), environment) {
+                result = Err(error);
+            }
+        }
+        self.deferred.pop();
+        result
+    }
+
     // simply call interpreters visitor implementation
     fn evaluate(&mut self, expr: &Expr) -> Result<Object, Error> {
         expr.accept(self)
     }
 
-    fn stringify(&self, object: Object) -> String {
-        match object {
-            Object::Null => "nil".to_string(),
-            Object::Number(n) => n.to_string(),
-            Object::Boolean(b) => b.to_string(),
-            Object::Class(class) => class.borrow().name.clone(),
-            Object::Instance(instance) => {
-                format!("{} instance", instance.borrow().class.borrow().name)
+    // Like execute_block, for an expression.
+    fn evaluate_in(
+        &mut self,
+        expr: &Expr,
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<Object, Error> {
+        self.limits.check(&self.usage)?;
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let result = self.evaluate(expr);
+        self.environment = previous;
+        result
+    }
+
+    // Whether the value matches the pattern, collecting the values of the
+    // names it binds. Only a class pattern whose class isn't one fails.
+    fn match_pattern(
+        &mut self,
+        pattern: &Pattern,
+        value: &Object,
+        bindings: &mut Vec<(String, Object)>,
+    ) -> Result<bool, Error> {
+        match pattern {
+            Pattern::Wildcard => Ok(true),
+            Pattern::Literal(literal) => {
+                let literal = expr::Visitor::<Object>::visit_literal_expr(self, literal)?;
+                Ok(value.equals(&literal))
+            }
+            Pattern::Binding(name) => {
+                bindings.push((name.lexeme.clone(), value.clone()));
+                Ok(true)
+            }
+            Pattern::Instance { class, fields } => {
+                let Object::Class(class) = self.evaluate(class)? else {
+                    let Expr::Variable { name, .. } = class else {
+                        unreachable!("The parser only makes variables classes of patterns.");
+                    };
+                    return Err(Error::runtime(name, Code::PatternNotClass, &[&name.lexeme]));
+                };
+                let Object::Instance(instance) = value else {
+                    return Ok(false);
+                };
+                if !inherits(&instance.borrow().class, &class) {
+                    return Ok(false);
+                }
+                for (name, pattern) in fields {
+                    if !instance.borrow().fields().contains_key(&name.lexeme) {
+                        return Ok(false);
+                    }
+                    let field = instance
+                        .borrow()
+                        .get(name, value, self.class_context.as_ref())?;
+                    if !self.match_pattern(pattern, &field, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Pattern::List { elements, .. } => {
+                let Ok(items) = list::items(value) else {
+                    return Ok(false);
+                };
+                if items.len() != elements.len() {
+                    return Ok(false);
+                }
+                for (element, item) in elements.iter().zip(&items) {
+                    if !self.match_pattern(element, item, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    // The result of the first of the host's rules for the operator that
+    // handles the operands. Rules run like natives called at the operator.
+    fn apply_operator_rules(
+        &mut self,
+        operator: &Token,
+        left: &Object,
+        right: &Object,
+    ) -> Result<Option<Object>, Error> {
+        let Some(kind) = Operator::from_token(&operator.token_type) else {
+            return Ok(None);
+        };
+        for rule in self.operators.rules(kind) {
+            let call_site = self.call_site.replace(operator.clone());
+            let result = rule(self, left, right);
+            self.call_site = call_site;
+            let callback_error = self.native_error.take();
+            let result = result.map_err(|message| {
+                callback_error
+                    .unwrap_or_else(|| Error::runtime(operator, Code::Native, &[&message]))
+            })?;
+            if let Some(result) = result {
+                return Ok(Some(match operator.token_type {
+                    TokenType::EqualEqual => Object::Boolean(self.is_truthy(&result)),
+                    TokenType::BangEqual => Object::Boolean(!self.is_truthy(&result)),
+                    _ => result,
+                }));
             }
-            Object::String(s) => s,
-            Object::Callable(f) => f.to_string(),
         }
+        Ok(None)
     }
 
-    // used like checkNumberOperands in the book
+    // used like checkNumberOperand in the book
     fn number_operand_error<R>(&self, operator: &Token) -> Result<R, Error> {
+        Err(Error::runtime(operator, Code::NumberOperand, &[]))
+    }
+
+    // used like checkNumberOperands in the book, which has a message of its
+    // own for two operands
+    fn number_operands_error<R>(&self, operator: &Token) -> Result<R, Error> {
+        let mut error = Error::runtime(operator, Code::NumberOperand, &[]);
+        if let (true, Error::Runtime { message, .. }) = (self.jlox, &mut error) {
+            *message = "Operands must be numbers.".to_string();
+        }
+        Err(error)
+    }
+
+    // A call with the wrong number of arguments, worded like jlox does for
+    // functions and classes alike.
+    fn check_jlox_arity(
+        &self,
+        arity: RangeInclusive<usize>,
+        count: usize,
+        paren: &Token,
+    ) -> Result<(), Error> {
+        if arity.contains(&count) {
+            return Ok(());
+        }
         Err(Error::Runtime {
-            token: operator.clone(),
-            message: "Operand must be a number".to_string(),
+            token: paren.clone(),
+            code: Code::Arity,
+            message: format!("Expected {} arguments but got {}.", arity.start(), count),
         })
     }
 
+    // In Lox, only instances of classes have properties. Foreign values of
+    // the host have the methods registered for their type.
+    fn get_property(&self, object: Object, name: &Token) -> Result<Object, Error> {
+        match object {
+            Object::Instance(ref instance) => {
+                instance
+                    .borrow()
+                    .get(name, &object, self.class_context.as_ref())
+            }
+            Object::Foreign(ref foreign) => {
+                let method = self
+                    .foreign_types
+                    .get(&foreign.type_id())
+                    .and_then(|foreign_type| foreign_type.methods.get(&name.lexeme));
+                match method {
+                    Some(method) => Ok(Object::Callable(method.bind(object.clone()))),
+                    None => Err(Error::runtime(
+                        name,
+                        Code::UndefinedMethod,
+                        &[&name.lexeme, &foreign.type_name],
+                    )),
+                }
+            }
+            _ => Err(Error::runtime(name, Code::NotInstanceGet, &[])),
+        }
+    }
+
+    // The method_missing method of the instance's class bound to it, if the
+    // class has one.
+    fn method_missing(&self, object: &Object) -> Option<Function> {
+        let Object::Instance(instance) = object else {
+            return None;
+        };
+        let method = instance
+            .borrow()
+            .class
+            .borrow()
+            .find_method("method_missing");
+        method.map(|method| method.bind(object.clone()))
+    }
+
+    // Calls method_missing with the name of the missing method and a List of
+    // the arguments it was called with.
+    fn call_method_missing(
+        &mut self,
+        handler: Function,
+        name: &Token,
+        arguments: &[Expr],
+        paren: &Token,
+    ) -> Result<Object, Error> {
+        let args: Vec<Object> = arguments
+            .iter()
+            .map(|expr| self.evaluate(expr))
+            .collect::<Result<_, _>>()?;
+        let args = [
+            Object::String(Rc::from(name.lexeme.as_str())),
+            list::list(self, args),
+        ];
+        self.call_value(Object::Callable(handler), &args, paren)
+    }
+
     fn is_truthy(&self, right: &Object) -> bool {
         match right {
             Object::Null => false,
-            Object::Boolean(b) => b.clone(),
+            Object::Boolean(b) => *b,
             _ => true,
         }
     }
 
     fn is_equal(&self, left: &Object, right: &Object) -> bool {
-        left.equals(right)
+        match (left, right) {
+            // Double.equals in jlox, for which NaN equals itself and -0
+            // doesn't equal 0.
+            (Object::Number(left), Object::Number(right)) if self.jlox => {
+                left.to_bits() == right.to_bits() || (left.is_nan() && right.is_nan())
+            }
+            _ => left.equals(right),
+        }
     }
 
-    // First, we look up the resolved distance in the map. Remember that we
-    // resolved only local variables. Globals are treated specially and don't
-    // end up in the map. So, if we don't find a distance in the map, it must be
-    // global. In that case, we look it up dynamically, directly in the global
-    // env. That throws a runtime error if the variable isn't defined.
-
-    // If we do get a distance, we have a local variable, and we get to take
-    // advantage of the results of our static analysis. Instead of calling
-    // get(), we call this new method on Environment.
-    fn look_up_variable(&self, name: &Token) -> Result<Object, Error> {
-        if let Some(distance) = self.locals.get(name) {
-            self.environment.borrow().get_at(*distance, &name.lexeme)
-        } else {
-            self.globals.borrow().get(name)
+    // In debug builds, checks that the distance and slot the resolver gave
+    // the node lead to the name before get_at or assign_at rely on them. When
+    // they don't, the resolver is wrong: the node is logged with where it is,
+    // and the program fails with an undefined variable instead of a panic
+    // deep in the environments or reading another variable.
+    fn check_resolution(
+        &self,
+        node: &Token,
+        name: &str,
+        distance: usize,
+        slot: usize,
+    ) -> Result<(), Error> {
+        if !cfg!(debug_assertions) || self.environment.borrow().defines_at(distance, slot, name) {
+            return Ok(());
         }
+        Err(self.unresolved(node, name, Some((distance, slot))))
+    }
+
+    fn unresolved(&self, node: &Token, name: &str, place: Option<(usize, usize)>) -> Error {
+        error!(
+            node = %node.lexeme,
+            name,
+            line = node.line,
+            column = node.column,
+            ?place,
+            "resolver invariant broken: the variable isn't where it was resolved to"
+        );
+        Error::runtime(node, Code::UndefinedVariable, &[name])
+    }
+
+    // Where the variable of a node is, as the resolver left it on the node, see
+    // Resolution. A node the resolver never saw is global.
+    fn depth(resolution: &Resolution) -> Depth {
+        resolution.get().unwrap_or(Depth::Global)
     }
 }
 
@@ -158,9 +1145,9 @@ impl expr::Visitor<Object> for Interpreter {
     fn visit_literal_expr(&self, value: &LiteralValue) -> Result<Object, Error> {
         // they implement copy
         match value {
-            LiteralValue::Boolean(b) => Ok(Object::Boolean(b.clone())),
+            LiteralValue::Boolean(b) => Ok(Object::Boolean(*b)),
             LiteralValue::Null => Ok(Object::Null),
-            LiteralValue::Number(n) => Ok(Object::Number(n.clone())),
+            LiteralValue::Number(n) => Ok(Object::Number(*n)),
             LiteralValue::String(s) => Ok(Object::String(s.clone())),
         }
     }
@@ -186,78 +1173,89 @@ impl expr::Visitor<Object> for Interpreter {
         &mut self,
         callee: &Expr,
         paren: &Token,
-        arguments: &Vec<Expr>,
+        arguments: &[Expr],
+        optional: bool,
     ) -> Result<Object, Error> {
-        let callee_value = self.evaluate(callee)?;
-
-        let argument_values: Result<Vec<Object>, Error> = arguments
-            .into_iter()
-            .map(|expr| self.evaluate(expr))
-            .collect();
-        let args = argument_values?;
-
-        match callee_value {
-            Object::Callable(function) => {
-                let args_size = args.len();
-                if args_size != function.arity() {
-                    Err(Error::Runtime {
-                        token: paren.clone(),
-                        message: format!(
-                            "Expected {} arguments but got {}.",
-                            function.arity(),
-                            args_size
-                        ),
-                    })
-                } else {
-                    function.call(self, &args)
+        let callee_value = match callee {
+            // For `obj?.method()` the object is checked here so a nil object
+            // skips the call and its arguments, while a property that exists
+            // but holds nil still fails to be called.
+            //
+            // Calling a property the instance doesn't have goes to the
+            // method_missing(name, arguments) method of its class, if there is
+            // one. Only calls do, reading the property is still an error.
+            Expr::Get {
+                object,
+                name,
+                optional: optional_get,
+            } => {
+                let object = self.evaluate(object)?;
+                match object {
+                    Object::Null if optional => return Ok(Object::Null),
+                    Object::Null if *optional_get => Object::Null,
+                    object => match self.get_property(object.clone(), name) {
+                        Err(
+                            error @ Error::Runtime {
+                                code: Code::UndefinedProperty,
+                                ..
+                            },
+                        ) => match self.method_missing(&object) {
+                            Some(handler) => {
+                                return self.call_method_missing(handler, name, arguments, paren)
+                            }
+                            None => return Err(error),
+                        },
+                        property => property?,
+                    },
                 }
             }
-            Object::Class(ref class) => {
-                // This is the call method of a class.
-                let args_size = args.len();
-                let instance = LoxInstance::new(class);
-                if let Some(initializer) = class.borrow().find_method("init") {
-                    if args_size != initializer.arity() {
-                        return Err(Error::Runtime {
-                            token: paren.clone(),
-                            message: format!(
-                                "Expected {} arguments but got {}.",
-                                initializer.arity(),
-                                args_size
-                            ),
-                        });
-                    } else {
-                        initializer.bind(instance.clone()).call(self, &args)?;
-                    }
-                }
+            _ => self.evaluate(callee)?,
+        };
 
-                Ok(instance)
-            }
-            _ => Err(Error::Runtime {
-                token: paren.clone(),
-                message: "Can only call functions and classes.".to_string(),
-            }),
+        let argument_values: Result<Vec<Object>, Error> =
+            arguments.iter().map(|expr| self.evaluate(expr)).collect();
+        let args = argument_values?;
+
+        // A wrong number of arguments for a superclass method, `super.init`
+        // chaining to the parent initializer usually, is reported at `super`
+        // rather than at the end of a call that may span several lines.
+        if let (Expr::Super { keyword, .. }, Object::Callable(function), false) =
+            (callee, &callee_value, self.jlox)
+        {
+            function.check_arity(args.len(), keyword)?;
         }
+
+        self.call_value(callee_value, &args, paren)
     }
 
     // First, we evaluate the expression whose property is being accessed. In
     // Lox, only instances of classes have properties. If the object is some
     // other type like a number, invoking a getter on it is a runtime error.
-    fn visit_get_expr(&mut self, object: &Expr, name: &Token) -> Result<Object, Error> {
+    // The exception is nil when the access is written `?.`.
+    fn visit_get_expr(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        optional: bool,
+    ) -> Result<Object, Error> {
         let object = self.evaluate(object)?;
-        if let Object::Instance(ref instance) = object {
-            instance.borrow().get(name, &object)
-        } else {
-            Err(Error::Runtime {
-                token: name.clone(),
-                message: "Only instances have properties.".to_string(),
-            })
+        if optional {
+            if let Object::Null = object {
+                return Ok(Object::Null);
+            }
         }
+        self.get_property(object, name)
     }
 
     // We evaluate the object whose property is being set and check to see if
     // it’s a LoxInstance. If not, that’s a runtime error. Otherwise, we
-    // evaluate the value being set and store it on the instance.
+    // evaluate the value being set and store it on the instance. Like any
+    // assignment, the expression produces the assigned value, which is what
+    // makes `a.x = b.y = 3` work.
+    //
+    // The object is evaluated before the value, so in `f().x = g()` f runs
+    // first. A plain variable target has nothing to evaluate, it is only
+    // looked up once the value is known.
     fn visit_set_expr(
         &mut self,
         object: &Expr,
@@ -267,23 +1265,32 @@ impl expr::Visitor<Object> for Interpreter {
         let object = self.evaluate(object)?;
         if let Object::Instance(ref instance) = object {
             let value = self.evaluate(value)?;
-            instance.borrow_mut().set(property_name, value);
-            let r = Object::Instance(Rc::clone(instance));
-            Ok(r)
+            instance
+                .borrow_mut()
+                .set(property_name, value.clone(), self.class_context.as_ref())?;
+            self.record(|| Event::Field {
+                line: property_name.line,
+                object: object.to_string(),
+                name: property_name.lexeme.clone(),
+                value: inspect(&value),
+            });
+            Ok(value)
         } else {
-            Err(Error::Runtime {
-                token: property_name.clone(),
-                message: "Only instances have fields.".to_string(),
-            })
+            Err(Error::runtime(property_name, Code::NotInstanceSet, &[]))
         }
     }
 
-    fn visit_super_expr(&mut self, keyword: &Token, method: &Token) -> Result<Object, Error> {
-        let distance = self
-            .locals
-            .get(keyword)
-            .expect("No local distance for 'super'");
-        let superclass = self.environment.borrow().get_at(*distance, "super")?;
+    fn visit_super_expr(
+        &mut self,
+        keyword: &Token,
+        method: &Token,
+        resolution: &Resolution,
+    ) -> Result<Object, Error> {
+        let Depth::Local { distance, slot } = Self::depth(resolution) else {
+            return Err(self.unresolved(keyword, "super", None));
+        };
+        self.check_resolution(keyword, "super", distance, slot)?;
+        let superclass = self.environment.borrow().get_at(distance, slot);
 
         //When we access a method, we also need to bind this to the object the
         //method is accessed from. In an expression like doughnut.cook, the
@@ -298,24 +1305,31 @@ impl expr::Visitor<Object> for Interpreter {
         // on. Fortunately, we do control the layout of the environment chains.
         // The environment where “this” is bound is always right inside the
         // environment where we store “super”.
-        let instance = self.environment.borrow().get_at(*distance - 1, "this")?;
+        // Both are the only variable of their environment.
+        self.check_resolution(keyword, "this", distance - 1, 0)?;
+        let instance = self.environment.borrow().get_at(distance - 1, 0);
 
         if let Object::Class(ref superclass) = superclass {
-            if let Some(method) = superclass.borrow().find_method(&method.lexeme) {
-                Ok(Object::Callable(method.bind(instance)))
+            if let Some(function) = superclass.borrow().find_method(&method.lexeme) {
+                Ok(Object::Callable(function.bind(instance)))
             } else {
-                Err(Error::Runtime {
-                    token: method.clone(),
-                    message: format!("Undefined property '{}'.", method.lexeme),
-                })
+                Err(Error::runtime(
+                    method,
+                    Code::UndefinedProperty,
+                    &[&method.lexeme],
+                ))
             }
         } else {
             unreachable!()
         }
     }
 
-    fn visit_this_expr(&mut self, keyword: &Token) -> Result<Object, Error> {
-        self.look_up_variable(keyword)
+    fn visit_this_expr(
+        &mut self,
+        keyword: &Token,
+        resolution: &Resolution,
+    ) -> Result<Object, Error> {
+        self.visit_variable_expr(keyword, resolution)
     }
 
     fn visit_binary_expr(
@@ -326,61 +1340,73 @@ impl expr::Visitor<Object> for Interpreter {
     ) -> Result<Object, Error> {
         let l = self.evaluate(left)?;
         let r = self.evaluate(right)?;
+        if !self.operators.is_empty() {
+            if let Some(result) = self.apply_operator_rules(operator, &l, &r)? {
+                return Ok(result);
+            }
+        }
 
         match operator.token_type {
             TokenType::Minus => match (l, r) {
                 (Object::Number(left_num), Object::Number(right_num)) => {
-                    Ok(Object::Number(left_num - right_num))
+                    self.arithmetic(operator, left_num, right_num, left_num - right_num)
                 }
-                _ => self.number_operand_error(operator),
+                _ => self.number_operands_error(operator),
             },
             TokenType::Slash => match (l, r) {
                 (Object::Number(left_num), Object::Number(right_num)) => {
                     Ok(Object::Number(left_num / right_num))
                 }
-                _ => self.number_operand_error(operator),
+                _ => self.number_operands_error(operator),
             },
             TokenType::Star => match (l, r) {
                 (Object::Number(left_num), Object::Number(right_num)) => {
-                    Ok(Object::Number(left_num * right_num))
+                    self.arithmetic(operator, left_num, right_num, left_num * right_num)
                 }
-                _ => self.number_operand_error(operator),
+                _ => self.number_operands_error(operator),
+            },
+            TokenType::StarStar => match (l, r) {
+                (Object::Number(left_num), Object::Number(right_num)) => {
+                    self.arithmetic(operator, left_num, right_num, left_num.powf(right_num))
+                }
+                _ => self.number_operands_error(operator),
             },
             TokenType::Plus => match (l, r) {
                 (Object::Number(left_num), Object::Number(right_num)) => {
-                    Ok(Object::Number(left_num + right_num))
+                    self.arithmetic(operator, left_num, right_num, left_num + right_num)
                 }
                 (Object::String(left_str), Object::String(right_str)) => {
-                    Ok(Object::String(left_str.clone() + &right_str))
+                    self.limits.check_string(left_str.len() + right_str.len())?;
+                    Ok(Object::String(Rc::from(format!(
+                        "{}{}",
+                        left_str, right_str
+                    ))))
                 }
-                _ => Err(Error::Runtime {
-                    token: operator.clone(),
-                    message: "Operands must be two numbers or two strings".to_string(),
-                }),
+                _ => Err(Error::runtime(operator, Code::AddOperands, &[])),
             },
             TokenType::GreaterEqual => match (l, r) {
                 (Object::Number(left_num), Object::Number(right_num)) => {
                     Ok(Object::Boolean(left_num >= right_num))
                 }
-                _ => self.number_operand_error(operator),
+                _ => self.number_operands_error(operator),
             },
             TokenType::Greater => match (l, r) {
                 (Object::Number(left_num), Object::Number(right_num)) => {
                     Ok(Object::Boolean(left_num > right_num))
                 }
-                _ => self.number_operand_error(operator),
+                _ => self.number_operands_error(operator),
             },
             TokenType::LessEqual => match (l, r) {
                 (Object::Number(left_num), Object::Number(right_num)) => {
                     Ok(Object::Boolean(left_num <= right_num))
                 }
-                _ => self.number_operand_error(operator),
+                _ => self.number_operands_error(operator),
             },
             TokenType::Less => match (l, r) {
                 (Object::Number(left_num), Object::Number(right_num)) => {
                     Ok(Object::Boolean(left_num < right_num))
                 }
-                _ => self.number_operand_error(operator),
+                _ => self.number_operands_error(operator),
             },
             TokenType::BangEqual => Ok(Object::Boolean(!self.is_equal(&l, &r))),
             TokenType::EqualEqual => Ok(Object::Boolean(self.is_equal(&l, &r))),
@@ -414,22 +1440,91 @@ impl expr::Visitor<Object> for Interpreter {
         self.evaluate(right)
     }
 
-    fn visit_variable_expr(&mut self, name: &Token) -> Result<Object, Error> {
-        self.look_up_variable(name)
+    fn visit_variable_expr(
+        &mut self,
+        name: &Token,
+        resolution: &Resolution,
+    ) -> Result<Object, Error> {
+        match Self::depth(resolution) {
+            Depth::Local { distance, slot } => {
+                self.check_resolution(name, &name.lexeme, distance, slot)?;
+                Ok(self.environment.borrow().get_at(distance, slot))
+            }
+            Depth::Global => self.globals.borrow().get(name),
+        }
     }
 
-    fn visit_assign_expr(&mut self, name: &Token, value: &Expr) -> Result<Object, Error> {
+    fn visit_assign_expr(
+        &mut self,
+        name: &Token,
+        value: &Expr,
+        resolution: &Resolution,
+    ) -> Result<Object, Error> {
         let v = self.evaluate(value)?;
-        if let Some(distance) = self.locals.get(name) {
-            self.environment
-                .borrow_mut()
-                .assign_at(*distance, name, v.clone())?;
-        } else {
-            // TODO: globals or environment?
-            self.globals.borrow_mut().assign(name, v.clone())?;
+        match Self::depth(resolution) {
+            Depth::Local { distance, slot } => {
+                self.check_resolution(name, &name.lexeme, distance, slot)?;
+                self.environment
+                    .borrow_mut()
+                    .assign_at(distance, slot, v.clone());
+            }
+            Depth::Global => {
+                // TODO: globals or environment?
+                self.globals.borrow_mut().assign(name, v.clone())?;
+            }
         }
+        self.record(|| Event::write(name.line, &name.lexeme, &v));
         Ok(v)
     }
+
+    // The arms are tried in order. The body of the first that matches runs in
+    // a new environment holding the names its pattern bound.
+    fn visit_match_expr(
+        &mut self,
+        keyword: &Token,
+        subject: &Expr,
+        arms: &[MatchArm],
+    ) -> Result<Object, Error> {
+        let value = self.evaluate(subject)?;
+        for arm in arms {
+            let mut bindings = Vec::new();
+            if self.match_pattern(&arm.pattern, &value, &mut bindings)? {
+                let mut environment = Environment::from(&self.environment);
+                for (name, value) in bindings {
+                    environment.define(name, value);
+                }
+                return self.evaluate_in(&arm.body, Rc::new(RefCell::new(environment)));
+            }
+        }
+        Err(Error::runtime(keyword, Code::NoMatch, &[&inspect(&value)]))
+    }
+
+    // The default is only evaluated when the body throws or fails with a
+    // runtime error, the errors a try statement catches. Interrupts and
+    // exceeded limits go on unwinding. The body may fail deep inside calls,
+    // so the environment it started in is put back before the default runs.
+    fn visit_try_expr(
+        &mut self,
+        _keyword: &Token,
+        body: &Expr,
+        default: &Expr,
+    ) -> Result<Object, Error> {
+        let environment = Rc::clone(&self.environment);
+        // A caught error leaves the frames of the last uncaught one alone.
+        let frames = self.frames.take();
+        match self.evaluate(body) {
+            Err(Error::Throw { .. }) => (),
+            Err(Error::Runtime { code, .. }) if code != Code::Interrupted => (),
+            Ok(value) => {
+                self.frames = frames;
+                return Ok(value);
+            }
+            other => return other,
+        }
+        self.frames = frames;
+        self.environment = environment;
+        self.evaluate(default)
+    }
 }
 
 impl stmt::Visitor<()> for Interpreter {
@@ -447,78 +1542,33 @@ impl stmt::Visitor<()> for Interpreter {
         &mut self,
         class_name: &Token,
         potential_superclass: &Option<Expr>,
-        methods: &Vec<Stmt>,
+        methods: &[Stmt],
     ) -> Result<(), Error> {
-        let superclass: Option<Rc<RefCell<LoxClass>>> = potential_superclass
-            .as_ref()
-            .map(|expr| {
-                // evaluate expression and check if the result is a class object
-                // if so then it extracts the Rc<RefCell<LoxClass>> (lox_class) and clones it
-                if let Object::Class(ref lox_class) = self.evaluate(expr)? {
-                    Ok(Rc::clone(lox_class))
-                // if the expression is a variable but evaluate did not return a class, this is a runtime error
-                } else if let Expr::Variable { name } = expr {
-                    Err(Error::Runtime {
-                        token: name.clone(),
-                        message: "Superclass must be a class.".to_string(),
-                    })
-                // if the parser is correct this should never happen
-                } else {
-                    unreachable!()
-                }
-            })
-            // we need Result<Option<...>>
-            .transpose()?;
-
-        self.environment
-            .borrow_mut()
-            .define(class_name.lexeme.clone(), Object::Null);
-
-        if let Some(ref class) = superclass {
-            self.environment = Rc::new(RefCell::new(Environment::from(&self.environment)));
-            self.environment
-                .borrow_mut()
-                .define("super".to_string(), Object::Class(Rc::clone(class)));
-        }
+        // The superclass is evaluated like any variable access, so it can be
+        // anything at runtime. Only a class can be inherited from, and the
+        // error points at the superclass name.
+        let superclass: Option<Rc<RefCell<LoxClass>>> = match potential_superclass {
+            Some(expr @ Expr::Variable { name, .. }) => match self.evaluate(expr)? {
+                Object::Class(lox_class) => Some(lox_class),
+                _ => return Err(Error::runtime(name, Code::SuperclassNotClass, &[])),
+            },
+            Some(_) => unreachable!("The parser only accepts a name as superclass."),
+            None => None,
+        };
 
-        // When we interpret a class declaration statement, we turn the
-        // syntactic representation of the class—its AST node—into its runtime
-        // representation. Now, we need to do that for the methods contained in
-        // the class as well. Each method declaration blossoms into a
-        // LoxFunction object.
-        let mut class_methods: HashMap<String, Function> = HashMap::new();
-        for method in methods {
-            if let Stmt::Function { name, params, body } = method {
-                let function = Function::User {
-                    name: name.clone(),
-                    params: params.clone(),
-                    body: body.clone(),
-                    closure: Rc::clone(&self.environment),
-                    is_initializer: name.lexeme == "init",
-                };
-                class_methods.insert(name.lexeme.clone(), function);
-            } else {
-                unreachable!()
+        if self.allow_reopen {
+            let existing = self.environment.borrow().get_own(&class_name.lexeme);
+            if let Some(Object::Class(class)) = existing {
+                return self.reopen_class(&class, potential_superclass, superclass, methods);
             }
         }
 
-        let lox_class = LoxClass {
-            name: class_name.lexeme.clone(),
-            superclass: superclass.clone(),
-            methods: class_methods,
-        };
-        let class = Object::Class(Rc::new(RefCell::new(lox_class)));
-
-        if superclass.is_some() {
-            let parent = self
-                .environment
-                .borrow()
-                .enclosing
-                .clone()
-                .expect("Superclass environment has no parent.");
-            self.environment = parent;
-        }
+        self.environment
+            .borrow_mut()
+            .define(class_name.lexeme.clone(), Object::Null);
 
+        let class =
+            Object::Class(self.new_class(class_name, superclass, methods, &self.environment));
         self.environment.borrow_mut().assign(class_name, class)?;
         Ok(())
     }
@@ -528,15 +1578,19 @@ impl stmt::Visitor<()> for Interpreter {
     fn visit_function_stmt(
         &mut self,
         name: &Token,
-        params: &Vec<Token>,
-        body: &Vec<Stmt>,
+        params: &[Token],
+        body: &[Stmt],
     ) -> Result<(), Error> {
         let function = Function::User {
-            name: name.clone(),
-            params: params.clone(),
-            body: body.clone(),
+            name: Rc::new(name.clone()),
+            params: Rc::from(params),
+            body: Rc::from(body),
             closure: Rc::clone(&self.environment),
+            globals: Rc::clone(&self.globals),
             is_initializer: false,
+            // Functions declared inside a method share its access to private
+            // members.
+            class: self.class_context.as_ref().map(Rc::downgrade),
         };
         self.environment
             .borrow_mut()
@@ -572,9 +1626,29 @@ impl stmt::Visitor<()> for Interpreter {
         Ok(())
     }
 
-    fn visit_while_stmt(&mut self, condition: &Expr, body: &Stmt) -> Result<(), Error> {
+    fn visit_while_stmt(
+        &mut self,
+        keyword: &Token,
+        condition: &Expr,
+        body: &Stmt,
+    ) -> Result<(), Error> {
+        // A literal condition, like the `true` the parser puts in for the
+        // missing condition of `for (;;)`, is the same on every iteration, so
+        // it is looked at once instead of evaluated every time around.
+        if let Expr::Literal { .. } = condition {
+            let value = self.evaluate(condition)?;
+            if !self.is_truthy(&value) {
+                return Ok(());
+            }
+            loop {
+                self.check_interrupt(keyword)?;
+                self.execute(body)?;
+            }
+        }
+
         let mut value = self.evaluate(condition)?;
         while self.is_truthy(&value) {
+            self.check_interrupt(keyword)?;
             self.execute(body)?;
             value = self.evaluate(condition)?
         }
@@ -584,8 +1658,12 @@ impl stmt::Visitor<()> for Interpreter {
 
     fn visit_print_stmt(&mut self, expression: &Expr) -> Result<(), Error> {
         let value = self.evaluate(expression)?;
-        println!("{}", self.stringify(value));
-        Ok(())
+        let value = if self.jlox {
+            jlox_string(&value)
+        } else {
+            value.to_string()
+        };
+        self.print(&value)
     }
     // if we strictly wanted to follow the book we could do
     // fn visit_var_stmt(&self, name: &Token, initializer: &Option<Expr>) -> Result<(), Error> {
@@ -609,6 +1687,7 @@ impl stmt::Visitor<()> for Interpreter {
             .map(|i| self.evaluate(i)) // if it was a some call self.evaluate and wrap the result in a Some, if None leave it as None
             .unwrap_or(Ok(Object::Null))?; // unwrap result or return Ok(Object::Null)
 
+        self.record(|| Event::write(name.line, &name.lexeme, &value));
         self.environment
             .borrow_mut()
             .define(name.lexeme.clone(), value);
@@ -616,11 +1695,78 @@ impl stmt::Visitor<()> for Interpreter {
         Ok(())
     }
 
-    fn visit_block_stmt(&mut self, statements: &Vec<Stmt>) -> Result<(), Error> {
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> Result<(), Error> {
         self.execute_block(
             statements,
             Rc::new(RefCell::new(Environment::from(&self.environment))),
         )?;
         Ok(())
     }
+
+    // The body runs in a block of its own. If it throws, or fails with a
+    // runtime error, the handler runs in another one with the thrown value, or
+    // an Error instance describing the runtime error, bound to `name`. Returns,
+    // interrupts and exceeded limits go on unwinding.
+    fn visit_try_stmt(
+        &mut self,
+        _keyword: &Token,
+        body: &[Stmt],
+        name: &Token,
+        handler: &[Stmt],
+    ) -> Result<(), Error> {
+        let environment = Rc::new(RefCell::new(Environment::from(&self.environment)));
+        // A caught error leaves the frames of the last uncaught one alone.
+        let frames = self.frames.take();
+        let value = match self.execute_block(body, environment) {
+            Err(Error::Throw { value, .. }) => value,
+            Err(Error::Runtime {
+                token,
+                code,
+                message,
+            }) if code != Code::Interrupted => self.error_instance(&message, token.line, code),
+            Ok(()) => {
+                self.frames = frames;
+                return Ok(());
+            }
+            other => return other,
+        };
+        self.frames = frames;
+
+        let mut environment = Environment::from(&self.environment);
+        environment.define(name.lexeme.clone(), value);
+        self.execute_block(handler, Rc::new(RefCell::new(environment)))
+    }
+
+    // A thrown Error gets the line of the throw, unless it already has one.
+    fn visit_throw_stmt(&mut self, keyword: &Token, value: &Expr) -> Result<(), Error> {
+        let value = self.evaluate(value)?;
+        if let Object::Instance(instance) = &value {
+            let mut instance = instance.borrow_mut();
+            if self.is_error(&instance.class)
+                && matches!(instance.fields().get("line"), Some(Object::Null))
+            {
+                instance.set_field("line".to_string(), Object::Number(keyword.line.into()));
+            }
+        }
+        Err(Error::Throw {
+            token: keyword.clone(),
+            value,
+        })
+    }
+
+    // Runs nothing yet, the body is kept for when the function exits, see
+    // run_deferred.
+    fn visit_defer_stmt(&mut self, _keyword: &Token, body: &Stmt) -> Result<(), Error> {
+        if let Some(deferred) = self.deferred.last_mut() {
+            deferred.push((body.clone(), Rc::clone(&self.environment)));
+        }
+        Ok(())
+    }
+}
+
+// Lets the initializer add fields to an instance of a sealed class.
+fn set_initializing(instance: &Object, initializing: bool) {
+    if let Object::Instance(instance) = instance {
+        instance.borrow_mut().initializing = initializing;
+    }
 }
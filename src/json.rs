@@ -0,0 +1,448 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::class::{LoxClass, LoxInstance};
+use crate::inspect::inspect;
+use crate::interpreter::Interpreter;
+use crate::list;
+use crate::map;
+use crate::object::Object;
+
+// JSON for the logs of the recorder and for scripts saving their data with
+// serialize(value) and reading it back with deserialize(class, json).
+//
+// An instance is an object with a "class" member naming its class, followed
+// by its fields sorted by name. No field can be called `class`, it's a
+// keyword. A List is an array, a Map with string keys an object without
+// "class", and nil, booleans, numbers and strings are themselves:
+//
+//   class Point { init(x, y) { this.x = x; this.y = y; } }
+//   print serialize(Point(1, 2)); // {"class": "Point", "x": 1, "y": 2}
+//
+// Deserializing turns objects naming a class back into instances of the
+// global class of that name, and the others into Maps.
+#[derive(Debug)]
+pub(crate) enum Json {
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+    String(String),
+    Number(f64),
+    Boolean(bool),
+    Null,
+}
+
+// The value of the text, which must hold nothing else.
+pub(crate) fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = JsonParser {
+        text,
+        position: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.position < text.len() {
+        return Err(parser.error("Expected the end of the JSON"));
+    }
+    Ok(value)
+}
+
+pub(crate) fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// serialize(value) is the JSON text of the value. Functions, classes, foreign
+// values, instances of the other native classes and numbers JSON has no
+// digits for, NaN and the infinities, can't be serialized. Neither can a
+// value that contains itself: a cycle is an error naming where it closes.
+// An instance referred to twice without a cycle is written out twice.
+pub(crate) fn serialize(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let mut writer = Writer {
+        text: String::new(),
+        path: Vec::new(),
+    };
+    writer.value(&args[0], "value")?;
+    Ok(Object::String(Rc::from(writer.text)))
+}
+
+// deserialize(class, json) is a new instance of the class with the fields of
+// the JSON object. Without `init` the initializer doesn't run, the fields
+// are all the instance gets, with `init` true it runs first without
+// arguments and the fields are set after. Nested objects with a "class"
+// become instances of that global class the same way.
+pub(crate) fn deserialize(
+    interpreter: &mut Interpreter,
+    args: &[Object],
+) -> Result<Object, String> {
+    let Object::Class(class) = &args[0] else {
+        return Err(format!(
+            "deserialize expects a class but got {}.",
+            inspect(&args[0])
+        ));
+    };
+    let Object::String(text) = &args[1] else {
+        return Err(format!(
+            "deserialize expects a JSON string but got {}.",
+            inspect(&args[1])
+        ));
+    };
+    let init = !matches!(
+        args.get(2),
+        None | Some(Object::Null | Object::Boolean(false))
+    );
+    let Json::Object(members) = parse(text)? else {
+        return Err(format!(
+            "Expected a JSON object for a {}.",
+            class.borrow().name
+        ));
+    };
+    let mut reader = Reader { interpreter, init };
+    reader.instance(class, members)
+}
+
+struct Writer {
+    text: String,
+    // Where the writer is, `value.items[2]`, and the address of every list,
+    // map and instance on the way there, to notice one containing itself.
+    path: Vec<(String, usize)>,
+}
+
+impl Writer {
+    fn value(&mut self, value: &Object, at: &str) -> Result<(), String> {
+        match value {
+            Object::Null => self.text.push_str("null"),
+            Object::Boolean(b) => self.text.push_str(&b.to_string()),
+            Object::Number(n) if n.is_finite() => self.text.push_str(&value.to_string()),
+            Object::String(s) => self.text.push_str(&quote(s)),
+            Object::Instance(instance) => {
+                let address = value.address().unwrap_or_default();
+                if let Some(start) = self.path.iter().position(|(_, seen)| *seen == address) {
+                    return Err(self.cycle(start, at));
+                }
+                self.path.push((at.to_string(), address));
+                if let Ok(items) = list::items(value) {
+                    self.list(&items, at)?;
+                } else if let Ok(entries) = map::entries(value) {
+                    self.map(&entries, at)?;
+                } else if instance.borrow().native.is_some() {
+                    return Err(format!("Cannot serialize {}.", inspect(value)));
+                } else {
+                    self.instance(&instance.borrow(), at)?;
+                }
+                self.path.pop();
+            }
+            _ => return Err(format!("Cannot serialize {}.", inspect(value))),
+        }
+        Ok(())
+    }
+
+    fn list(&mut self, items: &[Object], at: &str) -> Result<(), String> {
+        self.text.push('[');
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.text.push_str(", ");
+            }
+            self.value(item, &format!("{}[{}]", at, i))?;
+        }
+        self.text.push(']');
+        Ok(())
+    }
+
+    fn map(&mut self, entries: &[(Object, Object)], at: &str) -> Result<(), String> {
+        self.text.push('{');
+        for (i, (key, value)) in entries.iter().enumerate() {
+            let Object::String(key) = key else {
+                return Err(format!(
+                    "Cannot serialize the key {} of {}, JSON keys are strings.",
+                    inspect(key),
+                    at
+                ));
+            };
+            if i > 0 {
+                self.text.push_str(", ");
+            }
+            self.text.push_str(&quote(key));
+            self.text.push_str(": ");
+            self.value(value, &format!("{}.{}", at, key))?;
+        }
+        self.text.push('}');
+        Ok(())
+    }
+
+    fn instance(&mut self, instance: &LoxInstance, at: &str) -> Result<(), String> {
+        self.text.push_str("{\"class\": ");
+        self.text.push_str(&quote(&instance.class.borrow().name));
+        let mut fields: Vec<_> = instance.fields().iter().collect();
+        fields.sort_by_key(|(name, _)| *name);
+        for (name, value) in fields {
+            self.text.push_str(", ");
+            self.text.push_str(&quote(name));
+            self.text.push_str(": ");
+            self.value(value, &format!("{}.{}", at, name))?;
+        }
+        self.text.push('}');
+        Ok(())
+    }
+
+    fn cycle(&self, start: usize, at: &str) -> String {
+        format!(
+            "Cannot serialize a cycle: {} refers back to {}.",
+            at, self.path[start].0
+        )
+    }
+}
+
+struct Reader<'a> {
+    interpreter: &'a mut Interpreter,
+    init: bool,
+}
+
+impl Reader<'_> {
+    fn value(&mut self, json: Json) -> Result<Object, String> {
+        Ok(match json {
+            Json::Null => Object::Null,
+            Json::Boolean(b) => Object::Boolean(b),
+            Json::Number(n) => Object::Number(n),
+            Json::String(s) => Object::String(Rc::from(s)),
+            Json::Array(values) => {
+                let items = values
+                    .into_iter()
+                    .map(|value| self.value(value))
+                    .collect::<Result<_, _>>()?;
+                list::list(self.interpreter, items)
+            }
+            Json::Object(members) => match members.iter().find(|(name, _)| name == "class") {
+                Some((_, Json::String(name))) => {
+                    let class = self.class(name)?;
+                    self.instance(&class, members)?
+                }
+                Some(_) => return Err("Expected the name of a class for 'class'.".to_string()),
+                None => {
+                    let entries = members
+                        .into_iter()
+                        .map(|(name, value)| {
+                            Ok((Object::String(Rc::from(name)), self.value(value)?))
+                        })
+                        .collect::<Result<_, String>>()?;
+                    map::map(self.interpreter, entries)?
+                }
+            },
+        })
+    }
+
+    fn instance(
+        &mut self,
+        class: &Rc<RefCell<LoxClass>>,
+        members: Vec<(String, Json)>,
+    ) -> Result<Object, String> {
+        let name = class.borrow().name.clone();
+        if class.borrow().is_native() {
+            return Err(format!(
+                "Cannot deserialize an instance of native class '{}'.",
+                name
+            ));
+        }
+        let instance = if self.init {
+            self.interpreter
+                .call_back(&Object::Class(Rc::clone(class)), &[])?
+        } else {
+            self.interpreter.new_instance(class)
+        };
+        for (field, json) in members {
+            if field == "class" {
+                match json {
+                    Json::String(named) if named == name => continue,
+                    _ => {
+                        return Err(format!(
+                            "Expected a {} but the JSON is another class.",
+                            name
+                        ))
+                    }
+                }
+            }
+            let value = self.value(json)?;
+            if let Object::Instance(instance) = &instance {
+                instance.borrow_mut().set_field(field, value);
+            }
+        }
+        Ok(instance)
+    }
+
+    // The global class of the name.
+    fn class(&self, name: &str) -> Result<Rc<RefCell<LoxClass>>, String> {
+        match self.interpreter.globals.borrow().get_own(name) {
+            Some(Object::Class(class)) => Ok(class),
+            _ => Err(format!(
+                "Cannot deserialize an instance of '{}', there is no such class.",
+                name
+            )),
+        }
+    }
+}
+
+// Arrays and objects nested deeper than this are refused rather than parsed
+// into a stack overflow.
+const MAX_DEPTH: usize = 512;
+
+// Enough JSON for everything written above.
+struct JsonParser<'a> {
+    text: &'a str,
+    position: usize,
+    // The arrays and objects the parser is inside of.
+    depth: usize,
+}
+
+impl JsonParser<'_> {
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('[' | '{') if self.depth == MAX_DEPTH => Err(self.error(&format!(
+                "Nested more than {} arrays and objects deep",
+                MAX_DEPTH
+            ))),
+            Some('[') => {
+                self.position += 1;
+                self.depth += 1;
+                let mut values = Vec::new();
+                if !self.eat(']') {
+                    loop {
+                        values.push(self.value()?);
+                        if self.eat(']') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                self.depth -= 1;
+                Ok(Json::Array(values))
+            }
+            Some('{') => {
+                self.position += 1;
+                self.depth += 1;
+                let mut fields = Vec::new();
+                if !self.eat('}') {
+                    loop {
+                        self.skip_whitespace();
+                        let name = self.string()?;
+                        self.expect(':')?;
+                        fields.push((name, self.value()?));
+                        if self.eat('}') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                self.depth -= 1;
+                Ok(Json::Object(fields))
+            }
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let rest = &self.text[self.position..];
+                let length = rest
+                    .find(|c: char| !(c.is_ascii_digit() || "-+.eE".contains(c)))
+                    .unwrap_or(rest.len());
+                self.position += length;
+                rest[..length]
+                    .parse()
+                    .map(Json::Number)
+                    .map_err(|_| self.error("Invalid number"))
+            }
+            Some(_) => {
+                for (word, value) in [
+                    ("true", Json::Boolean(true)),
+                    ("false", Json::Boolean(false)),
+                    ("null", Json::Null),
+                ] {
+                    if self.text[self.position..].starts_with(word) {
+                        self.position += word.len();
+                        return Ok(value);
+                    }
+                }
+                Err(self.error("Expected a value"))
+            }
+            None => Err(self.error("Expected a value")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if !self.eat('"') {
+            return Err(self.error("Expected a string"));
+        }
+        let mut value = String::new();
+        loop {
+            let c = self
+                .peek()
+                .ok_or_else(|| self.error("Unterminated string"))?;
+            self.position += c.len_utf8();
+            match c {
+                '"' => return Ok(value),
+                '\\' => {
+                    let escaped = self
+                        .peek()
+                        .ok_or_else(|| self.error("Unterminated string"))?;
+                    self.position += 1;
+                    match escaped {
+                        'n' => value.push('\n'),
+                        'r' => value.push('\r'),
+                        't' => value.push('\t'),
+                        'u' => {
+                            let code = self
+                                .text
+                                .get(self.position..self.position + 4)
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("Invalid escape"))?;
+                            self.position += 4;
+                            value.push(code);
+                        }
+                        other => value.push(other),
+                    }
+                }
+                c => value.push(c),
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", expected)))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{} at byte {}.", message, self.position)
+    }
+}
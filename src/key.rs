@@ -0,0 +1,72 @@
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use crate::function::Function;
+use crate::object::Object;
+
+// A value used as a key of a hash map or set, the elements of Set and the keys
+// of Map. Two keys are the same when the values are `==`, with one exception:
+// every NaN is the same key, so a NaN that went in can be found again. That
+// makes -0 and 0 one key as well. Strings are compared by their characters,
+// everything on the heap by identity: an instance, a class, a function or a
+// foreign value is only ever the same key as itself.
+//
+// Bound methods can't be keys. Every `instance.method` binds a new one, so a
+// method that went in would never be found again; Key::new refuses them
+// instead.
+//
+//   let mut counts: HashMap<Key, usize> = HashMap::new();
+//   if let Some(key) = Key::new(&value) {
+//       *counts.entry(key).or_default() += 1;
+//   }
+#[derive(Debug, Clone)]
+pub struct Key(Object);
+
+impl Key {
+    // The key for the value, None when it can't be one.
+    pub fn new(value: &Object) -> Option<Key> {
+        match value {
+            Object::Callable(function) if function.is_bound() => None,
+            _ => Some(Key(value.clone())),
+        }
+    }
+
+    pub fn value(&self) -> &Object {
+        &self.0
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        match (&self.0, &other.0) {
+            (Object::Number(left), Object::Number(right)) => {
+                left == right || (left.is_nan() && right.is_nan())
+            }
+            (left, right) => left.equals(right),
+        }
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(&self.0).hash(state);
+        match &self.0 {
+            Object::Null => (),
+            Object::Boolean(b) => b.hash(state),
+            Object::Number(n) if n.is_nan() => f64::NAN.to_bits().hash(state),
+            // Adding 0 turns -0 into 0.
+            Object::Number(n) => (n + 0.0).to_bits().hash(state),
+            Object::String(s) => s.hash(state),
+            Object::Callable(Function::Native { name, .. }) => name.hash(state),
+            Object::Callable(Function::User { body, closure, .. }) => {
+                Rc::as_ptr(body).hash(state);
+                Rc::as_ptr(closure).hash(state);
+            }
+            Object::Class(_) | Object::Instance(_) | Object::Foreign(_) => {
+                self.0.address().hash(state)
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+// Errors carry the offending token and double as the unwinding mechanism for
+// `return`, so they are intentionally passed around by value.
+#![allow(clippy::result_large_err)]
+
+pub mod ast_graph;
+pub mod bench;
+pub mod builder;
+pub mod call_graph;
+pub mod cancel;
+pub mod class;
+pub mod compiler;
+pub mod difftest;
+pub mod env_graph;
+pub mod environment;
+pub mod error;
+pub mod features;
+pub mod frames;
+pub mod function;
+pub mod include_cache;
+pub mod inspect;
+pub mod interpreter;
+mod json;
+pub mod key;
+pub mod limits;
+mod list;
+pub mod lox;
+mod map;
+pub mod natives;
+pub mod object;
+pub mod operators;
+mod parallel;
+pub mod parser;
+pub mod preprocessor;
+pub mod profiler;
+mod random;
+pub mod recorder;
+pub mod rename;
+pub mod resolver;
+pub mod scanner;
+mod set;
+pub mod shake;
+pub mod snapshot;
+pub mod stats;
+mod streams;
+pub mod symbol;
+pub mod syntax;
+mod time;
+pub mod token;
+pub mod token_stream;
+pub mod vm;
+
+pub use lox::Lox;
+
+// The version of the interpreter, which scripts get from version().
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,111 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::error::Error;
+
+// Ceilings for running scripts that can't be trusted not to eat all the memory
+// of the host. None means unlimited, which is the default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    // Environments alive at the same time: one for every block being
+    // executed, every call in progress and every closure kept around.
+    pub environments: Option<usize>,
+    // Class instances alive at the same time.
+    pub instances: Option<usize>,
+    // Length in bytes of any single string a script builds.
+    pub string_bytes: Option<usize>,
+    // Calls of Lox functions in progress at the same time, how deep recursion
+    // can go. Without it a runaway recursion overflows the stack of the host.
+    pub calls: Option<usize>,
+    // What scripts write with print and print_err in total, in bytes and in
+    // lines, so one printing in a tight loop can't flood the host's output.
+    // Counted since the limits were set.
+    pub output_bytes: Option<usize>,
+    pub output_lines: Option<usize>,
+}
+
+// How many environments and instances are alive. Every one of them holds an
+// Allocation, which gives its slot back when it is dropped.
+#[derive(Debug, Default)]
+pub(crate) struct Usage {
+    pub(crate) environments: Counter,
+    pub(crate) instances: Counter,
+    pub(crate) calls: Counter,
+    pub(crate) output_bytes: Cell<usize>,
+    pub(crate) output_lines: Cell<usize>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Counter(Rc<Cell<usize>>);
+
+impl Counter {
+    pub(crate) fn allocate(&self) -> Allocation {
+        self.0.set(self.0.get() + 1);
+        Allocation(Rc::clone(&self.0))
+    }
+
+    pub(crate) fn live(&self) -> usize {
+        self.0.get()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Allocation(Rc<Cell<usize>>);
+
+impl Allocation {
+    // Another allocation on the same counter, for something derived from the
+    // owner of this one.
+    pub(crate) fn another(&self) -> Allocation {
+        Counter(Rc::clone(&self.0)).allocate()
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+    }
+}
+
+impl Limits {
+    // Counts can go over a limit for a moment, they are checked at the points
+    // where the interpreter allocates: entering a block or function body,
+    // creating an instance and concatenating strings.
+    pub(crate) fn check(&self, usage: &Usage) -> Result<(), Error> {
+        exceeded(
+            self.environments,
+            usage.environments.live(),
+            "live environments",
+        )?;
+        exceeded(self.instances, usage.instances.live(), "live instances")
+    }
+
+    pub(crate) fn check_calls(&self, usage: &Usage) -> Result<(), Error> {
+        exceeded(self.calls, usage.calls.live(), "calls in progress")
+    }
+
+    // Counts the text as written, a line of it, and fails instead when that
+    // goes over the output limits. The text isn't counted then, nothing was
+    // written.
+    pub(crate) fn count_output(&self, usage: &Usage, text: &str) -> Result<(), Error> {
+        let bytes = usage.output_bytes.get() + text.len() + 1;
+        let lines = usage.output_lines.get() + text.matches('\n').count() + 1;
+        exceeded(self.output_bytes, bytes, "bytes of output")?;
+        exceeded(self.output_lines, lines, "lines of output")?;
+        usage.output_bytes.set(bytes);
+        usage.output_lines.set(lines);
+        Ok(())
+    }
+
+    pub(crate) fn check_string(&self, bytes: usize) -> Result<(), Error> {
+        exceeded(self.string_bytes, bytes, "bytes in a string")
+    }
+}
+
+fn exceeded(limit: Option<usize>, count: usize, what: &str) -> Result<(), Error> {
+    match limit {
+        Some(limit) if count > limit => Err(Error::ResourceLimit {
+            message: format!("Resource limit exceeded: more than {} {}.", limit, what),
+        }),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,284 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::class::LoxClass;
+use crate::function::NativeFn;
+use crate::inspect::inspect;
+use crate::interpreter::Interpreter;
+use crate::natives::{native_state, set_native_state};
+use crate::object::Object;
+
+// List is a native class backed by a Vec, so reading or writing an element by
+// its index takes constant time. Methods taking a function call it through the
+// interpreter, it can be any Lox function, method, class or native.
+//
+//   var numbers = List();
+//   numbers.push(3);
+//   numbers.push(1);
+//   numbers.push(2);
+//   numbers.sort();
+//   fun tenfold(n) { return n * 10; }
+//   print numbers.map(tenfold).get(0); // 10
+pub(crate) const METHODS: &[(&str, NativeFn)] = &[
+    ("init()", init),
+    ("length()", length),
+    ("get(index)", get),
+    ("set(index, value)", set),
+    ("push(value)", push),
+    ("pop()", pop),
+    ("insert(index, value)", insert),
+    ("remove(index)", remove),
+    ("sort(comparator?)", sort),
+    ("map(function)", map),
+    ("filter(function)", filter),
+    ("reduce(function, initial)", reduce),
+];
+
+fn init(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    set_native_state(&args[0], Vec::<Object>::new());
+    Ok(Object::Null)
+}
+
+fn length(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    with_items(&args[0], |items| Ok(Object::Number(items.len() as f64)))
+}
+
+fn get(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let index = integer(&args[1])?;
+    with_items(&args[0], |items| {
+        let index = bounded(index, items.len(), false)?;
+        Ok(items[index].clone())
+    })
+}
+
+// Returns the value like assignment does.
+fn set(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let index = integer(&args[1])?;
+    with_items(&args[0], |items| {
+        let index = bounded(index, items.len(), false)?;
+        items[index] = args[2].clone();
+        Ok(args[2].clone())
+    })
+}
+
+fn push(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    with_items(&args[0], |items| {
+        items.push(args[1].clone());
+        Ok(Object::Null)
+    })
+}
+
+fn pop(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    with_items(&args[0], |items| {
+        items
+            .pop()
+            .ok_or_else(|| "Cannot pop from an empty list.".to_string())
+    })
+}
+
+// The index can be the length of the list, which appends.
+fn insert(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let index = integer(&args[1])?;
+    with_items(&args[0], |items| {
+        let index = bounded(index, items.len(), true)?;
+        items.insert(index, args[2].clone());
+        Ok(Object::Null)
+    })
+}
+
+fn remove(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let index = integer(&args[1])?;
+    with_items(&args[0], |items| {
+        let index = bounded(index, items.len(), false)?;
+        Ok(items.remove(index))
+    })
+}
+
+// sort(comparator?) sorts the list in place, keeping equal elements in their
+// order. comparator(a, b) returns a negative number when a goes first, a
+// positive one when b does and 0 when either can. Without one the list must
+// hold only numbers or only strings, which are sorted ascending.
+//
+// An error raised by the comparator stops the sort and leaves the list as it
+// was. So does a comparator that isn't consistent, one that orders a before b
+// and then b before a: the sorted list is checked against it, which takes
+// another comparison per element.
+fn sort(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let items = with_items(&args[0], |items| Ok(items.clone()))?;
+    let mut after = |a: &Object, b: &Object| match args.get(1) {
+        Some(comparator) => match interpreter.call_back(comparator, &[a.clone(), b.clone()])? {
+            Object::Number(n) if n.is_nan() => Err(format!(
+                "The comparator returned NaN for {} and {}.",
+                inspect(a),
+                inspect(b)
+            )),
+            Object::Number(n) => Ok(n > 0.0),
+            other => Err(format!(
+                "The comparator must return a number but got {}.",
+                inspect(&other)
+            )),
+        },
+        None => match natural_order(a, b) {
+            Some(ordering) => Ok(ordering == Ordering::Greater),
+            None => Err(format!(
+                "Cannot sort {} and {} without a comparator.",
+                inspect(a),
+                inspect(b)
+            )),
+        },
+    };
+    let sorted = merge_sort(items, &mut after)?;
+    for pair in sorted.windows(2) {
+        if after(&pair[0], &pair[1])? {
+            return Err(format!(
+                "Inconsistent comparator: {} was sorted before {} but the comparator puts it after.",
+                inspect(&pair[0]),
+                inspect(&pair[1])
+            ));
+        }
+    }
+
+    // The comparator could have changed the list, the sorted copy wins.
+    with_items(&args[0], |items| {
+        *items = sorted;
+        Ok(Object::Null)
+    })
+}
+
+// map(function) is a new list with the function applied to every element.
+fn map(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let items = with_items(&args[0], |items| Ok(items.clone()))?;
+    let mut mapped = Vec::with_capacity(items.len());
+    for item in items {
+        mapped.push(interpreter.call_back(&args[1], &[item])?);
+    }
+    new_list(interpreter, &args[0], mapped)
+}
+
+// filter(function) is a new list with the elements the function returns a
+// truthy value for.
+fn filter(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let items = with_items(&args[0], |items| Ok(items.clone()))?;
+    let mut kept = Vec::new();
+    for item in items {
+        match interpreter.call_back(&args[1], std::slice::from_ref(&item))? {
+            Object::Null | Object::Boolean(false) => (),
+            _ => kept.push(item),
+        }
+    }
+    new_list(interpreter, &args[0], kept)
+}
+
+// reduce(function, initial) calls function(accumulator, element) for every
+// element, starting with initial, and returns the last result.
+fn reduce(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let items = with_items(&args[0], |items| Ok(items.clone()))?;
+    let mut accumulator = args[2].clone();
+    for item in items {
+        accumulator = interpreter.call_back(&args[1], &[accumulator, item])?;
+    }
+    Ok(accumulator)
+}
+
+// The elements are never borrowed while Lox code runs, a callback is free to
+// use the list it was called for.
+pub(crate) fn with_items<R>(
+    this: &Object,
+    f: impl FnOnce(&mut Vec<Object>) -> Result<R, String>,
+) -> Result<R, String> {
+    native_state(this, "List", f)
+}
+
+// Another instance of the class of `this`.
+fn new_list(
+    interpreter: &Interpreter,
+    this: &Object,
+    items: Vec<Object>,
+) -> Result<Object, String> {
+    let Object::Instance(instance) = this else {
+        return Err("Expected a List instance.".to_string());
+    };
+    let class = Rc::clone(&instance.borrow().class);
+    Ok(list_of(interpreter, &class, items))
+}
+
+// A List holding the items, for natives returning several values.
+pub(crate) fn list(interpreter: &Interpreter, items: Vec<Object>) -> Object {
+    list_of(interpreter, &interpreter.native_class("List"), items)
+}
+
+fn list_of(interpreter: &Interpreter, class: &Rc<RefCell<LoxClass>>, items: Vec<Object>) -> Object {
+    let list = interpreter.new_instance(class);
+    set_native_state(&list, items);
+    list
+}
+
+// The elements of a List, for natives taking one.
+pub(crate) fn items(list: &Object) -> Result<Vec<Object>, String> {
+    with_items(list, |items| Ok(items.clone()))
+}
+
+// The index argument, which must be an integer. It is checked before the
+// elements are taken out of the list, while a message showing the list itself,
+// as in `list.get(list)`, can still show what it holds.
+fn integer(index: &Object) -> Result<f64, String> {
+    match index {
+        Object::Number(n) if n.fract() == 0.0 => Ok(*n),
+        other => Err(format!(
+            "A list index must be an integer but got {}.",
+            inspect(other)
+        )),
+    }
+}
+
+// Checks that the index is an element of a list of the given length, or one
+// past the end when `end` is allowed.
+fn bounded(index: f64, length: usize, end: bool) -> Result<usize, String> {
+    let limit = if end { length + 1 } else { length };
+    if index >= 0.0 && (index as usize) < limit {
+        Ok(index as usize)
+    } else {
+        Err(format!(
+            "Index {} is out of bounds for a list of length {}.",
+            index, length
+        ))
+    }
+}
+
+fn natural_order(a: &Object, b: &Object) -> Option<Ordering> {
+    match (a, b) {
+        (Object::Number(a), Object::Number(b)) => a.partial_cmp(b),
+        (Object::String(a), Object::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+// A merge sort, which unlike the one in the standard library can stop at the
+// first comparison that fails. It is stable: `after(a, b)` says whether a
+// must go after b, equal elements keep their order.
+fn merge_sort(
+    mut items: Vec<Object>,
+    after: &mut impl FnMut(&Object, &Object) -> Result<bool, String>,
+) -> Result<Vec<Object>, String> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, after)?;
+    let right = merge_sort(right, after)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        if after(a, b)? {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
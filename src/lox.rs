@@ -0,0 +1,1168 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tracing::{debug, debug_span};
+
+use crate::builder::LoxBuilder;
+use crate::cancel::CancellationToken;
+use crate::class::ClassInfo;
+use crate::compiler;
+use crate::environment::Environment;
+use crate::error::{runtime_error, uncaught, Code, Diagnostic, Error};
+use crate::features::Features;
+use crate::frames::Frame;
+use crate::function::{Function, NativeFn};
+use crate::include_cache::IncludeCache;
+use crate::interpreter::Interpreter;
+use crate::limits::Limits;
+use crate::object::Object;
+use crate::operators::{Operator, OperatorFn};
+use crate::parser::Parser;
+use crate::preprocessor::{IncludeGraph, LineMap, Preprocessor};
+use crate::profiler::{Profile, Profiler};
+use crate::recorder::Recorder;
+use crate::rename;
+use crate::resolver::Resolver;
+use crate::scanner::{LiteralLimits, Scanner};
+use crate::shake::{self, Reason};
+use crate::snapshot;
+use crate::stats::Stats;
+use crate::symbol::{Symbol, SymbolTable};
+use crate::syntax::Stmt;
+use crate::token::{Span, Token, TokenType};
+use crate::token_stream::TokenStream;
+use crate::vm::Vm;
+
+pub struct Lox {
+    interpreter: Interpreter,
+    // Like hadError and hadRuntimeError in jlox. They are sticky for a whole
+    // script so the exit code reflects any error, and reset for every line in
+    // the REPL.
+    had_error: bool,
+    had_runtime_error: bool,
+    // Used to line up the caret under errors in source lines containing tabs.
+    tab_width: usize,
+    // Redeclaring a global is an error. Ignored in the REPL, where defining
+    // something again is how you fix it.
+    strict: bool,
+    // Files go through the preprocessor first, see preprocessor.rs.
+    preprocess: bool,
+    // The preprocessor keeps the tokens of the files it reads in the user's
+    // cache directory, see include_cache.rs.
+    include_cache: bool,
+    // Where the lines of the last preprocessed program come from.
+    line_map: Option<LineMap>,
+    // The language extensions programs may use, see features.rs.
+    features: Features,
+    // How long string and number literals may be.
+    literal_limits: LiteralLimits,
+    // Leave out the functions and classes the program doesn't use before
+    // running it, see shake.rs, and say why the others are kept.
+    tree_shake: bool,
+    why_kept: bool,
+    // Programs are compiled to bytecode and run by the VM instead, see vm.rs.
+    vm: bool,
+    // Errors are printed the way jlox prints them, see set_jlox.
+    jlox: bool,
+    // Diagnostics are gathered here instead of printed while set, see
+    // diagnostics.
+    collected: Option<Vec<Diagnostic>>,
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lox {
+    pub fn new() -> Self {
+        Lox {
+            interpreter: Interpreter::new(),
+            had_error: false,
+            had_runtime_error: false,
+            tab_width: 4,
+            strict: false,
+            preprocess: false,
+            include_cache: false,
+            line_map: None,
+            features: Features::default(),
+            literal_limits: LiteralLimits::default(),
+            tree_shake: false,
+            why_kept: false,
+            vm: false,
+            jlox: false,
+            collected: None,
+        }
+    }
+
+    // A Lox with the options chosen on the builder, see builder.rs.
+    pub fn builder() -> LoxBuilder {
+        LoxBuilder::new()
+    }
+
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = tab_width;
+    }
+
+    // Redeclaring a global is an error, and so is an integer-like operation
+    // losing precision, see Interpreter::check_exact.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+        self.interpreter.set_strict(strict);
+    }
+
+    pub fn set_preprocess(&mut self, preprocess: bool) {
+        self.preprocess = preprocess;
+    }
+
+    pub fn set_include_cache(&mut self, include_cache: bool) {
+        self.include_cache = include_cache;
+    }
+
+    // Declaring a class again adds methods to the existing class instead of
+    // replacing it.
+    pub fn set_allow_reopen(&mut self, allow_reopen: bool) {
+        self.interpreter.set_allow_reopen(allow_reopen);
+    }
+
+    // Lets scripts open files for writing with open_writer and write them
+    // with dump_env_graph, which they can unless a host running scripts it
+    // doesn't trust turns it off.
+    pub fn set_allow_file_writes(&mut self, allow_file_writes: bool) {
+        self.interpreter.set_allow_file_writes(allow_file_writes);
+    }
+
+    // Runs the function and class declarations of the top level of a program
+    // before its other statements, so they can be used before they are
+    // declared. The book's Lox runs everything in order.
+    pub fn set_hoist(&mut self, hoist: bool) {
+        self.interpreter.set_hoist(hoist);
+    }
+
+    // Programs run without the functions and classes declared at the top
+    // level that they never use. With `why_kept` the reason every other one
+    // is kept, and what was left out, are printed to stderr.
+    pub fn set_tree_shake(&mut self, tree_shake: bool, why_kept: bool) {
+        self.tree_shake = tree_shake || why_kept;
+        self.why_kept = why_kept;
+    }
+
+    pub fn set_features(&mut self, features: Features) {
+        self.features = features;
+    }
+
+    // Behaves like jlox, the interpreter of the book, for teaching with the
+    // book at hand or comparing with it: only the book's Lox and its clock()
+    // native, numbers printed like Java prints doubles, `==` on numbers like
+    // Double.equals, errors worded and printed like jlox without codes or
+    // source lines, and calls with the wrong number of arguments failing when
+    // they run. Turning it on removes every other global, like
+    // remove_natives, and turns off the extensions.
+    pub fn set_jlox(&mut self, jlox: bool) {
+        self.jlox = jlox;
+        self.interpreter.set_jlox(jlox);
+        if jlox {
+            self.features = Features::BOOK;
+            let clock = self.interpreter.globals.borrow().get_own("clock");
+            self.remove_natives();
+            if let Some(clock) = clock {
+                self.define("clock", clock);
+            }
+        }
+    }
+
+    // Setting the flag stops the running program with an "Interrupted."
+    // runtime error. Meant to be set from a signal handler.
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        self.interpreter.interrupt_flag()
+    }
+
+    // Tripping the token stops the running program, from any thread, with
+    // Error::Cancelled. Programs run after fail the same way until a new
+    // token is set with set_cancellation_token.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.interpreter.cancellation_token()
+    }
+
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.interpreter.set_cancellation_token(token);
+    }
+
+    // Programs with longer literals fail to compile. Strings of up to 1MB and
+    // numbers of up to 400 digits are accepted by default.
+    pub fn set_literal_limits(&mut self, limits: LiteralLimits) {
+        self.literal_limits = limits;
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.interpreter.set_limits(limits);
+    }
+
+    // See Interpreter::remove_natives.
+    pub fn remove_natives(&mut self) {
+        self.interpreter.remove_natives();
+    }
+
+    // See Interpreter::set_output.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.interpreter.set_output(output);
+    }
+
+    // Defines a global variable for the scripts run from now on, a way to hand
+    // them values of the host.
+    pub fn define(&mut self, name: &str, value: Object) {
+        self.interpreter
+            .globals
+            .borrow_mut()
+            .define(name.to_string(), value);
+    }
+
+    // See Interpreter::register_class.
+    pub fn register_class(&mut self, name: &str, methods: &[(&'static str, NativeFn)]) {
+        self.interpreter.register_class(name, methods);
+    }
+
+    // See Interpreter::register_type.
+    pub fn register_type<T: Any>(&mut self, name: &str, methods: &[(&'static str, NativeFn)]) {
+        self.interpreter.register_type::<T>(name, methods);
+    }
+
+    // See Interpreter::register_operator.
+    pub fn register_operator(&mut self, operator: Operator, rule: OperatorFn) {
+        self.interpreter.register_operator(operator, rule);
+    }
+
+    // A foreign value holding the host value, see Interpreter::foreign.
+    pub fn foreign<T: Any>(&self, value: T) -> Object {
+        self.interpreter.foreign(value)
+    }
+
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.interpreter.set_random_seed(seed);
+    }
+
+    // Logs everything the programs run from now on do to the file, see
+    // recorder.rs.
+    pub fn start_recording(&mut self, path: &str) -> Result<(), Error> {
+        self.interpreter.set_recorder(Some(Recorder::create(path)?));
+        Ok(())
+    }
+
+    // Completes the log. Does nothing when not recording.
+    pub fn stop_recording(&mut self) -> Result<(), Error> {
+        match self.interpreter.take_recorder() {
+            Some(recorder) => Ok(recorder.finish()?),
+            None => Ok(()),
+        }
+    }
+
+    // Times the calls of the programs run from now on, see profiler.rs.
+    pub fn start_profiling(&mut self) {
+        self.interpreter.set_profiler(Some(Profiler::new()));
+    }
+
+    // What was found since start_profiling, None when not profiling.
+    pub fn finish_profiling(&mut self) -> Option<Profile> {
+        self.interpreter.take_profiler().map(Profiler::finish)
+    }
+
+    // A scan, parse or resolution error was reported.
+    pub fn had_error(&self) -> bool {
+        self.had_error
+    }
+
+    // Runs programs on the bytecode VM of vm.rs instead of walking their
+    // syntax trees. The REPL and eval always use the interpreter.
+    pub fn set_vm(&mut self, vm: bool) {
+        self.vm = vm;
+    }
+
+    pub fn had_runtime_error(&self) -> bool {
+        self.had_runtime_error
+    }
+
+    pub fn run_file(&mut self, file_path: &str) -> Result<(), Error> {
+        if !self.preprocess {
+            let contents = read_file(file_path)?;
+            return self.run(contents);
+        }
+
+        let result = self
+            .preprocess(file_path)
+            .and_then(|(tokens, diagnostics)| {
+                let (statements, _) = self.compile_tokens("", tokens, diagnostics, false)?;
+                let statements = self.shake(statements);
+                self.execute("", &statements)
+            });
+        self.record(result)
+    }
+
+    pub fn run_prompt(&mut self) -> Result<(), Error> {
+        let strict = std::mem::replace(&mut self.strict, false);
+        let result = self.prompt();
+        self.strict = strict;
+        result
+    }
+
+    fn prompt(&mut self) -> Result<(), Error> {
+        let stdin = io::stdin();
+        // Only for people typing, not for input piped in.
+        if stdin.is_terminal() {
+            println!(
+                "Lox {}. Type :help for the commands, Ctrl-D to quit.",
+                crate::VERSION
+            );
+        }
+
+        let mut handle = stdin.lock();
+        // The lines of a statement spanning several of them, like a function
+        // or a class, until it is complete.
+        let mut buffer = String::new();
+
+        loop {
+            print!("{}", if buffer.is_empty() { "> " } else { "..> " });
+            io::stdout().flush()?;
+
+            let mut bytes = Vec::new();
+            if handle.read_until(b'\n', &mut bytes)? == 0 {
+                if !buffer.is_empty() {
+                    self.run_line(&buffer);
+                }
+                break;
+            }
+            let line = match String::from_utf8(bytes) {
+                Ok(line) => line,
+                Err(_) => {
+                    eprintln!("Input is not valid UTF-8.");
+                    continue;
+                }
+            };
+
+            if let Some(command) = line.trim().strip_prefix(':').filter(|_| buffer.is_empty()) {
+                self.command(command);
+                continue;
+            }
+
+            // An empty line runs what was typed so far even if it isn't
+            // complete, a way out of a mistake the next line can't fix.
+            let blank = line.trim().is_empty();
+            buffer.push_str(&line);
+            if !blank && is_incomplete(&buffer) {
+                continue;
+            }
+            let source = std::mem::take(&mut buffer);
+
+            // An interrupt while waiting for input is not meant for this line.
+            self.interpreter
+                .interrupt_flag()
+                .store(false, Ordering::Relaxed);
+
+            // Errors have already been reported, a mistake on one line
+            // shouldn't end the session.
+            self.run_line(&source);
+            self.had_error = false;
+            self.had_runtime_error = false;
+        }
+
+        Ok(())
+    }
+
+    // Runs a line typed into the REPL. Like Python's, the REPL echoes the
+    // value of an expression statement unless it is nil, scripts never do. As
+    // with eval, the semicolon at the end can be left out.
+    fn run_line(&mut self, line: &str) {
+        let result = self.eval_source(line, None).and_then(|value| match value {
+            Object::Null => Ok(None),
+            value => {
+                // Every line of the REPL is scanned on its own, from line 1.
+                let paren = Token::new(TokenType::RightParen, ")", 1, 1);
+                self.interpreter.stringify(&value, &paren).map(Some)
+            }
+        });
+        if let Ok(Some(text)) = self.record(result) {
+            println!("{}", text);
+        }
+    }
+
+    // REPL commands start with a colon:
+    //
+    //   :save <file> [name...]  saves all globals, or the named ones
+    //   :load <file>            defines the globals saved in the file
+    //   :doc <name>             shows the declaration and doc comment of a
+    //                           global, or of a method as Class.method
+    //   :type <expression>      evaluates the expression and shows its type
+    //   :frames                 lists the frames of the last runtime error
+    //   :eval-at-frame <n> <expression>
+    //                           evaluates the expression in frame n of them
+    fn command(&mut self, command: &str) {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let result = match words.as_slice() {
+            ["doc", name] => {
+                self.print_doc(name);
+                return;
+            }
+            ["type", _, ..] => {
+                let expression = command.trim().trim_start_matches("type").trim_start();
+                self.print_type(expression);
+                return;
+            }
+            ["frames"] => {
+                self.print_frames();
+                return;
+            }
+            ["eval-at-frame", _, _, ..] => {
+                let arguments = command
+                    .trim()
+                    .trim_start_matches("eval-at-frame")
+                    .trim_start();
+                let (index, expression) = arguments
+                    .split_once(char::is_whitespace)
+                    .expect("There are at least two arguments.");
+                self.eval_at_frame(index, expression);
+                return;
+            }
+            ["save", file_path, names @ ..] => {
+                let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+                let names = if names.is_empty() {
+                    None
+                } else {
+                    Some(&names[..])
+                };
+                self.save_snapshot(names)
+                    .and_then(|snapshot| Ok(fs::write(file_path, snapshot)?))
+            }
+            ["load", file_path] => {
+                read_file(file_path).and_then(|snapshot| self.load_snapshot(&snapshot))
+            }
+            ["help"] => {
+                print!("{}", REPL_HELP);
+                return;
+            }
+            _ => {
+                eprintln!(
+                    "Unknown command ':{}'. Type :help for the commands.",
+                    command.trim()
+                );
+                return;
+            }
+        };
+        if let Err(Error::Io(error)) = result {
+            eprintln!("{}", error);
+        }
+    }
+
+    fn print_doc(&self, name: &str) {
+        let (global, method) = match name.split_once('.') {
+            Some((class, method)) => (class, Some(method)),
+            None => (name, None),
+        };
+        let value = self.interpreter.globals.borrow().get_own(global);
+        let (declaration, key) = match (value, method) {
+            (Some(Object::Class(class)), Some(method)) => {
+                // The doc is on the class declaring the method, maybe a
+                // superclass.
+                let mut owner = Some(Rc::clone(&class));
+                while let Some(class) = owner.take() {
+                    if class.borrow().methods.contains_key(method) {
+                        owner = Some(class);
+                        break;
+                    }
+                    owner = class.borrow().superclass.clone();
+                }
+                match owner {
+                    Some(owner) => {
+                        let function = owner.borrow().methods[method].clone();
+                        let owner = owner.borrow().name.clone();
+                        (
+                            format!("{}.{}", owner, signature(&function)),
+                            format!("{}.{}", owner, method),
+                        )
+                    }
+                    None => {
+                        eprintln!("Class '{}' has no method '{}'.", global, method);
+                        return;
+                    }
+                }
+            }
+            (Some(Object::Callable(function)), None) => {
+                (format!("fun {}", signature(&function)), name.to_string())
+            }
+            (Some(Object::Class(class)), None) => {
+                let class = class.borrow();
+                let declaration = match &class.superclass {
+                    Some(superclass) => {
+                        format!("class {} < {}", class.name, superclass.borrow().name)
+                    }
+                    None => format!("class {}", class.name),
+                };
+                (declaration, name.to_string())
+            }
+            (Some(value), None) => (
+                format!("var {} ({})", name, value.type_name()),
+                name.to_string(),
+            ),
+            (Some(_), Some(_)) => {
+                eprintln!("'{}' is not a class.", global);
+                return;
+            }
+            (None, _) => {
+                eprintln!("No global named '{}'.", global);
+                return;
+            }
+        };
+        println!("{}", declaration);
+        if let Some(doc) = self.interpreter.docs.get(&key) {
+            println!("{}", doc);
+        }
+    }
+
+    fn print_type(&mut self, expression: &str) {
+        let result = self.eval_source(expression, None);
+        if let Ok(value) = self.record(result) {
+            println!("{}", value.type_name());
+        }
+        self.had_error = false;
+        self.had_runtime_error = false;
+    }
+
+    fn print_frames(&self) {
+        let frames = self.error_frames();
+        if frames.is_empty() {
+            println!("No runtime error to inspect.");
+        }
+        for (index, frame) in frames.iter().enumerate() {
+            println!("#{} {} [line {}]", index, frame.function, frame.line);
+        }
+    }
+
+    fn eval_at_frame(&mut self, index: &str, expression: &str) {
+        let frame = match index.parse::<usize>() {
+            Ok(index) if index < self.error_frames().len() => self.error_frames()[index].clone(),
+            _ => {
+                eprintln!("No frame '{}', see ':frames'.", index);
+                return;
+            }
+        };
+        let result = self
+            .eval_in_frame_source(expression, &frame)
+            .and_then(|value| {
+                // The expression is scanned on its own, from line 1.
+                let paren = Token::new(TokenType::RightParen, ")", 1, 1);
+                self.interpreter.stringify(&value, &paren)
+            });
+        if let Ok(text) = self.record(result) {
+            println!("{}", text);
+        }
+        self.had_error = false;
+        self.had_runtime_error = false;
+    }
+
+    // The globals, or the ones with the given names, and everything they refer
+    // to as text for load_snapshot(). See snapshot.rs for what can be saved.
+    // See Interpreter::classes.
+    pub fn classes(&self) -> Vec<ClassInfo> {
+        self.interpreter.classes()
+    }
+
+    pub fn save_snapshot(&self, names: Option<&[String]>) -> Result<String, Error> {
+        snapshot::save(&self.interpreter, names)
+    }
+
+    pub fn load_snapshot(&mut self, snapshot: &str) -> Result<(), Error> {
+        snapshot::load(&mut self.interpreter, snapshot)
+    }
+
+    // Runs the source and reports runtime errors. Compile errors are reported
+    // as they are found by the scanner, parser and resolver. Either kind is
+    // also returned so callers can react to them.
+    pub fn run(&mut self, source: String) -> Result<(), Error> {
+        let result = self.run_source(source);
+        self.record(result)
+    }
+
+    // Runs the source like run and returns the value of its last statement if
+    // that is an expression statement, nil otherwise. The semicolon at the end
+    // can be left out, so a configuration file can be a single expression:
+    //
+    //   let value = lox.eval("1 + 2".to_string())?; // Object::Number(3.0)
+    pub fn eval(&mut self, source: String) -> Result<Object, Error> {
+        let result = self.eval_source(&source, None);
+        self.record(result)
+    }
+
+    // The frames of the last runtime error, innermost first, to look at with
+    // eval_in_frame.
+    pub fn error_frames(&self) -> &[Frame] {
+        self.interpreter.error_frames()
+    }
+
+    // Evaluates an expression in a frame of the last runtime error, where its
+    // variables, `this` included, are visible:
+    //
+    //   let frame = lox.error_frames()[0].clone();
+    //   let value = lox.eval_in_frame("count + 1", &frame)?;
+    pub fn eval_in_frame(&mut self, source: &str, frame: &Frame) -> Result<Object, Error> {
+        let result = self.eval_in_frame_source(source, frame);
+        self.record(result)
+    }
+
+    fn eval_in_frame_source(&mut self, source: &str, frame: &Frame) -> Result<Object, Error> {
+        self.line_map = None;
+        let mut scanner = Scanner::with_limits(source, self.literal_limits);
+        let mut tokens = scanner.scan_tokens();
+        terminate(&mut tokens);
+        let statements = self.parse_tokens(source, tokens, scanner.diagnostics)?;
+        let [Stmt::Expression { expression }] = &statements[..] else {
+            eprintln!("Only an expression can be evaluated in a frame.");
+            return Err(Error::Parse);
+        };
+
+        let mut resolver = Resolver::new(&mut self.interpreter);
+        resolver.resolve_in(expression, &frame.environment);
+        if resolver.had_error {
+            let diagnostics = std::mem::take(&mut resolver.diagnostics);
+            self.report(source, &diagnostics);
+            return Err(Error::Parse);
+        }
+        self.interpreter.eval_in_env(expression, &frame.environment)
+    }
+
+    // See Interpreter::new_environment.
+    pub fn new_environment(&self) -> Rc<RefCell<Environment>> {
+        self.interpreter.new_environment()
+    }
+
+    // Like eval with the environment as the globals of the source, see
+    // Interpreter::run_with_env. Hosts run the scripts of different users
+    // in environments of their own, and call the functions of one with an
+    // expression run in its environment:
+    //
+    //   let sandbox = lox.new_environment();
+    //   sandbox.borrow_mut().define("user".to_string(), name);
+    //   lox.run_with_env(script, &sandbox)?;
+    //   lox.run_with_env("greet()".to_string(), &sandbox)?;
+    pub fn run_with_env(
+        &mut self,
+        source: String,
+        environment: &Rc<RefCell<Environment>>,
+    ) -> Result<Object, Error> {
+        // The resolver checks global names against the environment too.
+        let globals = std::mem::replace(&mut self.interpreter.globals, Rc::clone(environment));
+        let result = self.eval_source(&source, Some(environment));
+        self.interpreter.globals = globals;
+        self.record(result)
+    }
+
+    fn eval_source(
+        &mut self,
+        source: &str,
+        environment: Option<&Rc<RefCell<Environment>>>,
+    ) -> Result<Object, Error> {
+        self.line_map = None;
+        let mut scanner = Scanner::with_limits(source, self.literal_limits);
+        scanner.keep_doc_comments();
+        let mut tokens = debug_span!("scan").in_scope(|| scanner.scan_tokens());
+        terminate(&mut tokens);
+
+        let (statements, _) = self.compile_tokens(source, tokens, scanner.diagnostics, false)?;
+        self.record_docs(&statements, &scanner.docs);
+        let _span = debug_span!("execute").entered();
+        match environment {
+            Some(environment) => self.interpreter.run_with_env(&statements, environment),
+            None => self.interpreter.interpret_value(&statements),
+        }
+    }
+
+    // Remembers and reports the error, if any, of running a program.
+    fn record<T>(&mut self, result: Result<T, Error>) -> Result<T, Error> {
+        match result {
+            Err(Error::Parse) => self.had_error = true,
+            Err(Error::Runtime {
+                ref token,
+                ref message,
+                ..
+            }) if self.jlox => {
+                self.runtime_error(token, message);
+                self.had_runtime_error = true;
+            }
+            Err(ref error @ Error::Runtime { ref token, .. }) => {
+                self.runtime_error(token, &error.to_string());
+                self.had_runtime_error = true;
+            }
+            Err(Error::Throw {
+                ref token,
+                ref value,
+            }) => {
+                self.runtime_error(token, &uncaught(value));
+                self.had_runtime_error = true;
+            }
+            Err(Error::ResourceLimit { ref message }) => {
+                eprintln!("{}", message);
+                self.had_runtime_error = true;
+            }
+            Err(ref error @ Error::Cancelled { ref token }) => {
+                self.runtime_error(token, &error.to_string());
+                self.had_runtime_error = true;
+            }
+            _ => (),
+        }
+        result
+    }
+
+    fn run_source(&mut self, source: String) -> Result<(), Error> {
+        let (statements, _) = self.compile(&source, false)?;
+        let statements = self.shake(statements);
+        self.execute(&source, &statements)
+    }
+
+    // Runs the program with the interpreter, or compiles it for the VM and
+    // runs it there. What the VM doesn't support is reported like a compile
+    // error.
+    fn execute(&mut self, source: &str, statements: &[Stmt]) -> Result<(), Error> {
+        if !self.vm {
+            let _span = debug_span!("execute").entered();
+            return self.interpreter.interpret(statements);
+        }
+        let script = match debug_span!("bytecode").in_scope(|| compiler::compile(statements)) {
+            Ok(script) => script,
+            Err(diagnostics) => {
+                self.report(source, &diagnostics);
+                return Err(Error::Parse);
+            }
+        };
+        let _span = debug_span!("execute").entered();
+        Vm::new(&mut self.interpreter).run(script)
+    }
+
+    // Like run but stops after the static passes, so nothing is executed.
+    // Returns Error::Parse if any of them reported an error.
+    pub fn check(&mut self, source: String) -> Result<(), Error> {
+        let result = self.compile(&source, false).map(|_| ());
+        if let Err(Error::Parse) = result {
+            self.had_error = true;
+        }
+        result
+    }
+
+    fn shake(&self, statements: Vec<Stmt>) -> Vec<Stmt> {
+        if !self.tree_shake {
+            return statements;
+        }
+        let shaken = debug_span!("shake").in_scope(|| shake::shake(statements));
+        debug!(removed = shaken.removed.len(), "shaken");
+        if self.why_kept {
+            for kept in &shaken.kept {
+                match &kept.reason {
+                    Reason::TopLevel(line) => eprintln!(
+                        "kept {}: used by the top level at {}",
+                        kept.name,
+                        self.describe_line(*line)
+                    ),
+                    Reason::Declaration(by, line) => eprintln!(
+                        "kept {}: used by {} at {}",
+                        kept.name,
+                        by,
+                        self.describe_line(*line)
+                    ),
+                    Reason::Always => eprintln!("kept {}: can't tell if it is used", kept.name),
+                }
+            }
+            for removed in &shaken.removed {
+                eprintln!("removed {}", removed);
+            }
+        }
+        shaken.statements
+    }
+
+    // "line 3", or "line 3 of lib.lox" in a preprocessed program.
+    fn describe_line(&self, line: i32) -> String {
+        match self.line_map.as_ref().and_then(|map| map.locate(line)) {
+            Some((file, line)) => format!("line {} of {}", line, file.name),
+            None => format!("line {}", line),
+        }
+    }
+
+    // Every error the static passes find in the source, for editors showing
+    // them all at once instead of the printed report. Nothing is run and
+    // nothing is printed. The resolver only looks at a program without syntax
+    // errors, like when running it.
+    pub fn diagnostics(&mut self, source: String) -> Vec<Diagnostic> {
+        self.collected = Some(Vec::new());
+        let _ = self.compile(&source, false);
+        self.collected.take().unwrap_or_default()
+    }
+
+    pub fn check_file(&mut self, file_path: &str) -> Result<(), Error> {
+        if !self.preprocess {
+            let contents = read_file(file_path)?;
+            return self.check(contents);
+        }
+
+        let result = self
+            .preprocess(file_path)
+            .and_then(|(tokens, diagnostics)| {
+                self.compile_tokens("", tokens, diagnostics, false)
+                    .map(|_| ())
+            });
+        if let Err(Error::Parse) = result {
+            self.had_error = true;
+        }
+        result
+    }
+
+    // Runs the file through the preprocessor and keeps its line map for
+    // reporting errors in the program it puts together.
+    fn preprocess(&mut self, file_path: &str) -> Result<(Vec<Token>, Vec<Diagnostic>), Error> {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.set_literal_limits(self.literal_limits);
+        if self.include_cache {
+            preprocessor.set_cache(IncludeCache::new());
+        }
+        let tokens = debug_span!("preprocess").in_scope(|| preprocessor.preprocess(file_path))?;
+        self.line_map = Some(preprocessor.take_line_map());
+        Ok((tokens, preprocessor.diagnostics))
+    }
+
+    // Scans, parses and resolves the source without running it and returns
+    // every declaration found together with the places referring to it.
+    pub fn symbols(&mut self, source: String) -> Result<SymbolTable, Error> {
+        let (_, symbols) = self.compile(&source, true)?;
+
+        Ok(symbols.expect("Resolver was created with a symbol table."))
+    }
+
+    // What the resolver decided for every use of a name in the source, see
+    // rename::explain_resolution.
+    pub fn explain_resolution(&mut self, source: String) -> Result<String, Error> {
+        let symbols = self.symbols(source)?;
+        let globals = self.interpreter.globals.borrow();
+        Ok(rename::explain_resolution(&symbols, |name| {
+            globals.contains(name)
+        }))
+    }
+
+    // Why renaming the symbol to the new name would change the program, see
+    // rename::rename_conflict.
+    pub fn rename_conflict(
+        &self,
+        symbols: &SymbolTable,
+        symbol: &Symbol,
+        new_name: &str,
+    ) -> Option<String> {
+        let globals = self.interpreter.globals.borrow();
+        rename::rename_conflict(symbols, symbol, new_name, |name| globals.contains(name))
+    }
+
+    // The statements of the source after every static pass, without running
+    // them. Returns Error::Parse if any of the passes reported an error.
+    pub fn parse(&mut self, source: String) -> Result<Vec<Stmt>, Error> {
+        let (statements, _) = self.compile(&source, false)?;
+
+        Ok(statements)
+    }
+
+    // Like parse, for a script read from the file, preprocessed first with
+    // --preprocess.
+    pub fn parse_file(&mut self, file_path: &str) -> Result<Vec<Stmt>, Error> {
+        if !self.preprocess {
+            return self.parse(read_file(file_path)?);
+        }
+        let (tokens, diagnostics) = self.preprocess(file_path)?;
+        let (statements, _) = self.compile_tokens("", tokens, diagnostics, false)?;
+
+        Ok(statements)
+    }
+
+    // Which files the script includes with the preprocessor, without parsing
+    // any of them.
+    pub fn include_graph(&mut self, file_path: &str) -> Result<IncludeGraph, Error> {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.set_literal_limits(self.literal_limits);
+        preprocessor.preprocess(file_path)?;
+        let graph = preprocessor.include_graph();
+        self.line_map = Some(preprocessor.take_line_map());
+        if preprocessor.had_error() {
+            self.report("", &preprocessor.diagnostics);
+            return Err(Error::Parse);
+        }
+
+        Ok(graph)
+    }
+
+    // Compiles the source without running it and measures the size and shape
+    // of the program.
+    pub fn stats(&mut self, source: String) -> Result<Stats, Error> {
+        let (statements, _) = self.compile(&source, false)?;
+
+        Ok(Stats::new(&statements))
+    }
+
+    // The static passes every mode goes through before anything runs. All
+    // diagnostics are reported here and turned into Error::Parse.
+    fn compile(
+        &mut self,
+        source: &str,
+        collect_symbols: bool,
+    ) -> Result<(Vec<Stmt>, Option<SymbolTable>), Error> {
+        // Plain source is one file, there is nothing to map.
+        self.line_map = None;
+        let mut scanner = Scanner::with_limits(source, self.literal_limits);
+        scanner.keep_doc_comments();
+        let tokens = debug_span!("scan").in_scope(|| scanner.scan_tokens());
+        debug!(tokens = tokens.len(), "scanned");
+
+        let compiled = self.compile_tokens(source, tokens, scanner.diagnostics, collect_symbols)?;
+        self.record_docs(&compiled.0, &scanner.docs);
+        Ok(compiled)
+    }
+
+    // Remembers the doc comments of the declarations at the top level and of
+    // their methods. A name declared again without one loses its doc.
+    fn record_docs(&mut self, statements: &[Stmt], docs: &HashMap<Span, String>) {
+        let mut record = |key: String, name: &Token| match docs.get(&name.span()) {
+            Some(doc) => self.interpreter.docs.insert(key, doc.clone()),
+            None => self.interpreter.docs.remove(&key),
+        };
+        for statement in statements {
+            match statement {
+                Stmt::Function { name, .. } | Stmt::Var { name, .. } => {
+                    record(name.lexeme.clone(), name);
+                }
+                Stmt::Class { name, methods, .. } => {
+                    record(name.lexeme.clone(), name);
+                    for method in methods {
+                        if let Stmt::Function { name: method, .. } = method {
+                            record(format!("{}.{}", name.lexeme, method.lexeme), method);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // Everything compile does after scanning. `diagnostics` are the errors
+    // found while producing the tokens. The source is only used to show the
+    // offending lines, the line map takes over for preprocessed programs.
+    fn compile_tokens(
+        &mut self,
+        source: &str,
+        tokens: Vec<Token>,
+        diagnostics: Vec<Diagnostic>,
+        collect_symbols: bool,
+    ) -> Result<(Vec<Stmt>, Option<SymbolTable>), Error> {
+        let statements = self.parse_tokens(source, tokens, diagnostics)?;
+
+        // We don’t run the resolver if there are any parse errors. If the code
+        // has a syntax error, it’s never going to run, so there’s little value
+        // in resolving it. If the syntax is clean, we tell the resolver to do
+        // its thing. The resolver has a reference to the interpreter and pokes
+        // the resolution data directly into it as it walks over variables. When
+        // the interpreter runs next, it has everything it needs.
+        let mut resolver = if collect_symbols {
+            Resolver::with_symbols(&mut self.interpreter)
+        } else {
+            Resolver::new(&mut self.interpreter)
+        };
+        resolver.strict = self.strict;
+        resolver.jlox = self.jlox;
+        debug_span!("resolve").in_scope(|| resolver.resolve(&statements));
+
+        if resolver.had_error {
+            let diagnostics = std::mem::take(&mut resolver.diagnostics);
+            self.report(source, &diagnostics);
+            return Err(Error::Parse);
+        }
+
+        // We could go farther and report warnings for code that isn’t
+        // necessarily wrong but probably isn’t useful. For example, many IDEs
+        // will warn if you have unreachable code after a return statement, or a
+        // local variable whose value is never read. All of that would be pretty
+        // easy to add to our static visiting pass, or as separate passes.
+
+        let symbols = resolver.take_symbols();
+        Ok((statements, symbols))
+    }
+
+    // Parses the tokens and reports the errors of the scanner and the parser.
+    fn parse_tokens(
+        &mut self,
+        source: &str,
+        tokens: Vec<Token>,
+        diagnostics: Vec<Diagnostic>,
+    ) -> Result<Vec<Stmt>, Error> {
+        let mut parser = Parser::new(TokenStream::new(tokens));
+        parser.set_features(self.features);
+        let statements = debug_span!("parse").in_scope(|| parser.parse())?;
+        debug!(statements = statements.len(), "parsed");
+
+        // Stop if there was a syntax error.
+        if !diagnostics.is_empty() || parser.had_error() {
+            self.report(source, &diagnostics);
+            self.report(source, &parser.take_diagnostics());
+            return Err(Error::Parse);
+        }
+        Ok(statements)
+    }
+
+    fn report(&mut self, source: &str, diagnostics: &[Diagnostic]) {
+        if let Some(collected) = self.collected.as_mut() {
+            collected.extend_from_slice(diagnostics);
+            return;
+        }
+        for diagnostic in diagnostics {
+            if self.jlox {
+                eprintln!("{}", diagnostic.render_jlox());
+                continue;
+            }
+            let located = self
+                .line_map
+                .as_ref()
+                .and_then(|line_map| line_map.locate(diagnostic.line));
+            match located {
+                Some((file, line)) => {
+                    let diagnostic = Diagnostic {
+                        line,
+                        file: Some(file.name.clone()),
+                        ..diagnostic.clone()
+                    };
+                    eprintln!("{}", diagnostic.render(&file.contents, self.tab_width));
+                }
+                None => eprintln!("{}", diagnostic.render(source, self.tab_width)),
+            }
+        }
+    }
+
+    fn runtime_error(&self, token: &Token, message: &str) {
+        let located = self
+            .line_map
+            .as_ref()
+            .and_then(|line_map| line_map.locate(token.line));
+        match located {
+            Some((file, line)) => eprintln!("{}\n[line {} in {}]", message, line, file.name),
+            None => runtime_error(token, message),
+        }
+    }
+}
+
+// What :help shows.
+const REPL_HELP: &str = "\
+Type Lox statements and expressions, the value of an expression is shown.
+A line left incomplete continues on the next one, an empty line runs it as is.
+
+:help                           This list
+:doc <name>                     How a global or Class.method is declared, with its /// comment
+:type <expression>              The type of the value of the expression
+:frames                         The calls the last runtime error unwound through
+:eval-at-frame <n> <expression> Evaluates the expression with the variables of frame n
+:save <file> [name...]          Writes the globals, or the ones named, to a file
+:load <file>                    Defines the globals saved in the file again
+
+Ctrl-C stops a running program, Ctrl-D quits.
+";
+
+// Adds the semicolon left out after the last statement of a source passed to
+// eval. Statements ending in a block don't need one, and after an unclosed
+// brace one would only add a confusing error.
+fn terminate(tokens: &mut Vec<Token>) {
+    let Some(eof) = tokens.pop() else {
+        return;
+    };
+    if let Some(last) = tokens.last() {
+        if !matches!(
+            last.token_type,
+            TokenType::Semicolon | TokenType::LeftBrace | TokenType::RightBrace
+        ) {
+            let semicolon = Token::new(TokenType::Semicolon, ";", last.line, last.column);
+            tokens.push(semicolon);
+        }
+    }
+    tokens.push(eof);
+}
+
+// Whether the input typed into the REPL stops in the middle of a statement
+// that the next line continues: a parenthesis, brace or bracket is still open,
+// a string isn't closed, a doc comment waits for its declaration or the parser
+// ran out of tokens, like after `if (ready)`, `fun add(a, b)` or `1 +`. The
+// semicolon an expression may leave out doesn't count as missing.
+fn is_incomplete(source: &str) -> bool {
+    let mut scanner = Scanner::new(source);
+    scanner.keep_doc_comments();
+    let tokens = scanner.scan_tokens();
+    if scanner.has_pending_doc()
+        || scanner
+            .diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.code == Code::UnterminatedString)
+    {
+        return true;
+    }
+
+    let mut open = 0;
+    for token in &tokens {
+        match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => open += 1,
+            TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => open -= 1,
+            _ => (),
+        }
+    }
+    if open > 0 {
+        return true;
+    }
+
+    let mut terminated = tokens.clone();
+    terminate(&mut terminated);
+    fails_at_end(tokens) && !parses(terminated)
+}
+
+// Whether the first syntax error in the tokens is at their end.
+fn fails_at_end(tokens: Vec<Token>) -> bool {
+    let mut parser = Parser::new(TokenStream::new(tokens));
+    let _ = parser.parse();
+    parser
+        .take_diagnostics()
+        .first()
+        .is_some_and(|diagnostic| diagnostic.location == " at end")
+}
+
+fn parses(tokens: Vec<Token>) -> bool {
+    let mut parser = Parser::new(TokenStream::new(tokens));
+    parser.parse().is_ok() && !parser.had_error()
+}
+
+// Lox source files are UTF-8. Invalid files are reported as an IO error that
+// says where the first bad byte is instead of the generic message.
+pub fn read_file(file_path: &str) -> Result<String, Error> {
+    let mut file = File::open(file_path)?;
+    let mut contents = Vec::new();
+
+    file.read_to_end(&mut contents)?;
+
+    String::from_utf8(contents).map_err(|error| {
+        Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "file is not valid UTF-8 (invalid byte at offset {})",
+                error.utf8_error().valid_up_to()
+            ),
+        ))
+    })
+}
+
+// How a function is called, `greet(person)`. Natives say it themselves.
+fn signature(function: &Function) -> String {
+    match function {
+        Function::Native { signature, .. } => signature.to_string(),
+        Function::User { name, params, .. } => {
+            let params: Vec<&str> = params.iter().map(|param| &*param.lexeme).collect();
+            format!("{}({})", name.lexeme, params.join(", "))
+        }
+    }
+}
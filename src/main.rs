@@ -1,107 +1,628 @@
-mod class;
-mod environment;
-mod error;
-mod function;
-mod interpreter;
-mod object;
-mod parser;
-mod resolver;
-mod scanner;
-mod syntax;
-mod token;
+#![allow(clippy::result_large_err)]
 
 use std::env;
-use std::fs::File;
-use std::io::{self, BufRead, Read};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
 use std::process::exit;
+use std::sync::atomic::Ordering;
+use std::thread;
 
-use error::Error;
-use interpreter::Interpreter;
-use parser::Parser;
-use resolver::Resolver;
-use scanner::Scanner;
+use lox_interpreter_rs::ast_graph::AstGraph;
+use lox_interpreter_rs::bench::bench;
+use lox_interpreter_rs::call_graph::CallGraph;
+use lox_interpreter_rs::difftest::difftest;
+use lox_interpreter_rs::error::Error;
+use lox_interpreter_rs::features::Features;
+use lox_interpreter_rs::interpreter::STACK_SIZE;
+use lox_interpreter_rs::lox::{read_file, Lox};
+use lox_interpreter_rs::recorder::Replay;
+use lox_interpreter_rs::rename::{explain_bindings, is_identifier, rename};
+use lox_interpreter_rs::symbol::SymbolKind;
+use lox_interpreter_rs::syntax::AstPrinter;
+use lox_interpreter_rs::token::Span;
+use tracing::warn;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
 
-struct Lox {
-    interpreter: Interpreter,
+// Scripts run on a thread of their own, with a stack deep enough for the
+// interpreter's CALLS_MAX calls.
+fn main() {
+    let main = thread::Builder::new()
+        .name("main".to_string())
+        .stack_size(STACK_SIZE)
+        .spawn(|| {
+            if let Err(error) = run() {
+                eprintln!("Error: {:?}", error);
+                exit(1)
+            }
+        });
+    match main.map(|main| main.join()) {
+        Ok(Ok(())) => (),
+        Ok(Err(_)) => exit(101),
+        Err(error) => {
+            eprintln!("Could not start the interpreter: {}", error);
+            exit(71)
+        }
+    }
 }
 
-impl Lox {
-    fn new() -> Self {
-        Lox {
-            interpreter: Interpreter::new(),
+fn run() -> Result<(), Box<dyn std::error::Error + 'static>> {
+    let mut args: Vec<String> = env::args().collect();
+    let verbose = args.iter().any(|arg| arg == "--verbose");
+    let strict = args.iter().any(|arg| arg == "--strict");
+    let preprocess = args.iter().any(|arg| arg == "--preprocess");
+    let book = args.iter().any(|arg| arg == "--book");
+    let allow_reopen = args.iter().any(|arg| arg == "--allow-reopen");
+    let hoist = args.iter().any(|arg| arg == "--hoist");
+    let no_cache = args.iter().any(|arg| arg == "--no-cache");
+    let tree_shake = args.iter().any(|arg| arg == "--tree-shake");
+    let why_kept = args.iter().any(|arg| arg == "--why-kept");
+    let deny_file_writes = args.iter().any(|arg| arg == "--deny-file-writes");
+    let vm = args.iter().any(|arg| arg == "--vm");
+    let jlox = args.iter().any(|arg| arg == "--jlox");
+    args.retain(|arg| {
+        arg != "--verbose"
+            && arg != "--strict"
+            && arg != "--preprocess"
+            && arg != "--book"
+            && arg != "--allow-reopen"
+            && arg != "--hoist"
+            && arg != "--no-cache"
+            && arg != "--tree-shake"
+            && arg != "--why-kept"
+            && arg != "--deny-file-writes"
+            && arg != "--vm"
+            && arg != "--jlox"
+    });
+    let record = take_option(&mut args, "--record", "a file name");
+    let inline = take_option(&mut args, "-e", "a program");
+    let mut features = if book { Features::BOOK } else { Features::ALL };
+    if let Some(disabled) = take_option(&mut args, "--disable", "a list of features") {
+        for name in disabled.split(',') {
+            if !features.disable(name) {
+                eprintln!(
+                    "Unknown feature '{}', expected one of {}.",
+                    name,
+                    Features::NAMES.join(", ")
+                );
+                exit(64)
+            }
+        }
+    }
+    let seed = take_option(&mut args, "--seed", "a number").map(|seed| match seed.parse() {
+        Ok(seed) => seed,
+        Err(_) => {
+            eprintln!("--seed expects a number but got '{}'.", seed);
+            exit(64)
+        }
+    });
+    let max_output = take_option(&mut args, "--max-output", "a number of bytes").map(|bytes| {
+        match bytes.parse() {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                eprintln!(
+                    "--max-output expects a number of bytes but got '{}'.",
+                    bytes
+                );
+                exit(64)
+            }
+        }
+    });
+    init_logging(verbose);
+
+    let mut builder = Lox::builder()
+        .strict(strict)
+        .preprocess(preprocess)
+        .include_cache(!no_cache)
+        .features(features)
+        .allow_reopen(allow_reopen)
+        .hoist(hoist)
+        .with_io(!deny_file_writes)
+        .tree_shake(tree_shake, why_kept)
+        .vm(vm)
+        .jlox(jlox);
+    if let Some(seed) = seed {
+        builder = builder.random_seed(seed);
+    }
+    if let Some(bytes) = max_output {
+        builder = builder.max_output_bytes(bytes);
+    }
+    let mut lox = builder.build();
+    handle_interrupts(&lox);
+    if let Some(path) = &record {
+        if let Err(Error::Io(error)) = lox.start_recording(path) {
+            write_error(path, &error);
+        }
+    }
+    if let Some(source) = inline {
+        if args.len() > 1 {
+            usage()
+        }
+        let _ = lox.run(source);
+        finish_recording(&mut lox, &record);
+        exit_on_errors(&lox);
+        return Ok(());
+    }
+    match &args[..] {
+        [_, flag, file_path] if flag == "--symbols" => match lox.symbols(read_source(file_path)) {
+            Ok(symbols) => print!("{}", symbols),
+            Err(_) => exit(65),
+        },
+        [_, flag, file_path] if flag == "--explain-bindings" => {
+            let source = read_source(file_path);
+            match lox.symbols(source.clone()) {
+                Ok(symbols) => println!("{}", explain_bindings(&source, &symbols)),
+                Err(_) => exit(65),
+            }
+        }
+        [_, flag, file_path] if flag == "--explain-resolution" => {
+            match lox.explain_resolution(read_source(file_path)) {
+                Ok(explanation) => print!("{}", explanation),
+                Err(_) => exit(65),
+            }
+        }
+        [_, command, file_paths @ ..] if command == "check" && !file_paths.is_empty() => {
+            for file_path in file_paths {
+                if let Err(Error::Io(error)) = lox.check_file(file_path) {
+                    io_error(file_path, &error);
+                }
+            }
+            if lox.had_error() {
+                exit(65)
+            }
+        }
+        [_, flag, output, file_path] if flag == "--ast-graph" => {
+            write_ast_graph(&mut lox, output, file_path)
+        }
+        [_, command, file_path] if command == "ast" => match lox.parse(read_source(file_path)) {
+            Ok(statements) => {
+                for statement in &statements {
+                    println!("{}", AstPrinter.print(statement));
+                }
+            }
+            Err(_) => exit(65),
+        },
+        [_, command, file_path] if command == "stats" => match lox.stats(read_source(file_path)) {
+            Ok(stats) => print!("{}", stats),
+            Err(_) => exit(65),
+        },
+        [_, command, file_path] if command == "eval" => match lox.eval(read_source(file_path)) {
+            Ok(value) => println!("{}", value),
+            Err(Error::Parse) => exit(65),
+            Err(_) => exit(70),
+        },
+        [_, command, dir, reference @ ..] if command == "difftest" => run_difftest(dir, reference),
+        [_, command, file_path, counts @ ..] if command == "bench" && counts.len() <= 2 => {
+            run_bench(file_path, counts)
+        }
+        [_, command, file_path, options @ ..] if command == "profile" && options.len() <= 2 => {
+            run_profile(&mut lox, file_path, options)
+        }
+        [_, command, options @ .., file_path] if command == "graph" && options.len() <= 2 => {
+            run_graph(&mut lox, file_path, options)
+        }
+        [_, command, file_path, position, new_name] if command == "rename" => {
+            run_rename(&mut lox, file_path, position, new_name)
+        }
+        [_, flag, file_path] if flag == "--replay" => run_replay(file_path),
+        [_, flag] if flag == "--help" || flag == "-h" => help(),
+        [_, flag] if flag == "--version" || flag == "-V" => {
+            println!("lox-rs {}", lox_interpreter_rs::VERSION)
+        }
+        [_, file_path] => {
+            if let Err(Error::Io(error)) = lox.run_file(file_path) {
+                io_error(file_path, &error);
+            }
+            finish_recording(&mut lox, &record);
+            exit_on_errors(&lox);
         }
+        [_] => {
+            lox.run_prompt()?;
+            finish_recording(&mut lox, &record);
+        }
+        _ => usage(),
     }
+    Ok(())
+}
 
-    fn run_file(&mut self, file_path: &String) -> Result<(), Error> {
-        let mut file = File::open(file_path)?;
-        let mut contents = String::new();
+// How the command line is used, printed by usage() and --help.
+const USAGE: &str = "\
+Usage: lox-rs [options] [script]
+       lox-rs [options] -e <program>
+       lox-rs --replay <events.json>
+       lox-rs --ast-graph <out.dot|out.html> <script>
+       lox-rs --symbols <script>
+       lox-rs --explain-bindings <script>
+       lox-rs --explain-resolution <script>
+       lox-rs check <script>...
+       lox-rs ast <script>
+       lox-rs stats <script>
+       lox-rs profile <script> [top] [folded-stacks-file]
+       lox-rs eval <script>
+       lox-rs difftest <dir> [reference command...]
+       lox-rs bench <script> [iterations] [warmup]
+       lox-rs rename <script> <line>:<column> <new-name>
+       lox-rs graph [--format=dot|text] [--includes] <script>
+       lox-rs --help | --version
+";
 
-        file.read_to_string(&mut contents)?;
+const OPTIONS: &str = "\
+Without a script or program the REPL starts, type :help in it for its commands.
 
-        self.run(contents)
+Options:
+  --strict                 Redeclaring a global is an error, and so is integer precision loss
+  --preprocess             Run #include and #define first
+  --book                   Only the Lox of the book, without the extensions
+  --disable <feature,...>  Turn off extensions: power, safe-navigation, exceptions, match, defer
+  --jlox                   Behave like jlox: the book's Lox, its output and its error messages
+  --allow-reopen           Declaring a class again adds to its methods
+  --hoist                  Run top-level function and class declarations first
+  --no-cache               Scan every file --preprocess reads instead of using the cache
+  --tree-shake             Leave out the functions and classes a program never uses
+  --why-kept               Tree shake and say why every declaration was kept
+  --deny-file-writes       Scripts can't write files
+  --vm                     Compile scripts to bytecode and run them on the VM
+  --seed <n>               Make random() give the same numbers on every run
+  --max-output <bytes>     Stop a script that prints more than that
+  --record <events.json>   Write an event log of the run for --replay
+  --verbose                Log every pass and call to stderr
+  -h, --help               Print this help
+  -V, --version            Print the version
+
+Exit codes: 65 compile error, 70 runtime error, 74 I/O error, 64 usage error.
+";
+
+// The exit codes of sysexits.h, like jlox: 64 for a wrong command line, 65
+// for a script with a syntax or resolution error, 70 for a runtime error and
+// 74 for a file that can't be read or written.
+fn usage() -> ! {
+    eprint!("{}", USAGE);
+    eprintln!("Run 'lox-rs --help' for the options.");
+    exit(64)
+}
+
+fn help() {
+    print!("{}\n{}", USAGE, OPTIONS);
+}
+
+fn exit_on_errors(lox: &Lox) {
+    if lox.had_error() {
+        exit(65)
     }
+    if lox.had_runtime_error() {
+        exit(70)
+    }
+}
 
-    fn run_prompt(&mut self) -> Result<(), Error> {
-        let stdin = io::stdin();
+// Logs go to stderr so they never mix with what the script prints. RUST_LOG
+// takes precedence, --verbose is a shorthand for seeing every pass and call.
+fn init_logging(verbose: bool) {
+    let default = if verbose { "trace" } else { "warn" };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .init();
+}
 
-        let handle = stdin.lock();
+// Ctrl-C stops the running program instead of the whole process, so the REPL
+// survives an endless loop. Pressing it again before the program noticed the
+// first one, or while the REPL waits for input, exits.
+fn handle_interrupts(lox: &Lox) {
+    let interrupted = lox.interrupt_flag();
+    let result = ctrlc::set_handler(move || {
+        if interrupted.swap(true, Ordering::Relaxed) {
+            exit(130)
+        }
+    });
+    if let Err(error) = result {
+        warn!("Could not install the Ctrl-C handler: {}", error);
+    }
+}
+
+// The reference is a command line like `java -jar jlox.jar`, given after the
+// directory or in LOX_REFERENCE. Exits with 1 if any script diverged.
+fn run_difftest(dir: &str, reference: &[String]) {
+    let reference: Vec<String> = if reference.is_empty() {
+        env::var("LOX_REFERENCE")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(String::from)
+            .collect()
+    } else {
+        reference.to_vec()
+    };
+    if reference.is_empty() {
+        eprintln!(
+            "No reference interpreter, pass its command after the directory or set LOX_REFERENCE."
+        );
+        exit(64)
+    }
 
-        for line in handle.lines() {
-            self.run(line?)?;
-            print!("> ");
+    let ours = match env::current_exe() {
+        Ok(path) => vec![path.display().to_string()],
+        Err(error) => {
+            eprintln!("Could not find the interpreter itself: {}", error);
+            exit(74)
         }
+    };
+    match difftest(Path::new(dir), &ours, &reference) {
+        Ok(report) => {
+            print!("{}", report);
+            if !report.divergences.is_empty() {
+                exit(1)
+            }
+        }
+        Err(error) => io_error(dir, &error),
+    }
+}
 
-        Ok(())
+// Runs the script 10 times after 2 warmup runs unless told otherwise, and
+// exits with 70 if a run fails.
+fn run_bench(file_path: &str, counts: &[String]) {
+    let count = |index: usize, default: usize| match counts.get(index) {
+        None => default,
+        Some(count) => count.parse().unwrap_or_else(|_| {
+            eprintln!("bench expects a number of runs but got '{}'.", count);
+            exit(64)
+        }),
+    };
+    // A script that can't be read fails like running it would.
+    read_source(file_path);
+    let (iterations, warmup) = (count(0, 10), count(1, 2));
+    if iterations == 0 {
+        eprintln!("bench needs at least one run.");
+        exit(64)
     }
 
-    fn run(&mut self, source: String) -> Result<(), Error> {
-        let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
+    let ours = match env::current_exe() {
+        Ok(path) => vec![path.display().to_string()],
+        Err(error) => {
+            eprintln!("Could not find the interpreter itself: {}", error);
+            exit(74)
+        }
+    };
+    match bench(&ours, Path::new(file_path), iterations, warmup) {
+        Ok(report) => print!("{}", report),
+        Err(error) if error.kind() == io::ErrorKind::Other => {
+            eprintln!("{}: {}", file_path, error);
+            exit(70)
+        }
+        Err(error) => io_error(file_path, &error),
+    }
+}
 
-        let mut parser = Parser::new(tokens);
-        let mut statements = parser.parse()?;
+// Runs the script with every call timed and prints the hottest functions to
+// stderr, apart from what the script prints. The folded call stacks for flame
+// graphs go to a file when one is given.
+fn run_profile(lox: &mut Lox, file_path: &str, options: &[String]) {
+    let top = match options.first() {
+        None => 10,
+        Some(top) => top.parse().unwrap_or_else(|_| {
+            eprintln!("profile expects a number of functions but got '{}'.", top);
+            exit(64)
+        }),
+    };
 
-        // We don’t run the resolver if there are any parse errors. If the code
-        // has a syntax error, it’s never going to run, so there’s little value
-        // in resolving it. If the syntax is clean, we tell the resolver to do
-        // its thing. The resolver has a reference to the interpreter and pokes
-        // the resolution data directly into it as it walks over variables. When
-        // the interpreter runs next, it has everything it needs.
-        let mut resolver = Resolver::new(&mut self.interpreter);
-        resolver.resolve_stmts(&statements);
+    lox.start_profiling();
+    if let Err(Error::Io(error)) = lox.run_file(file_path) {
+        io_error(file_path, &error);
+    }
+    // Nothing ran when the script didn't compile.
+    let profile = lox.finish_profiling().filter(|_| !lox.had_error());
+    if let Some(profile) = profile {
+        eprint!("{}", profile.top(top));
+        if let Some(output) = options.get(1) {
+            if let Err(error) = fs::write(output, profile.folded()) {
+                write_error(output, &error);
+            }
+        }
+    }
+    exit_on_errors(lox);
+}
 
-        if resolver.had_error {
-            return Ok(());
+// The call graph of the script, or with --includes which files include which,
+// printed for Graphviz or one edge a line.
+fn run_graph(lox: &mut Lox, file_path: &str, options: &[String]) {
+    let mut dot = true;
+    let mut includes = false;
+    for option in options {
+        match option.as_str() {
+            "--format=dot" => dot = true,
+            "--format=text" => dot = false,
+            "--includes" => includes = true,
+            _ => usage(),
         }
+    }
 
-        // We could go farther and report warnings for code that isn’t
-        // necessarily wrong but probably isn’t useful. For example, many IDEs
-        // will warn if you have unreachable code after a return statement, or a
-        // local variable whose value is never read. All of that would be pretty
-        // easy to add to our static visiting pass, or as separate passes.
+    let graph = if includes {
+        lox.include_graph(file_path).map(|graph| {
+            if dot {
+                graph.to_dot()
+            } else {
+                graph.to_string()
+            }
+        })
+    } else {
+        lox.parse_file(file_path).map(|statements| {
+            let graph = CallGraph::new(&statements);
+            if dot {
+                graph.to_dot()
+            } else {
+                graph.to_string()
+            }
+        })
+    };
+    match graph {
+        Ok(graph) => print!("{}", graph),
+        Err(Error::Io(error)) => io_error(file_path, &error),
+        Err(_) => exit(65),
+    }
+}
 
-        self.interpreter.interpret(&mut statements)?;
+// Written as an HTML page when the output ends in .html, as Graphviz otherwise.
+fn write_ast_graph(lox: &mut Lox, output: &str, file_path: &str) {
+    let statements = match lox.parse(read_source(file_path)) {
+        Ok(statements) => statements,
+        Err(_) => exit(65),
+    };
+    let graph = AstGraph::new(&statements);
+    let contents = if output.ends_with(".html") {
+        graph.to_html()
+    } else {
+        graph.to_dot()
+    };
+    if let Err(error) = fs::write(output, contents) {
+        write_error(output, &error);
+    }
+}
 
-        Ok(())
+// Removes an option and the value following it from the arguments.
+fn take_option(args: &mut Vec<String>, name: &str, value: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == name)?;
+    if index + 1 >= args.len() {
+        eprintln!("{} expects {}.", name, value);
+        exit(64)
     }
+    args.remove(index);
+    Some(args.remove(index))
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
-    let args: Vec<String> = env::args().collect();
-    let mut lox = Lox::new();
-    match &args[..] {
-        [_, file_path] => match lox.run_file(file_path) {
+fn finish_recording(lox: &mut Lox, record: &Option<String>) {
+    if let (Some(path), Err(Error::Io(error))) = (record, lox.stop_recording()) {
+        write_error(path, &error);
+    }
+}
+
+// Shows one event per press of Enter, `b` goes back one, `p name` shows the
+// value of a variable and `h name` every value it had so far, `c` shows the
+// rest and `q` stops. When the input isn't a terminal all events are printed
+// at once.
+fn run_replay(file_path: &str) {
+    let mut replay = match Replay::load(&read_source(file_path)) {
+        Ok(replay) => replay,
+        Err(message) => {
+            eprintln!("Invalid event log '{}': {}", file_path, message);
+            exit(65)
+        }
+    };
+    let total = replay.len();
+    let mut stepping = io::stdin().is_terminal();
+    if stepping {
+        println!(
+            "{} events. Enter: next, b: back, p <name>: value, h <name>: history, c: continue to the end, q: quit.",
+            total
+        );
+    }
+    let mut current = replay.next();
+    while let Some((number, event)) = &current {
+        if !stepping {
+            println!("{:>5} {}", number, event);
+            current = replay.next();
+            continue;
+        }
+        print!("{:>5}/{} {}", number, total, event);
+        let _ = io::stdout().flush();
+        let mut command = String::new();
+        match io::stdin().read_line(&mut command) {
+            Ok(0) | Err(_) => break,
             Ok(_) => (),
-            Err(Error::Runtime { .. }) => exit(70),
-            Err(Error::Return { .. }) => unreachable!(),
-            Err(Error::Parse) => exit(65),
-            Err(Error::Io(_)) => unimplemented!(),
+        }
+        match command.trim().split_once(' ') {
+            Some(("p", name)) => match replay.value(name.trim()) {
+                Some(write) => println!(
+                    "{} = {} (event {}, line {})",
+                    name.trim(),
+                    write.value,
+                    write.number,
+                    write.line
+                ),
+                None => println!("{} hasn't been written yet.", name.trim()),
+            },
+            Some(("h", name)) => {
+                for write in replay.history(name.trim()) {
+                    println!("{:>5} [line {}] {}", write.number, write.line, write.value);
+                }
+            }
+            _ => match command.trim() {
+                "q" => break,
+                "c" => {
+                    stepping = false;
+                    current = replay.next();
+                }
+                "b" => match replay.back() {
+                    Some(previous) => current = Some(previous),
+                    None => println!("This is the first event."),
+                },
+                _ => current = replay.next(),
+            },
+        }
+    }
+}
+
+fn write_error(file_path: &str, error: &io::Error) -> ! {
+    eprintln!("Could not write '{}': {}", file_path, error);
+    exit(74)
+}
+
+fn io_error(file_path: &str, error: &io::Error) -> ! {
+    eprintln!("Could not read '{}': {}", file_path, error);
+    exit(74)
+}
+
+fn read_source(file_path: &str) -> String {
+    match read_file(file_path) {
+        Ok(source) => source,
+        Err(Error::Io(error)) => io_error(file_path, &error),
+        Err(_) => unreachable!(),
+    }
+}
+
+fn parse_position(position: &str) -> Option<Span> {
+    let (line, column) = position.split_once(':')?;
+    Some(Span {
+        line: line.parse().ok()?,
+        column: column.parse().ok()?,
+    })
+}
+
+fn run_rename(lox: &mut Lox, file_path: &str, position: &str, new_name: &str) {
+    let position = parse_position(position).unwrap_or_else(|| {
+        eprintln!("Invalid position '{}', expected <line>:<column>.", position);
+        exit(64)
+    });
+    if !is_identifier(new_name) {
+        eprintln!("'{}' is not a valid identifier.", new_name);
+        exit(64)
+    }
+
+    let source = read_source(file_path);
+    let symbols = match lox.symbols(source.clone()) {
+        Ok(symbols) => symbols,
+        Err(_) => exit(65),
+    };
+
+    match symbols.symbol_at(position) {
+        Some(symbol) if symbol.kind == SymbolKind::Method => {
+            eprintln!("Methods are looked up dynamically and can't be renamed.");
+            exit(65)
+        }
+        Some(symbol) => match lox.rename_conflict(&symbols, symbol, new_name) {
+            Some(conflict) => {
+                eprintln!(
+                    "Can't rename '{}' to '{}': {}",
+                    symbol.name, new_name, conflict
+                );
+                exit(65)
+            }
+            None => print!("{}", rename(&source, symbol, new_name)),
         },
-        [_] => lox.run_prompt()?,
-        _ => {
-            eprintln!("Usage: lox-rs [script]");
-            exit(64)
+        None => {
+            eprintln!("No variable, function or class at {}.", position);
+            exit(65)
         }
     }
-    Ok(())
 }
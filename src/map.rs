@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use crate::function::NativeFn;
+use crate::inspect::inspect;
+use crate::interpreter::Interpreter;
+use crate::key::Key;
+use crate::list;
+use crate::natives::{native_state, set_native_state};
+use crate::object::Object;
+
+// Map is a native class from keys to values, keeping the keys in the order
+// they were first set. Keys are compared like the elements of a Set, see Key:
+// numbers, strings, booleans and nil by value, everything else by identity.
+// Setting a bound method as a key is an error.
+//
+//   var ages = Map();
+//   ages.set("Ada", 36);
+//   print ages.get("Ada"); // 36
+//   print ages.get("Grace", 0); // 0
+pub(crate) const METHODS: &[(&str, NativeFn)] = &[
+    ("init()", init),
+    ("length()", length),
+    ("get(key, default?)", get),
+    ("set(key, value)", set),
+    ("has(key)", has),
+    ("remove(key)", remove),
+    ("keys()", keys),
+    ("values()", values),
+    ("for_each(function)", for_each),
+];
+
+#[derive(Default, Clone)]
+struct Entries {
+    order: Vec<Key>,
+    values: HashMap<Key, Object>,
+}
+
+impl Entries {
+    fn get(&self, key: &Object) -> Option<Object> {
+        Key::new(key).and_then(|key| self.values.get(&key).cloned())
+    }
+
+    fn set(&mut self, key: &Object, value: Object) -> Result<(), String> {
+        let key = Key::new(key)
+            .ok_or_else(|| format!("Map keys can't be bound methods but got {}.", inspect(key)))?;
+        if !self.values.contains_key(&key) {
+            self.order.push(key.clone());
+        }
+        self.values.insert(key, value);
+        Ok(())
+    }
+
+    // Whether the key was there.
+    fn remove(&mut self, key: &Object) -> bool {
+        let Some(key) = Key::new(key) else {
+            return false;
+        };
+        if self.values.remove(&key).is_none() {
+            return false;
+        }
+        self.order.retain(|other| *other != key);
+        true
+    }
+}
+
+fn init(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    set_native_state(&args[0], Entries::default());
+    Ok(Object::Null)
+}
+
+fn length(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    with_entries(&args[0], |entries| {
+        Ok(Object::Number(entries.order.len() as f64))
+    })
+}
+
+// get(key) is nil for a key that isn't there, get(key, default) the default.
+fn get(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let default = args.get(2).cloned().unwrap_or(Object::Null);
+    with_entries(&args[0], |entries| {
+        Ok(entries.get(&args[1]).unwrap_or(default))
+    })
+}
+
+// Returns the value like assignment does.
+fn set(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    with_entries(&args[0], |entries| {
+        entries.set(&args[1], args[2].clone())?;
+        Ok(args[2].clone())
+    })
+}
+
+fn has(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    with_entries(&args[0], |entries| {
+        Ok(Object::Boolean(entries.get(&args[1]).is_some()))
+    })
+}
+
+// remove(key) returns whether the key was in the map.
+fn remove(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    with_entries(&args[0], |entries| {
+        Ok(Object::Boolean(entries.remove(&args[1])))
+    })
+}
+
+fn keys(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let entries = copy(&args[0])?;
+    let keys = entries
+        .order
+        .iter()
+        .map(|key| key.value().clone())
+        .collect();
+    Ok(list::list(interpreter, keys))
+}
+
+fn values(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let entries = copy(&args[0])?;
+    let values = entries
+        .order
+        .iter()
+        .map(|key| entries.values[key].clone())
+        .collect();
+    Ok(list::list(interpreter, values))
+}
+
+// for_each(function) calls the function with every key and its value. Entries
+// set or removed by the function don't change which ones it is called with.
+fn for_each(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let entries = copy(&args[0])?;
+    for key in &entries.order {
+        let value = entries.values[key].clone();
+        interpreter.call_back(&args[1], &[key.value().clone(), value])?;
+    }
+    Ok(Object::Null)
+}
+
+fn with_entries<R>(
+    this: &Object,
+    f: impl FnOnce(&mut Entries) -> Result<R, String>,
+) -> Result<R, String> {
+    native_state(this, "Map", f)
+}
+
+// The entries are never borrowed while Lox code runs.
+fn copy(map: &Object) -> Result<Entries, String> {
+    with_entries(map, |entries| Ok(entries.clone()))
+}
+
+// A new Map with the entries, for natives returning one. Later entries win
+// over earlier ones with the same key.
+pub(crate) fn map(
+    interpreter: &Interpreter,
+    entries: Vec<(Object, Object)>,
+) -> Result<Object, String> {
+    let map = interpreter.new_instance(&interpreter.native_class("Map"));
+    set_entries(&map, entries)?;
+    Ok(map)
+}
+
+// Gives an instance of Map or a class extending it the entries, replacing the
+// ones it had. Later entries win over earlier ones with the same key.
+pub(crate) fn set_entries(map: &Object, entries: Vec<(Object, Object)>) -> Result<(), String> {
+    let mut state = Entries::default();
+    for (key, value) in entries {
+        state.set(&key, value)?;
+    }
+    set_native_state(map, state);
+    Ok(())
+}
+
+// The entries of a Map in order, for natives taking one.
+pub(crate) fn entries(map: &Object) -> Result<Vec<(Object, Object)>, String> {
+    let entries = copy(map)?;
+    Ok(entries
+        .order
+        .iter()
+        .map(|key| (key.value().clone(), entries.values[key].clone()))
+        .collect())
+}
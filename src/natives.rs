@@ -0,0 +1,529 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::class::LoxClass;
+use crate::env_graph::env_graph;
+use crate::environment::Environment;
+use crate::error::{Code, Error};
+use crate::function::{Function, NativeFn};
+use crate::inspect::{diff, inspect};
+use crate::interpreter::Interpreter;
+use crate::json;
+use crate::list;
+use crate::map;
+use crate::object::{self, format_number, Object};
+use crate::parallel;
+use crate::random;
+use crate::set;
+use crate::streams;
+use crate::time;
+
+// The built-in functions every program starts with. A native reports a failure
+// by returning a message, the interpreter turns it into a runtime error at the
+// call site.
+pub fn define_natives(globals: &mut Environment) {
+    define(globals, "clock()", clock);
+    define(globals, "version()", version);
+    globals.define(
+        "__version__".to_string(),
+        Object::String(Rc::from(crate::VERSION)),
+    );
+    define(globals, "assert(value)", assert);
+    define(globals, "assert_eq(expected, actual)", assert_eq);
+    define(globals, "same(a, b)", same);
+    define(globals, "to_number(string, radix?)", to_number);
+    define(globals, "parse_number(string, radix?)", parse_number);
+    define(globals, "to_string(value)", to_string);
+    define(globals, "to_precision(number, digits)", to_precision);
+    define(globals, "is_integer(n)", is_integer);
+    define(globals, "trunc(n)", trunc);
+    define(globals, "clamp(n, lo, hi)", clamp);
+    define(globals, "serialize(value)", json::serialize);
+    define(
+        globals,
+        "deserialize(class, json, init?)",
+        json::deserialize,
+    );
+    define(globals, "print_err(value)", streams::print_err);
+    define(globals, "open_writer(path, append?)", streams::open_writer);
+    define(globals, "dump_env_graph(path)", dump_env_graph);
+    define(globals, "globals()", globals_snapshot);
+    define(globals, "freeze(instance)", freeze);
+    define(globals, "seal(class)", seal);
+    define(globals, "random_seed(n)", random::random_seed);
+    define(globals, "random()", random::random);
+    define(globals, "shuffle(list)", random::shuffle);
+    define(globals, "time_now()", time::time_now);
+    define(globals, "time_parts(timestamp)", time::time_parts);
+    define(globals, "time_format(timestamp, format)", time::time_format);
+    define(
+        globals,
+        "parallel_map(list, function)",
+        parallel::parallel_map,
+    );
+
+    define_class(globals, "List", list::METHODS);
+    define_class(globals, "Set", set::METHODS);
+    define_class(globals, "Map", map::METHODS);
+    define_class(globals, "Error", &[("init(message, code?)", error_init)]);
+    define_class(
+        globals,
+        "StringBuilder",
+        &[
+            ("init(text?)", string_builder_init),
+            ("append(value)", string_builder_append),
+            ("to_string()", string_builder_to_string),
+        ],
+    );
+}
+
+// The name and arity come from the signature, parameters ending in `?` are
+// optional.
+fn define(globals: &mut Environment, signature: &'static str, body: NativeFn) {
+    let function = native(signature, body);
+    // In Lox functions and variables occupy the same namespace.
+    globals.define(function.name().to_string(), Object::Callable(function));
+}
+
+// A class whose methods are natives, they get the instance as their first
+// argument. An `init` method sets up the Rust state of new instances with
+// set_native_state.
+pub(crate) fn define_class(
+    globals: &mut Environment,
+    name: &str,
+    methods: &[(&'static str, NativeFn)],
+) -> Rc<RefCell<LoxClass>> {
+    let methods = methods
+        .iter()
+        .map(|&(signature, body)| {
+            let method = native(signature, body);
+            (method.name().to_string(), method)
+        })
+        .collect();
+    let class = Rc::new(RefCell::new(LoxClass::new(name.to_string(), None, methods)));
+    globals.define(name.to_string(), Object::Class(Rc::clone(&class)));
+    class
+}
+
+pub(crate) fn native(signature: &'static str, body: NativeFn) -> Function {
+    let (name, params) = signature
+        .strip_suffix(')')
+        .and_then(|signature| signature.split_once('('))
+        .expect("Native signature should look like name(params).");
+    let params: Vec<&str> = params
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .collect();
+    let required = params.iter().filter(|param| !param.ends_with('?')).count();
+
+    Function::Native {
+        name,
+        signature,
+        arity: required..=params.len(),
+        body,
+        this: None,
+    }
+}
+
+fn clock(_interpreter: &mut Interpreter, _args: &[Object]) -> Result<Object, String> {
+    Ok(Object::Number(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Could not retrieve time.")
+            .as_millis() as f64,
+    ))
+}
+
+// version() is the version of the interpreter, like "0.1.0", the same as the
+// global __version__.
+fn version(_interpreter: &mut Interpreter, _args: &[Object]) -> Result<Object, String> {
+    Ok(Object::String(Rc::from(crate::VERSION)))
+}
+
+// assert(value) fails unless value is truthy.
+fn assert(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    match args[0] {
+        Object::Null | Object::Boolean(false) => {
+            let message = format!("Assertion failed: got {}.", inspect(&args[0]));
+            Err(assertion_failed(interpreter, &message))
+        }
+        _ => Ok(Object::Null),
+    }
+}
+
+// Failed assertions have their own code, so a test runner catching errors can
+// tell them from the program failing.
+fn assertion_failed(interpreter: &mut Interpreter, message: &str) -> String {
+    let error = Error::runtime(interpreter.call_site(), Code::AssertionFailed, &[message]);
+    interpreter.fail(error)
+}
+
+// assert_eq(expected, actual) compares instances field by field and native
+// collections by their contents, see inspect::diff, so two separately built
+// values with the same contents are equal. On failure both values are printed
+// along with every field that differs.
+fn assert_eq(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let (expected, actual) = (&args[0], &args[1]);
+    let differences = diff(expected, actual);
+    if differences.is_empty() {
+        return Ok(Object::Null);
+    }
+
+    let mut message = format!(
+        "assert_eq failed.\n  expected: {}\n    actual: {}",
+        inspect(expected),
+        inspect(actual)
+    );
+    if matches!(
+        (expected, actual),
+        (Object::Instance(_), Object::Instance(_))
+    ) {
+        message.push_str("\n  differences:");
+        for difference in differences {
+            message.push_str("\n    ");
+            message.push_str(&difference);
+        }
+    }
+    Err(assertion_failed(interpreter, &message))
+}
+
+// same(a, b) is true when a and b are one object, even when a host's `==`
+// rule says otherwise or says two different ones are equal. Lists, sets and
+// other instances are the same only as themselves, numbers when they are
+// identical, NaN included.
+fn same(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    Ok(Object::Boolean(args[0].is_same(&args[1])))
+}
+
+// to_number(string, radix?) converts a string to a number, or returns nil if it
+// isn't one. Without a radix the string uses Lox's number syntax plus an
+// optional sign and exponent. With a radix between 2 and 36 it must be an
+// integer in that base, `to_number("ff", 16)` is 255. Surrounding whitespace
+// is ignored.
+fn to_number(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    Ok(number_from(interpreter, args, "to_number")?.map_or(Object::Null, Object::Number))
+}
+
+// parse_number(string, radix?) is to_number for input that must be valid: it
+// fails instead of returning nil.
+fn parse_number(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    match number_from(interpreter, args, "parse_number")? {
+        Some(number) => Ok(Object::Number(number)),
+        None => Err(format!(
+            "Cannot parse {} as a number{}.",
+            inspect(&args[0]),
+            match args.get(1) {
+                Some(radix) => format!(" in base {}", radix),
+                None => String::new(),
+            }
+        )),
+    }
+}
+
+// to_string(value) is the text `print` shows for the value.
+fn to_string(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    Ok(Object::String(Rc::from(args[0].to_string())))
+}
+
+// to_precision(number, digits) is the number rounded to that many significant
+// digits (1 to 17) as a string, keeping trailing zeros: to_precision(3.14159, 3)
+// is "3.14" and to_precision(2, 3) is "2.00". Magnitudes that print writes with
+// an exponent get one here too, to_precision(1e-9, 2) is "1.0e-9".
+fn to_precision(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let Object::Number(number) = args[0] else {
+        return Err(format!(
+            "to_precision expects a number but got {}.",
+            inspect(&args[0])
+        ));
+    };
+    let digits = match args[1] {
+        Object::Number(digits) if digits.fract() == 0.0 && (1.0..=17.0).contains(&digits) => {
+            digits as usize
+        }
+        ref other => {
+            return Err(format!(
+                "to_precision expects a whole number of digits from 1 to 17 but got {}.",
+                inspect(other)
+            ))
+        }
+    };
+    if !number.is_finite() {
+        return Ok(Object::String(Rc::from(format_number(number))));
+    }
+
+    // Rounding to the digits happens in the exponent notation, which also
+    // tells where the decimal point goes.
+    let scientific = format!("{:.*e}", digits - 1, number);
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("Exponent notation has an exponent.");
+    let exponent: i32 = exponent.parse().expect("The exponent is a number.");
+    let text = if number != 0.0 && !(-6..21).contains(&exponent) {
+        scientific
+    } else {
+        let decimals = (digits as i32 - 1 - exponent).max(0) as usize;
+        let rounded: f64 = format!("{}e{}", mantissa, exponent)
+            .parse()
+            .expect("Exponent notation reads back.");
+        format!("{:.*}", decimals, rounded)
+    };
+    Ok(Object::String(Rc::from(text)))
+}
+
+// is_integer(n) is whether n is a whole number. Anything but a number isn't,
+// and neither are the infinities and NaN.
+fn is_integer(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    Ok(Object::Boolean(matches!(
+        args[0],
+        Object::Number(n) if object::is_integer(n)
+    )))
+}
+
+// trunc(n) is n without its fraction, rounded toward zero: trunc(-2.7) is -2.
+fn trunc(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    Ok(Object::Number(number_arg(&args[0], "trunc")?.trunc()))
+}
+
+// clamp(n, lo, hi) is n if it is between lo and hi, the nearer of the two
+// otherwise. NaN stays NaN, and lo can't be greater than hi.
+fn clamp(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let n = number_arg(&args[0], "clamp")?;
+    let lo = number_arg(&args[1], "clamp")?;
+    let hi = number_arg(&args[2], "clamp")?;
+    if lo.is_nan() || hi.is_nan() || lo > hi {
+        return Err(format!(
+            "clamp expects lo to be at most hi but got {} and {}.",
+            format_number(lo),
+            format_number(hi)
+        ));
+    }
+    Ok(Object::Number(n.clamp(lo, hi)))
+}
+
+fn number_arg(value: &Object, native: &str) -> Result<f64, String> {
+    match value {
+        Object::Number(n) => Ok(*n),
+        other => Err(format!(
+            "{} expects a number but got {}.",
+            native,
+            inspect(other)
+        )),
+    }
+}
+
+// globals() is a Globals instance with a field for every global variable,
+// function and class, the built-in ones included. It is a snapshot, defining
+// or assigning a global later doesn't change it.
+fn globals_snapshot(interpreter: &mut Interpreter, _args: &[Object]) -> Result<Object, String> {
+    let snapshot = interpreter.new_instance(&interpreter.native_class("Globals"));
+    if let Object::Instance(fields) = &snapshot {
+        let mut fields = fields.borrow_mut();
+        for (name, value) in interpreter.globals.borrow().values() {
+            fields.set_field(name.to_string(), value.clone());
+        }
+    }
+    Ok(snapshot)
+}
+
+// freeze(instance) makes every field of the instance read-only and no new
+// ones can be added. Returns the instance.
+fn freeze(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    match &args[0] {
+        Object::Instance(instance) => {
+            instance.borrow_mut().frozen = true;
+            Ok(args[0].clone())
+        }
+        other => Err(format!(
+            "freeze expects an instance but got {}.",
+            inspect(other)
+        )),
+    }
+}
+
+// seal(class) stops instances of the class and its subclasses from getting
+// fields that weren't set by the initializer. Returns the class.
+fn seal(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    match &args[0] {
+        Object::Class(class) => {
+            class.borrow_mut().sealed = true;
+            Ok(args[0].clone())
+        }
+        other => Err(format!("seal expects a class but got {}.", inspect(other))),
+    }
+}
+
+// dump_env_graph(path) writes the environments reachable from the calling
+// scope to a Graphviz file, see env_graph.
+fn dump_env_graph(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let path = match &args[0] {
+        Object::String(path) => path,
+        other => {
+            return Err(format!(
+                "dump_env_graph expects a file name but got {}.",
+                inspect(other)
+            ))
+        }
+    };
+    if !interpreter.allow_file_writes() {
+        return Err(format!(
+            "Cannot write '{}': writing files isn't allowed.",
+            path
+        ));
+    }
+    fs::write(&**path, env_graph(interpreter))
+        .map_err(|error| format!("Cannot write '{}': {}.", path, error))?;
+    Ok(Object::Null)
+}
+
+// Error(message, code?) is what scripts throw, and what a catch gets for a
+// runtime error. It has the fields message, code and line, the line of the
+// throw or of the failed code.
+fn error_init(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    if let Object::Instance(instance) = &args[0] {
+        let mut instance = instance.borrow_mut();
+        instance.set_field("message".to_string(), args[1].clone());
+        instance.set_field(
+            "code".to_string(),
+            args.get(2).cloned().unwrap_or(Object::Null),
+        );
+        instance.set_field("line".to_string(), Object::Null);
+        instance.set_field("category".to_string(), Object::Null);
+    }
+    Ok(Object::Null)
+}
+
+// StringBuilder collects text in a buffer that grows in place, which avoids
+// copying the whole string on every `+` when building a long one piece by
+// piece:
+//
+//   var out = StringBuilder();
+//   for (var i = 0; i < 3; i = i + 1) out.append(i).append(" ");
+//   print out.to_string(); // "0 1 2 "
+fn string_builder_init(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let text = match args.get(1) {
+        Some(text) => text.to_string(),
+        None => String::new(),
+    };
+    set_native_state(&args[0], text);
+    Ok(Object::Null)
+}
+
+// append(value) adds the text `print` shows for the value. Returns the
+// builder so calls can be chained.
+fn string_builder_append(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let text = args[1].to_string();
+    let limits = *interpreter.limits();
+    native_state(&args[0], "StringBuilder", |buffer: &mut String| {
+        if let Err(Error::ResourceLimit { message }) =
+            limits.check_string(buffer.len() + text.len())
+        {
+            return Err(message);
+        }
+        buffer.push_str(&text);
+        Ok(())
+    })?;
+    Ok(args[0].clone())
+}
+
+fn string_builder_to_string(
+    _interpreter: &mut Interpreter,
+    args: &[Object],
+) -> Result<Object, String> {
+    let text = native_state(&args[0], "StringBuilder", |buffer: &mut String| {
+        Ok(Rc::from(buffer.as_str()))
+    })?;
+    Ok(Object::String(text))
+}
+
+// Gives an instance of a native class its Rust state, replacing any it had.
+// Does nothing for other values.
+pub fn set_native_state<T: Any>(this: &Object, state: T) {
+    if let Object::Instance(instance) = this {
+        instance.borrow_mut().native = Some(Box::new(state));
+    }
+}
+
+// The Rust state of an instance of a native class, `this` of its methods.
+// Methods taken from an instance and bound to something else find none.
+//
+// The state is taken out of the instance while `f` runs, so the instance isn't
+// borrowed if `f` reaches it again. Meanwhile it shows no contents, so
+// arguments are checked and formatted for error messages before, see
+// list::integer.
+pub fn native_state<T: Any, R>(
+    this: &Object,
+    class: &str,
+    f: impl FnOnce(&mut T) -> Result<R, String>,
+) -> Result<R, String> {
+    let expected = || format!("Expected a {} instance.", class);
+    let Object::Instance(instance) = this else {
+        return Err(expected());
+    };
+    let mut native = instance.borrow_mut().native.take();
+    let result = match native
+        .as_mut()
+        .and_then(|native| native.downcast_mut::<T>())
+    {
+        Some(state) => f(state),
+        None => Err(expected()),
+    };
+    instance.borrow_mut().native = native;
+    result
+}
+
+// Wrong argument types are always an error, only text that isn't a number
+// gives None. In strict mode so are digits in a radix naming an integer that
+// can't be exact, see Interpreter::check_exact.
+fn number_from(
+    interpreter: &mut Interpreter,
+    args: &[Object],
+    native: &str,
+) -> Result<Option<f64>, String> {
+    let text = match &args[0] {
+        Object::String(text) => text.trim(),
+        other => {
+            return Err(format!(
+                "{} expects a string but got {}.",
+                native,
+                inspect(other)
+            ))
+        }
+    };
+
+    match args.get(1) {
+        None => Ok(parse_decimal(text)),
+        Some(Object::Number(radix)) if radix.fract() == 0.0 && (2.0..=36.0).contains(radix) => {
+            let Ok(number) = i64::from_str_radix(text, *radix as u32) else {
+                return Ok(None);
+            };
+            let call_site = interpreter.call_site().clone();
+            interpreter
+                .check_exact(number as f64, &call_site, || {
+                    format!("{}(\"{}\", {})", native, text, radix)
+                })
+                .map_err(|error| interpreter.fail(error))?;
+            Ok(Some(number as f64))
+        }
+        Some(other) => Err(format!(
+            "{} expects a radix between 2 and 36 but got {}.",
+            native,
+            inspect(other)
+        )),
+    }
+}
+
+// Rust also parses words like "inf" and "NaN", which aren't numbers in Lox.
+fn parse_decimal(text: &str) -> Option<f64> {
+    let valid = text
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'));
+    if valid {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
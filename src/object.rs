@@ -1,31 +1,222 @@
 use crate::class::{LoxClass, LoxInstance};
 use crate::function::Function;
 
+use std::any::{Any, TypeId};
 use std::cell::RefCell;
+use std::fmt;
 use std::rc::Rc;
 
 // The book is using java.lang.Object
+//
+// Values are cloned whenever they are read from a variable or passed around,
+// so every variant is cheap to clone: booleans, nil and numbers are stored
+// inline and everything owning heap data sits behind an Rc.
 #[derive(Debug, Clone)]
 pub enum Object {
     Boolean(bool),
     Callable(Function),
     Class(Rc<RefCell<LoxClass>>),
+    Foreign(Foreign),
     Instance(Rc<RefCell<LoxInstance>>),
     Null,
     Number(f64),
-    String(String),
+    String(Rc<str>),
 }
 
 impl Object {
+    // `==` in Lox. Numbers compare as IEEE 754 doubles, so NaN isn't equal to
+    // itself and -0 equals 0, strings by their characters, and everything
+    // that lives on the heap by identity: an instance, a class, a function or
+    // a foreign value is only equal to itself. Values of different types are
+    // never equal, there is no coercion, `1 == "1"` and `nil == false` are
+    // false.
     pub fn equals(&self, other: &Object) -> bool {
         match (self, other) {
             (Object::Null, Object::Null) => true,
-            (_, Object::Null) => false,
-            (Object::Null, _) => false,
             (Object::Boolean(left), Object::Boolean(right)) => left == right,
             (Object::Number(left), Object::Number(right)) => left == right,
             (Object::String(left), Object::String(right)) => left.eq(right),
-            _ => false, // TODO: should work for all
+            (Object::Callable(left), Object::Callable(right)) => left.is_same(right),
+            (Object::Class(left), Object::Class(right)) => Rc::ptr_eq(left, right),
+            (Object::Instance(left), Object::Instance(right)) => Rc::ptr_eq(left, right),
+            (Object::Foreign(left), Object::Foreign(right)) => left.address() == right.address(),
+            (Object::Null, _)
+            | (Object::Boolean(_), _)
+            | (Object::Number(_), _)
+            | (Object::String(_), _)
+            | (Object::Callable(_), _)
+            | (Object::Class(_), _)
+            | (Object::Instance(_), _)
+            | (Object::Foreign(_), _) => false,
+        }
+    }
+
+    // Whether both are the very same value, for same(a, b). Heap values are
+    // the same when they are one object, whatever `==` rules a host adds for
+    // them. Numbers are the same when they have the same bits, so NaN is the
+    // same as NaN but -0 isn't the same as 0, and strings, which can't change,
+    // when they have the same characters.
+    pub fn is_same(&self, other: &Object) -> bool {
+        match (self, other) {
+            (Object::Number(left), Object::Number(right)) => {
+                left.to_bits() == right.to_bits() || (left.is_nan() && right.is_nan())
+            }
+            (Object::Callable(left), Object::Callable(right)) => left.is_same(right),
+            _ => match (self.address(), other.address()) {
+                (Some(left), Some(right)) => left == right,
+                (None, None) => self.equals(other),
+                _ => false,
+            },
+        }
+    }
+
+    // Where a class, an instance or a foreign value lives, which identifies
+    // it: every copy of the value has the same address.
+    pub fn address(&self) -> Option<usize> {
+        match self {
+            Object::Class(class) => Some(Rc::as_ptr(class) as *const () as usize),
+            Object::Instance(instance) => Some(Rc::as_ptr(instance) as *const () as usize),
+            Object::Foreign(foreign) => Some(foreign.address()),
+            _ => None,
+        }
+    }
+
+    // The kind of value, for the REPL's :type: `number`, `Point instance`.
+    pub fn type_name(&self) -> String {
+        match self {
+            Object::Null => "nil".to_string(),
+            Object::Number(_) => "number".to_string(),
+            Object::Boolean(_) => "boolean".to_string(),
+            Object::String(_) => "string".to_string(),
+            Object::Class(_) => "class".to_string(),
+            Object::Instance(instance) => {
+                format!("{} instance", instance.borrow().class.borrow().name)
+            }
+            Object::Foreign(foreign) => foreign.type_name.to_string(),
+            Object::Callable(Function::Native { .. }) => "native function".to_string(),
+            Object::Callable(Function::User { .. }) => "function".to_string(),
+        }
+    }
+
+    // The Rust value of a foreign object, if this is one holding a T.
+    pub fn downcast_foreign<T: Any>(&self) -> Option<&T> {
+        match self {
+            Object::Foreign(foreign) => foreign.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+// A value of the host passed around by scripts, an open file or a database
+// handle. Lox can't look inside, it can only call the methods the host
+// registered for its type with Interpreter::register_type. The value is
+// shared by every copy, so it takes a RefCell for methods to change it.
+#[derive(Clone)]
+pub struct Foreign {
+    pub type_name: Rc<str>,
+    value: Rc<dyn Any>,
+}
+
+impl Foreign {
+    pub fn new<T: Any>(type_name: Rc<str>, value: T) -> Self {
+        Foreign {
+            type_name,
+            value: Rc::new(value),
+        }
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.value.downcast_ref()
+    }
+
+    // The type the methods are registered for.
+    pub(crate) fn type_id(&self) -> TypeId {
+        (*self.value).type_id()
+    }
+
+    // Identifies the value, two foreign objects are equal when they share it.
+    pub(crate) fn address(&self) -> usize {
+        Rc::as_ptr(&self.value) as *const () as usize
+    }
+}
+
+impl fmt::Debug for Foreign {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Foreign({})", self.type_name)
+    }
+}
+
+// How a number is printed, the same on every platform. Whole numbers have no
+// fraction, like in jlox, and very large or small magnitudes are written with
+// an exponent instead of hundreds of digits. The digits are the fewest that
+// read back as the same number.
+//
+//   1e21 → 1e21     1e20 → 100000000000000000000     1e-7 → 1e-7
+//   -0 → -0         1/0 → Infinity                   0/0 → NaN
+pub fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if n != 0.0 && (n.abs() >= 1e21 || n.abs() < 1e-6) {
+        format!("{:e}", n)
+    } else {
+        format!("{}", n)
+    }
+}
+
+// How jlox prints a number, Java's Double.toString without a trailing ".0".
+// Magnitudes from 10^-3 up to 10^7 are written out, the others with an
+// exponent whose mantissa always has a fraction.
+//
+//   1e7 → 1.0E7     123456789 → 1.23456789E8     0.0001 → 1.0E-4
+pub fn format_number_jlox(n: f64) -> String {
+    if !n.is_finite() || n == 0.0 || (1e-3..1e7).contains(&n.abs()) {
+        return format_number(n);
+    }
+    let scientific = format!("{:e}", n);
+    let (mantissa, exponent) = scientific.split_once('e').expect("An exponent.");
+    if mantissa.contains('.') {
+        format!("{}E{}", mantissa, exponent)
+    } else {
+        format!("{}.0E{}", mantissa, exponent)
+    }
+}
+
+// What `print` shows for a value in jlox, which also differs from stringify
+// here for natives.
+pub fn jlox_string(value: &Object) -> String {
+    match value {
+        Object::Number(n) => format_number_jlox(*n),
+        Object::Callable(Function::Native { .. }) => "<native fn>".to_string(),
+        _ => value.to_string(),
+    }
+}
+
+// The largest integer every smaller integer below which is a float too, 2^53
+// - 1. Past it some integers round to their neighbours, 2^53 + 1 is 2^53.
+pub const MAX_EXACT_INTEGER: f64 = 9007199254740991.0;
+
+// Whether the number is a whole number, for is_integer() and the natives
+// taking indexes and counts.
+pub fn is_integer(n: f64) -> bool {
+    n.is_finite() && n.fract() == 0.0
+}
+
+// What `print` shows for a value, stringify in the book.
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::Null => write!(f, "nil"),
+            Object::Number(n) => write!(f, "{}", format_number(*n)),
+            Object::Boolean(b) => write!(f, "{}", b),
+            Object::Class(class) => write!(f, "{}", class.borrow().name),
+            Object::Instance(instance) => {
+                write!(f, "{} instance", instance.borrow().class.borrow().name)
+            }
+            Object::String(s) => write!(f, "{}", s),
+            Object::Foreign(foreign) => write!(f, "<{}>", foreign.type_name),
+            Object::Callable(function) => write!(f, "{}", function),
         }
     }
 }
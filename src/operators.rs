@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+use crate::token::TokenType;
+
+// The binary operators a host can give a meaning to for its own values, like
+// `+` on foreign vectors or units, or `==` comparing instances field by field.
+// `!=` is the opposite of `==`, and `and` and `or` can't be changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+}
+
+impl Operator {
+    // The operator a token stands for, with `!=` standing for `==`.
+    pub(crate) fn from_token(token_type: &TokenType) -> Option<Operator> {
+        Some(match token_type {
+            TokenType::Plus => Operator::Add,
+            TokenType::Minus => Operator::Subtract,
+            TokenType::Star => Operator::Multiply,
+            TokenType::Slash => Operator::Divide,
+            TokenType::StarStar => Operator::Power,
+            TokenType::Less => Operator::Less,
+            TokenType::LessEqual => Operator::LessEqual,
+            TokenType::Greater => Operator::Greater,
+            TokenType::GreaterEqual => Operator::GreaterEqual,
+            TokenType::EqualEqual | TokenType::BangEqual => Operator::Equal,
+            _ => return None,
+        })
+    }
+}
+
+// A host's rule for an operator. It gets both operands and returns the result,
+// None to leave them to the next rule or the built-in one, or a message for a
+// runtime error reported at the operator. Rules can call back into Lox like
+// natives do. The result of an `==` rule is made a boolean by its truthiness.
+pub type OperatorFn = fn(&mut Interpreter, &Object, &Object) -> Result<Option<Object>, String>;
+
+// The rules registered for every operator, consulted in the order they were
+// registered before the built-in rules, so a rule can change what `1 + 2` is
+// as well as give a meaning to `vector + vector`.
+#[derive(Default)]
+pub struct OperatorTable {
+    rules: HashMap<Operator, Vec<OperatorFn>>,
+}
+
+impl OperatorTable {
+    pub fn register(&mut self, operator: Operator, rule: OperatorFn) {
+        self.rules.entry(operator).or_default().push(rule);
+    }
+
+    // The rules of the operator, copied so they can run while the table is
+    // borrowed from the interpreter.
+    pub(crate) fn rules(&self, operator: Operator) -> Vec<OperatorFn> {
+        self.rules.get(&operator).cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
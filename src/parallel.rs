@@ -0,0 +1,304 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use crate::cancel::CancellationToken;
+use crate::class::LoxInstance;
+use crate::error::{uncaught, Code, Diagnostic, Error};
+use crate::function::Function;
+use crate::inspect::inspect;
+use crate::interpreter::{Interpreter, STACK_SIZE};
+use crate::limits::Limits;
+use crate::list;
+use crate::object::Object;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
+use crate::syntax::{SourcePrinter, Stmt};
+use crate::token::{Token, TokenType};
+use crate::token_stream::TokenStream;
+
+// parallel_map(list, function) calls the function for every element of the
+// list on worker threads and returns a List of the results, in order. It is
+// for CPU-bound work, the function can't see or change anything of the
+// program calling it:
+//
+// - The function is given as its source, a string declaring one or more
+//   functions of which the last is called, or as a function declared at the
+//   top level, printed back to source like for snapshots. Every worker
+//   compiles the source into an interpreter of its own, which has the natives
+//   but none of the program's globals.
+// - The elements and the results are copied between the threads, so they can
+//   only be nil, booleans, numbers, strings and Lists of them.
+//
+// The first element whose call fails makes parallel_map fail with its error.
+// Interrupting or cancelling the program stops the workers, and parallel_map
+// fails like the program's own loops and calls would.
+pub(crate) fn parallel_map(
+    interpreter: &mut Interpreter,
+    args: &[Object],
+) -> Result<Object, String> {
+    let items = list::items(&args[0])
+        .map_err(|_| format!("parallel_map expects a List but got {}.", inspect(&args[0])))?;
+    let source = function_source(interpreter, &args[1])?;
+    let items = items
+        .iter()
+        .map(|item| Value::from_object(item, "Elements", &mut Vec::new()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if items.is_empty() {
+        return Ok(list::list(interpreter, Vec::new()));
+    }
+
+    let workers = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len());
+    let chunk = items.len().div_ceil(workers);
+    let limits = *interpreter.limits();
+    let cancellation = interpreter.cancellation_token();
+    let interrupted = interpreter.interrupt_flag();
+    // The workers' own token, tripped when the program is interrupted or
+    // cancelled. Each worker wakes this thread when it is done so it doesn't
+    // have to wait out the time between checks.
+    let stop = CancellationToken::new();
+    let waiting = thread::current();
+    let results = thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(chunk)
+            .enumerate()
+            .map(|(i, items)| {
+                let source = &source;
+                let stop = stop.clone();
+                let waiting = waiting.clone();
+                thread::Builder::new()
+                    .stack_size(STACK_SIZE)
+                    .spawn_scoped(scope, move || {
+                        let result = map_chunk(source, limits, stop, items, i * chunk);
+                        waiting.unpark();
+                        result
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| format!("Could not start a parallel_map worker: {}.", error))?;
+        while !handles.iter().all(|handle| handle.is_finished()) {
+            if cancellation.is_cancelled() || interrupted.load(Ordering::Relaxed) {
+                stop.cancel();
+            }
+            thread::park_timeout(Duration::from_millis(10));
+        }
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err("A parallel_map worker panicked.".to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()
+    });
+
+    if stop.is_cancelled() {
+        let token = interpreter.call_site().clone();
+        let error = if cancellation.is_cancelled() {
+            Error::Cancelled { token }
+        } else {
+            interrupted.store(false, Ordering::Relaxed);
+            Error::runtime(&token, Code::Interrupted, &[])
+        };
+        return Err(interpreter.fail(error));
+    }
+    let results = results?;
+
+    let results = results
+        .iter()
+        .flatten()
+        .map(|value| value.to_object(interpreter))
+        .collect();
+    Ok(list::list(interpreter, results))
+}
+
+// What a worker can be handed and hand back: a Lox value without anything
+// shared, which Rc makes impossible to send to another thread.
+enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    List(Vec<Value>),
+}
+
+impl Value {
+    // `what` says whose values these are, for the error message. `path` holds
+    // the Lists being copied, a List inside itself can't be copied.
+    fn from_object(
+        object: &Object,
+        what: &str,
+        path: &mut Vec<*const RefCell<LoxInstance>>,
+    ) -> Result<Value, String> {
+        Ok(match object {
+            Object::Null => Value::Nil,
+            Object::Boolean(b) => Value::Boolean(*b),
+            Object::Number(n) => Value::Number(*n),
+            Object::String(s) => Value::String(s.to_string()),
+            Object::Instance(instance) => match list::items(object) {
+                Ok(_) if path.contains(&Rc::as_ptr(instance)) => {
+                    return Err(not_sendable(object, what))
+                }
+                Ok(items) => {
+                    path.push(Rc::as_ptr(instance));
+                    let items = items
+                        .iter()
+                        .map(|item| Value::from_object(item, what, path))
+                        .collect::<Result<_, _>>();
+                    path.pop();
+                    Value::List(items?)
+                }
+                Err(_) => return Err(not_sendable(object, what)),
+            },
+            _ => return Err(not_sendable(object, what)),
+        })
+    }
+
+    fn to_object(&self, interpreter: &Interpreter) -> Object {
+        match self {
+            Value::Nil => Object::Null,
+            Value::Boolean(b) => Object::Boolean(*b),
+            Value::Number(n) => Object::Number(*n),
+            Value::String(s) => Object::String(Rc::from(s.as_str())),
+            Value::List(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| item.to_object(interpreter))
+                    .collect();
+                list::list(interpreter, items)
+            }
+        }
+    }
+}
+
+fn not_sendable(object: &Object, what: &str) -> String {
+    format!(
+        "{} of parallel_map can only be nil, booleans, numbers, strings and Lists of them, not {}.",
+        what,
+        inspect(object)
+    )
+}
+
+fn function_source(interpreter: &Interpreter, function: &Object) -> Result<String, String> {
+    match function {
+        Object::String(source) => Ok(source.to_string()),
+        Object::Callable(Function::User {
+            name,
+            params,
+            body,
+            closure,
+            class: None,
+            ..
+        }) if Rc::ptr_eq(closure, &interpreter.globals) => {
+            Ok(SourcePrinter::new().print(&Stmt::Function {
+                name: (**name).clone(),
+                params: params.to_vec(),
+                body: body.to_vec(),
+            }))
+        }
+        Object::Callable(Function::User { .. }) => Err(
+            "parallel_map can only run a function declared at the top level, it must not close over local variables."
+                .to_string(),
+        ),
+        other => Err(format!(
+            "parallel_map expects a function or its source but got {}.",
+            inspect(other)
+        )),
+    }
+}
+
+// The work of one thread: the elements starting at index `first`, until
+// `stop` is tripped.
+fn map_chunk(
+    source: &str,
+    limits: Limits,
+    stop: CancellationToken,
+    items: &[Value],
+    first: usize,
+) -> Result<Vec<Value>, String> {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_limits(limits);
+    interpreter.set_cancellation_token(stop);
+    let function = compile(&mut interpreter, source)?;
+    let paren = Token::new(TokenType::RightParen, ")", 1, 1);
+
+    let mut results = Vec::with_capacity(items.len());
+    for (i, item) in items.iter().enumerate() {
+        let failed =
+            |message: String| format!("parallel_map failed on element {}: {}", first + i, message);
+        let argument = item.to_object(&interpreter);
+        let result = interpreter
+            .call_value(function.clone(), &[argument], &paren)
+            .map_err(|error| failed(describe(&error)))?;
+        results.push(Value::from_object(&result, "Results", &mut Vec::new()).map_err(failed)?);
+    }
+    Ok(results)
+}
+
+// Runs the source in the worker's interpreter and returns the last function
+// it declares.
+fn compile(interpreter: &mut Interpreter, source: &str) -> Result<Object, String> {
+    let invalid = |diagnostics: &[Diagnostic]| match diagnostics.first() {
+        Some(diagnostic) => format!(
+            "parallel_map got invalid source code: [line {}] Error{}: {}",
+            diagnostic.line, diagnostic.location, diagnostic.message
+        ),
+        None => "parallel_map got invalid source code.".to_string(),
+    };
+
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan_tokens();
+    if scanner.had_error() {
+        return Err(invalid(&scanner.diagnostics));
+    }
+    let mut parser = Parser::new(TokenStream::new(tokens));
+    let statements = parser.parse();
+    if parser.had_error() {
+        return Err(invalid(&parser.take_diagnostics()));
+    }
+    let statements = statements.map_err(|_| invalid(&[]))?;
+
+    let mut resolver = Resolver::new(interpreter);
+    resolver.resolve(&statements);
+    if resolver.had_error {
+        return Err(invalid(&resolver.diagnostics));
+    }
+
+    let Some(name) = statements
+        .iter()
+        .rev()
+        .find_map(|statement| match statement {
+            Stmt::Function { name, .. } => Some(name.lexeme.clone()),
+            _ => None,
+        })
+    else {
+        return Err("The source given to parallel_map declares no function.".to_string());
+    };
+    interpreter
+        .interpret(&statements)
+        .map_err(|error| describe(&error))?;
+    interpreter
+        .globals
+        .borrow()
+        .get_own(&name)
+        .ok_or_else(|| "The source given to parallel_map declares no function.".to_string())
+}
+
+// A worker's error becomes part of the message of parallel_map's own error,
+// which has its own code, so the worker's code is left out.
+fn describe(error: &Error) -> String {
+    match error {
+        Error::Runtime { code, message, .. } => match code.category() {
+            Some(category) => format!("{}: {}", category, message),
+            None => message.clone(),
+        },
+        Error::Throw { value, .. } => uncaught(value),
+        Error::ResourceLimit { message } => message.clone(),
+        other => other.to_string(),
+    }
+}
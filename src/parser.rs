@@ -1,11 +1,20 @@
-use crate::error::{parser_error, Error};
+use std::cell::RefCell;
+use std::rc::Rc;
 
-use crate::syntax::{Expr, LiteralValue, Stmt};
-use crate::token::{Token, TokenType};
+use crate::error::{Code, Diagnostic, Error};
+use crate::features::Features;
 
-pub struct Parser<'t> {
-    tokens: &'t Vec<Token>,
-    current: usize,
+use crate::syntax::{Expr, LiteralValue, MatchArm, Pattern, Resolution, Stmt};
+use crate::token::{Token, TokenType};
+use crate::token_stream::TokenStream;
+
+pub struct Parser {
+    tokens: TokenStream,
+    // Every reported error, including the ones the parser recovers from
+    // without unwinding.
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    // The extensions of the book's grammar that may be used.
+    features: Features,
 }
 
 macro_rules! matches {
@@ -21,21 +30,42 @@ macro_rules! matches {
     };
 }
 
-impl<'t> Parser<'t> {
-    pub fn new(tokens: &'t Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+impl Parser {
+    pub fn new(tokens: TokenStream) -> Self {
+        Self {
+            tokens,
+            diagnostics: RefCell::new(Vec::new()),
+            features: Features::default(),
+        }
+    }
+
+    pub fn set_features(&mut self, features: Features) {
+        self.features = features;
+    }
+
+    pub fn had_error(&self) -> bool {
+        !self.diagnostics.borrow().is_empty()
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.diagnostics.take()
     }
     // program        → declaration* EOF ;
+    //
+    // Declarations that had a syntax error are left out, so after an error the
+    // statements are only good for reporting more errors. Check had_error()
+    // before resolving or running them.
     pub fn parse(&mut self) -> Result<Vec<Stmt>, Error> {
         let mut statements: Vec<Stmt> = Vec::new();
         while !self.is_at_end() {
-            statements.push(self.declaration()?);
+            statements.extend(self.declaration()?);
         }
         Ok(statements)
     }
 
     // declaration    → classDecl | funDecl | varDecl | statement ;
-    fn declaration(&mut self) -> Result<Stmt, Error> {
+    // Returns None for a declaration that had a syntax error.
+    fn declaration(&mut self) -> Result<Option<Stmt>, Error> {
         let statement = if matches!(self, TokenType::Var) {
             self.var_declaration()
         } else if matches!(self, TokenType::Class) {
@@ -50,33 +80,48 @@ impl<'t> Parser<'t> {
         match statement {
             Err(Error::Parse) => {
                 self.synchronize();
-                Ok(Stmt::Null)
+                Ok(None)
             }
-            other => other,
+            other => other.map(Some),
         }
     }
 
     // classDecl      → "class" IDENTIFIER ( "<" IDENTIFIER )? "{" function* "}" ;
     fn class_declaration(&mut self) -> Result<Stmt, Error> {
-        let name = self.consume(TokenType::Identifier, "Expect class name.")?;
+        let name = self.consume(TokenType::Identifier, Code::ExpectName, &["class name"])?;
         let superclass = if matches!(self, TokenType::Less) {
-            self.consume(TokenType::Identifier, "Expect superclass name.")?;
+            self.consume(
+                TokenType::Identifier,
+                Code::ExpectName,
+                &["superclass name"],
+            )?;
             Some(self.previous().clone())
         } else {
             None
         };
-        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+        self.consume(
+            TokenType::LeftBrace,
+            Code::ExpectLeftBrace,
+            &["before class body"],
+        )?;
 
         let mut methods: Vec<Stmt> = Vec::new();
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
             methods.push(self.function("method")?);
         }
 
-        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+        self.consume(
+            TokenType::RightBrace,
+            Code::ExpectRightBrace,
+            &["class body"],
+        )?;
 
         Ok(Stmt::Class {
             name,
-            superclass: superclass.map(|name| Expr::Variable { name }),
+            superclass: superclass.map(|name| Expr::Variable {
+                name,
+                resolution: Resolution::default(),
+            }),
             methods,
         })
     }
@@ -95,42 +140,54 @@ impl<'t> Parser<'t> {
         // First we consume the identifier token for the function's name
         let name = self.consume(
             TokenType::Identifier,
-            format!("Expect {} name.", kind).as_str(),
+            Code::ExpectName,
+            &[&format!("{} name", kind)],
         )?;
 
         // Next, we parse the parameter list and the pair of parantheses wrapped around it
         // The result is a list of tokens for each parameter's name
         self.consume(
             TokenType::LeftParen,
-            format!("Expect '(' after {} name.", kind).as_str(),
+            Code::ExpectLeftParen,
+            &[&format!("{} name", kind)],
         )?;
         let mut params: Vec<Token> = Vec::new();
         if !self.check(TokenType::RightParen) {
             loop {
                 if params.len() >= 255 {
                     // No error returned
-                    self.error(self.peek(), "Can't have more than 255 parameters.");
+                    self.error(self.peek(), Code::TooManyParameters, &[]);
                 }
 
-                params.push(self.consume(TokenType::Identifier, "Expect parameter name.")?);
+                params.push(self.consume(
+                    TokenType::Identifier,
+                    Code::ExpectName,
+                    &["parameter name"],
+                )?);
 
                 if !matches!(self, TokenType::Comma) {
                     break;
                 }
             }
         }
-        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+        self.consume(
+            TokenType::RightParen,
+            Code::ExpectRightParen,
+            &["parameters"],
+        )?;
 
         // Finally we parse the body and wrap it all up in a funciton node
         self.consume(
             TokenType::LeftBrace,
-            format!("Expected '{{' before {} body", kind).as_str(),
+            Code::ExpectLeftBrace,
+            &[&format!("before {} body", kind)],
         )?;
         let body = self.block()?;
         Ok(Stmt::Function { name, params, body })
     }
 
-    // statement      → exprStmt | printStmt | ifStmt | block | returnStmt | whileStmt | forStmt ;
+    // statement      → exprStmt | printStmt | ifStmt | block | returnStmt | whileStmt | forStmt
+    //                | tryStmt | throwStmt | deferStmt ;
     fn statement(&mut self) -> Result<Stmt, Error> {
         if matches!(self, TokenType::For) {
             self.for_statement()
@@ -142,6 +199,15 @@ impl<'t> Parser<'t> {
             self.return_statement()
         } else if matches!(self, TokenType::While) {
             self.while_statement()
+        } else if self.check(TokenType::Try)
+            && self.tokens.peek_nth(1).token_type == TokenType::LeftBrace
+        {
+            self.advance();
+            self.try_statement()
+        } else if matches!(self, TokenType::Throw) {
+            self.throw_statement()
+        } else if matches!(self, TokenType::Defer) {
+            self.defer_statement()
         } else if matches!(self, TokenType::LeftBrace) {
             Ok(Stmt::Block {
                 statements: self.block()?,
@@ -161,16 +227,24 @@ impl<'t> Parser<'t> {
             None
         };
 
-        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        self.consume(
+            TokenType::Semicolon,
+            Code::ExpectSemicolon,
+            &["return value"],
+        )?;
         Ok(Stmt::Return { keyword, value })
     }
 
     // the else is bound to the nearest if that precedes it
     // ifStmt         → "if" "(" expression ")" statement ( "else" statement )? ;
     fn if_statement(&mut self) -> Result<Stmt, Error> {
-        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        self.consume(TokenType::LeftParen, Code::ExpectLeftParen, &["'if'"])?;
         let condition = self.expression()?;
-        self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
+        self.consume(
+            TokenType::RightParen,
+            Code::ExpectRightParen,
+            &["if condition"],
+        )?;
 
         let then_branch = Box::new(self.statement()?);
 
@@ -192,21 +266,85 @@ impl<'t> Parser<'t> {
         let mut statements: Vec<Stmt> = Vec::new();
 
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            statements.extend(self.declaration()?);
         }
 
-        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+        self.consume(TokenType::RightBrace, Code::ExpectRightBrace, &["block"])?;
         Ok(statements)
     }
 
+    // tryStmt        → "try" block "catch" "(" IDENTIFIER ")" block ;
+    fn try_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().clone();
+        self.require(self.features.exceptions, &keyword, "exceptions");
+        self.consume(
+            TokenType::LeftBrace,
+            Code::ExpectLeftBrace,
+            &["after 'try'"],
+        )?;
+        let body = self.block()?;
+        self.consume(TokenType::Catch, Code::ExpectCatch, &[])?;
+        self.consume(TokenType::LeftParen, Code::ExpectLeftParen, &["'catch'"])?;
+        let name = self.consume(
+            TokenType::Identifier,
+            Code::ExpectName,
+            &["error variable name"],
+        )?;
+        self.consume(
+            TokenType::RightParen,
+            Code::ExpectRightParen,
+            &["error variable"],
+        )?;
+        self.consume(
+            TokenType::LeftBrace,
+            Code::ExpectLeftBrace,
+            &["after catch clause"],
+        )?;
+        let handler = self.block()?;
+
+        Ok(Stmt::Try {
+            keyword,
+            body,
+            name,
+            handler,
+        })
+    }
+
+    // throwStmt      → "throw" expression ";" ;
+    fn throw_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().clone();
+        self.require(self.features.exceptions, &keyword, "exceptions");
+        let value = self.expression()?;
+        self.consume(
+            TokenType::Semicolon,
+            Code::ExpectSemicolon,
+            &["thrown value"],
+        )?;
+        Ok(Stmt::Throw { keyword, value })
+    }
+
+    // deferStmt      → "defer" statement ;
+    fn defer_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().clone();
+        self.require(self.features.defer, &keyword, "defer");
+        let body = Box::new(self.statement()?);
+        Ok(Stmt::Defer { keyword, body })
+    }
+
     // whileStmt      → "while" "(" expression ")" statement ;
     fn while_statement(&mut self) -> Result<Stmt, Error> {
-        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, Code::ExpectLeftParen, &["'while'"])?;
         let condition = self.expression()?;
-        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        self.consume(
+            TokenType::RightParen,
+            Code::ExpectRightParen,
+            &["condition"],
+        )?;
         let body = self.statement()?;
 
         Ok(Stmt::While {
+            keyword,
             condition,
             body: Box::new(body),
         })
@@ -214,7 +352,8 @@ impl<'t> Parser<'t> {
 
     // forStmt        → "for" "(" ( varDecl | exprStmt | ";" ) expression? ";" expression? ")" statement ;
     fn for_statement(&mut self) -> Result<Stmt, Error> {
-        self.consume(TokenType::LeftParen, "Expected '(' after 'for'.")?;
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, Code::ExpectLeftParen, &["'for'"])?;
 
         let initializer = if matches!(self, TokenType::Semicolon) {
             None
@@ -230,7 +369,11 @@ impl<'t> Parser<'t> {
             None
         };
 
-        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+        self.consume(
+            TokenType::Semicolon,
+            Code::ExpectSemicolon,
+            &["loop condition"],
+        )?;
 
         let increment = if !self.check(TokenType::RightParen) {
             Some(self.expression()?)
@@ -238,7 +381,11 @@ impl<'t> Parser<'t> {
             None
         };
 
-        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+        self.consume(
+            TokenType::RightParen,
+            Code::ExpectRightParen,
+            &["for clauses"],
+        )?;
 
         let mut body = self.statement()?;
 
@@ -250,6 +397,7 @@ impl<'t> Parser<'t> {
         }
 
         body = Stmt::While {
+            keyword,
             condition: condition.unwrap_or(Expr::Literal {
                 value: LiteralValue::Boolean(true),
             }),
@@ -267,7 +415,7 @@ impl<'t> Parser<'t> {
 
     // varDecl        → "var" IDENTIFIER ( "=" expression )? ";" ;
     fn var_declaration(&mut self) -> Result<Stmt, Error> {
-        let name = self.consume(TokenType::Identifier, "Expected variable name.")?;
+        let name = self.consume(TokenType::Identifier, Code::ExpectName, &["variable name"])?;
         let initializer = if matches!(self, TokenType::Equal) {
             Some(self.expression()?)
         } else {
@@ -276,15 +424,26 @@ impl<'t> Parser<'t> {
 
         self.consume(
             TokenType::Semicolon,
-            "Expected ; after variable declaration.",
+            Code::ExpectSemicolon,
+            &["variable declaration"],
         )?;
 
         Ok(Stmt::Var { name, initializer })
     }
 
     // expression     → assignment ;
+    //
+    // Lox has no comma operator. The only place a comma can follow an
+    // expression is an argument list, which parses its arguments with
+    // assignment() directly, so a comma here is stray, like in `print a, b;`.
     fn expression(&mut self) -> Result<Expr, Error> {
-        self.assignment()
+        let expr = self.assignment()?;
+
+        if self.check(TokenType::Comma) {
+            return Err(self.error(self.peek(), Code::CommaOperator, &[]));
+        }
+
+        Ok(expr)
     }
 
     // The trick is that the parser first processes the left side as it it were an expression (r-value),
@@ -300,13 +459,24 @@ impl<'t> Parser<'t> {
         let expr = self.logic_or()?;
 
         if matches!(self, TokenType::Equal) {
+            let equals = self.previous().clone();
             // contrary to binary operators we don't loop to build up a sequence of the same operator
             // since assignment is right-associative, we instead recurisvely call assignment() to parse the right hand side
+            // so `a = b = 2` is parsed as `a = (b = 2)`
             let value = Box::new(self.assignment()?);
 
-            if let Expr::Variable { name } = expr {
-                return Ok(Expr::Assign { name, value });
-            } else if let Expr::Get { object, name } = expr {
+            if let Expr::Variable { name, .. } = expr {
+                return Ok(Expr::Assign {
+                    name,
+                    value,
+                    resolution: Resolution::default(),
+                });
+            } else if let Expr::Get {
+                object,
+                name,
+                optional: false,
+            } = expr
+            {
                 return Ok(Expr::Set {
                     object,
                     name,
@@ -314,9 +484,8 @@ impl<'t> Parser<'t> {
                 });
             }
 
-            let equals = self.previous();
             // we are not throwing because the parser is not in a confused state where we need to go into panic mode and synchronize
-            self.error(equals, "Invalid assignment target.");
+            self.error(&equals, Code::InvalidAssignment, &[]);
         }
 
         Ok(expr)
@@ -343,7 +512,7 @@ impl<'t> Parser<'t> {
     fn logic_and(&mut self) -> Result<Expr, Error> {
         let mut expr = self.equality()?;
 
-        while matches!(self, TokenType::Or) {
+        while matches!(self, TokenType::And) {
             let operator = (*self.previous()).clone();
             let right = self.equality()?;
             expr = Expr::Logical {
@@ -427,7 +596,6 @@ impl<'t> Parser<'t> {
         let mut expr: Expr = self.unary()?;
 
         while matches!(self, TokenType::Slash, TokenType::Star) {
-            print!("In the while in factor");
             let operator = (*self.previous()).clone();
             let right: Expr = self.unary()?;
             expr = Expr::Binary {
@@ -440,7 +608,7 @@ impl<'t> Parser<'t> {
         Ok(expr)
     }
 
-    // unary          → ( "!" | "-" ) unary | call ;
+    // unary          → ( "!" | "-" ) unary | power ;
     fn unary(&mut self) -> Result<Expr, Error> {
         if matches!(self, TokenType::Bang, TokenType::Minus) {
             let operator = (*self.previous()).clone();
@@ -452,10 +620,32 @@ impl<'t> Parser<'t> {
             return Ok(expr);
         }
 
-        self.call()
+        self.power()
+    }
+
+    // power          → call ( "**" unary )? ;
+    // Exponentiation binds tighter than a unary operator on its left, so
+    // `-2 ** 2` is `-(2 ** 2)`, but its right operand may be unary, `2 ** -1`.
+    // Parsing the right operand with unary(), which comes back here, makes it
+    // right-associative: `2 ** 3 ** 2` is `2 ** (3 ** 2)`.
+    fn power(&mut self) -> Result<Expr, Error> {
+        let expr = self.call()?;
+
+        if matches!(self, TokenType::StarStar) {
+            let operator = (*self.previous()).clone();
+            self.require(self.features.power, &operator, "power");
+            let right = self.unary()?;
+            return Ok(Expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            });
+        }
+
+        Ok(expr)
     }
 
-    // call           → primary ( "(" arguments? ")" | "." IDENTIFIER )* ;
+    // call           → primary ( "(" arguments? ")" | ( "." | "?." ) IDENTIFIER )* ;
     // This rule matches a primary expression followed by zero or more function calls.
     // If there are no parentheses, this parses a bare primary expression.
     // Otherwise, each call is recognized by a pair of parentheses with an optional list of arguments inside.
@@ -466,10 +656,28 @@ impl<'t> Parser<'t> {
             if matches!(self, TokenType::LeftParen) {
                 expr = self.finish_call(expr)?;
             } else if matches!(self, TokenType::Dot) {
-                let name = self.consume(TokenType::Identifier, "Expect property after '.'.")?;
+                let name = self.consume(
+                    TokenType::Identifier,
+                    Code::ExpectName,
+                    &["property name after '.'"],
+                )?;
+                expr = Expr::Get {
+                    object: Box::new(expr),
+                    name,
+                    optional: false,
+                }
+            } else if matches!(self, TokenType::QuestionDot) {
+                let operator = self.previous().clone();
+                self.require(self.features.safe_navigation, &operator, "safe-navigation");
+                let name = self.consume(
+                    TokenType::Identifier,
+                    Code::ExpectName,
+                    &["property name after '?.'"],
+                )?;
                 expr = Expr::Get {
                     object: Box::new(expr),
-                    name: name,
+                    name,
+                    optional: true,
                 }
             } else {
                 break;
@@ -487,10 +695,10 @@ impl<'t> Parser<'t> {
                     // Only reporting error, not throwing.
                     // Throwing is how we kick into panic mode which is what we want if the parser is in a confused state and doesn't know where it is in the grammar anymore.
                     // But here, the parser is still in a prefectly valid state - it just found too many arguments.
-                    self.error(self.peek(), "Can't have more than 255 arguments.");
+                    self.error(self.peek(), Code::TooManyArguments, &[]);
                 }
 
-                arguments.push(self.expression()?);
+                arguments.push(self.assignment()?);
 
                 if !matches!(self, TokenType::Comma) {
                     break;
@@ -498,12 +706,25 @@ impl<'t> Parser<'t> {
             }
         }
 
-        let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
+        let paren = self.consume(
+            TokenType::RightParen,
+            Code::ExpectRightParen,
+            &["arguments"],
+        )?;
+
+        // Each `?.` only guards its own receiver: in `obj?.method()` the call
+        // is skipped along with the access, in `obj?.a.b` the `.b` is not.
+        let optional = if let Expr::Get { optional, .. } = calle {
+            optional
+        } else {
+            false
+        };
 
         Ok(Expr::Call {
             callee: Box::new(calle),
             paren,
             arguments,
+            optional,
         })
     }
 
@@ -511,9 +732,17 @@ impl<'t> Parser<'t> {
     // This rule requires at least one argument expression, followed by zero or more other expressions, each preceded by a comma.
     // To handle zero-argument calls, the call rule itself considers the entire arguments production to be optional.
 
-    // primary        → NUMBER | STRING | "true" | "false" | "nil" | "(" expression ")" | IDENTIFIER | "super" "." IDENTIFIER ;
+    // primary        → NUMBER | STRING | "true" | "false" | "nil" | "(" expression ")" | IDENTIFIER | "super" "." IDENTIFIER | matchExpr | tryExpr ;
     fn primary(&mut self) -> Result<Expr, Error> {
         let expr = match &self.peek().token_type {
+            TokenType::Match => {
+                self.advance();
+                return self.match_expression();
+            }
+            TokenType::Try => {
+                self.advance();
+                return self.try_expression();
+            }
             TokenType::False => Expr::Literal {
                 value: LiteralValue::Boolean(false),
             },
@@ -524,32 +753,46 @@ impl<'t> Parser<'t> {
                 value: LiteralValue::Null,
             },
             TokenType::Number { literal } => Expr::Literal {
-                value: LiteralValue::Number(literal.clone()),
+                value: LiteralValue::Number(*literal),
             },
             TokenType::String { literal } => Expr::Literal {
-                value: LiteralValue::String(literal.clone()),
+                value: LiteralValue::String(Rc::from(literal.as_str())),
             },
             TokenType::LeftParen => {
+                self.advance();
                 let expr = self.expression()?;
-                self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
-                Expr::Grouping {
+                self.consume(
+                    TokenType::RightParen,
+                    Code::ExpectRightParen,
+                    &["expression"],
+                )?;
+                return Ok(Expr::Grouping {
                     expression: Box::new(expr),
-                }
+                });
             }
             TokenType::Identifier => Expr::Variable {
                 name: self.peek().clone(),
+                resolution: Resolution::default(),
             },
             TokenType::This => Expr::This {
                 keyword: self.peek().clone(),
+                resolution: Resolution::default(),
             },
             TokenType::Super => {
                 let keyword = self.advance().clone();
-                self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
-                let method =
-                    self.consume(TokenType::Identifier, "Expect superclass method name.")?;
-                return Ok(Expr::Super { keyword, method });
+                self.consume(TokenType::Dot, Code::ExpectDot, &[])?;
+                let method = self.consume(
+                    TokenType::Identifier,
+                    Code::ExpectName,
+                    &["superclass method name"],
+                )?;
+                return Ok(Expr::Super {
+                    keyword,
+                    method,
+                    resolution: Resolution::default(),
+                });
             }
-            _ => return Err(self.error(self.peek(), "Expect expression")),
+            _ => return Err(self.error(self.peek(), Code::ExpectExpression, &[])),
         };
 
         self.advance();
@@ -557,17 +800,151 @@ impl<'t> Parser<'t> {
         Ok(expr)
     }
 
+    // matchExpr      → "match" expression "{" arm ( "," arm )* ","? "}" ;
+    // arm            → pattern "->" assignment ;
+    fn match_expression(&mut self) -> Result<Expr, Error> {
+        let keyword = self.previous().clone();
+        self.require(self.features.match_expressions, &keyword, "match");
+        let subject = self.expression()?;
+        self.consume(
+            TokenType::LeftBrace,
+            Code::ExpectLeftBrace,
+            &["after match subject"],
+        )?;
+
+        let mut arms = Vec::new();
+        loop {
+            let pattern = self.pattern()?;
+            self.consume(TokenType::Arrow, Code::ExpectArrow, &[])?;
+            let body = self.assignment()?;
+            arms.push(MatchArm { pattern, body });
+
+            if !matches!(self, TokenType::Comma) || self.check(TokenType::RightBrace) {
+                break;
+            }
+        }
+        self.consume(
+            TokenType::RightBrace,
+            Code::ExpectRightBrace,
+            &["match arms"],
+        )?;
+
+        Ok(Expr::Match {
+            keyword,
+            subject: Box::new(subject),
+            arms,
+        })
+    }
+
+    // tryExpr        → "try" assignment "else" assignment ;
+    //
+    // A `try` followed by a brace starts a try statement instead, see
+    // statement.
+    fn try_expression(&mut self) -> Result<Expr, Error> {
+        let keyword = self.previous().clone();
+        self.require(self.features.exceptions, &keyword, "exceptions");
+        let body = self.assignment()?;
+        self.consume(TokenType::Else, Code::ExpectElse, &[])?;
+        let default = self.assignment()?;
+
+        Ok(Expr::Try {
+            keyword,
+            body: Box::new(body),
+            default: Box::new(default),
+        })
+    }
+
+    // pattern        → "_" | literal | "-" NUMBER | IDENTIFIER
+    //                | IDENTIFIER "(" ( field ( "," field )* )? ")"
+    //                | "[" ( pattern ( "," pattern )* )? "]" ;
+    // field          → IDENTIFIER ( ":" pattern )? ;
+    fn pattern(&mut self) -> Result<Pattern, Error> {
+        let literal = match &self.peek().token_type {
+            TokenType::False => Some(LiteralValue::Boolean(false)),
+            TokenType::True => Some(LiteralValue::Boolean(true)),
+            TokenType::Nil => Some(LiteralValue::Null),
+            TokenType::Number { literal } => Some(LiteralValue::Number(*literal)),
+            TokenType::String { literal } => Some(LiteralValue::String(Rc::from(literal.as_str()))),
+            _ => None,
+        };
+        if let Some(literal) = literal {
+            self.advance();
+            return Ok(Pattern::Literal(literal));
+        }
+
+        if matches!(self, TokenType::Minus) {
+            if let TokenType::Number { literal } = self.peek().token_type {
+                self.advance();
+                return Ok(Pattern::Literal(LiteralValue::Number(-literal)));
+            }
+            return Err(self.error(self.peek(), Code::ExpectPatternNumber, &[]));
+        }
+
+        if matches!(self, TokenType::LeftBracket) {
+            let bracket = self.previous().clone();
+            let mut elements = Vec::new();
+            if !self.check(TokenType::RightBracket) {
+                loop {
+                    elements.push(self.pattern()?);
+                    if !matches!(self, TokenType::Comma) {
+                        break;
+                    }
+                }
+            }
+            self.consume(
+                TokenType::RightBracket,
+                Code::ExpectRightBracket,
+                &["list pattern"],
+            )?;
+            return Ok(Pattern::List { bracket, elements });
+        }
+
+        let name = self.consume(TokenType::Identifier, Code::ExpectPattern, &[])?;
+        if name.lexeme == "_" {
+            return Ok(Pattern::Wildcard);
+        }
+        if !matches!(self, TokenType::LeftParen) {
+            return Ok(Pattern::Binding(name));
+        }
+
+        let mut fields = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                let field =
+                    self.consume(TokenType::Identifier, Code::ExpectName, &["field name"])?;
+                let pattern = if matches!(self, TokenType::Colon) {
+                    self.pattern()?
+                } else {
+                    Pattern::Binding(field.clone())
+                };
+                fields.push((field, pattern));
+                if !matches!(self, TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, Code::ExpectRightParen, &["fields"])?;
+
+        Ok(Pattern::Instance {
+            class: Expr::Variable {
+                name,
+                resolution: Resolution::default(),
+            },
+            fields,
+        })
+    }
+
     // printStmt      → "print" expression ";" ;
     fn print_statement(&mut self) -> Result<Stmt, Error> {
         let value = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expected ; after value.")?;
+        self.consume(TokenType::Semicolon, Code::ExpectSemicolon, &["value"])?;
         Ok(Stmt::Print { expression: value })
     }
 
     // exprStmt       → expression ";" ;
     fn expression_statement(&mut self) -> Result<Stmt, Error> {
         let value = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expected ; after value.")?;
+        self.consume(TokenType::Semicolon, Code::ExpectSemicolon, &["value"])?;
         Ok(Stmt::Expression { expression: value })
     }
 
@@ -580,11 +957,15 @@ impl<'t> Parser<'t> {
             }
 
             match self.peek().token_type {
-                TokenType::Fun
+                TokenType::Class
+                | TokenType::Fun
                 | TokenType::Var
                 | TokenType::For
                 | TokenType::If
                 | TokenType::While
+                | TokenType::Try
+                | TokenType::Throw
+                | TokenType::Defer
                 | TokenType::Print
                 | TokenType::Return => return,
                 _ => self.advance(),
@@ -612,38 +993,47 @@ impl<'t> Parser<'t> {
     }
 
     fn advance(&mut self) -> &Token {
-        if !self.is_at_end() {
-            self.current += 1;
-        }
+        self.tokens.advance();
         self.previous()
     }
 
     fn is_at_end(&self) -> bool {
-        return self.peek().token_type == TokenType::Eof;
+        self.tokens.is_at_end()
     }
 
     fn peek(&self) -> &Token {
-        self.tokens
-            .get(self.current)
-            .expect("Peek into end of token stream.")
+        self.tokens.peek()
     }
 
     fn previous(&self) -> &Token {
-        self.tokens
-            .get(self.current - 1)
-            .expect("Previous was empty.")
+        self.tokens.previous().expect("Previous was empty.")
     }
 
-    fn consume(&mut self, token_type: TokenType, msg: &str) -> Result<Token, Error> {
+    fn consume(
+        &mut self,
+        token_type: TokenType,
+        code: Code,
+        args: &[&str],
+    ) -> Result<Token, Error> {
         if self.check(token_type) {
             Ok(self.advance().clone())
         } else {
-            Err(self.error(self.peek(), msg))
+            Err(self.error(self.peek(), code, args))
+        }
+    }
+
+    // Reports the use of a disabled extension. Parsing goes on as if it was
+    // enabled, there is nothing to recover from.
+    fn require(&self, enabled: bool, token: &Token, feature: &str) {
+        if !enabled {
+            self.error(token, Code::FeatureDisabled, &[feature]);
         }
     }
 
-    fn error(&self, token: &Token, msg: &str) -> Error {
-        parser_error(token, msg);
+    fn error(&self, token: &Token, code: Code, args: &[&str]) -> Error {
+        self.diagnostics
+            .borrow_mut()
+            .push(Diagnostic::at(token, code, args));
         Error::Parse
     }
 }
@@ -0,0 +1,442 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use crate::call_graph::to_dot;
+use crate::error::{Code, Diagnostic, Error};
+use crate::include_cache::IncludeCache;
+use crate::lox::read_file;
+use crate::rename::is_identifier;
+use crate::scanner::{LiteralLimits, Scanner};
+use crate::token::{Span, Token, TokenType};
+
+// An optional pass in front of the parser for splitting a program over several
+// files until Lox has modules. It understands two directives, each on a line of
+// its own:
+//
+//   #include "file.lox"   the tokens of the file, relative to the including one
+//   #define NAME value    NAME is replaced by the tokens of value from here on
+//
+// Files are scanned one at a time and the directives work on tokens, so a
+// define never touches strings, comments or parts of longer identifiers.
+// Including a file a second time, directly or through a cycle, does nothing.
+//
+// Every file gets its own range of lines, one after the other, and its tokens
+// are numbered accordingly. The LineMap turns such a line back into the file
+// and the line within it for diagnostics.
+//
+// With an IncludeCache files whose text was scanned before aren't scanned
+// again, their tokens come from the cache.
+pub struct Preprocessor {
+    files: Vec<SourceFile>,
+    // The name every file read so far was read under.
+    included: HashMap<PathBuf, String>,
+    // Which file includes which, each time it does.
+    includes: Vec<(String, String)>,
+    defines: HashMap<String, Vec<Token>>,
+    tokens: Vec<Token>,
+    // First line of the next file to be read.
+    next_line: i32,
+    literal_limits: LiteralLimits,
+    cache: IncludeCache,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+// How the files of a program depend on each other. Including a file read
+// before, like one closing a cycle, is an edge too.
+#[derive(Debug)]
+pub struct IncludeGraph {
+    // In the order they were read, the file given first.
+    pub files: Vec<String>,
+    // The including file and the included one.
+    pub includes: Vec<(String, String)>,
+}
+
+impl IncludeGraph {
+    pub fn to_dot(&self) -> String {
+        to_dot("includes", &self.files, &self.includes)
+    }
+}
+
+// One include a line, `including -> included`.
+impl fmt::Display for IncludeGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (including, included) in &self.includes {
+            writeln!(f, "{} -> {}", including, included)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct SourceFile {
+    pub name: String,
+    pub contents: String,
+    first_line: i32,
+    lines: i32,
+}
+
+#[derive(Debug, Default)]
+pub struct LineMap {
+    files: Vec<SourceFile>,
+}
+
+impl LineMap {
+    // The file a line of the preprocessed program comes from and the line
+    // within that file.
+    pub fn locate(&self, line: i32) -> Option<(&SourceFile, i32)> {
+        self.files
+            .iter()
+            .find(|file| file.first_line <= line && line < file.first_line + file.lines)
+            .map(|file| (file, line - file.first_line + 1))
+    }
+}
+
+enum Directive {
+    Include {
+        path: String,
+        column: usize,
+    },
+    Define {
+        name: Token,
+        value: String,
+        column: usize,
+    },
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Preprocessor {
+            files: Vec::new(),
+            included: HashMap::new(),
+            includes: Vec::new(),
+            defines: HashMap::new(),
+            tokens: Vec::new(),
+            next_line: 1,
+            literal_limits: LiteralLimits::default(),
+            cache: IncludeCache::disabled(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    pub fn set_cache(&mut self, cache: IncludeCache) {
+        self.cache = cache;
+    }
+
+    pub fn set_literal_limits(&mut self, limits: LiteralLimits) {
+        self.literal_limits = limits;
+    }
+
+    pub fn had_error(&self) -> bool {
+        !self.diagnostics.is_empty()
+    }
+
+    // The tokens of the file and everything it includes, ending with EOF. Only
+    // a file that can't be read at all is an error, everything else is
+    // reported in the diagnostics like the scanner does.
+    pub fn preprocess(&mut self, path: &str) -> Result<Vec<Token>, Error> {
+        let contents = read_file(path)?;
+        let eof = self.process(path, contents);
+        self.tokens.push(eof);
+        // Directives are checked before the tokens of their file are read, put
+        // the errors back in order.
+        self.diagnostics
+            .sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+        debug!(
+            hits = self.cache.hits,
+            misses = self.cache.misses,
+            "include cache"
+        );
+        Ok(std::mem::take(&mut self.tokens))
+    }
+
+    // The files read by preprocess and the #include directives between them.
+    pub fn include_graph(&self) -> IncludeGraph {
+        IncludeGraph {
+            files: self.files.iter().map(|file| file.name.clone()).collect(),
+            includes: self.includes.clone(),
+        }
+    }
+
+    pub fn take_line_map(&mut self) -> LineMap {
+        LineMap {
+            files: std::mem::take(&mut self.files),
+        }
+    }
+
+    // Adds the tokens of the file to the output and returns its EOF token.
+    fn process(&mut self, path: &str, contents: String) -> Token {
+        self.included.insert(
+            fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path)),
+            path.to_string(),
+        );
+        let first_line = self.next_line;
+        let lines = contents.split('\n').count() as i32;
+        self.next_line += lines;
+
+        // Directive lines are left empty for the scanner so everything else
+        // keeps its line and column. A line starting inside a string that
+        // spans lines is part of the string, whatever it starts with.
+        let mut directives = Vec::new();
+        let mut text = String::new();
+        let mut in_string = false;
+        for (index, line) in contents.split('\n').enumerate() {
+            if index > 0 {
+                text.push('\n');
+            }
+            let line_number = first_line + index as i32;
+            // The shebang line of a script is no directive, the scanner skips it.
+            if index == 0 && line.starts_with("#!") {
+                text.push_str(line);
+                continue;
+            }
+            if !in_string {
+                match self.directive(line, line_number) {
+                    Some(directive) => {
+                        directives.push((line_number, directive));
+                        continue;
+                    }
+                    None if line.trim_start().starts_with('#') => continue,
+                    None => (),
+                }
+            }
+            text.push_str(line);
+            in_string = ends_in_string(line, in_string);
+        }
+        self.files.push(SourceFile {
+            name: path.to_string(),
+            contents,
+            first_line,
+            lines,
+        });
+
+        let mut tokens = self.scan_file(&text, first_line).into_iter().peekable();
+        for (line, directive) in directives {
+            while let Some(token) = tokens.next_if(|token| token.line < line) {
+                self.emit(token);
+            }
+            match directive {
+                Directive::Include {
+                    path: included,
+                    column,
+                } => self.include(path, &included, line, column),
+                Directive::Define {
+                    name,
+                    value,
+                    column,
+                } => self.define(name, &value, column),
+            }
+        }
+
+        let mut eof = None;
+        for token in tokens {
+            if token.token_type == TokenType::Eof {
+                eof = Some(token);
+            } else {
+                self.emit(token);
+            }
+        }
+        eof.expect("The scanner always ends with EOF.")
+    }
+
+    // Parses a line starting with `#`, reporting anything malformed.
+    fn directive(&mut self, line: &str, line_number: i32) -> Option<Directive> {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let trimmed = line.trim_start();
+        let rest = trimmed.strip_prefix('#')?;
+        let column = line.chars().count() - trimmed.chars().count() + 1;
+
+        let keyword: String = rest.chars().take_while(|c| c.is_alphanumeric()).collect();
+        let arguments = &rest[keyword.len()..];
+        let arguments_column = column + 1 + keyword.chars().count();
+        match keyword.as_str() {
+            "include" => {
+                let path = arguments
+                    .trim()
+                    .strip_prefix('"')
+                    .and_then(|path| path.strip_suffix('"'))
+                    .filter(|path| !path.is_empty() && !path.contains('"'));
+                match path {
+                    Some(path) => Some(Directive::Include {
+                        path: path.to_string(),
+                        column,
+                    }),
+                    None => {
+                        self.error(line_number, column, Code::ExpectIncludePath, &[]);
+                        None
+                    }
+                }
+            }
+            "define" => {
+                let trimmed_arguments = arguments.trim_start();
+                let name_column = arguments_column + arguments.chars().count()
+                    - trimmed_arguments.chars().count();
+                let (name, value) = trimmed_arguments
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((trimmed_arguments, ""));
+                if !is_identifier(name) {
+                    self.error(line_number, name_column, Code::ExpectDefineName, &[]);
+                    return None;
+                }
+                if value.trim().is_empty() {
+                    self.error(line_number, name_column, Code::ExpectDefineValue, &[name]);
+                    return None;
+                }
+                Some(Directive::Define {
+                    name: Token::new(TokenType::Identifier, name, line_number, name_column),
+                    value: value.to_string(),
+                    column: name_column + name.chars().count() + 1,
+                })
+            }
+            _ => {
+                self.error(line_number, column, Code::UnknownDirective, &[&keyword]);
+                None
+            }
+        }
+    }
+
+    fn include(&mut self, including: &str, included: &str, line: i32, column: usize) {
+        let path = Path::new(including)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(included);
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if let Some(name) = self.included.get(&canonical) {
+            let edge = (including.to_string(), name.clone());
+            self.includes.push(edge);
+            return;
+        }
+
+        let name = path.display().to_string();
+        match read_file(&name) {
+            Ok(contents) => {
+                self.includes.push((including.to_string(), name.clone()));
+                self.process(&name, contents);
+            }
+            Err(Error::Io(error)) => {
+                self.error(
+                    line,
+                    column,
+                    Code::CannotInclude,
+                    &[included, &error.to_string()],
+                );
+            }
+            Err(_) => unreachable!(),
+        }
+    }
+
+    fn define(&mut self, name: Token, value: &str, column: usize) {
+        if self.defines.contains_key(&name.lexeme) {
+            let diagnostic = Diagnostic::at(&name, Code::AlreadyDefined, &[&name.lexeme]);
+            self.diagnostics.push(diagnostic);
+            return;
+        }
+
+        // Names defined earlier are expanded right away, so a define can't
+        // refer to itself.
+        let mut tokens = Vec::new();
+        for token in self.scan(value, name.line, column) {
+            if token.token_type != TokenType::Eof {
+                tokens.extend(self.expand(token));
+            }
+        }
+        self.defines.insert(name.lexeme, tokens);
+    }
+
+    fn emit(&mut self, token: Token) {
+        let expanded = self.expand(token);
+        self.tokens.extend(expanded);
+    }
+
+    // The replacement for a defined name, moved to where the name is used so
+    // errors in it point there.
+    fn expand(&self, token: Token) -> Vec<Token> {
+        match self.defines.get(&token.lexeme) {
+            Some(value) if token.token_type == TokenType::Identifier => value
+                .iter()
+                .map(|replacement| Token {
+                    line: token.line,
+                    column: token.column,
+                    ..replacement.clone()
+                })
+                .collect(),
+            _ => vec![token],
+        }
+    }
+
+    // Scans the text of a file that starts at the given line of the program,
+    // or takes its tokens from the cache.
+    fn scan_file(&mut self, text: &str, line: i32) -> Vec<Token> {
+        if let Some(tokens) = self.cache.tokens(text, self.literal_limits) {
+            return self.place(tokens, Vec::new(), line, 1);
+        }
+        let mut scanner = Scanner::with_limits(text, self.literal_limits);
+        let tokens = scanner.scan_tokens();
+        if !scanner.had_error() {
+            self.cache.store(text, self.literal_limits, &tokens);
+        }
+        self.place(tokens, scanner.diagnostics, line, 1)
+    }
+
+    // Scans text that starts at the given line and column of the program.
+    fn scan(&mut self, text: &str, line: i32, column: usize) -> Vec<Token> {
+        let mut scanner = Scanner::with_limits(text, self.literal_limits);
+        let tokens = scanner.scan_tokens();
+        self.place(tokens, scanner.diagnostics, line, column)
+    }
+
+    // Moves the tokens and errors of text scanned on its own to where the
+    // text starts in the program.
+    fn place(
+        &mut self,
+        mut tokens: Vec<Token>,
+        diagnostics: Vec<Diagnostic>,
+        line: i32,
+        column: usize,
+    ) -> Vec<Token> {
+        for token in tokens.iter_mut() {
+            if token.line == 1 {
+                token.column += column - 1;
+            }
+            token.line += line - 1;
+        }
+        for mut diagnostic in diagnostics {
+            if diagnostic.line == 1 {
+                diagnostic.column += column - 1;
+            }
+            diagnostic.line += line - 1;
+            self.diagnostics.push(diagnostic);
+        }
+        tokens
+    }
+
+    fn error(&mut self, line: i32, column: usize, code: Code, args: &[&str]) {
+        let span = Span { line, column };
+        self.diagnostics.push(Diagnostic::new(span, code, args));
+    }
+}
+
+// Whether a line ends inside a string literal, given whether it starts inside
+// one, by the rules of the scanner: a `"` opens a string and the next one
+// closes it, on the same line or a later one, and `//` outside a string
+// comments out the rest of the line.
+fn ends_in_string(line: &str, mut in_string: bool) -> bool {
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_string = !in_string,
+            '/' if !in_string && chars.peek() == Some(&'/') => break,
+            _ => (),
+        }
+    }
+    in_string
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::time::{Duration, Instant};
+
+// Where a program spends its time, by function. The interpreter tells the
+// profiler when every call starts and ends, natives included, and it adds up
+// for each function:
+//
+// - inclusive time, from the start of a call to its end, counted once for a
+//   recursive function, by the outermost of its calls in progress
+// - exclusive time, the inclusive time minus that of the calls made from it
+//
+// The exclusive time is also kept per call stack, `script;outer;inner`, the
+// folded format flame graph tools like inferno and flamegraph.pl read.
+pub struct Profiler {
+    // The calls in progress, the script at the bottom.
+    stack: Vec<Call>,
+    functions: HashMap<String, Totals>,
+    stacks: HashMap<String, Duration>,
+}
+
+struct Call {
+    name: String,
+    // The names of the calls below this one and its own, separated by `;`.
+    path: String,
+    start: Instant,
+    // The inclusive time of the calls made from this one.
+    children: Duration,
+}
+
+#[derive(Default)]
+struct Totals {
+    calls: usize,
+    inclusive: Duration,
+    exclusive: Duration,
+    // Calls of the function in progress, so recursion isn't counted twice.
+    active: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionProfile {
+    // Methods are qualified with their class, `Point.init`, the top level is
+    // `script`.
+    pub name: String,
+    pub calls: usize,
+    pub inclusive: Duration,
+    pub exclusive: Duration,
+}
+
+// What the profiler found once the program finished.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    // Hottest first, by exclusive time.
+    pub functions: Vec<FunctionProfile>,
+    // The exclusive time of every call stack, in no particular order.
+    pub stacks: Vec<(String, Duration)>,
+    pub total: Duration,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    // Starts timing the script.
+    pub fn new() -> Self {
+        let mut profiler = Profiler {
+            stack: Vec::new(),
+            functions: HashMap::new(),
+            stacks: HashMap::new(),
+        };
+        profiler.enter("script".to_string());
+        profiler
+    }
+
+    pub(crate) fn enter(&mut self, name: String) {
+        let path = match self.stack.last() {
+            Some(caller) => format!("{};{}", caller.path, name),
+            None => name.clone(),
+        };
+        let totals = self.functions.entry(name.clone()).or_default();
+        totals.calls += 1;
+        totals.active += 1;
+        self.stack.push(Call {
+            name,
+            path,
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    pub(crate) fn exit(&mut self) {
+        let Some(call) = self.stack.pop() else {
+            return;
+        };
+        let inclusive = call.start.elapsed();
+        let exclusive = inclusive.saturating_sub(call.children);
+        if let Some(caller) = self.stack.last_mut() {
+            caller.children += inclusive;
+        }
+
+        let totals = self
+            .functions
+            .get_mut(&call.name)
+            .expect("A function is counted when it is entered.");
+        totals.active -= 1;
+        if totals.active == 0 {
+            totals.inclusive += inclusive;
+        }
+        totals.exclusive += exclusive;
+        *self.stacks.entry(call.path).or_default() += exclusive;
+    }
+
+    // Stops timing, ending the calls still in progress when the program
+    // failed.
+    pub fn finish(mut self) -> Profile {
+        while !self.stack.is_empty() {
+            self.exit();
+        }
+        let total = self
+            .functions
+            .get("script")
+            .map_or(Duration::ZERO, |script| script.inclusive);
+        let mut functions: Vec<FunctionProfile> = self
+            .functions
+            .into_iter()
+            .map(|(name, totals)| FunctionProfile {
+                name,
+                calls: totals.calls,
+                inclusive: totals.inclusive,
+                exclusive: totals.exclusive,
+            })
+            .collect();
+        functions.sort_by(|a, b| {
+            b.exclusive
+                .cmp(&a.exclusive)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        Profile {
+            functions,
+            stacks: self.stacks.into_iter().collect(),
+            total,
+        }
+    }
+}
+
+impl Profile {
+    // A table of the `count` hottest functions.
+    pub fn top(&self, count: usize) -> Top<'_> {
+        Top {
+            profile: self,
+            count,
+        }
+    }
+
+    // One line per call stack with its exclusive time in microseconds, for
+    // flame graph tools:
+    //
+    //   script;fib;fib 1520
+    pub fn folded(&self) -> String {
+        let mut stacks = self.stacks.clone();
+        stacks.sort();
+        let mut folded = String::new();
+        for (path, time) in stacks {
+            let _ = writeln!(folded, "{} {}", path, time.as_micros());
+        }
+        folded
+    }
+}
+
+pub struct Top<'p> {
+    profile: &'p Profile,
+    count: usize,
+}
+
+impl fmt::Display for Top<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = |time: Duration| time.as_secs_f64() * 1000.0;
+        let total = millis(self.profile.total);
+        writeln!(
+            f,
+            "{:<30} {:>10} {:>14} {:>14} {:>7}",
+            "function", "calls", "inclusive ms", "exclusive ms", "self %"
+        )?;
+        for function in self.profile.functions.iter().take(self.count) {
+            let share = if total > 0.0 {
+                millis(function.exclusive) / total * 100.0
+            } else {
+                0.0
+            };
+            writeln!(
+                f,
+                "{:<30} {:>10} {:>14.3} {:>14.3} {:>7.1}",
+                function.name,
+                function.calls,
+                millis(function.inclusive),
+                millis(function.exclusive),
+                share
+            )?;
+        }
+        Ok(())
+    }
+}
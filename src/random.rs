@@ -0,0 +1,86 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::inspect::inspect;
+use crate::interpreter::Interpreter;
+use crate::list;
+use crate::object::Object;
+
+// The random numbers of a program come from one generator owned by the
+// interpreter, so a program run with the same seed, from random_seed(n),
+// Lox::set_random_seed or --seed, makes the same choices every time. Without
+// one the generator is seeded from the clock.
+//
+// The generator is SplitMix64: fast, every seed is a good one, and plenty
+// random for games and simulations. It is not for anything secret.
+pub(crate) struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub(crate) fn new(seed: u64) -> Self {
+        Random { state: seed }
+    }
+
+    pub(crate) fn from_clock() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Could not retrieve time.");
+        Random::new(now.as_nanos() as u64)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1), with all 53 bits of the mantissa random.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Uniform in [0, n) for n > 0.
+    fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+}
+
+// random_seed(n) restarts the generator, the same seed gives the same
+// numbers. Seeds are whole numbers from 0, the same as the ones of --seed.
+pub(crate) fn random_seed(
+    interpreter: &mut Interpreter,
+    args: &[Object],
+) -> Result<Object, String> {
+    match args[0] {
+        Object::Number(seed) if seed.fract() == 0.0 && (0.0..=u64::MAX as f64).contains(&seed) => {
+            interpreter.set_random_seed(seed as u64);
+            Ok(Object::Null)
+        }
+        ref other => Err(format!(
+            "random_seed expects a whole number from 0 but got {}.",
+            inspect(other)
+        )),
+    }
+}
+
+// random() is a number from 0 up to but not including 1.
+pub(crate) fn random(interpreter: &mut Interpreter, _args: &[Object]) -> Result<Object, String> {
+    Ok(Object::Number(interpreter.random().next_f64()))
+}
+
+// shuffle(list) puts the elements of a List in random order, every order
+// being equally likely. Returns the list.
+pub(crate) fn shuffle(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let random = interpreter.random();
+    list::with_items(&args[0], |items| {
+        // Fisher-Yates: pick the last element among all of them, the one
+        // before it among the rest, and so on.
+        for i in (1..items.len()).rev() {
+            items.swap(i, random.below(i + 1));
+        }
+        Ok(())
+    })?;
+    Ok(args[0].clone())
+}
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::inspect::inspect;
+use crate::json::{self, quote, Json};
+use crate::object::Object;
+use crate::syntax::{Expr, SourcePrinter, Stmt};
+
+// A log of everything a program did, written while it runs so a run that went
+// wrong can be stepped through afterwards with `--replay`. Values are stored as
+// `inspect` shows them, the log is for reading and not for restoring state.
+//
+// The file is a JSON array with one event per line:
+//
+//   {"event": "statement", "line": 3, "source": "var a = add(1, 2);"}
+//   {"event": "call", "line": 3, "function": "add", "arguments": ["1", "2"]}
+//   {"event": "return", "line": 3, "function": "add", "value": "3"}
+//   {"event": "write", "line": 3, "name": "a", "value": "3"}
+//   {"event": "field", "line": 4, "object": "Point instance", "name": "x", "value": "1"}
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    // Only the first line of the statement's source, a block or a function is
+    // followed by the events of its body anyway.
+    Statement {
+        line: Option<i32>,
+        source: String,
+    },
+    Call {
+        line: i32,
+        function: String,
+        arguments: Vec<String>,
+    },
+    // The call returned normally, an error unwinding through it has no value.
+    // The line is the one of the call.
+    Return {
+        line: i32,
+        function: String,
+        value: String,
+    },
+    // A variable was declared or assigned.
+    Write {
+        line: i32,
+        name: String,
+        value: String,
+    },
+    Field {
+        line: i32,
+        object: String,
+        name: String,
+        value: String,
+    },
+}
+
+impl Event {
+    pub fn statement(statement: &Stmt) -> Self {
+        let source = SourcePrinter::new().print(statement);
+        Event::Statement {
+            line: statement_line(statement),
+            source: source.lines().next().unwrap_or_default().to_string(),
+        }
+    }
+
+    pub fn call(line: i32, function: &str, arguments: &[Object]) -> Self {
+        Event::Call {
+            line,
+            function: function.to_string(),
+            arguments: arguments.iter().map(inspect).collect(),
+        }
+    }
+
+    pub fn write(line: i32, name: &str, value: &Object) -> Self {
+        Event::Write {
+            line,
+            name: name.to_string(),
+            value: inspect(value),
+        }
+    }
+}
+
+// The first line with a token in it, literals don't have one.
+fn statement_line(statement: &Stmt) -> Option<i32> {
+    match statement {
+        Stmt::Block { statements } => statements.iter().find_map(statement_line),
+        Stmt::Class { name, .. } | Stmt::Function { name, .. } | Stmt::Var { name, .. } => {
+            Some(name.line)
+        }
+        Stmt::Return { keyword, .. }
+        | Stmt::While { keyword, .. }
+        | Stmt::Try { keyword, .. }
+        | Stmt::Throw { keyword, .. }
+        | Stmt::Defer { keyword, .. } => Some(keyword.line),
+        Stmt::Expression { expression } | Stmt::Print { expression } => expression_line(expression),
+        Stmt::If { condition, .. } => expression_line(condition),
+    }
+}
+
+fn expression_line(expression: &Expr) -> Option<i32> {
+    match expression {
+        Expr::Binary { left, operator, .. } | Expr::Logical { left, operator, .. } => {
+            expression_line(left).or(Some(operator.line))
+        }
+        Expr::Call { callee, paren, .. } => expression_line(callee).or(Some(paren.line)),
+        Expr::Get { object, name, .. } | Expr::Set { object, name, .. } => {
+            expression_line(object).or(Some(name.line))
+        }
+        Expr::Grouping { expression } => expression_line(expression),
+        Expr::Literal { .. } => None,
+        Expr::Super { keyword, .. }
+        | Expr::This { keyword, .. }
+        | Expr::Match { keyword, .. }
+        | Expr::Try { keyword, .. } => Some(keyword.line),
+        Expr::Unary { operator, .. } => Some(operator.line),
+        Expr::Variable { name, .. } | Expr::Assign { name, .. } => Some(name.line),
+    }
+}
+
+pub struct Recorder {
+    out: BufWriter<File>,
+    events: usize,
+    // The first write that failed. Recording carries on without complaint
+    // so the program isn't disturbed, the error is reported by finish.
+    error: Option<io::Error>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(b"[")?;
+        Ok(Recorder {
+            out,
+            events: 0,
+            error: None,
+        })
+    }
+
+    pub fn record(&mut self, event: &Event) {
+        let separator = if self.events == 0 { "\n" } else { ",\n" };
+        self.events += 1;
+        if self.error.is_none() {
+            if let Err(error) = write!(self.out, "{}{}", separator, event) {
+                self.error = Some(error);
+            }
+        }
+    }
+
+    // Closes the array. The log is only valid JSON after this.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.out.write_all(b"\n]\n")?;
+        self.out.flush()
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Statement { line, source } => {
+                write!(f, "{{\"event\": \"statement\", ")?;
+                if let Some(line) = line {
+                    write!(f, "\"line\": {}, ", line)?;
+                }
+                write!(f, "\"source\": {}}}", quote(source))
+            }
+            Event::Call {
+                line,
+                function,
+                arguments,
+            } => {
+                let arguments: Vec<String> = arguments.iter().map(|a| quote(a)).collect();
+                write!(
+                    f,
+                    "{{\"event\": \"call\", \"line\": {}, \"function\": {}, \"arguments\": [{}]}}",
+                    line,
+                    quote(function),
+                    arguments.join(", ")
+                )
+            }
+            Event::Return {
+                line,
+                function,
+                value,
+            } => write!(
+                f,
+                "{{\"event\": \"return\", \"line\": {}, \"function\": {}, \"value\": {}}}",
+                line,
+                quote(function),
+                quote(value)
+            ),
+            Event::Write { line, name, value } => write!(
+                f,
+                "{{\"event\": \"write\", \"line\": {}, \"name\": {}, \"value\": {}}}",
+                line,
+                quote(name),
+                quote(value)
+            ),
+            Event::Field {
+                line,
+                object,
+                name,
+                value,
+            } => write!(
+                f,
+                "{{\"event\": \"field\", \"line\": {}, \"object\": {}, \"name\": {}, \"value\": {}}}",
+                line,
+                quote(object),
+                quote(name),
+                quote(value)
+            ),
+        }
+    }
+}
+
+// Steps through a recorded log, indenting the events inside calls, forwards
+// and backwards. Going back replays the log from the last checkpoint before
+// the event, a copy of the state kept every CHECKPOINT events, instead of from
+// the start. The state is what the log knows: how deep in calls the run was
+// and the last value written to every variable, so the viewer can show how a
+// variable got its value at any point. Replaying a log never runs the program,
+// every step back sees exactly what happened, random numbers and clocks
+// included.
+pub struct Replay {
+    events: Vec<Event>,
+    // How many events were shown, the last of them is the current one.
+    position: usize,
+    state: State,
+    // The state before the events CHECKPOINT * i, for every i reached so far.
+    checkpoints: Vec<State>,
+}
+
+const CHECKPOINT: usize = 64;
+
+#[derive(Clone, Default)]
+struct State {
+    depth: usize,
+    // The number of the event that last wrote each variable, its line and
+    // the value.
+    values: HashMap<String, (usize, i32, String)>,
+}
+
+// A variable being declared or assigned, for its history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    // The number of the event, as the viewer shows it.
+    pub number: usize,
+    pub line: i32,
+    pub value: String,
+}
+
+impl Replay {
+    pub fn load(text: &str) -> Result<Self, String> {
+        let events = match json::parse(text)? {
+            Json::Array(values) => values
+                .into_iter()
+                .map(event_from)
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => return Err("Expected an array of events.".to_string()),
+        };
+        Ok(Replay {
+            events,
+            position: 0,
+            state: State::default(),
+            checkpoints: Vec::new(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    // Goes back to the event before the current one and returns it like next
+    // does, None at the first event.
+    pub fn back(&mut self) -> Option<(usize, String)> {
+        if self.position <= 1 {
+            return None;
+        }
+        let target = self.position - 1;
+        let checkpoint = (target - 1) / CHECKPOINT;
+        self.state = self.checkpoints[checkpoint].clone();
+        self.position = checkpoint * CHECKPOINT;
+        let mut shown = None;
+        while self.position < target {
+            shown = self.next();
+        }
+        shown
+    }
+
+    // The value of the variable after the current event, with the number and
+    // line of the event that wrote it. Names aren't scoped, a local variable
+    // and a global of the same name are the same to the log.
+    pub fn value(&self, name: &str) -> Option<Assignment> {
+        self.state
+            .values
+            .get(name)
+            .map(|(number, line, value)| Assignment {
+                number: *number,
+                line: *line,
+                value: value.clone(),
+            })
+    }
+
+    // Every write of the variable up to the current event, first to last.
+    pub fn history(&self, name: &str) -> Vec<Assignment> {
+        self.events[..self.position]
+            .iter()
+            .enumerate()
+            .filter_map(|(i, event)| match event {
+                Event::Write {
+                    line,
+                    name: written,
+                    value,
+                } if written == name => Some(Assignment {
+                    number: i + 1,
+                    line: *line,
+                    value: value.clone(),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Iterator for Replay {
+    // The number of the event and how it is shown.
+    type Item = (usize, String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.events.get(self.position)?;
+        if self.position == self.checkpoints.len() * CHECKPOINT {
+            self.checkpoints.push(self.state.clone());
+        }
+        self.position += 1;
+        if let Event::Return { .. } = event {
+            self.state.depth = self.state.depth.saturating_sub(1);
+        }
+        let indent = "  ".repeat(self.state.depth);
+        let shown = match event {
+            Event::Statement { line, source } => match line {
+                Some(line) => format!("[line {}] {}{}", line, indent, source),
+                None => format!("{}{}", indent, source),
+            },
+            Event::Call {
+                line,
+                function,
+                arguments,
+            } => format!(
+                "[line {}] {}call {}({})",
+                line,
+                indent,
+                function,
+                arguments.join(", ")
+            ),
+            Event::Return {
+                line,
+                function,
+                value,
+            } => format!("[line {}] {}{} returned {}", line, indent, function, value),
+            Event::Write { line, name, value } => {
+                let write = (self.position, *line, value.clone());
+                self.state.values.insert(name.clone(), write);
+                format!("[line {}] {}{} = {}", line, indent, name, value)
+            }
+            Event::Field {
+                line,
+                object,
+                name,
+                value,
+            } => format!("[line {}] {}{}.{} = {}", line, indent, object, name, value),
+        };
+        if let Event::Call { .. } = event {
+            self.state.depth += 1;
+        }
+        Some((self.position, shown))
+    }
+}
+
+fn event_from(value: Json) -> Result<Event, String> {
+    let Json::Object(fields) = value else {
+        return Err("Expected an event object.".to_string());
+    };
+    let string = |key: &str| match fields.iter().find(|(name, _)| name == key) {
+        Some((_, Json::String(value))) => Ok(value.clone()),
+        _ => Err(format!("Expected a string for '{}'.", key)),
+    };
+    let line = |key: &str| match fields.iter().find(|(name, _)| name == key) {
+        Some((_, Json::Number(value))) => Ok(*value as i32),
+        _ => Err(format!("Expected a number for '{}'.", key)),
+    };
+    match string("event")?.as_str() {
+        "statement" => Ok(Event::Statement {
+            line: line("line").ok(),
+            source: string("source")?,
+        }),
+        "call" => {
+            let arguments = match fields.iter().find(|(name, _)| name == "arguments") {
+                Some((_, Json::Array(values))) => values
+                    .iter()
+                    .map(|value| match value {
+                        Json::String(value) => Ok(value.clone()),
+                        _ => Err("Expected the arguments as strings.".to_string()),
+                    })
+                    .collect::<Result<_, _>>()?,
+                _ => return Err("Expected an array for 'arguments'.".to_string()),
+            };
+            Ok(Event::Call {
+                line: line("line")?,
+                function: string("function")?,
+                arguments,
+            })
+        }
+        "return" => Ok(Event::Return {
+            line: line("line")?,
+            function: string("function")?,
+            value: string("value")?,
+        }),
+        "write" => Ok(Event::Write {
+            line: line("line")?,
+            name: string("name")?,
+            value: string("value")?,
+        }),
+        "field" => Ok(Event::Field {
+            line: line("line")?,
+            object: string("object")?,
+            name: string("name")?,
+            value: string("value")?,
+        }),
+        other => Err(format!("Unknown event '{}'.", other)),
+    }
+}
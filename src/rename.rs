@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+
+use crate::symbol::{Symbol, SymbolKind, SymbolTable};
+use crate::syntax::Depth;
+use crate::token::{Span, KEYWORDS};
+
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_alphanumeric() || c == '_') && !KEYWORDS.contains_key(name)
+}
+
+// Renames a declaration and all of its references by rewriting the source text
+// at the positions recorded in the symbol table. Everything else, including
+// comments and formatting, is left untouched.
+pub fn rename(source: &str, symbol: &Symbol, new_name: &str) -> String {
+    let mut lines: Vec<String> = source.split('\n').map(String::from).collect();
+
+    // Rewrite from the end of each line so earlier columns stay valid when the
+    // new name has a different length.
+    let mut positions: Vec<Span> = symbol.occurrences().collect();
+    positions.sort_by_key(|span| (span.line, span.column));
+
+    for span in positions.into_iter().rev() {
+        let line = match lines.get_mut((span.line - 1) as usize) {
+            Some(line) => line,
+            None => continue,
+        };
+        let start = match line.char_indices().nth(span.column - 1) {
+            Some((index, _)) => index,
+            None => continue,
+        };
+        if line[start..].starts_with(&symbol.name) {
+            line.replace_range(start..start + symbol.name.len(), new_name);
+        }
+    }
+
+    lines.join("\n")
+}
+
+// Why renaming the symbol to the new name would change what some use of a
+// name refers to, if it would. The new name must not be declared in the
+// symbol's scope, in a scope around it (which the symbol would then hide) or
+// in one nested in it (which would hide the symbol), and no use of the name
+// inside the symbol's scope may refer to a global the program doesn't
+// declare. Declarations of the name in unrelated scopes, like another
+// function's parameters, are fine. `is_builtin` tells the globals the
+// interpreter defines.
+pub fn rename_conflict(
+    symbols: &SymbolTable,
+    symbol: &Symbol,
+    new_name: &str,
+    is_builtin: impl Fn(&str) -> bool,
+) -> Option<String> {
+    if new_name == symbol.name {
+        return None;
+    }
+    if is_builtin(new_name) {
+        return Some(format!(
+            "'{}' would shadow the built-in '{}'.",
+            new_name, new_name
+        ));
+    }
+
+    for other in symbols.symbols() {
+        if other.name != new_name || other.kind == SymbolKind::Method {
+            continue;
+        }
+        if other.scope == symbol.scope {
+            return Some(format!(
+                "'{}' is already declared in the same scope by {} {} at {}.",
+                new_name, other.kind, other.name, other.span
+            ));
+        }
+        if symbols.encloses(other.scope, symbol.scope) {
+            return Some(format!(
+                "'{}' would shadow {} {} at {}.",
+                new_name, other.kind, other.name, other.span
+            ));
+        }
+        if symbols.encloses(symbol.scope, other.scope) {
+            return Some(format!(
+                "'{}' would be shadowed by {} {} at {}.",
+                new_name, other.kind, other.name, other.span
+            ));
+        }
+    }
+
+    symbols
+        .bindings()
+        .into_iter()
+        .find(|binding| {
+            binding.name == new_name
+                && binding.declaration.is_none()
+                && symbols.encloses(symbol.scope, binding.scope)
+        })
+        .map(|binding| {
+            format!(
+                "'{}' would capture the use of the undefined variable at {}.",
+                new_name, binding.span
+            )
+        })
+}
+
+// The source with a comment after every line declaring a name that hides one
+// of an enclosing scope, for learning how scopes work:
+//
+//   var x = "global";
+//   fun show(x) {      // shadows outer 'x' from line 1
+//     print x;
+//   }
+//
+// The comments start in the same column, past the longest line they are on.
+pub fn explain_bindings(source: &str, symbols: &SymbolTable) -> String {
+    let mut notes: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for symbol in symbols.symbols() {
+        if let Some(outer) = symbols.shadowed(symbol) {
+            notes
+                .entry(symbol.span.line as usize)
+                .or_default()
+                .push(format!(
+                    "shadows outer '{}' from line {}",
+                    outer.name, outer.span.line
+                ));
+        }
+    }
+
+    let lines: Vec<&str> = source.split('\n').collect();
+    let width = notes
+        .keys()
+        .filter_map(|line| lines.get(line - 1))
+        .map(|line| line.trim_end().chars().count())
+        .max()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| match notes.get(&(index + 1)) {
+            Some(notes) => format!(
+                "{:<width$}  // {}",
+                line.trim_end(),
+                notes.join("; "),
+                width = width
+            ),
+            None => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// A line for every use of a name saying what the resolver decided: how many
+// scopes out the variable is and its slot there, or that it is global, and
+// the declaration it ends up at. A local used from a function nested in the
+// one declaring it is marked as captured by that function's closure:
+//
+//   span     name             resolved        declaration
+//   4:12     count            local 1 slot 0  var count at 2:7, captured
+//   7:7      clock            global          built-in
+//
+// `is_builtin` tells the globals the program doesn't declare but the
+// interpreter defines from the undefined ones.
+pub fn explain_resolution(symbols: &SymbolTable, is_builtin: impl Fn(&str) -> bool) -> String {
+    let mut text = format!(
+        "{:<8} {:<16} {:<15} declaration\n",
+        "span", "name", "resolved"
+    );
+    for binding in symbols.bindings() {
+        let resolved = match binding.depth {
+            Depth::Local { distance, slot } => format!("local {} slot {}", distance, slot),
+            Depth::Global => "global".to_string(),
+        };
+        let mut declaration = match symbols.declaration(binding) {
+            Some(symbol) => format!("{} {} at {}", symbol.kind, symbol.name, symbol.span),
+            None if binding.name == "this" => "the instance".to_string(),
+            None if binding.name == "super" => "the superclass".to_string(),
+            None if is_builtin(&binding.name) => "built-in".to_string(),
+            None => "undefined".to_string(),
+        };
+        if binding.captured {
+            declaration.push_str(", captured");
+        }
+        text.push_str(&format!(
+            "{:<8} {:<16} {:<15} {}\n",
+            binding.span.to_string(),
+            binding.name,
+            resolved,
+            declaration
+        ));
+    }
+    text
+}
@@ -1,11 +1,20 @@
-use crate::error::{report, Error};
+use crate::class::is_private;
+use crate::environment::Environment;
+use crate::error::{Code, Diagnostic, Error};
+use crate::function::describe_arity;
 use crate::interpreter::Interpreter;
+use crate::object::Object;
+use crate::symbol::{SymbolKind, SymbolTable};
 use crate::syntax::{expr, stmt};
-use crate::syntax::{Expr, LiteralValue, Stmt};
-use crate::token::{Token, TokenType};
+use crate::syntax::{Depth, Expr, LiteralValue, MatchArm, Pattern, Resolution, Stmt};
+use crate::token::{Span, Token};
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::mem;
+use std::rc::Rc;
+
+use tracing::trace;
 
 // Much like we track scopes as we walk the tree, we can track whether or not
 // the code we are currently visiting is inside a function declaration.
@@ -24,40 +33,176 @@ enum ClassType {
     SubClass,
 }
 
+// A variable of a local scope: the slot it gets in the environment the scope
+// becomes when it runs, and whether its initializer has been resolved.
+#[derive(Debug, Clone, Copy)]
+struct Local {
+    slot: usize,
+    defined: bool,
+}
+
 pub struct Resolver<'i> {
     interpreter: &'i mut Interpreter,
     // This field keeps track of the stack of scopes currently, uh, in scope.
     // Each element in the stack is a Map representing a single block scope.
-    // Keys, as in Environment, are variable names.
+    // Keys, as in Environment, are variable names, numbered in the order they
+    // are declared.
 
     // The scope stack is only used for local block scopes. Variables declared
     // at the top level in the global scope are not tracked by the resolver
     // since they are more dynamic in Lox. When resolving a variable, if we
     // can’t find it in the stack of local scopes, we assume it must be global.
-    scopes: Vec<HashMap<String, bool>>,
+    scopes: Vec<HashMap<String, Local>>,
 
     current_function: FunctionType,
     current_class: ClassType,
-
+    // Inside the body of a `defer`, which can't return: the function has
+    // already returned or failed when it runs.
+    in_defer: bool,
+
+    // Only built on request, for tooling like `--symbols`.
+    symbols: Option<SymbolTable>,
+
+    // In strict mode declaring a global that already exists is an error
+    // instead of silently replacing it, except for a class when reopening is
+    // allowed. Globals declared earlier in the same
+    // program are remembered here, older ones are found in the interpreter.
+    pub strict: bool,
+    globals: HashSet<String>,
+
+    // Calls with the wrong number of arguments are left for the interpreter
+    // to report when they run, like jlox does.
+    pub jlox: bool,
+
+    // With reopening allowed, whether each global declared so far is a class
+    // with a superclass, which a reopening of it without one keeps.
+    subclasses: HashMap<String, bool>,
+
+    // Resolving never stops at an error, every statement is looked at so all
+    // of them are reported together. A node is only reported once for the
+    // same problem, however many times it is visited.
     pub had_error: bool,
+    pub diagnostics: Vec<Diagnostic>,
+    reported: HashSet<(Span, Code)>,
+
+    arities: Arities,
+}
+
+// Calls whose number of arguments can be checked before running: the callee
+// is a variable bound to a function declaration of the program, and nothing
+// assigns to it. Calls are collected while resolving and checked at the end,
+// once every assignment has been seen.
+#[derive(Default)]
+struct Arities {
+    // The number of parameters of every function declaration, by its name.
+    functions: HashMap<Token, usize>,
+    // The declaration each name of a local scope refers to, one map for each
+    // of `scopes`. Names put in a scope without a declaration, like `this`,
+    // aren't in them.
+    locals: Vec<HashMap<String, Token>>,
+    // Every declaration of each global in the program.
+    globals: HashMap<String, Vec<Token>>,
+    assigned: HashSet<Binding>,
+    // The callee, its name where it is called and the number of arguments.
+    calls: Vec<(Binding, Token, usize)>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Binding {
+    Local(Token),
+    Global(String),
 }
 
 impl<'i> Resolver<'i> {
     pub fn new(interpreter: &'i mut Interpreter) -> Self {
         Resolver {
-            interpreter: interpreter,
+            interpreter,
             scopes: Vec::new(),
             current_function: FunctionType::None,
+            in_defer: false,
             current_class: ClassType::None,
+            symbols: None,
+            strict: false,
+            jlox: false,
+            globals: HashSet::new(),
+            subclasses: HashMap::new(),
             had_error: false,
+            diagnostics: Vec::new(),
+            reported: HashSet::new(),
+            arities: Arities::default(),
+        }
+    }
+
+    // Same as new() but also records every declaration and its references in a
+    // symbol table that can be retrieved with take_symbols() after resolving.
+    pub fn with_symbols(interpreter: &'i mut Interpreter) -> Self {
+        Resolver {
+            symbols: Some(SymbolTable::new()),
+            ..Resolver::new(interpreter)
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &[Stmt]) {
+        self.resolve_stmts(statements);
+        if !self.jlox {
+            self.check_arities();
+        }
+        if let Some(symbols) = self.symbols.as_mut() {
+            symbols.finish();
         }
     }
 
+    // Resolves an expression evaluated in the environment, a frame of a
+    // failed or paused program, instead of where it was written. Every
+    // environment up to the globals becomes a scope with its variables, and
+    // `this` and `super` can be used when the frame is a method.
+    pub fn resolve_in(&mut self, expression: &Expr, environment: &Rc<RefCell<Environment>>) {
+        let mut scopes = Vec::new();
+        let mut current = Some(Rc::clone(environment));
+        while let Some(environment) = current {
+            let environment = environment.borrow();
+            // The globals are not a scope.
+            if environment.enclosing.is_some() {
+                let names = environment.values().enumerate().map(|(slot, (name, _))| {
+                    (
+                        name.to_string(),
+                        Local {
+                            slot,
+                            defined: true,
+                        },
+                    )
+                });
+                scopes.push(names.collect::<HashMap<_, _>>());
+            }
+            current = environment.enclosing.clone();
+        }
+        scopes.reverse();
+
+        let defines = |name| scopes.iter().any(|scope| scope.contains_key(name));
+        let class = match (defines("this"), defines("super")) {
+            (true, true) => ClassType::SubClass,
+            (true, false) => ClassType::Class,
+            _ => ClassType::None,
+        };
+        let enclosing_class = mem::replace(&mut self.current_class, class);
+        let locals = vec![HashMap::new(); scopes.len()];
+        let enclosing_scopes = mem::replace(&mut self.scopes, scopes);
+        let enclosing_locals = mem::replace(&mut self.arities.locals, locals);
+        self.resolve_expr(expression);
+        self.scopes = enclosing_scopes;
+        self.arities.locals = enclosing_locals;
+        self.current_class = enclosing_class;
+    }
+
+    pub fn take_symbols(&mut self) -> Option<SymbolTable> {
+        self.symbols.take()
+    }
+
     fn resolve_stmt(&mut self, statement: &Stmt) {
         let _ = statement.accept(self);
     }
 
-    pub fn resolve_stmts(&mut self, statements: &Vec<Stmt>) {
+    fn resolve_stmts(&mut self, statements: &[Stmt]) {
         for statement in statements {
             self.resolve_stmt(statement)
         }
@@ -73,10 +218,18 @@ impl<'i> Resolver<'i> {
     // In the resolver, we use a vector like a stack.
     fn begin_scope(&mut self) {
         self.scopes.push(HashMap::new());
+        self.arities.locals.push(HashMap::new());
+        if let Some(symbols) = self.symbols.as_mut() {
+            symbols.begin_scope();
+        }
     }
 
     fn end_scope(&mut self) {
         self.scopes.pop();
+        self.arities.locals.pop();
+        if let Some(symbols) = self.symbols.as_mut() {
+            symbols.end_scope();
+        }
     }
 
     // Declaration adds the variable to the innermost scope so that it shadows
@@ -86,22 +239,107 @@ impl<'i> Resolver<'i> {
     // finished resolving that variable’s initializer.
 
     // This would help us catch errors like var a = a + 1;
-    fn declare(&mut self, name: &Token) {
+    fn declare(&mut self, name: &Token, kind: SymbolKind) {
         let mut already_defined: bool = false;
-        match self.scopes.last_mut() {
-            Some(ref mut scope) => {
-                already_defined = scope.contains_key(&name.lexeme);
-                scope.insert(name.lexeme.clone(), false);
+        if let Some(scope) = self.scopes.last_mut() {
+            let slot = match scope.get(&name.lexeme) {
+                Some(local) => {
+                    already_defined = true;
+                    local.slot
+                }
+                None => scope.len(),
+            };
+            let local = Local {
+                slot,
+                defined: false,
+            };
+            scope.insert(name.lexeme.clone(), local);
+            if let Some(locals) = self.arities.locals.last_mut() {
+                locals.insert(name.lexeme.clone(), name.clone());
             }
-            None => (),
-        };
+        } else {
+            self.arities
+                .globals
+                .entry(name.lexeme.clone())
+                .or_default()
+                .push(name.clone());
+            let allow_reopen = self.interpreter.allow_reopen;
+            if allow_reopen && kind != SymbolKind::Class {
+                self.subclasses.insert(name.lexeme.clone(), false);
+            }
+            if self.strict && !(allow_reopen && kind == SymbolKind::Class) {
+                let redeclared = !self.globals.insert(name.lexeme.clone())
+                    || self.interpreter.globals.borrow().contains(&name.lexeme);
+                if redeclared {
+                    self.error(name, Code::GlobalRedeclared, &[&name.lexeme]);
+                }
+            }
+        }
+        if let Some(symbols) = self.symbols.as_mut() {
+            symbols.declare(name, kind);
+        }
 
         // Report an error if the variable was already defined.
         if already_defined {
-            self.error(
-                name,
-                "Variable with this name already declared in this scope.",
-            );
+            self.error(name, Code::AlreadyDeclared, &[]);
+        }
+    }
+
+    // Whether a class declaration with the name at the top level reopens a
+    // class with a superclass: one declared earlier in the program, or a
+    // global from before, like in the REPL.
+    fn reopens_subclass(&self, name: &str) -> bool {
+        if !self.interpreter.allow_reopen || !self.scopes.is_empty() {
+            return false;
+        }
+        match self.subclasses.get(name) {
+            Some(&inherits) => inherits,
+            None => match self.interpreter.globals.borrow().get_own(name) {
+                Some(Object::Class(class)) => class.borrow().superclass.is_some(),
+                _ => false,
+            },
+        }
+    }
+
+    // The declaration the name refers to here, if it has one the arity check
+    // can follow.
+    fn binding(&self, name: &Token) -> Option<Binding> {
+        for (scope, locals) in self.scopes.iter().zip(&self.arities.locals).rev() {
+            if scope.contains_key(&name.lexeme) {
+                return locals.get(&name.lexeme).cloned().map(Binding::Local);
+            }
+        }
+        Some(Binding::Global(name.lexeme.clone()))
+    }
+
+    // Reports the calls of functions of the program with the wrong number of
+    // arguments. A global declared more than once could be either
+    // declaration when the call runs, it isn't checked.
+    fn check_arities(&mut self) {
+        let calls = mem::take(&mut self.arities.calls);
+        for (binding, name, count) in calls {
+            if self.arities.assigned.contains(&binding) {
+                continue;
+            }
+            let declaration = match &binding {
+                Binding::Local(declaration) => declaration,
+                Binding::Global(global) => {
+                    match self.arities.globals.get(global).map(Vec::as_slice) {
+                        Some([declaration]) => declaration,
+                        _ => continue,
+                    }
+                }
+            };
+            if let Some(&arity) = self.arities.functions.get(declaration) {
+                if arity != count {
+                    let expected = describe_arity(&(arity..=arity));
+                    self.error(
+                        &name,
+                        Code::CallArity,
+                        &[&expected, &name.lexeme, &count.to_string()],
+                    );
+                }
+            }
         }
     }
 
@@ -110,27 +348,57 @@ impl<'i> Resolver<'i> {
     // Once the initializer expression is done, the variable is ready for prime
     // time.
     fn define(&mut self, name: &Token) {
-        if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.lexeme.clone(), true);
+        if let Some(local) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(&name.lexeme))
+        {
+            local.defined = true;
         }
     }
 
+    // Puts `this` or `super` in the innermost scope, the only variable of
+    // the environment the interpreter binds it in.
+    fn bind(&mut self, name: &str) {
+        let scope = self.scopes.last_mut().expect("Scopes is empty.");
+        let local = Local {
+            slot: scope.len(),
+            defined: true,
+        };
+        scope.insert(name.to_string(), local);
+    }
+
     // After that check, we actually resolve the variable itself using this helper
 
     // We start at the innermost scope and work outwards, looking in each map
-    // for a matching name. If we find the variable, we resolve it, passing in
-    // the number of scopes between the current innermost scope and the scope
-    // where the variable was found. So, if the variable was found in the
-    // current scope, we pass in 0.
-
-    // If we walk through all of the block scopes and never find the variable, we leave it unresolved and assume it's global.
+    // for a matching name. If we find the variable, we resolve it, storing on
+    // the node the number of scopes between the current innermost scope and
+    // the scope where the variable was found. So, if the variable was found in
+    // the current scope, we store 0.
+
+    // If we walk through all of the block scopes and never find the variable, we mark it global.
+
+    fn resolve_local(&mut self, name: &Token, resolution: &Resolution) {
+        let depth = self.find_local(name);
+        resolution.set(depth);
+        if let Some(symbols) = self.symbols.as_mut() {
+            symbols.reference(name, depth);
+        }
+    }
 
-    fn resolve_local(&mut self, name: &Token) {
+    fn find_local(&self, name: &Token) -> Depth {
         for (i, scope) in self.scopes.iter().rev().enumerate() {
-            if scope.contains_key(&name.lexeme) {
-                self.interpreter.resolve(name, i);
+            if let Some(local) = scope.get(&name.lexeme) {
+                trace!(name = %name.lexeme, line = name.line, depth = i, slot = local.slot, "resolved local");
+                return Depth::Local {
+                    distance: i,
+                    slot: local.slot,
+                };
             }
         }
+        // Not found. Assume it is global.
+        trace!(name = %name.lexeme, line = name.line, "resolved global");
+        Depth::Global
     }
 
     // Create a new scope for the body and then binds variables for each of the
@@ -140,30 +408,69 @@ impl<'i> Resolver<'i> {
     // the function's body. The body doesn't get touched until later when the
     // function is called. In static analysis, we immediately traverse into the
     // body right then and there.
-    fn resolve_function(&mut self, params: &Vec<Token>, body: &Vec<Stmt>, tpe: FunctionType) {
+    fn resolve_function(&mut self, params: &[Token], body: &[Stmt], tpe: FunctionType) {
         // We stash the previous value of the field in a local variable first.
         // Remember, Lox has local functions, so you can nest function
         // declarations arbitrarily deeply. We need to track not just that we’re
         // in a function, but how many we’re in.
         let enclosing_function = self.current_function.clone();
         self.current_function = tpe;
+        let in_defer = std::mem::replace(&mut self.in_defer, false);
         self.begin_scope();
+        if let Some(symbols) = self.symbols.as_mut() {
+            symbols.begin_function();
+        }
         for param in params {
-            self.declare(param);
+            self.declare(param, SymbolKind::Param);
             self.define(param);
         }
         self.resolve_stmts(body);
         self.end_scope();
+        self.in_defer = in_defer;
         self.current_function = enclosing_function;
     }
 
-    fn error(&mut self, token: &Token, message: &str) {
-        if token.token_type == TokenType::Eof {
-            report(token.line, " at end", message);
-        } else {
-            report(token.line, &format!(" at '{}'", token.lexeme), message);
+    // Private members can only ever be reached from code inside a class body,
+    // so any access from outside of one can be rejected before running. Whether
+    // an access inside a class is allowed depends on the instance and is
+    // checked at runtime.
+    fn check_private_access(&mut self, name: &Token) {
+        if let ClassType::None = self.current_class {
+            if is_private(&name.lexeme) {
+                self.error(name, Code::PrivateOutsideClass, &[]);
+            }
         }
+    }
+
+    // The names a pattern binds are variables of its arm's scope. The class of
+    // an instance pattern is a variable access like any other.
+    fn resolve_pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard | Pattern::Literal(_) => {}
+            Pattern::Binding(name) => {
+                self.declare(name, SymbolKind::Var);
+                self.define(name);
+            }
+            Pattern::Instance { class, fields } => {
+                self.resolve_expr(class);
+                for (name, pattern) in fields {
+                    self.check_private_access(name);
+                    self.resolve_pattern(pattern);
+                }
+            }
+            Pattern::List { elements, .. } => {
+                for element in elements {
+                    self.resolve_pattern(element);
+                }
+            }
+        }
+    }
+
+    fn error(&mut self, token: &Token, code: Code, args: &[&str]) {
         self.had_error = true;
+        if self.reported.insert((token.span(), code)) {
+            self.diagnostics.push(Diagnostic::at(token, code, args));
+        }
     }
 }
 
@@ -178,27 +485,35 @@ impl<'i> Resolver<'i> {
 // variables to resolve, either of its operands might.
 
 impl<'i> expr::Visitor<()> for Resolver<'i> {
-    fn visit_variable_expr(&mut self, name: &Token) -> Result<(), Error> {
+    fn visit_variable_expr(&mut self, name: &Token, resolution: &Resolution) -> Result<(), Error> {
         // First, we check to see if the variable is being accessed inside its
         // own initializer. If the variable exists in the current scope but its
         // value is false, that means we have declared it but not yet defined
         if let Some(scope) = self.scopes.last() {
-            if let Some(flag) = scope.get(&name.lexeme) {
-                if *flag == false {
-                    self.error(name, "Cannot read local variable in its own initializer.");
+            if let Some(local) = scope.get(&name.lexeme) {
+                if !local.defined {
+                    self.error(name, Code::OwnInitializer, &[]);
                 }
             }
         };
-        self.resolve_local(name);
+        self.resolve_local(name, resolution);
         Ok(())
     }
 
     // First, we resolve the expression for the assigned value in case it also
     // contains references to other variables. Then we use our existing
     // resolveLocal() method to resolve the variable that’s being assigned to.ß
-    fn visit_assign_expr(&mut self, name: &Token, value: &Expr) -> Result<(), Error> {
+    fn visit_assign_expr(
+        &mut self,
+        name: &Token,
+        value: &Expr,
+        resolution: &Resolution,
+    ) -> Result<(), Error> {
         self.resolve_expr(value);
-        self.resolve_local(name);
+        self.resolve_local(name, resolution);
+        if let Some(binding) = self.binding(name) {
+            self.arities.assigned.insert(binding);
+        }
         Ok(())
     }
 
@@ -215,7 +530,13 @@ impl<'i> expr::Visitor<()> for Resolver<'i> {
 
     // During resolution, we recurse only into the expression to the left of the
     // dot. The actual property access happens in the interpreter.
-    fn visit_get_expr(&mut self, object: &Expr, _name: &Token) -> Result<(), Error> {
+    fn visit_get_expr(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        _optional: bool,
+    ) -> Result<(), Error> {
+        self.check_private_access(name);
         self.resolve_expr(object);
         Ok(())
     }
@@ -224,28 +545,32 @@ impl<'i> expr::Visitor<()> for Resolver<'i> {
     // there’s nothing to resolve there. All we need to do is recurse into the
     // two subexpressions of Expr.Set, the object whose property is being set,
     // and the value it’s being set to.
-    fn visit_set_expr(&mut self, object: &Expr, _name: &Token, value: &Expr) -> Result<(), Error> {
+    fn visit_set_expr(&mut self, object: &Expr, name: &Token, value: &Expr) -> Result<(), Error> {
+        self.check_private_access(name);
         self.resolve_expr(value);
         self.resolve_expr(object);
         Ok(())
     }
 
-    fn visit_super_expr(&mut self, keyword: &Token, _method: &Token) -> Result<(), Error> {
+    fn visit_super_expr(
+        &mut self,
+        keyword: &Token,
+        _method: &Token,
+        resolution: &Resolution,
+    ) -> Result<(), Error> {
         match self.current_class {
-            ClassType::None => self.error(keyword, "Cannot use 'super' outside of a class."),
-            ClassType::Class => {
-                self.error(keyword, "Cannot use 'super' in a class with no superclass.")
-            }
-            _ => self.resolve_local(keyword),
+            ClassType::None => self.error(keyword, Code::SuperOutsideClass, &[]),
+            ClassType::Class => self.error(keyword, Code::SuperWithoutSuperclass, &[]),
+            _ => self.resolve_local(keyword, resolution),
         }
         Ok(())
     }
 
-    fn visit_this_expr(&mut self, keyword: &Token) -> Result<(), Error> {
+    fn visit_this_expr(&mut self, keyword: &Token, resolution: &Resolution) -> Result<(), Error> {
         if let ClassType::None = self.current_class {
-            self.error(keyword, "Cannot use 'this' outside of a class.");
+            self.error(keyword, Code::ThisOutsideClass, &[]);
         } else {
-            self.resolve_local(keyword);
+            self.resolve_local(keyword, resolution);
         }
         Ok(())
     }
@@ -260,8 +585,16 @@ impl<'i> expr::Visitor<()> for Resolver<'i> {
         &mut self,
         callee: &Expr,
         _paren: &Token,
-        arguments: &Vec<Expr>,
+        arguments: &[Expr],
+        _optional: bool,
     ) -> Result<(), Error> {
+        if let Expr::Variable { name, .. } = callee {
+            if let Some(binding) = self.binding(name) {
+                self.arities
+                    .calls
+                    .push((binding, name.clone(), arguments.len()));
+            }
+        }
         self.resolve_expr(callee);
         for argument in arguments {
             self.resolve_expr(argument);
@@ -294,10 +627,38 @@ impl<'i> expr::Visitor<()> for Resolver<'i> {
         self.resolve_expr(right);
         Ok(())
     }
+
+    // Each arm is a scope of its own holding the names its pattern binds.
+    fn visit_match_expr(
+        &mut self,
+        _keyword: &Token,
+        subject: &Expr,
+        arms: &[MatchArm],
+    ) -> Result<(), Error> {
+        self.resolve_expr(subject);
+        for arm in arms {
+            self.begin_scope();
+            self.resolve_pattern(&arm.pattern);
+            self.resolve_expr(&arm.body);
+            self.end_scope();
+        }
+        Ok(())
+    }
+
+    fn visit_try_expr(
+        &mut self,
+        _keyword: &Token,
+        body: &Expr,
+        default: &Expr,
+    ) -> Result<(), Error> {
+        self.resolve_expr(body);
+        self.resolve_expr(default);
+        Ok(())
+    }
 }
 
 impl<'i> stmt::Visitor<()> for Resolver<'i> {
-    fn visit_block_stmt(&mut self, statements: &Vec<Stmt>) -> Result<(), Error> {
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> Result<(), Error> {
         self.begin_scope();
         self.resolve_stmts(statements);
         self.end_scope();
@@ -311,36 +672,51 @@ impl<'i> stmt::Visitor<()> for Resolver<'i> {
         &mut self,
         name: &Token,
         superclass: &Option<Expr>,
-        methods: &Vec<Stmt>,
+        methods: &[Stmt],
     ) -> Result<(), Error> {
         let enclosing_class = mem::replace(&mut self.current_class, ClassType::Class);
 
-        self.declare(name);
+        // A class declared again without a superclass keeps the one it has
+        // when reopening is allowed, its new methods can use `super` too.
+        let reopened_subclass = superclass.is_none() && self.reopens_subclass(&name.lexeme);
+        if self.scopes.is_empty() && self.interpreter.allow_reopen {
+            let inherits = superclass.is_some() || reopened_subclass;
+            self.subclasses.insert(name.lexeme.clone(), inherits);
+        }
+
+        self.declare(name, SymbolKind::Class);
         self.define(name);
 
+        if let Some(symbols) = self.symbols.as_mut() {
+            for method in methods {
+                if let Stmt::Function { name, .. } = method {
+                    symbols.declare_method(name);
+                }
+            }
+        }
+
         if let Some(Expr::Variable {
             name: superclass_name,
+            resolution,
         }) = superclass
         {
             if name.lexeme == superclass_name.lexeme {
-                self.error(superclass_name, "A class cannot inherit from itself.")
+                self.error(superclass_name, Code::InheritsItself, &[])
             }
 
             self.current_class = ClassType::SubClass;
-            self.resolve_local(superclass_name);
+            self.resolve_local(superclass_name, resolution);
 
             self.begin_scope();
-            self.scopes
-                .last_mut()
-                .expect("Scopes is empty.")
-                .insert("super".to_owned(), true);
+            self.bind("super");
+        } else if reopened_subclass {
+            self.current_class = ClassType::SubClass;
+            self.begin_scope();
+            self.bind("super");
         }
 
         self.begin_scope();
-        self.scopes
-            .last_mut()
-            .expect("Scopes is empty.")
-            .insert("this".to_owned(), true);
+        self.bind("this");
 
         for method in methods {
             if let Stmt::Function { name, params, body } = method {
@@ -355,7 +731,7 @@ impl<'i> stmt::Visitor<()> for Resolver<'i> {
             }
         }
 
-        if superclass.is_some() {
+        if superclass.is_some() || reopened_subclass {
             self.end_scope()
         }
 
@@ -394,12 +770,15 @@ impl<'i> stmt::Visitor<()> for Resolver<'i> {
 
     fn visit_return_stmt(&mut self, keyword: &Token, value: &Option<Expr>) -> Result<(), Error> {
         if let FunctionType::None = self.current_function {
-            self.error(keyword, "Cannot return from top-level code.");
+            self.error(keyword, Code::TopLevelReturn, &[]);
+        }
+        if self.in_defer {
+            self.error(keyword, Code::DeferReturn, &[]);
         }
 
         if let Some(return_value) = value {
             if let FunctionType::Initializer = self.current_function {
-                self.error(keyword, "Can't return a value from an initializer.");
+                self.error(keyword, Code::InitializerReturn, &[]);
             }
             self.resolve_expr(return_value);
         }
@@ -407,12 +786,54 @@ impl<'i> stmt::Visitor<()> for Resolver<'i> {
     }
 
     // We resolve its condition and resolve the body exactly once
-    fn visit_while_stmt(&mut self, condition: &Expr, body: &Stmt) -> Result<(), Error> {
+    fn visit_while_stmt(
+        &mut self,
+        _keyword: &Token,
+        condition: &Expr,
+        body: &Stmt,
+    ) -> Result<(), Error> {
         self.resolve_expr(condition);
         self.resolve_stmt(body);
         Ok(())
     }
 
+    // The body and the handler are blocks of their own. The error variable is
+    // declared in the handler's scope, which is how the interpreter binds it.
+    fn visit_try_stmt(
+        &mut self,
+        _keyword: &Token,
+        body: &[Stmt],
+        name: &Token,
+        handler: &[Stmt],
+    ) -> Result<(), Error> {
+        self.begin_scope();
+        self.resolve_stmts(body);
+        self.end_scope();
+
+        self.begin_scope();
+        self.declare(name, SymbolKind::Var);
+        self.define(name);
+        self.resolve_stmts(handler);
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_throw_stmt(&mut self, _keyword: &Token, value: &Expr) -> Result<(), Error> {
+        self.resolve_expr(value);
+        Ok(())
+    }
+
+    // The body runs when the function exits, so there must be one.
+    fn visit_defer_stmt(&mut self, keyword: &Token, body: &Stmt) -> Result<(), Error> {
+        if let FunctionType::None = self.current_function {
+            self.error(keyword, Code::TopLevelDefer, &[]);
+        }
+        let in_defer = std::mem::replace(&mut self.in_defer, true);
+        self.resolve_stmt(body);
+        self.in_defer = in_defer;
+        Ok(())
+    }
+
     // We split binding into two steps, declaring then defining, in order to handle funny edge cases like this:
     /*
     var a = "outer";
@@ -421,7 +842,7 @@ impl<'i> stmt::Visitor<()> for Resolver<'i> {
     }
     */
     fn visit_var_stmt(&mut self, name: &Token, initializer: &Option<Expr>) -> Result<(), Error> {
-        self.declare(name);
+        self.declare(name, SymbolKind::Var);
         if let Some(init) = initializer {
             self.resolve_expr(init);
         }
@@ -436,11 +857,12 @@ impl<'i> stmt::Visitor<()> for Resolver<'i> {
     fn visit_function_stmt(
         &mut self,
         name: &Token,
-        params: &Vec<Token>,
-        body: &Vec<Stmt>,
+        params: &[Token],
+        body: &[Stmt],
     ) -> Result<(), Error> {
-        self.declare(name);
+        self.declare(name, SymbolKind::Fun);
         self.define(name);
+        self.arities.functions.insert(name.clone(), params.len());
 
         self.resolve_function(params, body, FunctionType::Function);
         Ok(())
@@ -1,36 +1,129 @@
 // A lexeme is the raw sequence of characters in the source code that represents a meaningful unit
 // A token is a categorized representation of a lexeme, pairing it with its type
 
-use crate::error::error;
-use crate::token::{Token, TokenType, KEYWORDS};
+use std::collections::HashMap;
+
+use crate::error::{Code, Diagnostic};
+use crate::token::{Span, Token, TokenType, KEYWORDS};
+
+// Ceilings on the size of literals, so a pathological program, maybe from an
+// untrusted place, is rejected with a diagnostic instead of taking the memory
+// of the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiteralLimits {
+    // Length in bytes of the text of a string literal.
+    pub string_bytes: usize,
+    // Digits of a number literal. 400 fit every double written out in full,
+    // the largest has 309 digits and the smallest 324 decimal places.
+    pub number_digits: usize,
+}
+
+impl Default for LiteralLimits {
+    fn default() -> Self {
+        LiteralLimits {
+            string_bytes: 1 << 20,
+            number_digits: 400,
+        }
+    }
+}
 
+// The source is stored as chars so every position (start, current, columns) is
+// a char index. Indexing the UTF-8 string by bytes would split multi-byte
+// characters.
 pub struct Scanner {
-    source: String,
+    source: Vec<char>,
     tokens: Vec<Token>,
     start: usize,
     current: usize,
     line: i32,
+    // index of the first character of the current line, used to compute columns
+    line_start: usize,
+    // column of the first character of the lexeme being scanned
+    column: usize,
+    limits: LiteralLimits,
+    // Whether `///` doc comments are kept, see keep_doc_comments.
+    keep_docs: bool,
+    // The lines of the doc comment before the next token.
+    pending_doc: Vec<String>,
+    // A doc comment waiting for the name after `fun`, `class` or `var`.
+    keyword_doc: Option<String>,
+    // Doc comments by the position of the name they document.
+    pub docs: HashMap<Span, String>,
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 impl Scanner {
-    pub fn new(source: String) -> Self {
+    pub fn new(source: &str) -> Self {
+        Self::with_limits(source, LiteralLimits::default())
+    }
+
+    pub fn with_limits(source: &str, limits: LiteralLimits) -> Self {
+        let mut source: Vec<char> = source.chars().collect();
+        // Editors on Windows like to start UTF-8 files with a byte order mark.
+        if source.first() == Some(&'\u{feff}') {
+            source.remove(0);
+        }
+        // A script run directly on Unix starts with a line like
+        // `#!/usr/bin/env lox`. It is skipped like a comment, up to the
+        // newline so the lines after it keep their numbers.
+        let current = if source.starts_with(&['#', '!']) {
+            source
+                .iter()
+                .position(|&c| c == '\n')
+                .unwrap_or(source.len())
+        } else {
+            0
+        };
+
         Self {
             source,
             tokens: Vec::new(),
             start: 0,
-            current: 0,
+            current,
             line: 1,
+            line_start: 0,
+            column: 1,
+            limits,
+            keep_docs: false,
+            pending_doc: Vec::new(),
+            keyword_doc: None,
+            docs: HashMap::new(),
+            diagnostics: Vec::new(),
         }
     }
 
-    pub fn scan_tokens(&mut self) -> &Vec<Token> {
+    // Keeps the `///` comments right before a declaration of a function,
+    // class, method or variable in docs, under the position of its name. The
+    // lines of a comment are joined, without the slashes and the space after
+    // them. Other comments are dropped as always.
+    pub fn keep_doc_comments(&mut self) {
+        self.keep_docs = true;
+    }
+
+    // Whether the source ends in a doc comment, or a declaration keyword
+    // after one, that has nothing to document yet.
+    pub fn has_pending_doc(&self) -> bool {
+        !self.pending_doc.is_empty() || self.keyword_doc.is_some()
+    }
+
+    pub fn had_error(&self) -> bool {
+        !self.diagnostics.is_empty()
+    }
+
+    pub fn scan_tokens(&mut self) -> Vec<Token> {
         while !self.is_at_end() {
             self.start = self.current;
+            self.column = self.start - self.line_start + 1;
             self.scan_token()
         }
 
-        self.tokens.push(Token::new(TokenType::Eof, "", self.line));
-        &self.tokens
+        self.tokens.push(Token::new(
+            TokenType::Eof,
+            "",
+            self.line,
+            self.current - self.line_start + 1,
+        ));
+        std::mem::take(&mut self.tokens)
     }
 
     fn scan_token(&mut self) {
@@ -41,12 +134,20 @@ impl Scanner {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
+            ':' => self.add_token(TokenType::Colon),
             ',' => self.add_token(TokenType::Comma),
             '.' => self.add_token(TokenType::Dot),
-            '-' => self.add_token(TokenType::Minus),
+            '-' => {
+                if self.r#match('>') {
+                    self.add_token(TokenType::Arrow);
+                } else {
+                    self.add_token(TokenType::Minus);
+                }
+            }
             '+' => self.add_token(TokenType::Plus),
             ';' => self.add_token(TokenType::Semicolon),
-            '*' => self.add_token(TokenType::Star),
 
             // can be double char
             '!' => {
@@ -77,13 +178,31 @@ impl Scanner {
                     self.add_token(TokenType::Greater);
                 }
             }
+            '?' if self.peek() == '.' => {
+                self.advance();
+                self.add_token(TokenType::QuestionDot);
+            }
+            '*' => {
+                if self.r#match('*') {
+                    self.add_token(TokenType::StarStar);
+                } else {
+                    self.add_token(TokenType::Star);
+                }
+            }
 
             // can be comment
             '/' => {
                 if self.r#match('/') {
+                    // `////` and longer are rulers, not docs.
+                    let doc = self.keep_docs && self.peek() == '/' && self.peek_next() != '/';
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    if doc {
+                        let line = self.text(self.start + 3, self.current);
+                        let line = line.strip_prefix(' ').unwrap_or(&line);
+                        self.pending_doc.push(line.trim_end().to_string());
+                    }
                 } else {
                     self.add_token(TokenType::Slash);
                 }
@@ -93,19 +212,17 @@ impl Scanner {
             ' ' | '\t' | '\r' => (),
 
             // handle new line
-            '\n' => {
-                self.line += 1;
-            }
+            '\n' => self.newline(),
 
             '"' => self.string(),
 
             c => {
-                if c.is_digit(10) {
+                if c.is_ascii_digit() {
                     self.number()
                 } else if c.is_alphabetic() || c == '_' {
                     self.identifier()
                 } else {
-                    error(self.line, "Unexpected character.")
+                    self.error(self.column, Code::UnexpectedCharacter, &[])
                 }
             }
         }
@@ -114,49 +231,73 @@ impl Scanner {
     // consume characters until we reach the closing "
     fn string(&mut self) {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            if self.advance() == '\n' {
+                self.newline();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            error(self.line, "Unterminated string");
+            self.error(
+                self.current - self.line_start + 1,
+                Code::UnterminatedString,
+                &[],
+            );
+            return;
         }
 
         // the closing "
         self.advance();
 
         // trim
-        let literal = self
-            .source
-            .get((self.start + 1)..(self.current - 1))
-            .expect("Unexpected string end.")
-            .to_string();
+        let bytes: usize = self.source[self.start + 1..self.current - 1]
+            .iter()
+            .map(|c| c.len_utf8())
+            .sum();
+        if bytes > self.limits.string_bytes {
+            let limit = describe_bytes(self.limits.string_bytes);
+            self.error(self.column, Code::StringLiteralTooLong, &[&limit]);
+            // Still a string as far as the parser is concerned, so the error
+            // doesn't bring others about.
+            self.add_token(TokenType::String {
+                literal: String::new(),
+            });
+            return;
+        }
+        let literal = self.text(self.start + 1, self.current - 1);
 
         self.add_token(TokenType::String { literal });
     }
 
     fn number(&mut self) {
-        while self.peek().is_digit(10) {
+        while self.peek().is_ascii_digit() {
             self.advance();
         }
 
         // consume the .
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             self.advance();
 
-            while self.peek().is_digit(10) {
+            while self.peek().is_ascii_digit() {
                 self.advance();
             }
         }
 
-        let literal: f64 = self
-            .source
-            .get(self.start..self.current)
-            .expect("Unexpected number end")
+        let text = self.text(self.start, self.current);
+        let digits = text.chars().filter(char::is_ascii_digit).count();
+        if digits > self.limits.number_digits {
+            let limit = self.limits.number_digits.to_string();
+            self.error(self.column, Code::NumberLiteralTooLong, &[&limit]);
+            self.add_token(TokenType::Number { literal: 0.0 });
+            return;
+        }
+
+        let literal: f64 = text
             .parse() // we could do .parse::<64> using the turbofish
             .expect("Scanned number could not be parsed");
+        // Too many digits before the dot parse as infinity.
+        if literal.is_infinite() {
+            self.error(self.column, Code::NumberLiteralTooLarge, &[]);
+        }
 
         self.add_token(TokenType::Number { literal });
     }
@@ -166,39 +307,71 @@ impl Scanner {
             self.advance();
         }
 
-        let text = self
-            .source
-            .get(self.start..self.current)
-            .expect("Unexpected identifier end.");
-        let tpe = KEYWORDS.get(text).cloned().unwrap_or(TokenType::Identifier);
+        let text = self.text(self.start, self.current);
+        let tpe = KEYWORDS
+            .get(text.as_str())
+            .cloned()
+            .unwrap_or(TokenType::Identifier);
 
         self.add_token(tpe);
     }
 
     fn advance(&mut self) -> char {
         self.current += 1;
-        return self
-            .source
-            .chars()
-            .nth(self.current - 1)
-            .expect("there is a next char");
+        self.source[self.current - 1]
+    }
+
+    fn text(&self, start: usize, end: usize) -> String {
+        self.source[start..end].iter().collect()
+    }
+
+    fn error(&mut self, column: usize, code: Code, args: &[&str]) {
+        let span = Span {
+            line: self.line,
+            column,
+        };
+        self.diagnostics.push(Diagnostic::new(span, code, args));
+    }
+
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
     }
 
     // it's like advance but doesn't consume the next character
     fn peek(&self) -> char {
-        self.source.chars().nth(self.current).unwrap_or('\0')
+        self.source.get(self.current).copied().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        self.source.chars().nth(self.current + 1).unwrap_or('\0')
+        self.source.get(self.current + 1).copied().unwrap_or('\0')
     }
 
     fn add_token(&mut self, token_type: TokenType) {
-        let text = self
-            .source
-            .get(self.start..self.current)
-            .expect("Source token is empty");
-        self.tokens.push(Token::new(token_type, text, self.line));
+        let text = self.text(self.start, self.current);
+        let token = Token::new(token_type, &text, self.line, self.column);
+        if self.keep_docs {
+            self.attach_doc(&token);
+        }
+        self.tokens.push(token);
+    }
+
+    // A doc comment belongs to the name after the keyword of a declaration or,
+    // for a method, to the name right after it. Before anything else it is
+    // dropped.
+    fn attach_doc(&mut self, token: &Token) {
+        let pending = (!self.pending_doc.is_empty()).then(|| self.pending_doc.join("\n"));
+        self.pending_doc.clear();
+        let doc = self.keyword_doc.take().or(pending);
+        match (&token.token_type, doc) {
+            (TokenType::Fun | TokenType::Class | TokenType::Var, Some(doc)) => {
+                self.keyword_doc = Some(doc)
+            }
+            (TokenType::Identifier, Some(doc)) => {
+                self.docs.insert(token.span(), doc);
+            }
+            _ => (),
+        }
     }
 
     fn is_at_end(&self) -> bool {
@@ -211,13 +384,7 @@ impl Scanner {
             return false;
         }
 
-        if self
-            .source
-            .chars()
-            .nth(self.current)
-            .expect("Unexpected EOF")
-            != expected
-        {
+        if self.source[self.current] != expected {
             return false;
         }
 
@@ -225,3 +392,15 @@ impl Scanner {
         true
     }
 }
+
+// A size in bytes the way people write it, `1MB` or `64KB` when it is a whole
+// number of those.
+fn describe_bytes(bytes: usize) -> String {
+    if bytes > 0 && bytes.is_multiple_of(1 << 20) {
+        format!("{}MB", bytes >> 20)
+    } else if bytes > 0 && bytes.is_multiple_of(1 << 10) {
+        format!("{}KB", bytes >> 10)
+    } else {
+        format!("{} bytes", bytes)
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::function::NativeFn;
+use crate::inspect::inspect;
+use crate::interpreter::Interpreter;
+use crate::key::Key;
+use crate::list;
+use crate::natives::{native_state, set_native_state};
+use crate::object::Object;
+
+// Set is a native class holding each value at most once, in the order they
+// were first added. Everything that reads the elements, like to_list() and
+// for_each(), goes in that order.
+//
+// Values are the same element when they are the same Key: numbers, strings,
+// booleans and nil when they are equal, everything else only when it is the
+// same object. Bound methods can't be elements.
+pub(crate) const METHODS: &[(&str, NativeFn)] = &[
+    ("init(list?)", init),
+    ("length()", length),
+    ("add(value)", add),
+    ("contains(value)", contains),
+    ("remove(value)", remove),
+    ("union(other)", union),
+    ("intersect(other)", intersect),
+    ("to_list()", to_list),
+    ("for_each(function)", for_each),
+];
+
+#[derive(Default, Clone)]
+struct Elements {
+    order: Vec<Object>,
+    keys: HashSet<Key>,
+}
+
+impl Elements {
+    // Whether the value is new.
+    fn add(&mut self, value: Object) -> Result<bool, String> {
+        let added = self.keys.insert(key(&value)?);
+        if added {
+            self.order.push(value);
+        }
+        Ok(added)
+    }
+
+    // A value that can't be an element isn't one.
+    fn contains(&self, value: &Object) -> bool {
+        Key::new(value).is_some_and(|key| self.keys.contains(&key))
+    }
+
+    // Whether the value was there.
+    fn remove(&mut self, value: &Object) -> bool {
+        let Some(key) = Key::new(value) else {
+            return false;
+        };
+        if !self.keys.remove(&key) {
+            return false;
+        }
+        self.order
+            .retain(|element| Key::new(element).is_some_and(|element| element != key));
+        true
+    }
+}
+
+fn key(value: &Object) -> Result<Key, String> {
+    Key::new(value).ok_or_else(|| {
+        format!(
+            "Set elements can't be bound methods but got {}.",
+            inspect(value)
+        )
+    })
+}
+
+// Set() is empty, Set(list) has the distinct elements of the list.
+fn init(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let mut elements = Elements::default();
+    if let Some(items) = args.get(1) {
+        for item in list::items(items)? {
+            elements.add(item)?;
+        }
+    }
+    set_native_state(&args[0], elements);
+    Ok(Object::Null)
+}
+
+fn length(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    with_elements(&args[0], |elements| {
+        Ok(Object::Number(elements.order.len() as f64))
+    })
+}
+
+// add(value) returns whether the value wasn't in the set yet.
+fn add(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    with_elements(&args[0], |elements| {
+        elements.add(args[1].clone()).map(Object::Boolean)
+    })
+}
+
+fn contains(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    with_elements(&args[0], |elements| {
+        Ok(Object::Boolean(elements.contains(&args[1])))
+    })
+}
+
+// remove(value) returns whether the value was in the set.
+fn remove(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    with_elements(&args[0], |elements| {
+        Ok(Object::Boolean(elements.remove(&args[1])))
+    })
+}
+
+// union(other) is a new set with the elements of this one followed by the
+// ones only in the other.
+fn union(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let mut elements = copy(&args[0])?;
+    for element in copy(&args[1])?.order {
+        elements.add(element)?;
+    }
+    Ok(new_set(interpreter, &args[0], elements))
+}
+
+// intersect(other) is a new set with the elements of this one that are also
+// in the other, in the order of this one.
+fn intersect(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let other = copy(&args[1])?;
+    let mut elements = Elements::default();
+    for element in copy(&args[0])?.order {
+        if other.contains(&element) {
+            elements.add(element)?;
+        }
+    }
+    Ok(new_set(interpreter, &args[0], elements))
+}
+
+fn to_list(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let elements = copy(&args[0])?;
+    Ok(list::list(interpreter, elements.order))
+}
+
+// for_each(function) calls the function with every element. Elements added or
+// removed by the function don't change which ones it is called with.
+fn for_each(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    for element in copy(&args[0])?.order {
+        interpreter.call_back(&args[1], &[element])?;
+    }
+    Ok(Object::Null)
+}
+
+fn with_elements<R>(
+    this: &Object,
+    f: impl FnOnce(&mut Elements) -> Result<R, String>,
+) -> Result<R, String> {
+    native_state(this, "Set", f)
+}
+
+// The elements are never borrowed while Lox code runs.
+fn copy(set: &Object) -> Result<Elements, String> {
+    with_elements(set, |elements| Ok(elements.clone()))
+}
+
+// The elements of a Set in order, for natives taking one.
+pub(crate) fn elements(set: &Object) -> Result<Vec<Object>, String> {
+    with_elements(set, |elements| Ok(elements.order.clone()))
+}
+
+// Gives an instance of Set or a class extending it the distinct values as its
+// elements, replacing the ones it had.
+pub(crate) fn set_elements(set: &Object, values: Vec<Object>) -> Result<(), String> {
+    let mut elements = Elements::default();
+    for value in values {
+        elements.add(value)?;
+    }
+    set_native_state(set, elements);
+    Ok(())
+}
+
+fn new_set(interpreter: &Interpreter, this: &Object, elements: Elements) -> Object {
+    let class = match this {
+        Object::Instance(instance) => Rc::clone(&instance.borrow().class),
+        _ => interpreter.native_class("Set"),
+    };
+    let set = interpreter.new_instance(&class);
+    set_native_state(&set, elements);
+    set
+}
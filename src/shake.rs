@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::syntax::{Expr, MatchArm, Pattern, Stmt};
+use crate::token::Token;
+
+// Tree shaking leaves out the functions and classes declared at the top level
+// that the program never refers to, so a script including a large library with
+// the preprocessor only declares what it uses.
+//
+// The statements of the top level other than function and class declarations
+// are the roots: everything they name is kept, then everything the kept
+// declarations name, and so on. Names are matched without scopes, a local
+// variable with the name of a global keeps it too, which is never wrong. A
+// name declared more than once at the top level is always kept, and so is
+// everything when the program calls globals(), which can reach any global.
+pub struct Shaken {
+    pub statements: Vec<Stmt>,
+    // Why every declaration is kept, in the order of the program.
+    pub kept: Vec<Kept>,
+    pub removed: Vec<String>,
+}
+
+pub struct Kept {
+    pub name: String,
+    pub reason: Reason,
+}
+
+pub enum Reason {
+    // Named on the line by a statement of the top level, or by the body of a
+    // kept declaration.
+    TopLevel(i32),
+    Declaration(String, i32),
+    // Declared more than once, or everything is kept.
+    Always,
+}
+
+pub fn shake(statements: Vec<Stmt>) -> Shaken {
+    let mut reached = Reached::default();
+    for statement in &statements {
+        if let Some(name) = declared_name(statement) {
+            *reached.declared.entry(name.lexeme.clone()).or_default() += 1;
+        }
+    }
+
+    let mut dynamic = false;
+    let mut bodies: HashMap<&str, Vec<Token>> = HashMap::new();
+    for statement in &statements {
+        let mut names = Vec::new();
+        stmt_names(statement, &mut names);
+        dynamic |= names.iter().any(|name| name.lexeme == "globals");
+        match declared_name(statement) {
+            Some(declaration) if reached.declared[&declaration.lexeme] == 1 => {
+                bodies.insert(&declaration.lexeme, names);
+            }
+            Some(declaration) => reached.reach(declaration, Reason::Always),
+            None => {
+                for name in &names {
+                    reached.reach(name, Reason::TopLevel(name.line));
+                }
+            }
+        }
+    }
+    if dynamic {
+        for statement in &statements {
+            if let Some(declaration) = declared_name(statement) {
+                reached.reach(declaration, Reason::Always);
+            }
+        }
+    }
+    while let Some(declaration) = reached.queue.pop_front() {
+        for name in bodies.get(declaration.as_str()).into_iter().flatten() {
+            let reason = Reason::Declaration(declaration.clone(), name.line);
+            reached.reach(name, reason);
+        }
+    }
+
+    let mut shaken = Shaken {
+        statements: Vec::new(),
+        kept: Vec::new(),
+        removed: Vec::new(),
+    };
+    let mut seen = HashSet::new();
+    for statement in statements {
+        if let Some(name) = declared_name(&statement) {
+            let name = name.lexeme.clone();
+            match reached.reasons.remove(&name) {
+                Some(reason) => shaken.kept.push(Kept {
+                    name: name.clone(),
+                    reason,
+                }),
+                // Another declaration of a name declared more than once.
+                None if seen.contains(&name) => (),
+                None => {
+                    shaken.removed.push(name);
+                    continue;
+                }
+            }
+            seen.insert(name);
+        }
+        shaken.statements.push(statement);
+    }
+    shaken
+}
+
+#[derive(Default)]
+struct Reached {
+    // How many times each function or class is declared at the top level.
+    declared: HashMap<String, usize>,
+    // Why each declaration reached so far is kept.
+    reasons: HashMap<String, Reason>,
+    // The declarations reached whose bodies haven't been looked at.
+    queue: VecDeque<String>,
+}
+
+impl Reached {
+    fn reach(&mut self, name: &Token, reason: Reason) {
+        if self.declared.contains_key(&name.lexeme) && !self.reasons.contains_key(&name.lexeme) {
+            self.reasons.insert(name.lexeme.clone(), reason);
+            self.queue.push_back(name.lexeme.clone());
+        }
+    }
+}
+
+// The name of a function or class declaration.
+fn declared_name(statement: &Stmt) -> Option<&Token> {
+    match statement {
+        Stmt::Function { name, .. } | Stmt::Class { name, .. } => Some(name),
+        _ => None,
+    }
+}
+
+// Every variable the statement reads or assigns, in order.
+fn stmt_names(statement: &Stmt, names: &mut Vec<Token>) {
+    match statement {
+        Stmt::Block { statements } => statements.iter().for_each(|s| stmt_names(s, names)),
+        Stmt::Class {
+            superclass,
+            methods,
+            ..
+        } => {
+            if let Some(superclass) = superclass {
+                expr_names(superclass, names);
+            }
+            methods.iter().for_each(|method| stmt_names(method, names));
+        }
+        Stmt::Expression { expression } | Stmt::Print { expression } => {
+            expr_names(expression, names)
+        }
+        Stmt::Function { body, .. } => body.iter().for_each(|s| stmt_names(s, names)),
+        Stmt::Return { value, .. } => value.iter().for_each(|value| expr_names(value, names)),
+        Stmt::Var { initializer, .. } => initializer
+            .iter()
+            .for_each(|initializer| expr_names(initializer, names)),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            expr_names(condition, names);
+            stmt_names(then_branch, names);
+            if let Some(else_branch) = else_branch.as_ref() {
+                stmt_names(else_branch, names);
+            }
+        }
+        Stmt::While {
+            condition, body, ..
+        } => {
+            expr_names(condition, names);
+            stmt_names(body, names);
+        }
+        Stmt::Try { body, handler, .. } => body
+            .iter()
+            .chain(handler)
+            .for_each(|s| stmt_names(s, names)),
+        Stmt::Throw { value, .. } => expr_names(value, names),
+        Stmt::Defer { body, .. } => stmt_names(body, names),
+    }
+}
+
+fn expr_names(expression: &Expr, names: &mut Vec<Token>) {
+    match expression {
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            expr_names(left, names);
+            expr_names(right, names);
+        }
+        Expr::Call {
+            callee, arguments, ..
+        } => {
+            expr_names(callee, names);
+            arguments
+                .iter()
+                .for_each(|argument| expr_names(argument, names));
+        }
+        Expr::Get { object, .. } => expr_names(object, names),
+        Expr::Set { object, value, .. } => {
+            expr_names(object, names);
+            expr_names(value, names);
+        }
+        Expr::Unary { right, .. } => expr_names(right, names),
+        Expr::Grouping { expression } => expr_names(expression, names),
+        Expr::Variable { name, .. } => names.push(name.clone()),
+        Expr::Assign { name, value, .. } => {
+            names.push(name.clone());
+            expr_names(value, names);
+        }
+        Expr::Match { subject, arms, .. } => {
+            expr_names(subject, names);
+            for MatchArm { pattern, body } in arms {
+                pattern_names(pattern, names);
+                expr_names(body, names);
+            }
+        }
+        Expr::Try { body, default, .. } => {
+            expr_names(body, names);
+            expr_names(default, names);
+        }
+        Expr::Super { .. } | Expr::This { .. } | Expr::Literal { .. } => (),
+    }
+}
+
+fn pattern_names(pattern: &Pattern, names: &mut Vec<Token>) {
+    match pattern {
+        Pattern::Instance { class, fields } => {
+            expr_names(class, names);
+            fields
+                .iter()
+                .for_each(|(_, pattern)| pattern_names(pattern, names));
+        }
+        Pattern::List { elements, .. } => elements
+            .iter()
+            .for_each(|pattern| pattern_names(pattern, names)),
+        Pattern::Wildcard | Pattern::Literal(_) | Pattern::Binding(_) => (),
+    }
+}
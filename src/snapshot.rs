@@ -0,0 +1,589 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+
+use crate::class::LoxClass;
+use crate::environment::Environment;
+use crate::error::Error;
+use crate::function::Function;
+use crate::inspect::inspect;
+use crate::interpreter::Interpreter;
+use crate::natives::{define_natives, set_native_state};
+use crate::object::Object;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
+use crate::syntax::{Expr, Resolution, SourcePrinter, Stmt};
+use crate::token::{Token, TokenType};
+use crate::token_stream::TokenStream;
+use crate::{list, map, set};
+
+const HEADER: &str = "lox snapshot 1";
+
+// A snapshot is a text file with one record per line:
+//
+//   fun <id> "<source>"                    a function
+//   class <id> <superclass> "<source>"     a class, superclass is a value
+//   instance <id> <class>                  an instance without its fields
+//   field <id> <name> <value>              a field of instance <id>
+//   items <id> <value>...                  the elements of List <id>
+//   elements <id> <value>...               the elements of Set <id>
+//   entries <id> <key> <value>...          the keys and values of Map <id>
+//   seal <id>                              class <id> is sealed
+//   freeze <id>                            instance <id> is frozen
+//   global <name> <value>                  a global variable
+//
+// where a value is `nil`, `true`, `false`, `number <n>`, `string "<text>"`,
+// `native <name>` for a native function or class, or `@<id>` for one of the
+// records above. Functions and classes are saved as their source, printed
+// from the AST, and compiled again when loading. Records only refer to earlier
+// ones, except fields and the contents of Lists, Sets and Maps which come last
+// so instances can refer to each other and to themselves.
+//
+// Only functions and classes declared at the top level can be saved: anything
+// else closes over local variables, and environments aren't saved. Of the
+// instances of native classes only Lists, Sets and Maps can be saved, the
+// state of the others belongs to Rust.
+
+// Saves the globals with the given names, or all of them, with everything they
+// refer to. Natives that still have their own name are left out, a fresh
+// interpreter has them already.
+pub fn save(interpreter: &Interpreter, names: Option<&[String]>) -> Result<String, Error> {
+    let globals = interpreter.globals.borrow();
+    let natives = natives();
+    let mut selected: Vec<(String, Object)> = match names {
+        Some(names) => names
+            .iter()
+            .map(
+                |name| match globals.values().find(|(global, _)| global == name) {
+                    Some((_, value)) => Ok((name.clone(), value.clone())),
+                    None => Err(invalid(format!("Undefined variable '{}'.", name))),
+                },
+            )
+            .collect::<Result<_, _>>()?,
+        None => globals
+            .values()
+            .filter(|(name, value)| match (value, natives.get(*name)) {
+                (Object::Callable(Function::Native { name: native, .. }), Some(_)) => {
+                    native != name
+                }
+                (Object::Class(class), Some(_)) => {
+                    let class = class.borrow();
+                    !class.is_native() || class.name != *name
+                }
+                // __version__, which a later version must not overwrite.
+                (Object::String(_), Some(native)) => !value.equals(native),
+                _ => true,
+            })
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect(),
+    };
+    selected.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut saver = Saver {
+        globals: &interpreter.globals,
+        natives: &natives,
+        ids: HashMap::new(),
+        records: vec![HEADER.to_string()],
+        fields: Vec::new(),
+    };
+    let mut variables = Vec::new();
+    for (name, value) in &selected {
+        let value = saver.value(value, name)?;
+        variables.push(format!("global {} {}", name, value));
+    }
+
+    let mut records = saver.records;
+    records.append(&mut saver.fields);
+    records.append(&mut variables);
+    Ok(records.join("\n") + "\n")
+}
+
+// Defines the globals of a snapshot made by save() in the interpreter,
+// replacing the ones with the same names.
+pub fn load(interpreter: &mut Interpreter, text: &str) -> Result<(), Error> {
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+        Some((_, HEADER)) => (),
+        _ => return Err(invalid("Not a Lox snapshot.".to_string())),
+    }
+    let records = lines
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            words(line)
+                .map(|words| (index + 1, words))
+                .ok_or_else(|| invalid(format!("Line {} of the snapshot is malformed.", index + 1)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let statements = compile(interpreter, &records)?;
+    let mut statements = statements.into_iter();
+    // The native classes of the interpreter, not the copies natives() makes,
+    // so loaded Lists are instances of its List.
+    let natives: HashMap<String, Object> = natives()
+        .into_iter()
+        .map(|(name, value)| match value {
+            Object::Class(_) => {
+                let class = interpreter.native_class(&name);
+                (name, Object::Class(class))
+            }
+            value => (name, value),
+        })
+        .collect();
+    let globals = Rc::clone(&interpreter.globals);
+    let mut loader = Loader {
+        objects: HashMap::new(),
+        natives: &natives,
+    };
+
+    for (line, words) in &records {
+        let error = || invalid(format!("Line {} of the snapshot is malformed.", line));
+        match words.as_slice() {
+            [kind, id, _] if kind == "fun" => {
+                let function = match statements.next() {
+                    Some(Stmt::Function { name, params, body }) => Function::User {
+                        name: Rc::new(name),
+                        params: Rc::from(params),
+                        body: Rc::from(body),
+                        closure: Rc::clone(&globals),
+                        globals: Rc::clone(&globals),
+                        is_initializer: false,
+                        class: None,
+                    },
+                    _ => return Err(error()),
+                };
+                loader
+                    .define(id, Object::Callable(function))
+                    .ok_or_else(error)?;
+            }
+            [kind, id, superclass @ .., _] if kind == "class" => {
+                let superclass = match loader.value(superclass) {
+                    Some(Object::Class(class)) => Some(class),
+                    Some(Object::Null) => None,
+                    _ => return Err(error()),
+                };
+                let class = match statements.next() {
+                    Some(Stmt::Class { name, methods, .. }) => {
+                        interpreter.new_class(&name, superclass, &methods, &globals)
+                    }
+                    _ => return Err(error()),
+                };
+                loader.define(id, Object::Class(class)).ok_or_else(error)?;
+            }
+            [kind, id, class @ ..] if kind == "instance" => {
+                let class = match loader.value(class) {
+                    Some(Object::Class(class)) => class,
+                    _ => return Err(error()),
+                };
+                let instance = interpreter.new_instance(&class);
+                loader.define(id, instance).ok_or_else(error)?;
+            }
+            [kind, id, name, value @ ..] if kind == "field" => {
+                let instance = loader.value(&[format!("@{}", id)]);
+                let value = loader.value(value);
+                match (instance, value) {
+                    (Some(Object::Instance(instance)), Some(value)) => {
+                        instance.borrow_mut().set_field(name.clone(), value)
+                    }
+                    _ => return Err(error()),
+                }
+            }
+            [kind, id, values @ ..] if kind == "items" => {
+                let list = loader.value(&[format!("@{}", id)]).ok_or_else(error)?;
+                let items = loader.values(values).ok_or_else(error)?;
+                set_native_state(&list, items);
+            }
+            [kind, id, values @ ..] if kind == "elements" => {
+                let set = loader.value(&[format!("@{}", id)]).ok_or_else(error)?;
+                let values = loader.values(values).ok_or_else(error)?;
+                set::set_elements(&set, values).map_err(|_| error())?;
+            }
+            [kind, id, values @ ..] if kind == "entries" => {
+                let map = loader.value(&[format!("@{}", id)]).ok_or_else(error)?;
+                let values = loader.values(values).ok_or_else(error)?;
+                if values.len() % 2 != 0 {
+                    return Err(error());
+                }
+                let entries = values
+                    .chunks(2)
+                    .map(|entry| (entry[0].clone(), entry[1].clone()))
+                    .collect();
+                map::set_entries(&map, entries).map_err(|_| error())?;
+            }
+            [kind, id] if kind == "seal" => match loader.value(&[format!("@{}", id)]) {
+                Some(Object::Class(class)) => class.borrow_mut().sealed = true,
+                _ => return Err(error()),
+            },
+            [kind, id] if kind == "freeze" => match loader.value(&[format!("@{}", id)]) {
+                Some(Object::Instance(instance)) => instance.borrow_mut().frozen = true,
+                _ => return Err(error()),
+            },
+            [kind, name, value @ ..] if kind == "global" => {
+                let value = loader.value(value).ok_or_else(error)?;
+                globals.borrow_mut().define(name.clone(), value);
+            }
+            _ => return Err(error()),
+        }
+    }
+    Ok(())
+}
+
+struct Saver<'a> {
+    globals: &'a Rc<RefCell<Environment>>,
+    natives: &'a HashMap<String, Object>,
+    // Records already written for functions, classes and instances, by
+    // address. A function is known by its body, which its copies share.
+    ids: HashMap<*const (), usize>,
+    records: Vec<String>,
+    fields: Vec<String>,
+}
+
+impl Saver<'_> {
+    // `path` says how the value was reached, for the error message when it
+    // can't be saved.
+    fn value(&mut self, value: &Object, path: &str) -> Result<String, Error> {
+        Ok(match value {
+            Object::Null => "nil".to_string(),
+            Object::Boolean(b) => b.to_string(),
+            // Debug formatting keeps every digit, so the number comes back
+            // exactly.
+            Object::Number(n) => format!("number {:?}", n),
+            Object::String(s) => format!("string {}", quote(s)),
+            Object::Callable(Function::Native { name, .. }) => format!("native {}", name),
+            Object::Callable(Function::User {
+                name,
+                params,
+                body,
+                closure,
+                class: None,
+                ..
+            }) if Rc::ptr_eq(closure, self.globals) => {
+                let address = Rc::as_ptr(body) as *const ();
+                if let Some(id) = self.ids.get(&address) {
+                    return Ok(format!("@{}", id));
+                }
+                let source = SourcePrinter::new().print(&Stmt::Function {
+                    name: (**name).clone(),
+                    params: params.to_vec(),
+                    body: body.to_vec(),
+                });
+                let id = self.next_id();
+                self.ids.insert(address, id);
+                self.records.push(format!("fun {} {}", id, quote(&source)));
+                format!("@{}", id)
+            }
+            Object::Callable(_) => return Err(not_top_level(path)),
+            Object::Foreign(foreign) => {
+                return Err(invalid(format!(
+                    "Cannot save '{}': {} values belong to the host.",
+                    path, foreign.type_name
+                )))
+            }
+            Object::Class(class) if class.borrow().is_native() => {
+                let name = class.borrow().name.clone();
+                match self.natives.get(&name) {
+                    Some(Object::Class(_)) => format!("native {}", name),
+                    _ => {
+                        return Err(invalid(format!(
+                            "Cannot save '{}': the class {} belongs to the host.",
+                            path, name
+                        )))
+                    }
+                }
+            }
+            Object::Class(class) => {
+                let address = Rc::as_ptr(class) as *const ();
+                if let Some(id) = self.ids.get(&address) {
+                    return Ok(format!("@{}", id));
+                }
+                let source = self.class_source(&class.borrow(), path)?;
+                let superclass = match class.borrow().superclass {
+                    Some(ref superclass) => {
+                        let superclass = Object::Class(Rc::clone(superclass));
+                        self.value(&superclass, path)?
+                    }
+                    None => "nil".to_string(),
+                };
+                let id = self.next_id();
+                self.ids.insert(address, id);
+                self.records
+                    .push(format!("class {} {} {}", id, superclass, quote(&source)));
+                if class.borrow().sealed {
+                    self.records.push(format!("seal {}", id));
+                }
+                format!("@{}", id)
+            }
+            Object::Instance(instance) => {
+                let address = Rc::as_ptr(instance) as *const ();
+                if let Some(id) = self.ids.get(&address) {
+                    return Ok(format!("@{}", id));
+                }
+                let class = Object::Class(Rc::clone(&instance.borrow().class));
+                let class = self.value(&class, path)?;
+                let id = self.next_id();
+                self.ids.insert(address, id);
+                self.records.push(format!("instance {} {}", id, class));
+
+                let mut fields: Vec<(String, Object)> = instance
+                    .borrow()
+                    .fields()
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect();
+                fields.sort_by(|a, b| a.0.cmp(&b.0));
+                for (name, value) in fields {
+                    let value = self.value(&value, &format!("{}.{}", path, name))?;
+                    self.fields.push(format!("field {} {} {}", id, name, value));
+                }
+                self.contents(value, id, path)?;
+                if instance.borrow().frozen {
+                    self.fields.push(format!("freeze {}", id));
+                }
+                format!("@{}", id)
+            }
+        })
+    }
+
+    // The record of what the instance holds if it is a List, Set or Map, an
+    // error if it is another native instance.
+    fn contents(&mut self, instance: &Object, id: usize, path: &str) -> Result<(), Error> {
+        let (kind, values) = if let Ok(items) = list::items(instance) {
+            ("items", items)
+        } else if let Ok(elements) = set::elements(instance) {
+            ("elements", elements)
+        } else if let Ok(entries) = map::entries(instance) {
+            let values = entries
+                .into_iter()
+                .flat_map(|(key, value)| [key, value])
+                .collect();
+            ("entries", values)
+        } else {
+            return match instance {
+                Object::Instance(native) if native.borrow().native.is_some() => {
+                    Err(invalid(format!(
+                        "Cannot save '{}': {} is a native instance, only Lists, Sets and Maps can be saved.",
+                        path,
+                        inspect(instance)
+                    )))
+                }
+                _ => Ok(()),
+            };
+        };
+
+        let mut record = format!("{} {}", kind, id);
+        for (i, value) in values.iter().enumerate() {
+            let value = self.value(value, &format!("{}[{}]", path, i))?;
+            record.push(' ');
+            record.push_str(&value);
+        }
+        self.fields.push(record);
+        Ok(())
+    }
+
+    // The class declaration with its methods sorted by name. The superclass is
+    // only named so the resolver accepts `super`, the loaded class gets the
+    // one from the snapshot.
+    fn class_source(&self, class: &LoxClass, path: &str) -> Result<String, Error> {
+        let mut methods: Vec<(&String, &Function)> = class.methods.iter().collect();
+        methods.sort_by_key(|(name, _)| name.as_str());
+
+        let mut declarations = Vec::new();
+        for (_, method) in methods {
+            match method {
+                Function::User {
+                    name,
+                    params,
+                    body,
+                    closure,
+                    ..
+                } if self.is_top_level(closure, class.superclass.is_some()) => {
+                    declarations.push(Stmt::Function {
+                        name: (**name).clone(),
+                        params: params.to_vec(),
+                        body: body.to_vec(),
+                    })
+                }
+                _ => return Err(not_top_level(path)),
+            }
+        }
+
+        let superclass = class.superclass.as_ref().map(|superclass| Expr::Variable {
+            resolution: Resolution::default(),
+            name: identifier(&superclass.borrow().name),
+        });
+        Ok(SourcePrinter::new().print(&Stmt::Class {
+            name: identifier(&class.name),
+            superclass,
+            methods: declarations,
+        }))
+    }
+
+    // Methods of a class with a superclass close over the environment binding
+    // "super", which is inside the one the class was declared in.
+    fn is_top_level(&self, closure: &Rc<RefCell<Environment>>, has_superclass: bool) -> bool {
+        if !has_superclass {
+            return Rc::ptr_eq(closure, self.globals);
+        }
+        match closure.borrow().enclosing {
+            Some(ref enclosing) => Rc::ptr_eq(enclosing, self.globals),
+            None => false,
+        }
+    }
+
+    fn next_id(&self) -> usize {
+        self.records.len()
+    }
+}
+
+struct Loader<'a> {
+    objects: HashMap<String, Object>,
+    natives: &'a HashMap<String, Object>,
+}
+
+impl Loader<'_> {
+    fn define(&mut self, id: &str, object: Object) -> Option<()> {
+        id.parse::<usize>().ok()?;
+        self.objects.insert(format!("@{}", id), object);
+        Some(())
+    }
+
+    fn value(&self, words: &[String]) -> Option<Object> {
+        match words {
+            [word] if word == "nil" => Some(Object::Null),
+            [word] if word == "true" => Some(Object::Boolean(true)),
+            [word] if word == "false" => Some(Object::Boolean(false)),
+            [kind, n] if kind == "number" => n.parse().ok().map(Object::Number),
+            [kind, s] if kind == "string" => Some(Object::String(Rc::from(s.as_str()))),
+            [kind, name] if kind == "native" => self.natives.get(name).cloned(),
+            [reference] => self.objects.get(reference).cloned(),
+            _ => None,
+        }
+    }
+
+    // The values written one after the other, of one word or two.
+    fn values(&self, mut words: &[String]) -> Option<Vec<Object>> {
+        let mut values = Vec::new();
+        while let Some(kind) = words.first() {
+            let length = match kind.as_str() {
+                "number" | "string" | "native" => 2,
+                _ => 1,
+            };
+            values.push(self.value(words.get(..length)?)?);
+            words = &words[length..];
+        }
+        Some(values)
+    }
+}
+
+// Compiles the sources of the functions and classes in the snapshot in one go,
+// so their tokens don't share lines, and resolves them into the interpreter.
+fn compile(
+    interpreter: &mut Interpreter,
+    records: &[(usize, Vec<String>)],
+) -> Result<Vec<Stmt>, Error> {
+    let sources: Vec<&str> = records
+        .iter()
+        .filter_map(|(_, words)| match words.as_slice() {
+            [kind, _, source] if kind == "fun" => Some(source.as_str()),
+            [kind, _, .., source] if kind == "class" => Some(source.as_str()),
+            _ => None,
+        })
+        .collect();
+    let source = sources.join("\n");
+
+    let mut scanner = Scanner::new(&source);
+    let tokens = scanner.scan_tokens();
+    let mut parser = Parser::new(TokenStream::new(tokens));
+    let statements = parser.parse()?;
+    if scanner.had_error() || parser.had_error() || statements.len() != sources.len() {
+        return Err(invalid(
+            "The snapshot contains invalid source code.".to_string(),
+        ));
+    }
+
+    let mut resolver = Resolver::new(interpreter);
+    resolver.resolve(&statements);
+    if resolver.had_error {
+        return Err(invalid(
+            "The snapshot contains invalid source code.".to_string(),
+        ));
+    }
+    Ok(statements)
+}
+
+fn natives() -> HashMap<String, Object> {
+    let mut environment = Environment::new();
+    define_natives(&mut environment);
+    environment
+        .values()
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .collect()
+}
+
+fn identifier(name: &str) -> Token {
+    Token::new(TokenType::Identifier, name, 1, 1)
+}
+
+// Lox strings can contain anything but a quote, so snapshot strings are
+// quoted with escapes to keep each record on a single line.
+pub(crate) fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Splits a record into words, unquoting quoted ones.
+pub(crate) fn words(line: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut word = String::new();
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => word.push(match chars.next()? {
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        c => c,
+                    }),
+                    c => word.push(c),
+                }
+            }
+            words.push(word);
+        } else {
+            let mut word = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+            words.push(word);
+        }
+    }
+    Some(words)
+}
+
+fn not_top_level(path: &str) -> Error {
+    invalid(format!(
+        "Cannot save '{}': only functions and classes declared at the top level can be saved.",
+        path
+    ))
+}
+
+fn invalid(message: String) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+}
@@ -0,0 +1,416 @@
+use std::fmt;
+
+use crate::error::Error;
+use crate::syntax::{expr, stmt};
+use crate::syntax::{Expr, LiteralValue, MatchArm, Resolution, Stmt};
+use crate::token::{Span, Token};
+
+// Size of one function or method, not counting the functions nested inside it,
+// which are measured on their own.
+#[derive(Debug, Clone)]
+pub struct FunctionStats {
+    // Qualified with the enclosing functions and class, `Point.init`.
+    pub name: String,
+    pub span: Span,
+    pub statements: usize,
+    pub expressions: usize,
+    // How deeply blocks, ifs and loops nest inside the body. A body without
+    // any of them has depth 1.
+    pub depth: usize,
+}
+
+// Metrics about a whole program, collected by a read-only pass over the AST.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub functions: Vec<FunctionStats>,
+    pub classes: usize,
+    pub methods: usize,
+    pub statements: usize,
+    pub expressions: usize,
+    pub depth: usize,
+}
+
+impl Stats {
+    pub fn new(statements: &[Stmt]) -> Self {
+        let mut counter = Counter {
+            stats: Stats::default(),
+            frames: vec![Frame::new(String::new(), Span { line: 0, column: 0 })],
+            class: None,
+        };
+        counter.count_stmts(statements);
+        let script = counter
+            .frames
+            .pop()
+            .expect("The script frame is never popped.");
+        counter.stats.depth = counter.stats.depth.max(script.max_depth);
+        counter
+            .stats
+            .functions
+            .sort_by_key(|function| (function.span.line, function.span.column));
+        counter.stats
+    }
+
+    // The function with the most statements, the first one on ties.
+    pub fn longest_function(&self) -> Option<&FunctionStats> {
+        self.functions
+            .iter()
+            .rev()
+            .max_by_key(|function| function.statements)
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<8} {:<10} {:<11} {:<5} function",
+            "span", "statements", "expressions", "depth"
+        )?;
+        for function in &self.functions {
+            writeln!(
+                f,
+                "{:<8} {:<10} {:<11} {:<5} {}",
+                function.span.to_string(),
+                function.statements,
+                function.expressions,
+                function.depth,
+                function.name
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "statements:  {}", self.statements)?;
+        writeln!(f, "expressions: {}", self.expressions)?;
+        writeln!(f, "max depth:   {}", self.depth)?;
+        writeln!(f, "functions:   {}", self.functions.len() - self.methods)?;
+        writeln!(f, "classes:     {}", self.classes)?;
+        writeln!(f, "methods:     {}", self.methods)?;
+        if let Some(longest) = self.longest_function() {
+            writeln!(
+                f,
+                "longest:     {} ({} statements)",
+                longest.name, longest.statements
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct Frame {
+    stats: FunctionStats,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Frame {
+    fn new(name: String, span: Span) -> Self {
+        Frame {
+            stats: FunctionStats {
+                name,
+                span,
+                statements: 0,
+                expressions: 0,
+                depth: 0,
+            },
+            depth: 0,
+            max_depth: 0,
+        }
+    }
+}
+
+struct Counter {
+    stats: Stats,
+    // The functions being counted, innermost last. The first frame is the
+    // top level of the script.
+    frames: Vec<Frame>,
+    // The class whose methods are being counted.
+    class: Option<String>,
+}
+
+impl Counter {
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("There is always a frame.")
+    }
+
+    fn count_stmts(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.count_stmt(statement);
+        }
+    }
+
+    fn count_stmt(&mut self, statement: &Stmt) {
+        self.frame().stats.statements += 1;
+        self.stats.statements += 1;
+        let _ = statement.accept(self);
+    }
+
+    fn count_expr(&mut self, expression: &Expr) {
+        self.frame().stats.expressions += 1;
+        self.stats.expressions += 1;
+        let _ = expression.accept(self);
+    }
+
+    // Counts statements one level deeper.
+    fn nested(&mut self, statements: &[&Stmt]) {
+        let frame = self.frame();
+        frame.depth += 1;
+        frame.max_depth = frame.max_depth.max(frame.depth);
+        for statement in statements {
+            self.count_stmt(statement);
+        }
+        self.frame().depth -= 1;
+    }
+
+    fn count_function(&mut self, name: &Token, body: &[Stmt]) {
+        let qualified = match (&self.class, self.frames.last()) {
+            (Some(class), _) => format!("{}.{}", class, name.lexeme),
+            (None, Some(frame)) if !frame.stats.name.is_empty() => {
+                format!("{}.{}", frame.stats.name, name.lexeme)
+            }
+            _ => name.lexeme.clone(),
+        };
+
+        // Methods of a class nested in this function are not methods of the
+        // class the function belongs to.
+        let class = self.class.take();
+        self.frames.push(Frame::new(qualified, name.span()));
+        self.nested(&body.iter().collect::<Vec<_>>());
+        let mut frame = self.frames.pop().expect("Pushed above.");
+        self.class = class;
+
+        frame.stats.depth = frame.max_depth;
+        self.stats.depth = self.stats.depth.max(frame.max_depth);
+        self.stats.functions.push(frame.stats);
+    }
+}
+
+impl expr::Visitor<()> for Counter {
+    fn visit_binary_expr(
+        &mut self,
+        left: &Expr,
+        _operator: &Token,
+        right: &Expr,
+    ) -> Result<(), Error> {
+        self.count_expr(left);
+        self.count_expr(right);
+        Ok(())
+    }
+
+    fn visit_call_expr(
+        &mut self,
+        callee: &Expr,
+        _paren: &Token,
+        arguments: &[Expr],
+        _optional: bool,
+    ) -> Result<(), Error> {
+        self.count_expr(callee);
+        for argument in arguments {
+            self.count_expr(argument);
+        }
+        Ok(())
+    }
+
+    fn visit_get_expr(
+        &mut self,
+        object: &Expr,
+        _name: &Token,
+        _optional: bool,
+    ) -> Result<(), Error> {
+        self.count_expr(object);
+        Ok(())
+    }
+
+    fn visit_set_expr(&mut self, object: &Expr, _name: &Token, value: &Expr) -> Result<(), Error> {
+        self.count_expr(object);
+        self.count_expr(value);
+        Ok(())
+    }
+
+    fn visit_super_expr(
+        &mut self,
+        _keyword: &Token,
+        _method: &Token,
+        _resolution: &Resolution,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_this_expr(&mut self, _keyword: &Token, _resolution: &Resolution) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_logical_expr(
+        &mut self,
+        left: &Expr,
+        _operator: &Token,
+        right: &Expr,
+    ) -> Result<(), Error> {
+        self.count_expr(left);
+        self.count_expr(right);
+        Ok(())
+    }
+
+    fn visit_grouping_expr(&mut self, expression: &Expr) -> Result<(), Error> {
+        self.count_expr(expression);
+        Ok(())
+    }
+
+    fn visit_literal_expr(&self, _value: &LiteralValue) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_unary_expr(&mut self, _operator: &Token, right: &Expr) -> Result<(), Error> {
+        self.count_expr(right);
+        Ok(())
+    }
+
+    fn visit_variable_expr(
+        &mut self,
+        _name: &Token,
+        _resolution: &Resolution,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_assign_expr(
+        &mut self,
+        _name: &Token,
+        value: &Expr,
+        _resolution: &Resolution,
+    ) -> Result<(), Error> {
+        self.count_expr(value);
+        Ok(())
+    }
+
+    fn visit_match_expr(
+        &mut self,
+        _keyword: &Token,
+        subject: &Expr,
+        arms: &[MatchArm],
+    ) -> Result<(), Error> {
+        self.count_expr(subject);
+        for arm in arms {
+            self.count_expr(&arm.body);
+        }
+        Ok(())
+    }
+
+    fn visit_try_expr(
+        &mut self,
+        _keyword: &Token,
+        body: &Expr,
+        default: &Expr,
+    ) -> Result<(), Error> {
+        self.count_expr(body);
+        self.count_expr(default);
+        Ok(())
+    }
+}
+
+impl stmt::Visitor<()> for Counter {
+    fn visit_expression_stmt(&mut self, expression: &Expr) -> Result<(), Error> {
+        self.count_expr(expression);
+        Ok(())
+    }
+
+    fn visit_print_stmt(&mut self, expression: &Expr) -> Result<(), Error> {
+        self.count_expr(expression);
+        Ok(())
+    }
+
+    fn visit_function_stmt(
+        &mut self,
+        name: &Token,
+        _params: &[Token],
+        body: &[Stmt],
+    ) -> Result<(), Error> {
+        self.count_function(name, body);
+        Ok(())
+    }
+
+    fn visit_return_stmt(&mut self, _keyword: &Token, value: &Option<Expr>) -> Result<(), Error> {
+        if let Some(value) = value {
+            self.count_expr(value);
+        }
+        Ok(())
+    }
+
+    fn visit_var_stmt(&mut self, _name: &Token, initializer: &Option<Expr>) -> Result<(), Error> {
+        if let Some(initializer) = initializer {
+            self.count_expr(initializer);
+        }
+        Ok(())
+    }
+
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> Result<(), Error> {
+        self.nested(&statements.iter().collect::<Vec<_>>());
+        Ok(())
+    }
+
+    fn visit_class_stmt(
+        &mut self,
+        name: &Token,
+        superclass: &Option<Expr>,
+        methods: &[Stmt],
+    ) -> Result<(), Error> {
+        self.stats.classes += 1;
+        self.stats.methods += methods.len();
+        if let Some(superclass) = superclass {
+            self.count_expr(superclass);
+        }
+
+        let enclosing = self.class.replace(name.lexeme.clone());
+        for method in methods {
+            if let Stmt::Function { name, body, .. } = method {
+                self.count_function(name, body);
+            }
+        }
+        self.class = enclosing;
+        Ok(())
+    }
+
+    fn visit_if_stmt(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: &Option<Stmt>,
+    ) -> Result<(), Error> {
+        self.count_expr(condition);
+        match else_branch {
+            Some(else_branch) => self.nested(&[then_branch, else_branch]),
+            None => self.nested(&[then_branch]),
+        }
+        Ok(())
+    }
+
+    fn visit_while_stmt(
+        &mut self,
+        _keyword: &Token,
+        condition: &Expr,
+        body: &Stmt,
+    ) -> Result<(), Error> {
+        self.count_expr(condition);
+        self.nested(&[body]);
+        Ok(())
+    }
+
+    fn visit_try_stmt(
+        &mut self,
+        _keyword: &Token,
+        body: &[Stmt],
+        _name: &Token,
+        handler: &[Stmt],
+    ) -> Result<(), Error> {
+        self.nested(&body.iter().chain(handler).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    fn visit_throw_stmt(&mut self, _keyword: &Token, value: &Expr) -> Result<(), Error> {
+        self.count_expr(value);
+        Ok(())
+    }
+
+    fn visit_defer_stmt(&mut self, _keyword: &Token, body: &Stmt) -> Result<(), Error> {
+        self.nested(&[body]);
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+
+use crate::function::NativeFn;
+use crate::inspect::inspect;
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+
+// Where a script writes besides what `print` writes to stdout: print_err(value)
+// writes to stderr, for diagnostics that shouldn't mix with the data a script
+// outputs, and open_writer(path, append?) opens a file to write to.
+//
+// A Writer is a foreign value, copies of it write to the same file. The file
+// is closed by close() or when the last copy is gone, whatever was written is
+// flushed then. Hosts running scripts they don't trust turn off opening files
+// with Interpreter::set_allow_file_writes.
+pub(crate) const WRITER_METHODS: &[(&str, NativeFn)] = &[
+    ("write(value)", write),
+    ("write_line(value)", write_line),
+    ("flush()", flush),
+    ("close()", close),
+];
+
+pub(crate) struct Writer {
+    path: String,
+    // None once closed.
+    file: RefCell<Option<BufWriter<File>>>,
+}
+
+// print_err(value) writes the value to stderr the way `print` shows it.
+pub(crate) fn print_err(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let line = args[0].to_string();
+    if let Err(error) = interpreter.count_output(&line) {
+        return Err(interpreter.fail(error));
+    }
+    eprintln!("{}", line);
+    Ok(Object::Null)
+}
+
+// open_writer(path, append?) opens the file for writing, emptying it unless
+// append is true.
+pub(crate) fn open_writer(
+    interpreter: &mut Interpreter,
+    args: &[Object],
+) -> Result<Object, String> {
+    let path = match &args[0] {
+        Object::String(path) => path.to_string(),
+        other => {
+            return Err(format!(
+                "open_writer expects a file name but got {}.",
+                inspect(other)
+            ))
+        }
+    };
+    if !interpreter.allow_file_writes() {
+        return Err(format!(
+            "Cannot open '{}': writing files isn't allowed.",
+            path
+        ));
+    }
+    let append = match args.get(1) {
+        None | Some(Object::Null) | Some(Object::Boolean(false)) => false,
+        Some(_) => true,
+    };
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(&path)
+        .map_err(|error| format!("Cannot open '{}': {}.", path, error))?;
+    Ok(interpreter.foreign(Writer {
+        path,
+        file: RefCell::new(Some(BufWriter::new(file))),
+    }))
+}
+
+// write(value) writes the text `print` shows for the value, without a newline.
+// Returns the writer so calls can be chained.
+fn write(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    with_file(&args[0], |file| write!(file, "{}", args[1]))?;
+    Ok(args[0].clone())
+}
+
+fn write_line(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    with_file(&args[0], |file| writeln!(file, "{}", args[1]))?;
+    Ok(args[0].clone())
+}
+
+fn flush(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    with_file(&args[0], |file| file.flush())?;
+    Ok(Object::Null)
+}
+
+// close() flushes and closes the file. Closing it again does nothing.
+fn close(_interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let writer = writer(&args[0])?;
+    if let Some(mut file) = writer.file.borrow_mut().take() {
+        file.flush()
+            .map_err(|error| format!("Cannot write '{}': {}.", writer.path, error))?;
+    }
+    Ok(Object::Null)
+}
+
+fn writer(this: &Object) -> Result<&Writer, String> {
+    this.downcast_foreign::<Writer>()
+        .ok_or_else(|| format!("Expected a Writer but got {}.", inspect(this)))
+}
+
+fn with_file(
+    this: &Object,
+    f: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> Result<(), String> {
+    let writer = writer(this)?;
+    let mut file = writer.file.borrow_mut();
+    let Some(file) = file.as_mut() else {
+        return Err(format!("The writer of '{}' is closed.", writer.path));
+    };
+    f(file).map_err(|error| format!("Cannot write '{}': {}.", writer.path, error))
+}
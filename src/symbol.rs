@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::syntax::Depth;
+use crate::token::{Span, Token, TokenType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Class,
+    Fun,
+    Method,
+    Param,
+    Var,
+}
+
+impl fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            SymbolKind::Class => "class",
+            SymbolKind::Fun => "fun",
+            SymbolKind::Method => "method",
+            SymbolKind::Param => "param",
+            SymbolKind::Var => "var",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+// A single declaration in the program together with every place that refers
+// to it.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // Number of scopes enclosing the declaration, 0 being the global scope.
+    pub depth: usize,
+    // Id of the scope declaring it, 0 being the global scope.
+    pub scope: usize,
+    pub span: Span,
+    pub references: Vec<Span>,
+    // Index in the table of the declaration in an enclosing scope this one
+    // hides, like a parameter named after a global.
+    pub shadows: Option<usize>,
+}
+
+// How the resolver resolved one use of a name, `this` and `super` included.
+#[derive(Debug, Clone)]
+pub struct Binding {
+    pub name: String,
+    pub span: Span,
+    // Id of the innermost scope around the use.
+    pub scope: usize,
+    // What the resolver stored on the node: how many scopes out the variable
+    // is, or that it is a global.
+    pub depth: Depth,
+    // Index in the table of the declaration it refers to. None for `this`,
+    // `super` and globals the program doesn't declare.
+    pub declaration: Option<usize>,
+    // Whether the use is in a function nested inside the one declaring the
+    // local, so the function's closure captures it.
+    pub captured: bool,
+}
+
+// The symbol table is built by the resolver as a side product of walking the
+// scopes. It follows the exact same scoping rules, so a reference ends up
+// attached to the declaration the interpreter will actually use at runtime.
+#[derive(Debug)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+    // Stack of name -> index into `symbols`. The first entry is the global scope.
+    scopes: Vec<HashMap<String, usize>>,
+    // The id of each scope on the stack, and the id of the scope enclosing
+    // every scope ever begun, indexed by id.
+    scope_ids: Vec<usize>,
+    parents: Vec<Option<usize>>,
+    // Every use of a name, in the order the resolver met them.
+    bindings: Vec<Binding>,
+    // The scopes that are the bodies of functions, innermost last.
+    functions: Vec<usize>,
+    // Globals can be used before they are declared (e.g. from inside a function
+    // body), so these are bound once the whole program has been seen. The
+    // index is the one of their binding.
+    unresolved: Vec<(Token, usize)>,
+    // Local declarations that hid nothing when they were seen.
+    unshadowed: Vec<usize>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable {
+            symbols: Vec::new(),
+            scopes: vec![HashMap::new()],
+            scope_ids: vec![0],
+            parents: vec![None],
+            bindings: Vec::new(),
+            functions: Vec::new(),
+            unresolved: Vec::new(),
+            unshadowed: Vec::new(),
+        }
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    // The uses of names sorted by where they are.
+    pub fn bindings(&self) -> Vec<&Binding> {
+        let mut bindings: Vec<&Binding> = self.bindings.iter().collect();
+        bindings.sort_by_key(|binding| (binding.span.line, binding.span.column));
+        bindings
+    }
+
+    pub fn declaration(&self, binding: &Binding) -> Option<&Symbol> {
+        binding.declaration.map(|index| &self.symbols[index])
+    }
+
+    // The declaration the symbol hides, if any.
+    pub fn shadowed(&self, symbol: &Symbol) -> Option<&Symbol> {
+        symbol.shadows.map(|index| &self.symbols[index])
+    }
+
+    // Finds the symbol whose declaration or one of whose references covers the
+    // given position.
+    pub fn symbol_at(&self, position: Span) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| {
+            symbol.occurrences().any(|span| {
+                let width = symbol.name.chars().count();
+                span.line == position.line
+                    && span.column <= position.column
+                    && position.column < span.column + width
+            })
+        })
+    }
+
+    // Whether the scope `inner` is `outer` or nested in it.
+    pub fn encloses(&self, outer: usize, inner: usize) -> bool {
+        let mut scope = Some(inner);
+        while let Some(id) = scope {
+            if id == outer {
+                return true;
+            }
+            scope = self.parents[id];
+        }
+        false
+    }
+
+    pub(crate) fn begin_scope(&mut self) {
+        self.parents.push(Some(self.current_scope()));
+        self.scope_ids.push(self.parents.len() - 1);
+        self.scopes.push(HashMap::new());
+    }
+
+    pub(crate) fn end_scope(&mut self) {
+        if self.functions.last() == Some(&(self.scopes.len() - 1)) {
+            self.functions.pop();
+        }
+        self.scopes.pop();
+        self.scope_ids.pop();
+    }
+
+    // Marks the scope just begun as the body of a function.
+    pub(crate) fn begin_function(&mut self) {
+        self.functions.push(self.scopes.len() - 1);
+    }
+
+    pub(crate) fn declare(&mut self, name: &Token, kind: SymbolKind) {
+        let index = self.push(name, kind);
+        self.scopes
+            .last_mut()
+            .expect("Symbol scopes is empty.")
+            .insert(name.lexeme.clone(), index);
+    }
+
+    // Methods are looked up dynamically on the instance, so they never shadow
+    // variables and are only recorded, not bound in the current scope.
+    pub(crate) fn declare_method(&mut self, name: &Token) {
+        self.push(name, SymbolKind::Method);
+    }
+
+    // Records a use of the name, resolved to the depth. `this` and `super`
+    // are recorded too but aren't references of a declaration.
+    pub(crate) fn reference(&mut self, name: &Token, depth: Depth) {
+        // The resolver's scopes leave out the global one, which is first here.
+        // `this` and `super` are bound just outside the body of every method,
+        // they are only captured by the functions nested in one.
+        let captured = match depth {
+            Depth::Local { distance, .. } => {
+                let scope = self.scopes.len() - 1 - distance;
+                let bodies = self.functions.iter().filter(|&&body| body > scope).count();
+                let method = usize::from(name.token_type != TokenType::Identifier);
+                bodies > method
+            }
+            Depth::Global => false,
+        };
+        let binding = self.bindings.len();
+        self.bindings.push(Binding {
+            name: name.lexeme.clone(),
+            span: name.span(),
+            scope: self.current_scope(),
+            depth,
+            declaration: None,
+            captured,
+        });
+        if name.token_type != TokenType::Identifier {
+            return;
+        }
+
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.lexeme).copied());
+        match found {
+            Some(index) => {
+                self.symbols[index].references.push(name.span());
+                self.bindings[binding].declaration = Some(index);
+            }
+            None => self.unresolved.push((name.clone(), binding)),
+        }
+    }
+
+    // Binds the references that were made to globals before their declaration,
+    // and the locals declared before a global of the same name to it. Anything
+    // still unknown is an undefined variable and is left out.
+    pub(crate) fn finish(&mut self) {
+        for (name, binding) in std::mem::take(&mut self.unresolved) {
+            if let Some(&index) = self.scopes[0].get(&name.lexeme) {
+                self.symbols[index].references.push(name.span());
+                self.bindings[binding].declaration = Some(index);
+            }
+        }
+        for index in std::mem::take(&mut self.unshadowed) {
+            let symbol = &mut self.symbols[index];
+            symbol.shadows = self.scopes[0].get(&symbol.name).copied();
+        }
+        for symbol in self.symbols.iter_mut() {
+            symbol
+                .references
+                .sort_by_key(|span| (span.line, span.column));
+        }
+    }
+
+    fn push(&mut self, name: &Token, kind: SymbolKind) -> usize {
+        // Declaring a name again in the same scope replaces it rather than
+        // hiding it, so only the enclosing scopes are looked at. A global
+        // declared further down can be hidden too, that is settled in finish.
+        let enclosing = &self.scopes[..self.scopes.len() - 1];
+        let shadows = match kind {
+            SymbolKind::Method => None,
+            _ => enclosing
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(&name.lexeme).copied()),
+        };
+        if shadows.is_none() && kind != SymbolKind::Method && self.scopes.len() > 1 {
+            self.unshadowed.push(self.symbols.len());
+        }
+        self.symbols.push(Symbol {
+            name: name.lexeme.clone(),
+            kind,
+            depth: self.scopes.len() - 1,
+            scope: self.current_scope(),
+            span: name.span(),
+            references: Vec::new(),
+            shadows,
+        });
+        self.symbols.len() - 1
+    }
+
+    fn current_scope(&self) -> usize {
+        *self.scope_ids.last().expect("Symbol scopes is empty.")
+    }
+}
+
+impl Symbol {
+    // The declaration followed by every reference.
+    pub fn occurrences(&self) -> impl Iterator<Item = Span> + '_ {
+        std::iter::once(self.span).chain(self.references.iter().copied())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let references: Vec<String> = self.references.iter().map(|r| r.to_string()).collect();
+        write!(
+            f,
+            "{:<8} {:<6} {:<5} {:<16} [{}]",
+            self.span.to_string(),
+            self.kind.to_string(),
+            self.depth,
+            self.name,
+            references.join(", ")
+        )
+    }
+}
+
+impl fmt::Display for SymbolTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<8} {:<6} {:<5} {:<16} references",
+            "span", "kind", "depth", "name"
+        )?;
+        for symbol in &self.symbols {
+            writeln!(f, "{}", symbol)?;
+        }
+        Ok(())
+    }
+}
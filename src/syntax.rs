@@ -1,4 +1,6 @@
+use std::cell::Cell;
 use std::fmt;
+use std::rc::Rc;
 
 use crate::error::Error;
 use crate::token::Token;
@@ -15,10 +17,15 @@ pub enum Expr {
         callee: Box<Expr>,
         paren: Token, // We are using this token's location when we report a runtime error caused by a function call (closing paren)
         arguments: Vec<Expr>,
+        // Calls a method accessed with `?.`, like `obj?.method()`. When the
+        // object is nil the whole call evaluates to nil.
+        optional: bool,
     },
     Get {
         object: Box<Expr>,
         name: Token,
+        // `obj?.name` evaluates to nil instead of failing when obj is nil.
+        optional: bool,
     },
     // we are using this instead of Binary to short-circuit
     Logical {
@@ -31,12 +38,15 @@ pub enum Expr {
         name: Token,
         value: Box<Expr>,
     },
+    // Where `super` and `this` are, like the resolution of a variable.
     Super {
         keyword: Token,
         method: Token,
+        resolution: Resolution,
     },
     This {
         keyword: Token,
+        resolution: Resolution,
     },
     Unary {
         operator: Token,
@@ -50,11 +60,58 @@ pub enum Expr {
     },
     Variable {
         name: Token,
+        resolution: Resolution,
     },
     Assign {
         name: Token,
         value: Box<Expr>,
+        resolution: Resolution,
     },
+    // Evaluates the body of the first arm whose pattern matches the subject.
+    Match {
+        keyword: Token,
+        subject: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
+    // `try body else default` evaluates to the default when the body fails.
+    Try {
+        keyword: Token,
+        body: Box<Expr>,
+        default: Box<Expr>,
+    },
+}
+
+// Where the variable of a Variable or Assign expression lives, or the `this`
+// and `super` of a This or Super expression. The resolver stores it on the node
+// instead of in a table keyed by tokens: every line of the REPL is scanned
+// from line 1, so nodes of different lines can have equal tokens and a table
+// would give a function typed earlier the place of a later line's variable.
+//
+// It never has to be updated: the resolver decides where a node's variable
+// lives once and for all, and code entered later in the REPL comes with nodes
+// of its own. Only the place is stored, globals are still looked up by name,
+// so redefining one in the REPL is seen by code referring to it.
+#[derive(Debug, Clone, Default)]
+pub struct Resolution(Cell<Option<Depth>>);
+
+#[derive(Debug, Clone, Copy)]
+pub enum Depth {
+    // The number of environments between the one the node runs in and the
+    // one holding the variable, and the variable's slot in it: locals are
+    // numbered in the order their scope declares them, which is the order
+    // they are defined in when it runs.
+    Local { distance: usize, slot: usize },
+    Global,
+}
+
+impl Resolution {
+    pub fn get(&self) -> Option<Depth> {
+        self.0.get()
+    }
+
+    pub fn set(&self, depth: Depth) {
+        self.0.set(Some(depth));
+    }
 }
 
 impl fmt::Display for Expr {
@@ -68,7 +125,8 @@ pub enum LiteralValue {
     Boolean(bool),
     Number(f64),
     Null,
-    String(String),
+    // Shared with every value evaluated from the literal.
+    String(Rc<str>),
 }
 
 impl fmt::Display for LiteralValue {
@@ -82,6 +140,68 @@ impl fmt::Display for LiteralValue {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Expr,
+}
+
+// What a match arm compares its subject against. Bindings are variables of
+// the arm's body, set to the part of the subject they stand for.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    // `_` matches anything.
+    Wildcard,
+    // Matches values equal to the literal.
+    Literal(LiteralValue),
+    Binding(Token),
+    // `Point(x, y: 0)` matches instances of Point or its subclasses with the
+    // fields x and y, y being 0. A field without a pattern binds its value to
+    // a variable of the same name.
+    Instance {
+        // Assuming Expr::Variable
+        class: Expr,
+        fields: Vec<(Token, Pattern)>,
+    },
+    // `[first, _]` matches Lists of exactly as many elements.
+    List {
+        bracket: Token,
+        elements: Vec<Pattern>,
+    },
+}
+
+// Prints the pattern the way it is written.
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Literal(LiteralValue::Null) => write!(f, "nil"),
+            Pattern::Literal(LiteralValue::String(s)) => write!(f, "\"{}\"", s),
+            Pattern::Literal(value) => write!(f, "{}", value),
+            Pattern::Binding(name) => write!(f, "{}", name.lexeme),
+            Pattern::Instance { class, fields } => {
+                if let Expr::Variable { name, .. } = class {
+                    write!(f, "{}", name.lexeme)?;
+                }
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, pattern)| match pattern {
+                        Pattern::Binding(binding) if binding.lexeme == name.lexeme => {
+                            name.lexeme.clone()
+                        }
+                        _ => format!("{}: {}", name.lexeme, pattern),
+                    })
+                    .collect();
+                write!(f, "({})", fields.join(", "))
+            }
+            Pattern::List { elements, .. } => {
+                let elements: Vec<String> = elements.iter().map(Pattern::to_string).collect();
+                write!(f, "[{}]", elements.join(", "))
+            }
+        }
+    }
+}
+
 impl Expr {
     // we could have used an opaque type pub fn accept<R>(&self, visitor: &impl Visitor<R>) -> R
     // or dynamic dispatch pub fn accept<R>(&self, visitor: &dyn Visitor<R>) -> R
@@ -97,8 +217,13 @@ impl Expr {
                 callee,
                 paren,
                 arguments,
-            } => visitor.visit_call_expr(callee, paren, arguments),
-            Expr::Get { object, name } => visitor.visit_get_expr(object, name),
+                optional,
+            } => visitor.visit_call_expr(callee, paren, arguments, *optional),
+            Expr::Get {
+                object,
+                name,
+                optional,
+            } => visitor.visit_get_expr(object, name, *optional),
             Expr::Logical {
                 left,
                 operator,
@@ -109,13 +234,34 @@ impl Expr {
                 name,
                 value,
             } => visitor.visit_set_expr(object, name, value),
-            Expr::Super { keyword, method } => visitor.visit_super_expr(keyword, method),
-            Expr::This { keyword } => visitor.visit_this_expr(keyword),
+            Expr::Super {
+                keyword,
+                method,
+                resolution,
+            } => visitor.visit_super_expr(keyword, method, resolution),
+            Expr::This {
+                keyword,
+                resolution,
+            } => visitor.visit_this_expr(keyword, resolution),
             Expr::Grouping { expression } => visitor.visit_grouping_expr(expression),
             Expr::Literal { value } => visitor.visit_literal_expr(value),
             Expr::Unary { operator, right } => visitor.visit_unary_expr(operator, right),
-            Expr::Variable { name } => visitor.visit_variable_expr(name),
-            Expr::Assign { name, value } => visitor.visit_assign_expr(name, value),
+            Expr::Variable { name, resolution } => visitor.visit_variable_expr(name, resolution),
+            Expr::Assign {
+                name,
+                value,
+                resolution,
+            } => visitor.visit_assign_expr(name, value, resolution),
+            Expr::Match {
+                keyword,
+                subject,
+                arms,
+            } => visitor.visit_match_expr(keyword, subject, arms),
+            Expr::Try {
+                keyword,
+                body,
+                default,
+            } => visitor.visit_try_expr(keyword, body, default),
         }
     }
 }
@@ -124,7 +270,7 @@ pub mod expr {
     use crate::error::Error;
     use crate::token::Token;
 
-    use super::{Expr, LiteralValue};
+    use super::{Expr, LiteralValue, MatchArm, Resolution};
 
     pub trait Visitor<R> {
         fn visit_binary_expr(
@@ -137,13 +283,25 @@ pub mod expr {
             &mut self,
             callee: &Expr,
             paren: &Token,
-            arguments: &Vec<Expr>,
+            arguments: &[Expr],
+            optional: bool,
+        ) -> Result<R, Error>;
+        fn visit_get_expr(
+            &mut self,
+            object: &Expr,
+            name: &Token,
+            optional: bool,
         ) -> Result<R, Error>;
-        fn visit_get_expr(&mut self, object: &Expr, name: &Token) -> Result<R, Error>;
         fn visit_set_expr(&mut self, object: &Expr, name: &Token, value: &Expr)
             -> Result<R, Error>;
-        fn visit_super_expr(&mut self, keyword: &Token, method: &Token) -> Result<R, Error>;
-        fn visit_this_expr(&mut self, keyword: &Token) -> Result<R, Error>;
+        fn visit_super_expr(
+            &mut self,
+            keyword: &Token,
+            method: &Token,
+            resolution: &Resolution,
+        ) -> Result<R, Error>;
+        fn visit_this_expr(&mut self, keyword: &Token, resolution: &Resolution)
+            -> Result<R, Error>;
         fn visit_logical_expr(
             &mut self,
             left: &Expr,
@@ -153,8 +311,29 @@ pub mod expr {
         fn visit_grouping_expr(&mut self, expression: &Expr) -> Result<R, Error>;
         fn visit_literal_expr(&self, value: &LiteralValue) -> Result<R, Error>;
         fn visit_unary_expr(&mut self, operator: &Token, right: &Expr) -> Result<R, Error>;
-        fn visit_variable_expr(&mut self, name: &Token) -> Result<R, Error>;
-        fn visit_assign_expr(&mut self, name: &Token, value: &Expr) -> Result<R, Error>;
+        fn visit_variable_expr(
+            &mut self,
+            name: &Token,
+            resolution: &Resolution,
+        ) -> Result<R, Error>;
+        fn visit_assign_expr(
+            &mut self,
+            name: &Token,
+            value: &Expr,
+            resolution: &Resolution,
+        ) -> Result<R, Error>;
+        fn visit_match_expr(
+            &mut self,
+            keyword: &Token,
+            subject: &Expr,
+            arms: &[MatchArm],
+        ) -> Result<R, Error>;
+        fn visit_try_expr(
+            &mut self,
+            keyword: &Token,
+            body: &Expr,
+            default: &Expr,
+        ) -> Result<R, Error>;
     }
 }
 #[derive(Debug, Clone)]
@@ -200,10 +379,29 @@ pub enum Stmt {
         else_branch: Box<Option<Stmt>>,
     },
     While {
+        // The `while` or `for` keyword, where an interrupted loop is reported.
+        keyword: Token,
         condition: Expr,
         body: Box<Stmt>,
     },
-    Null, // placeholder until statement handling is figured out after synchronize()
+    // Runs the handler when the body throws or fails with a runtime error,
+    // with the thrown value or an Error instance bound to `name`.
+    Try {
+        keyword: Token,
+        body: Vec<Stmt>,
+        name: Token,
+        handler: Vec<Stmt>,
+    },
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+    // Runs the body when the function it is in exits, with the environment
+    // the `defer` was executed in.
+    Defer {
+        keyword: Token,
+        body: Box<Stmt>,
+    },
 }
 
 impl Stmt {
@@ -222,13 +420,24 @@ impl Stmt {
                 superclass,
                 methods,
             } => visitor.visit_class_stmt(name, superclass, methods),
-            Stmt::Null => unimplemented!(),
             Stmt::If {
                 condition,
                 then_branch,
                 else_branch,
             } => visitor.visit_if_stmt(condition, then_branch, else_branch),
-            Stmt::While { condition, body } => visitor.visit_while_stmt(condition, body),
+            Stmt::While {
+                keyword,
+                condition,
+                body,
+            } => visitor.visit_while_stmt(keyword, condition, body),
+            Stmt::Try {
+                keyword,
+                body,
+                name,
+                handler,
+            } => visitor.visit_try_stmt(keyword, body, name, handler),
+            Stmt::Throw { keyword, value } => visitor.visit_throw_stmt(keyword, value),
+            Stmt::Defer { keyword, body } => visitor.visit_defer_stmt(keyword, body),
         }
     }
 }
@@ -245,17 +454,17 @@ pub mod stmt {
         fn visit_function_stmt(
             &mut self,
             name: &Token,
-            params: &Vec<Token>,
-            body: &Vec<Stmt>,
+            params: &[Token],
+            body: &[Stmt],
         ) -> Result<R, Error>;
         fn visit_return_stmt(&mut self, keyword: &Token, value: &Option<Expr>) -> Result<R, Error>;
         fn visit_var_stmt(&mut self, name: &Token, initializer: &Option<Expr>) -> Result<R, Error>;
-        fn visit_block_stmt(&mut self, statements: &Vec<Stmt>) -> Result<R, Error>;
+        fn visit_block_stmt(&mut self, statements: &[Stmt]) -> Result<R, Error>;
         fn visit_class_stmt(
             &mut self,
             name: &Token,
             superclass: &Option<Expr>,
-            methods: &Vec<Stmt>,
+            methods: &[Stmt],
         ) -> Result<R, Error>;
         fn visit_if_stmt(
             &mut self,
@@ -263,28 +472,83 @@ pub mod stmt {
             then_branch: &Stmt,
             else_branch: &Option<Stmt>,
         ) -> Result<R, Error>;
-        fn visit_while_stmt(&mut self, condition: &Expr, body: &Stmt) -> Result<R, Error>;
+        fn visit_while_stmt(
+            &mut self,
+            keyword: &Token,
+            condition: &Expr,
+            body: &Stmt,
+        ) -> Result<R, Error>;
+        fn visit_try_stmt(
+            &mut self,
+            keyword: &Token,
+            body: &[Stmt],
+            name: &Token,
+            handler: &[Stmt],
+        ) -> Result<R, Error>;
+        fn visit_throw_stmt(&mut self, keyword: &Token, value: &Expr) -> Result<R, Error>;
+        fn visit_defer_stmt(&mut self, keyword: &Token, body: &Stmt) -> Result<R, Error>;
     }
 }
 
+// Prints the AST as s-expressions in the style of the book, one top-level
+// statement per line. The format is stable, the golden files in examples/ast
+// depend on it.
 pub struct AstPrinter;
 
 impl AstPrinter {
+    pub fn print(&mut self, statement: &Stmt) -> String {
+        statement
+            .accept(self)
+            .expect("Printing the AST never fails.")
+    }
+
+    pub fn print_expr(&mut self, expression: &Expr) -> String {
+        expression
+            .accept(self)
+            .expect("Printing the AST never fails.")
+    }
+
     fn parenthesize(&mut self, name: String, exprs: Vec<&Expr>) -> Result<String, Error> {
         let mut builder = String::new();
 
-        builder.push_str("(");
+        builder.push('(');
         builder.push_str(&name);
 
         for expr in exprs {
-            builder.push_str(" ");
+            builder.push(' ');
             builder.push_str(&expr.accept(self)?);
         }
 
-        builder.push_str(")");
+        builder.push(')');
 
         Ok(builder)
     }
+
+    // Like parenthesize, for statements and the names and other parts that
+    // are already printed.
+    fn parenthesize_parts(&mut self, name: &str, parts: Vec<String>) -> String {
+        let mut builder = format!("({}", name);
+        for part in parts {
+            builder.push(' ');
+            builder.push_str(&part);
+        }
+        builder.push(')');
+        builder
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) -> Result<Vec<String>, Error> {
+        statements
+            .iter()
+            .map(|statement| statement.accept(self))
+            .collect()
+    }
+
+    fn function(&mut self, name: &Token, params: &[Token], body: &[Stmt]) -> Result<String, Error> {
+        let params: Vec<&str> = params.iter().map(|param| param.lexeme.as_str()).collect();
+        let mut parts = vec![name.lexeme.clone(), format!("({})", params.join(" "))];
+        parts.extend(self.statements(body)?);
+        Ok(self.parenthesize_parts("fun", parts))
+    }
 }
 
 impl expr::Visitor<String> for AstPrinter {
@@ -303,19 +567,39 @@ impl expr::Visitor<String> for AstPrinter {
         name: &Token,
         value: &Expr,
     ) -> Result<String, Error> {
-        self.parenthesize(name.lexeme.clone(), vec![object, value])
+        let parts = vec![
+            object.accept(self)?,
+            name.lexeme.clone(),
+            value.accept(self)?,
+        ];
+        Ok(self.parenthesize_parts("set", parts))
     }
 
-    fn visit_super_expr(&mut self, _keyword: &Token, _method: &Token) -> Result<String, Error> {
-        Ok("super".to_string())
+    fn visit_super_expr(
+        &mut self,
+        _keyword: &Token,
+        method: &Token,
+        _resolution: &Resolution,
+    ) -> Result<String, Error> {
+        Ok(self.parenthesize_parts("super", vec![method.lexeme.clone()]))
     }
 
-    fn visit_this_expr(&mut self, _keyword: &Token) -> Result<String, Error> {
+    fn visit_this_expr(
+        &mut self,
+        _keyword: &Token,
+        _resolution: &Resolution,
+    ) -> Result<String, Error> {
         Ok("this".to_string())
     }
 
-    fn visit_get_expr(&mut self, object: &Expr, name: &Token) -> Result<String, Error> {
-        self.parenthesize(name.lexeme.clone(), vec![object])
+    fn visit_get_expr(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        optional: bool,
+    ) -> Result<String, Error> {
+        let parts = vec![object.accept(self)?, name.lexeme.clone()];
+        Ok(self.parenthesize_parts(if optional { "get?" } else { "get" }, parts))
     }
 
     fn visit_grouping_expr(&mut self, expression: &Expr) -> Result<String, Error> {
@@ -323,19 +607,33 @@ impl expr::Visitor<String> for AstPrinter {
     }
 
     fn visit_literal_expr(&self, value: &LiteralValue) -> Result<String, Error> {
-        Ok(value.to_string())
+        Ok(match value {
+            LiteralValue::Null => "nil".to_string(),
+            LiteralValue::String(s) => format!("\"{}\"", s),
+            LiteralValue::Boolean(_) | LiteralValue::Number(_) => value.to_string(),
+        })
     }
 
     fn visit_unary_expr(&mut self, operator: &Token, right: &Expr) -> Result<String, Error> {
         self.parenthesize(operator.lexeme.clone(), vec![right])
     }
 
-    fn visit_variable_expr(&mut self, name: &Token) -> Result<String, Error> {
+    fn visit_variable_expr(
+        &mut self,
+        name: &Token,
+        _resolution: &Resolution,
+    ) -> Result<String, Error> {
         Ok(name.lexeme.clone())
     }
 
-    fn visit_assign_expr(&mut self, name: &Token, value: &Expr) -> Result<String, Error> {
-        self.parenthesize(name.lexeme.clone(), vec![value])
+    fn visit_assign_expr(
+        &mut self,
+        name: &Token,
+        value: &Expr,
+        _resolution: &Resolution,
+    ) -> Result<String, Error> {
+        let parts = vec![name.lexeme.clone(), value.accept(self)?];
+        Ok(self.parenthesize_parts("=", parts))
     }
 
     fn visit_logical_expr(
@@ -349,10 +647,473 @@ impl expr::Visitor<String> for AstPrinter {
 
     fn visit_call_expr(
         &mut self,
-        _callee: &Expr,
+        callee: &Expr,
         _paren: &Token,
-        _arguments: &Vec<Expr>,
+        arguments: &[Expr],
+        _optional: bool,
+    ) -> Result<String, Error> {
+        let mut exprs = vec![callee];
+        exprs.extend(arguments);
+        self.parenthesize("call".to_string(), exprs)
+    }
+
+    fn visit_match_expr(
+        &mut self,
+        _keyword: &Token,
+        subject: &Expr,
+        arms: &[MatchArm],
+    ) -> Result<String, Error> {
+        let mut parts = vec![subject.accept(self)?];
+        for arm in arms {
+            let arm = vec![arm.pattern.to_string(), arm.body.accept(self)?];
+            parts.push(self.parenthesize_parts("->", arm));
+        }
+        Ok(self.parenthesize_parts("match", parts))
+    }
+
+    fn visit_try_expr(
+        &mut self,
+        _keyword: &Token,
+        body: &Expr,
+        default: &Expr,
     ) -> Result<String, Error> {
-        unimplemented!()
+        self.parenthesize("try".to_string(), vec![body, default])
+    }
+}
+
+impl stmt::Visitor<String> for AstPrinter {
+    fn visit_expression_stmt(&mut self, expression: &Expr) -> Result<String, Error> {
+        self.parenthesize(";".to_string(), vec![expression])
+    }
+
+    fn visit_print_stmt(&mut self, expression: &Expr) -> Result<String, Error> {
+        self.parenthesize("print".to_string(), vec![expression])
+    }
+
+    fn visit_function_stmt(
+        &mut self,
+        name: &Token,
+        params: &[Token],
+        body: &[Stmt],
+    ) -> Result<String, Error> {
+        self.function(name, params, body)
+    }
+
+    fn visit_return_stmt(
+        &mut self,
+        _keyword: &Token,
+        value: &Option<Expr>,
+    ) -> Result<String, Error> {
+        match value {
+            Some(value) => self.parenthesize("return".to_string(), vec![value]),
+            None => Ok("(return)".to_string()),
+        }
+    }
+
+    fn visit_var_stmt(
+        &mut self,
+        name: &Token,
+        initializer: &Option<Expr>,
+    ) -> Result<String, Error> {
+        let mut parts = vec![name.lexeme.clone()];
+        if let Some(initializer) = initializer {
+            parts.push(initializer.accept(self)?);
+        }
+        Ok(self.parenthesize_parts("var", parts))
+    }
+
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> Result<String, Error> {
+        let parts = self.statements(statements)?;
+        Ok(self.parenthesize_parts("block", parts))
+    }
+
+    fn visit_class_stmt(
+        &mut self,
+        name: &Token,
+        superclass: &Option<Expr>,
+        methods: &[Stmt],
+    ) -> Result<String, Error> {
+        let mut parts = vec![name.lexeme.clone()];
+        if let Some(superclass) = superclass {
+            parts.push("<".to_string());
+            parts.push(superclass.accept(self)?);
+        }
+        parts.extend(self.statements(methods)?);
+        Ok(self.parenthesize_parts("class", parts))
+    }
+
+    fn visit_if_stmt(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: &Option<Stmt>,
+    ) -> Result<String, Error> {
+        let mut parts = vec![condition.accept(self)?, then_branch.accept(self)?];
+        if let Some(else_branch) = else_branch {
+            parts.push(else_branch.accept(self)?);
+        }
+        Ok(self.parenthesize_parts("if", parts))
+    }
+
+    fn visit_while_stmt(
+        &mut self,
+        _keyword: &Token,
+        condition: &Expr,
+        body: &Stmt,
+    ) -> Result<String, Error> {
+        let parts = vec![condition.accept(self)?, body.accept(self)?];
+        Ok(self.parenthesize_parts("while", parts))
+    }
+
+    fn visit_try_stmt(
+        &mut self,
+        _keyword: &Token,
+        body: &[Stmt],
+        name: &Token,
+        handler: &[Stmt],
+    ) -> Result<String, Error> {
+        let body = self.statements(body)?;
+        let mut catch = vec![name.lexeme.clone()];
+        catch.extend(self.statements(handler)?);
+        let parts = vec![
+            self.parenthesize_parts("block", body),
+            self.parenthesize_parts("catch", catch),
+        ];
+        Ok(self.parenthesize_parts("try", parts))
+    }
+
+    fn visit_throw_stmt(&mut self, _keyword: &Token, value: &Expr) -> Result<String, Error> {
+        self.parenthesize("throw".to_string(), vec![value])
+    }
+
+    fn visit_defer_stmt(&mut self, _keyword: &Token, body: &Stmt) -> Result<String, Error> {
+        let parts = vec![body.accept(self)?];
+        Ok(self.parenthesize_parts("defer", parts))
+    }
+}
+
+// Prints the AST back as Lox source. Groupings are kept in the tree, so the
+// output parses to the same tree again, but comments and formatting are lost
+// and `for` loops come out as the `while` loops they desugar to.
+pub struct SourcePrinter {
+    indent: usize,
+}
+
+impl Default for SourcePrinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SourcePrinter {
+    pub fn new() -> Self {
+        SourcePrinter { indent: 0 }
+    }
+
+    pub fn print(&mut self, statement: &Stmt) -> String {
+        statement
+            .accept(self)
+            .expect("Printing source never fails.")
+    }
+
+    fn block(&mut self, statements: &[Stmt]) -> Result<String, Error> {
+        let mut builder = String::from("{\n");
+        self.indent += 1;
+        for statement in statements {
+            builder.push_str(&"  ".repeat(self.indent));
+            builder.push_str(&statement.accept(self)?);
+            builder.push('\n');
+        }
+        self.indent -= 1;
+        builder.push_str(&"  ".repeat(self.indent));
+        builder.push('}');
+        Ok(builder)
+    }
+
+    // A function declaration or a method, without the `fun` keyword.
+    fn function(&mut self, name: &Token, params: &[Token], body: &[Stmt]) -> Result<String, Error> {
+        let params: Vec<&str> = params.iter().map(|param| param.lexeme.as_str()).collect();
+        Ok(format!(
+            "{}({}) {}",
+            name.lexeme,
+            params.join(", "),
+            self.block(body)?
+        ))
+    }
+}
+
+impl expr::Visitor<String> for SourcePrinter {
+    fn visit_binary_expr(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<String, Error> {
+        Ok(format!(
+            "{} {} {}",
+            left.accept(self)?,
+            operator.lexeme,
+            right.accept(self)?
+        ))
+    }
+
+    fn visit_call_expr(
+        &mut self,
+        callee: &Expr,
+        _paren: &Token,
+        arguments: &[Expr],
+        _optional: bool,
+    ) -> Result<String, Error> {
+        let arguments = arguments
+            .iter()
+            .map(|argument| argument.accept(self))
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(format!(
+            "{}({})",
+            callee.accept(self)?,
+            arguments.join(", ")
+        ))
+    }
+
+    fn visit_get_expr(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        optional: bool,
+    ) -> Result<String, Error> {
+        let dot = if optional { "?." } else { "." };
+        Ok(format!("{}{}{}", object.accept(self)?, dot, name.lexeme))
+    }
+
+    fn visit_set_expr(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<String, Error> {
+        Ok(format!(
+            "{}.{} = {}",
+            object.accept(self)?,
+            name.lexeme,
+            value.accept(self)?
+        ))
+    }
+
+    fn visit_super_expr(
+        &mut self,
+        _keyword: &Token,
+        method: &Token,
+        _resolution: &Resolution,
+    ) -> Result<String, Error> {
+        Ok(format!("super.{}", method.lexeme))
+    }
+
+    fn visit_this_expr(
+        &mut self,
+        _keyword: &Token,
+        _resolution: &Resolution,
+    ) -> Result<String, Error> {
+        Ok("this".to_string())
+    }
+
+    fn visit_logical_expr(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<String, Error> {
+        self.visit_binary_expr(left, operator, right)
+    }
+
+    fn visit_grouping_expr(&mut self, expression: &Expr) -> Result<String, Error> {
+        Ok(format!("({})", expression.accept(self)?))
+    }
+
+    fn visit_literal_expr(&self, value: &LiteralValue) -> Result<String, Error> {
+        Ok(match value {
+            LiteralValue::Null => "nil".to_string(),
+            // Lox strings have no escapes, so there is nothing to escape.
+            LiteralValue::String(s) => format!("\"{}\"", s),
+            LiteralValue::Boolean(_) | LiteralValue::Number(_) => value.to_string(),
+        })
+    }
+
+    fn visit_unary_expr(&mut self, operator: &Token, right: &Expr) -> Result<String, Error> {
+        Ok(format!("{}{}", operator.lexeme, right.accept(self)?))
+    }
+
+    fn visit_variable_expr(
+        &mut self,
+        name: &Token,
+        _resolution: &Resolution,
+    ) -> Result<String, Error> {
+        Ok(name.lexeme.clone())
+    }
+
+    fn visit_assign_expr(
+        &mut self,
+        name: &Token,
+        value: &Expr,
+        _resolution: &Resolution,
+    ) -> Result<String, Error> {
+        Ok(format!("{} = {}", name.lexeme, value.accept(self)?))
+    }
+
+    fn visit_match_expr(
+        &mut self,
+        _keyword: &Token,
+        subject: &Expr,
+        arms: &[MatchArm],
+    ) -> Result<String, Error> {
+        let arms = arms
+            .iter()
+            .map(|arm| Ok(format!("{} -> {}", arm.pattern, arm.body.accept(self)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(format!(
+            "match {} {{ {} }}",
+            subject.accept(self)?,
+            arms.join(", ")
+        ))
+    }
+
+    fn visit_try_expr(
+        &mut self,
+        _keyword: &Token,
+        body: &Expr,
+        default: &Expr,
+    ) -> Result<String, Error> {
+        Ok(format!(
+            "try {} else {}",
+            body.accept(self)?,
+            default.accept(self)?
+        ))
+    }
+}
+
+impl stmt::Visitor<String> for SourcePrinter {
+    fn visit_expression_stmt(&mut self, expression: &Expr) -> Result<String, Error> {
+        Ok(format!("{};", expression.accept(self)?))
+    }
+
+    fn visit_print_stmt(&mut self, expression: &Expr) -> Result<String, Error> {
+        Ok(format!("print {};", expression.accept(self)?))
+    }
+
+    fn visit_function_stmt(
+        &mut self,
+        name: &Token,
+        params: &[Token],
+        body: &[Stmt],
+    ) -> Result<String, Error> {
+        Ok(format!("fun {}", self.function(name, params, body)?))
+    }
+
+    fn visit_return_stmt(
+        &mut self,
+        _keyword: &Token,
+        value: &Option<Expr>,
+    ) -> Result<String, Error> {
+        match value {
+            Some(value) => Ok(format!("return {};", value.accept(self)?)),
+            None => Ok("return;".to_string()),
+        }
+    }
+
+    fn visit_var_stmt(
+        &mut self,
+        name: &Token,
+        initializer: &Option<Expr>,
+    ) -> Result<String, Error> {
+        match initializer {
+            Some(initializer) => Ok(format!(
+                "var {} = {};",
+                name.lexeme,
+                initializer.accept(self)?
+            )),
+            None => Ok(format!("var {};", name.lexeme)),
+        }
+    }
+
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> Result<String, Error> {
+        self.block(statements)
+    }
+
+    fn visit_class_stmt(
+        &mut self,
+        name: &Token,
+        superclass: &Option<Expr>,
+        methods: &[Stmt],
+    ) -> Result<String, Error> {
+        let mut builder = format!("class {}", name.lexeme);
+        if let Some(superclass) = superclass {
+            builder.push_str(&format!(" < {}", superclass.accept(self)?));
+        }
+        builder.push_str(" {\n");
+        self.indent += 1;
+        for method in methods {
+            if let Stmt::Function { name, params, body } = method {
+                builder.push_str(&"  ".repeat(self.indent));
+                builder.push_str(&self.function(name, params, body)?);
+                builder.push('\n');
+            }
+        }
+        self.indent -= 1;
+        builder.push_str(&"  ".repeat(self.indent));
+        builder.push('}');
+        Ok(builder)
+    }
+
+    fn visit_if_stmt(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: &Option<Stmt>,
+    ) -> Result<String, Error> {
+        // An else after a nested if without one would bind to the nested if.
+        let then_branch = match (then_branch, else_branch) {
+            (Stmt::If { .. }, Some(_)) => self.block(std::slice::from_ref(then_branch))?,
+            _ => then_branch.accept(self)?,
+        };
+        let mut builder = format!("if ({}) {}", condition.accept(self)?, then_branch);
+        if let Some(else_branch) = else_branch {
+            builder.push_str(&format!(" else {}", else_branch.accept(self)?));
+        }
+        Ok(builder)
+    }
+
+    fn visit_while_stmt(
+        &mut self,
+        _keyword: &Token,
+        condition: &Expr,
+        body: &Stmt,
+    ) -> Result<String, Error> {
+        Ok(format!(
+            "while ({}) {}",
+            condition.accept(self)?,
+            body.accept(self)?
+        ))
+    }
+
+    fn visit_try_stmt(
+        &mut self,
+        _keyword: &Token,
+        body: &[Stmt],
+        name: &Token,
+        handler: &[Stmt],
+    ) -> Result<String, Error> {
+        Ok(format!(
+            "try {} catch ({}) {}",
+            self.block(body)?,
+            name.lexeme,
+            self.block(handler)?
+        ))
+    }
+
+    fn visit_throw_stmt(&mut self, _keyword: &Token, value: &Expr) -> Result<String, Error> {
+        Ok(format!("throw {};", value.accept(self)?))
+    }
+
+    fn visit_defer_stmt(&mut self, _keyword: &Token, body: &Stmt) -> Result<String, Error> {
+        Ok(format!("defer {}", body.accept(self)?))
     }
 }
@@ -0,0 +1,204 @@
+use std::fmt::Write;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::inspect::inspect;
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+
+// Timestamps are seconds since the Unix epoch, with the milliseconds as the
+// fraction. Everything is in UTC: time zones would take a database of their
+// rules, which is more than a script logging when something happened needs.
+// clock() stays the way the book has it, for timing.
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+// A timestamp taken apart.
+struct Parts {
+    year: i64,
+    // 1 to 12.
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    millisecond: u32,
+    // 0 is Sunday.
+    weekday: u32,
+    // 1 to 366.
+    yearday: u32,
+}
+
+impl Parts {
+    fn new(timestamp: f64) -> Self {
+        let milliseconds = (timestamp * 1000.0).floor() as i64;
+        let days = milliseconds.div_euclid(86_400_000);
+        let time = milliseconds.rem_euclid(86_400_000) as u32;
+        let (year, month, day) = civil_from_days(days);
+        Parts {
+            year,
+            month,
+            day,
+            hour: time / 3_600_000,
+            minute: time / 60_000 % 60,
+            second: time / 1000 % 60,
+            millisecond: time % 1000,
+            // The epoch was a Thursday.
+            weekday: (days + 4).rem_euclid(7) as u32,
+            yearday: (days - days_from_civil(year, 1, 1)) as u32 + 1,
+        }
+    }
+}
+
+// The date `days` days after 1970-01-01 in the proleptic Gregorian calendar,
+// Howard Hinnant's algorithm. Eras are the 400 year cycles of the calendar,
+// counted from March so the leap day comes last.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// The inverse of civil_from_days.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = (i64::from(month) + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// time_now() is the current timestamp.
+pub(crate) fn time_now(_interpreter: &mut Interpreter, _args: &[Object]) -> Result<Object, String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Could not retrieve time.");
+    Ok(Object::Number(now.as_millis() as f64 / 1000.0))
+}
+
+// time_parts(timestamp) is a TimeParts instance with the fields year, month
+// (1 to 12), day, hour, minute, second, millisecond, weekday (0 is Sunday) and
+// yearday (1 to 366).
+pub(crate) fn time_parts(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object, String> {
+    let parts = Parts::new(timestamp(&args[0], "time_parts")?);
+    let instance = interpreter.new_instance(&interpreter.native_class("TimeParts"));
+    if let Object::Instance(fields) = &instance {
+        let mut fields = fields.borrow_mut();
+        let mut set =
+            |name: &str, value: f64| fields.set_field(name.to_string(), Object::Number(value));
+        set("year", parts.year as f64);
+        set("month", parts.month.into());
+        set("day", parts.day.into());
+        set("hour", parts.hour.into());
+        set("minute", parts.minute.into());
+        set("second", parts.second.into());
+        set("millisecond", parts.millisecond.into());
+        set("weekday", parts.weekday.into());
+        set("yearday", parts.yearday.into());
+    }
+    Ok(instance)
+}
+
+// time_format(timestamp, format) is the format with these directives replaced
+// by the parts of the timestamp, like strftime:
+//
+//   %Y year          %m month 01-12    %d day 01-31
+//   %H hour 00-23    %M minute 00-59   %S second 00-59
+//   %L millisecond   %j yearday        %a Mon    %A Monday
+//   %b Jan           %B January        %% a literal %
+//
+// time_format(0, "%Y-%m-%d %H:%M:%S") is "1970-01-01 00:00:00".
+pub(crate) fn time_format(
+    _interpreter: &mut Interpreter,
+    args: &[Object],
+) -> Result<Object, String> {
+    let parts = Parts::new(timestamp(&args[0], "time_format")?);
+    let format = match &args[1] {
+        Object::String(format) => format,
+        other => {
+            return Err(format!(
+                "time_format expects a format string but got {}.",
+                inspect(other)
+            ))
+        }
+    };
+
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let weekday = WEEKDAYS[parts.weekday as usize];
+        let month = MONTHS[parts.month as usize - 1];
+        match chars.next() {
+            Some('Y') => write!(out, "{:04}", parts.year),
+            Some('m') => write!(out, "{:02}", parts.month),
+            Some('d') => write!(out, "{:02}", parts.day),
+            Some('H') => write!(out, "{:02}", parts.hour),
+            Some('M') => write!(out, "{:02}", parts.minute),
+            Some('S') => write!(out, "{:02}", parts.second),
+            Some('L') => write!(out, "{:03}", parts.millisecond),
+            Some('j') => write!(out, "{:03}", parts.yearday),
+            Some('a') => write!(out, "{}", &weekday[..3]),
+            Some('A') => write!(out, "{}", weekday),
+            Some('b') => write!(out, "{}", &month[..3]),
+            Some('B') => write!(out, "{}", month),
+            Some('%') => write!(out, "%"),
+            Some(other) => return Err(format!("Unknown time format directive '%{}'.", other)),
+            None => return Err("The time format ends in a lone '%'.".to_string()),
+        }
+        .unwrap();
+    }
+    Ok(Object::String(Rc::from(out)))
+}
+
+// Only timestamps within about 285,000 years of the epoch have a date.
+fn timestamp(value: &Object, native: &str) -> Result<f64, String> {
+    match value {
+        Object::Number(n) if n.abs() < 9e12 => Ok(*n),
+        other => Err(format!(
+            "{} expects a timestamp but got {}.",
+            native,
+            inspect(other)
+        )),
+    }
+}
@@ -8,6 +8,9 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -25,6 +28,9 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    StarStar,
+    QuestionDot,
+    Arrow,
 
     // Literals.
     Identifier,
@@ -33,19 +39,24 @@ pub enum TokenType {
 
     // Keywords.
     And,
+    Catch,
     Class,
+    Defer,
     Else,
     False,
     Fun,
     For,
     If,
+    Match,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
@@ -60,19 +71,44 @@ pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub line: i32,
+    // 1-based column of the first character of the lexeme, counted in chars
+    pub column: usize,
     // in the original code it has the literals here but we can encode them in enums so we don't have to store the separately
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, lexeme: &str, line: i32) -> Self {
+    pub fn new(token_type: TokenType, lexeme: &str, line: i32, column: usize) -> Self {
         Self {
             token_type,
             lexeme: lexeme.to_string(),
             line,
+            column,
         }
     }
 }
 
+// Source position of a token, used by diagnostics and tooling
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub line: i32,
+    pub column: usize,
+}
+
+impl Token {
+    pub fn span(&self) -> Span {
+        Span {
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self.token_type {
@@ -87,6 +123,7 @@ impl Hash for Token {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.lexeme.hash(state);
         self.line.hash(state);
+        self.column.hash(state);
     }
 }
 
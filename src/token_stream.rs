@@ -0,0 +1,101 @@
+use crate::token::{Token, TokenType};
+
+// The tokens between the scanner and the parser. The parser reads them through
+// this instead of indexing into the scanner's output, so a tool can step in
+// between the two: look ahead as far as it needs, try something and back out
+// again with a checkpoint, or replace tokens in place to expand a macro before
+// the parser sees them.
+#[derive(Debug, Clone)]
+pub struct TokenStream {
+    tokens: Vec<Token>,
+    current: usize,
+}
+
+// A position in a TokenStream to go back to with restore().
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint(usize);
+
+impl TokenStream {
+    // The stream always ends with an EOF token, the parser relies on it to know
+    // when to stop. One is added if the tokens don't have it.
+    pub fn new(mut tokens: Vec<Token>) -> Self {
+        if tokens.last().map(|token| &token.token_type) != Some(&TokenType::Eof) {
+            let (line, column) = tokens.last().map_or((1, 1), |token| {
+                (token.line, token.column + token.lexeme.chars().count())
+            });
+            tokens.push(Token::new(TokenType::Eof, "", line, column));
+        }
+        TokenStream { tokens, current: 0 }
+    }
+
+    // The next token, without consuming it.
+    pub fn peek(&self) -> &Token {
+        self.peek_nth(0)
+    }
+
+    // The token `n` tokens ahead of the next one. Looking past the end gives
+    // the EOF token.
+    pub fn peek_nth(&self, n: usize) -> &Token {
+        let index = (self.current + n).min(self.tokens.len() - 1);
+        &self.tokens[index]
+    }
+
+    // The most recently consumed token.
+    pub fn previous(&self) -> Option<&Token> {
+        self.current.checked_sub(1).map(|index| &self.tokens[index])
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.peek().token_type == TokenType::Eof
+    }
+
+    // Consumes the next token and returns it. At the end the EOF token is
+    // returned without being consumed, so it can be seen any number of times.
+    pub fn advance(&mut self) -> &Token {
+        if self.is_at_end() {
+            return self.peek();
+        }
+        self.current += 1;
+        &self.tokens[self.current - 1]
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.current)
+    }
+
+    // Goes back to where the stream was when the checkpoint was taken. Tokens
+    // replaced with splice() since then stay replaced.
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        self.current = checkpoint.0.min(self.tokens.len() - 1);
+    }
+
+    // Replaces the next `count` tokens with `replacement`, which will be read
+    // next. The EOF token can't be replaced.
+    pub fn splice(&mut self, count: usize, replacement: Vec<Token>) {
+        let end = (self.current + count).min(self.tokens.len() - 1);
+        self.tokens.splice(self.current..end, replacement);
+    }
+
+    // Every token including the consumed ones, ending with EOF.
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+}
+
+impl From<Vec<Token>> for TokenStream {
+    fn from(tokens: Vec<Token>) -> Self {
+        TokenStream::new(tokens)
+    }
+}
+
+// Yields the remaining tokens up to, but not including, EOF.
+impl Iterator for TokenStream {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.is_at_end() {
+            return None;
+        }
+        Some(self.advance().clone())
+    }
+}
@@ -0,0 +1,648 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::compiler::{Constant, Op, Prototype};
+use crate::error::{Code, Error};
+use crate::function::{describe_arity, Function};
+use crate::interpreter::Interpreter;
+use crate::limits::Allocation;
+use crate::object::{format_number, Object};
+use crate::token::{Token, TokenType};
+
+// Runs the bytecode of compiler.rs on a stack, like clox. The interpreter
+// isn't used to run anything, only for what both share: the natives, the
+// output, the limits and the flags for Ctrl-C and cancellation.
+//
+// The VM's values are its own, closures have a prototype instead of a body
+// of statements. Numbers, strings, booleans and nil are the same as the
+// interpreter's, so those are what natives can be called with and return;
+// anything else, like a List, is an error. The globals the interpreter
+// defines start out as the VM's globals when they are one of those values or
+// a native.
+// The most calls in progress, the script's own frame included. The frames live
+// on the heap, so this only stops runaway recursion before it takes all the
+// memory there is. clox has 64, too few for recursive programs.
+const FRAMES_MAX: usize = 1024;
+
+pub struct Vm<'a> {
+    interpreter: &'a mut Interpreter,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<Rc<str>, Value>,
+    // The upvalues still pointing into the stack, so closures created in the
+    // same scope share them. Closed when their slot leaves the stack.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+#[derive(Clone)]
+enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Rc<str>),
+    Closure(Rc<Closure>),
+    Native(Function),
+    Class(Rc<RefCell<Class>>),
+    Instance(Rc<RefCell<Instance>>),
+    BoundMethod(Rc<BoundMethod>),
+}
+
+struct Closure {
+    prototype: Rc<Prototype>,
+    upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+// A variable captured by a closure: its slot on the stack while the function
+// declaring it runs, its own value once it left the stack.
+enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+struct Class {
+    name: Rc<str>,
+    methods: HashMap<Rc<str>, Rc<Closure>>,
+}
+
+struct Instance {
+    class: Rc<RefCell<Class>>,
+    fields: HashMap<Rc<str>, Value>,
+    _allocation: Allocation,
+}
+
+struct BoundMethod {
+    receiver: Value,
+    method: Rc<Closure>,
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    // Where the function's slots start on the stack, slot 0 being the
+    // function itself or the instance of a method.
+    base: usize,
+    // Counts the call against the limits, the top level isn't one.
+    _call: Option<Allocation>,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(interpreter: &'a mut Interpreter) -> Self {
+        let globals = interpreter
+            .globals
+            .borrow()
+            .values()
+            .filter_map(|(name, value)| Some((Rc::from(name), Value::from_object(value)?)))
+            .collect();
+        Vm {
+            interpreter,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals,
+            open_upvalues: Vec::new(),
+        }
+    }
+
+    // Runs the compiled program until its top level returns.
+    pub fn run(&mut self, script: Rc<Prototype>) -> Result<(), Error> {
+        let closure = Rc::new(Closure {
+            prototype: script,
+            upvalues: Vec::new(),
+        });
+        self.stack.push(Value::Closure(Rc::clone(&closure)));
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: 0,
+            _call: None,
+        });
+        let result = self.execute();
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    fn execute(&mut self) -> Result<(), Error> {
+        loop {
+            let frame = self.frames.last_mut().expect("VM has no call frame.");
+            let op = frame.closure.prototype.chunk.code[frame.ip];
+            frame.ip += 1;
+            match op {
+                Op::Constant(index) => {
+                    let value = match &self.frame().closure.prototype.chunk.constants[index] {
+                        Constant::Number(n) => Value::Number(*n),
+                        Constant::String(s) => Value::String(Rc::clone(s)),
+                        Constant::Function(_) => unreachable!("Functions are made by Closure."),
+                    };
+                    self.stack.push(value);
+                }
+                Op::Nil => self.stack.push(Value::Nil),
+                Op::True => self.stack.push(Value::Bool(true)),
+                Op::False => self.stack.push(Value::Bool(false)),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::GetLocal(slot) => {
+                    let value = self.stack[self.frame().base + slot].clone();
+                    self.stack.push(value);
+                }
+                Op::SetLocal(slot) => {
+                    let slot = self.frame().base + slot;
+                    self.stack[slot] = self.peek(0).clone();
+                }
+                Op::GetGlobal(index) => {
+                    let name = self.name(index);
+                    match self.globals.get(&name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => return Err(self.error(Code::UndefinedVariable, &[&name])),
+                    }
+                }
+                Op::DefineGlobal(index) => {
+                    let name = self.name(index);
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                Op::SetGlobal(index) => {
+                    let name = self.name(index);
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(&name) {
+                        Some(global) => *global = value,
+                        None => return Err(self.error(Code::UndefinedVariable, &[&name])),
+                    }
+                }
+                Op::GetUpvalue(index) => {
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[index]);
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                Op::SetUpvalue(index) => {
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[index]);
+                    let value = self.peek(0).clone();
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                Op::GetProperty(index) => {
+                    let name = self.name(index);
+                    let Value::Instance(instance) = self.peek(0).clone() else {
+                        return Err(self.error(Code::NotInstanceGet, &[]));
+                    };
+                    let field = instance.borrow().fields.get(&name).cloned();
+                    let value = match field {
+                        Some(value) => value,
+                        None => {
+                            let class = Rc::clone(&instance.borrow().class);
+                            self.bind_method(&class, &name, Value::Instance(instance))?
+                        }
+                    };
+                    self.pop();
+                    self.stack.push(value);
+                }
+                Op::SetProperty(index) => {
+                    let name = self.name(index);
+                    let Value::Instance(instance) = self.peek(1).clone() else {
+                        return Err(self.error(Code::NotInstanceSet, &[]));
+                    };
+                    let value = self.pop();
+                    instance.borrow_mut().fields.insert(name, value.clone());
+                    self.pop();
+                    self.stack.push(value);
+                }
+                Op::GetSuper(index) => {
+                    let name = self.name(index);
+                    let Value::Class(superclass) = self.pop() else {
+                        unreachable!("'super' is always a class.");
+                    };
+                    let receiver = self.pop();
+                    let method = self.bind_method(&superclass, &name, receiver)?;
+                    self.stack.push(method);
+                }
+                Op::Equal => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Value::Bool(left.equals(&right)));
+                }
+                Op::NotEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Value::Bool(!left.equals(&right)));
+                }
+                Op::Greater => self.compare(|left, right| left > right)?,
+                Op::GreaterEqual => self.compare(|left, right| left >= right)?,
+                Op::Less => self.compare(|left, right| left < right)?,
+                Op::LessEqual => self.compare(|left, right| left <= right)?,
+                Op::Add => {
+                    let value = match (self.peek(1), self.peek(0)) {
+                        (&Value::Number(left), &Value::Number(right)) => {
+                            self.number(left, "+", right, left + right)?
+                        }
+                        (Value::String(left), Value::String(right)) => {
+                            self.interpreter
+                                .limits()
+                                .check_string(left.len() + right.len())?;
+                            Value::String(Rc::from(format!("{}{}", left, right)))
+                        }
+                        _ => return Err(self.error(Code::AddOperands, &[])),
+                    };
+                    self.pop();
+                    self.pop();
+                    self.stack.push(value);
+                }
+                Op::Subtract => self.arithmetic("-", |left, right| left - right)?,
+                Op::Multiply => self.arithmetic("*", |left, right| left * right)?,
+                Op::Divide => self.arithmetic("/", |left, right| left / right)?,
+                Op::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Bool(!value.is_truthy()));
+                }
+                Op::Negate => {
+                    let Value::Number(n) = self.peek(0) else {
+                        return Err(self.error(Code::NumberOperand, &[]));
+                    };
+                    let n = *n;
+                    self.pop();
+                    self.stack.push(Value::Number(-n));
+                }
+                Op::Print => {
+                    let text = self.pop().to_string();
+                    self.interpreter.print(&text)?;
+                }
+                Op::Jump(target) => self.frame_mut().ip = target,
+                Op::JumpIfFalse(target) => {
+                    if !self.peek(0).is_truthy() {
+                        self.frame_mut().ip = target;
+                    }
+                }
+                Op::JumpIfTrue(target) => {
+                    if self.peek(0).is_truthy() {
+                        self.frame_mut().ip = target;
+                    }
+                }
+                Op::Loop(target) => {
+                    self.interpreter.check_interrupt(&self.token(""))?;
+                    self.frame_mut().ip = target;
+                }
+                Op::Call(count) => {
+                    let callee = self.peek(count).clone();
+                    self.call_value(callee, count)?;
+                }
+                Op::Closure(index) => {
+                    let Constant::Function(prototype) =
+                        &self.frame().closure.prototype.chunk.constants[index]
+                    else {
+                        unreachable!("Closure needs a function.");
+                    };
+                    let prototype = Rc::clone(prototype);
+                    let base = self.frame().base;
+                    let upvalues = prototype
+                        .upvalues
+                        .iter()
+                        .map(|upvalue| {
+                            if upvalue.is_local {
+                                self.capture_upvalue(base + upvalue.index)
+                            } else {
+                                Rc::clone(&self.frame().closure.upvalues[upvalue.index])
+                            }
+                        })
+                        .collect();
+                    let closure = Closure {
+                        prototype,
+                        upvalues,
+                    };
+                    self.stack.push(Value::Closure(Rc::new(closure)));
+                }
+                Op::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                Op::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("VM has no call frame.");
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                }
+                Op::Class(index) => {
+                    let class = Class {
+                        name: self.name(index),
+                        methods: HashMap::new(),
+                    };
+                    self.stack.push(Value::Class(Rc::new(RefCell::new(class))));
+                }
+                Op::Inherit => {
+                    let Value::Class(superclass) = self.peek(1) else {
+                        return Err(self.error(Code::SuperclassNotClass, &[]));
+                    };
+                    let methods = superclass.borrow().methods.clone();
+                    let Value::Class(subclass) = self.pop() else {
+                        unreachable!("Only classes inherit.");
+                    };
+                    subclass.borrow_mut().methods.extend(methods);
+                }
+                Op::Method(index) => {
+                    let name = self.name(index);
+                    let Value::Closure(method) = self.pop() else {
+                        unreachable!("Methods are closures.");
+                    };
+                    let Value::Class(class) = self.peek(0) else {
+                        unreachable!("Methods belong to a class.");
+                    };
+                    class.borrow_mut().methods.insert(name, method);
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, count: usize) -> Result<(), Error> {
+        match callee {
+            Value::Closure(closure) => self.call_closure(closure, count),
+            Value::BoundMethod(bound) => {
+                let slot = self.stack.len() - count - 1;
+                self.stack[slot] = bound.receiver.clone();
+                self.call_closure(Rc::clone(&bound.method), count)
+            }
+            Value::Class(class) => {
+                let instance = Instance {
+                    class: Rc::clone(&class),
+                    fields: HashMap::new(),
+                    _allocation: self.interpreter.count_instance()?,
+                };
+                let slot = self.stack.len() - count - 1;
+                self.stack[slot] = Value::Instance(Rc::new(RefCell::new(instance)));
+                let class = class.borrow();
+                match class.methods.get("init") {
+                    Some(initializer) => {
+                        self.check_arity(&class.name, initializer.prototype.arity, count)?;
+                        self.call_closure(Rc::clone(initializer), count)
+                    }
+                    // Without an initializer a class takes no arguments.
+                    None => self.check_arity(&class.name, 0, count),
+                }
+            }
+            Value::Native(function) => self.call_native(&function, count),
+            _ => Err(self.error(Code::NotCallable, &[])),
+        }
+    }
+
+    fn call_closure(&mut self, closure: Rc<Closure>, count: usize) -> Result<(), Error> {
+        let prototype = &closure.prototype;
+        self.check_arity(&prototype.name, prototype.arity, count)?;
+        let token = self.token(")");
+        self.interpreter.check_interrupt(&token)?;
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.error(Code::StackOverflow, &[]));
+        }
+        let call = self.interpreter.enter_call(&token)?;
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - count - 1,
+            _call: Some(call),
+        });
+        Ok(())
+    }
+
+    // Natives are called like the interpreter calls them, with the arguments
+    // turned into its values and the result back into the VM's.
+    fn call_native(&mut self, function: &Function, count: usize) -> Result<(), Error> {
+        let paren = self.token(")");
+        function.check_arity(count, &paren)?;
+        let arguments = self.stack[self.stack.len() - count..]
+            .iter()
+            .map(|argument| {
+                argument.to_object().ok_or_else(|| {
+                    let message = format!(
+                        "The VM can't pass {} to the native '{}'.",
+                        argument,
+                        function.name()
+                    );
+                    Error::runtime(&paren, Code::Native, &[&message])
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let result = function.call(self.interpreter, &arguments, &paren)?;
+        let Some(result) = Value::from_object(&result) else {
+            let message = format!(
+                "The VM can't use {}, returned by the native '{}'.",
+                result,
+                function.name()
+            );
+            return Err(Error::runtime(&paren, Code::Native, &[&message]));
+        };
+        self.stack.truncate(self.stack.len() - count - 1);
+        self.stack.push(result);
+        Ok(())
+    }
+
+    fn check_arity(&self, callee: &str, arity: usize, count: usize) -> Result<(), Error> {
+        if arity == count {
+            return Ok(());
+        }
+        Err(self.error(
+            Code::Arity,
+            &[
+                &describe_arity(&(arity..=arity)),
+                callee,
+                &count.to_string(),
+                "",
+            ],
+        ))
+    }
+
+    fn bind_method(
+        &self,
+        class: &Rc<RefCell<Class>>,
+        name: &str,
+        receiver: Value,
+    ) -> Result<Value, Error> {
+        match class.borrow().methods.get(name) {
+            Some(method) => Ok(Value::BoundMethod(Rc::new(BoundMethod {
+                receiver,
+                method: Rc::clone(method),
+            }))),
+            None => Err(self.error(Code::UndefinedProperty, &[name])),
+        }
+    }
+
+    // The upvalue of the slot, the open one already there if another closure
+    // captured it too.
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open == slot));
+        if let Some(upvalue) = existing {
+            return Rc::clone(upvalue);
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(Rc::clone(&upvalue));
+        upvalue
+    }
+
+    // Moves the variables in the slots from `first` up off the stack into the
+    // upvalues capturing them.
+    fn close_upvalues(&mut self, first: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= first => {
+                    *upvalue = Upvalue::Closed(stack[slot].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    fn arithmetic(&mut self, operator: &str, op: fn(f64, f64) -> f64) -> Result<(), Error> {
+        let (left, right) = self.number_operands()?;
+        let value = self.number(left, operator, right, op(left, right))?;
+        self.stack.push(value);
+        Ok(())
+    }
+
+    // The result of arithmetic, checked like the interpreter checks it in
+    // strict mode.
+    fn number(&self, left: f64, operator: &str, right: f64, result: f64) -> Result<Value, Error> {
+        if !self.interpreter.is_strict() {
+            return Ok(Value::Number(result));
+        }
+        let operator = self.token(operator);
+        self.interpreter
+            .arithmetic(&operator, left, right, result)?;
+        Ok(Value::Number(result))
+    }
+
+    fn compare(&mut self, op: fn(f64, f64) -> bool) -> Result<(), Error> {
+        let (left, right) = self.number_operands()?;
+        self.stack.push(Value::Bool(op(left, right)));
+        Ok(())
+    }
+
+    fn number_operands(&mut self) -> Result<(f64, f64), Error> {
+        let (Value::Number(left), Value::Number(right)) = (self.peek(1), self.peek(0)) else {
+            return Err(self.error(Code::NumberOperand, &[]));
+        };
+        let operands = (*left, *right);
+        self.pop();
+        self.pop();
+        Ok(operands)
+    }
+
+    // The name a global or property instruction refers to.
+    fn name(&self, index: usize) -> Rc<str> {
+        match &self.frame().closure.prototype.chunk.constants[index] {
+            Constant::String(name) => Rc::clone(name),
+            _ => unreachable!("Names are string constants."),
+        }
+    }
+
+    // A runtime error at the line of the instruction running.
+    fn error(&self, code: Code, args: &[&str]) -> Error {
+        Error::runtime(&self.token(""), code, args)
+    }
+
+    // Runtime errors are reported at a token, the VM only knows the line.
+    fn token(&self, lexeme: &str) -> Token {
+        let frame = self.frame();
+        let line = frame.closure.prototype.chunk.lines[frame.ip - 1];
+        Token::new(TokenType::Identifier, lexeme, line, 0)
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("VM has no call frame.")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("VM has no call frame.")
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("VM stack underflow.")
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+}
+
+impl Value {
+    // The interpreter's value as one of the VM's, if the VM can use it.
+    fn from_object(object: &Object) -> Option<Value> {
+        Some(match object {
+            Object::Null => Value::Nil,
+            Object::Boolean(b) => Value::Bool(*b),
+            Object::Number(n) => Value::Number(*n),
+            Object::String(s) => Value::String(Rc::clone(s)),
+            Object::Callable(function @ Function::Native { this: None, .. }) => {
+                Value::Native(function.clone())
+            }
+            _ => return None,
+        })
+    }
+
+    // The value for a native, if it is one the interpreter has too.
+    fn to_object(&self) -> Option<Object> {
+        Some(match self {
+            Value::Nil => Object::Null,
+            Value::Bool(b) => Object::Boolean(*b),
+            Value::Number(n) => Object::Number(*n),
+            Value::String(s) => Object::String(Rc::clone(s)),
+            Value::Native(function) => Object::Callable(function.clone()),
+            _ => return None,
+        })
+    }
+
+    fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    // `==` like the interpreter's: numbers and strings by value, everything
+    // else by identity.
+    fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(left), Value::Bool(right)) => left == right,
+            (Value::Number(left), Value::Number(right)) => left == right,
+            (Value::String(left), Value::String(right)) => left == right,
+            (Value::Closure(left), Value::Closure(right)) => Rc::ptr_eq(left, right),
+            (Value::Native(left), Value::Native(right)) => left.is_same(right),
+            (Value::Class(left), Value::Class(right)) => Rc::ptr_eq(left, right),
+            (Value::Instance(left), Value::Instance(right)) => Rc::ptr_eq(left, right),
+            (Value::BoundMethod(left), Value::BoundMethod(right)) => Rc::ptr_eq(left, right),
+            _ => false,
+        }
+    }
+}
+
+// What `print` shows, the same as for the interpreter's values.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", format_number(*n)),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Closure(closure) => write!(f, "<fn {}>", closure.prototype.name),
+            Value::Native(function) => write!(f, "{}", function),
+            Value::Class(class) => write!(f, "{}", class.borrow().name),
+            Value::Instance(instance) => {
+                write!(f, "{} instance", instance.borrow().class.borrow().name)
+            }
+            Value::BoundMethod(bound) => write!(f, "<fn {}>", bound.method.prototype.name),
+        }
+    }
+}